@@ -20,6 +20,7 @@ impl error::Error for ExpressionError {}
 enum Token {
     True,
     False,
+    Not,
     And,
     Or,
     Implies,
@@ -34,6 +35,7 @@ impl Display for Token {
         let fmt = match self {
             Token::True => "T",
             Token::False => "F",
+            Token::Not => "!",
             Token::And => "&",
             Token::Or => "|",
             Token::Implies => ">",
@@ -118,6 +120,7 @@ impl<'a> Tokenizer<'a> {
         match self.tokens.next() {
             Some('T') => Some(Token::True),
             Some('F') => Some(Token::False),
+            Some('!') => Some(Token::Not),
             Some('&') => Some(Token::And),
             Some('|') => Some(Token::Or),
             Some('>') => Some(Token::Implies),
@@ -155,6 +158,11 @@ impl<'a> Expression<'a> {
                 self.iter.next();
                 return Ok(false);
             }
+            // negation applies to the atomic expression right after it
+            Some(Token::Not) => {
+                self.iter.next();
+                return Ok(!self.compute_atomic()?);
+            }
             // if it is a left parenthesis, evaluate the entire expression inside
             Some(Token::LeftParenthesis) => {
                 self.iter.next();
@@ -248,6 +256,17 @@ mod tests {
         assert_eq!(Ok(true), expr_parsed.eval());
     }
 
+    #[test]
+    fn negation_computes() {
+        let expr_str = "!F & !(T & F)";
+        let mut expr_parsed = Expression::new(expr_str);
+        assert_eq!(Ok(true), expr_parsed.eval());
+
+        let expr_str = "!!T | F";
+        let mut expr_parsed = Expression::new(expr_str);
+        assert_eq!(Ok(true), expr_parsed.eval());
+    }
+
     #[test]
     fn expression_error() {
         let expr_str = "T & | T";
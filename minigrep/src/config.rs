@@ -0,0 +1,562 @@
+use std::env;
+use std::fmt;
+
+use crate::{Encoding, FileTypes, LineRanges, Matcher, Template};
+
+/// which files to report when only file names are printed
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ListFiles {
+    WithMatches,
+    WithoutMatch,
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub patterns: Vec<String>,
+    pub pattern_files: Vec<String>,
+    pub file_paths: Vec<String>,
+    pub ignore_case: bool,
+    pub turkic: bool,
+    pub whole_word: bool,
+    pub only_matching: bool,
+    pub byte_offset: bool,
+    pub max_count: Option<usize>,
+    pub line_ranges: LineRanges,
+    pub multiline: bool,
+    pub list_files: Option<ListFiles>,
+    pub null_separated: bool,
+    pub format: Option<Template>,
+    pub quiet: bool,
+    pub decompress: bool,
+    pub recursive: bool,
+    pub follow_links: bool,
+    pub max_depth: Option<usize>,
+    pub file_types: FileTypes,
+    pub stats: bool,
+    pub watch: bool,
+    pub threads: Option<usize>,
+    pub no_sort: bool,
+    pub encoding: Option<Encoding>,
+    pub matcher: Matcher,
+    pub replace: Option<String>,
+    pub write: bool,
+    pub filter: Option<String>,
+}
+
+/// everything that can go wrong while reading the command line
+#[derive(PartialEq, Debug)]
+pub enum ArgsError {
+    /// `-h` or `--help` was passed, the caller should print `usage()` and stop
+    Help,
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue {
+        flag: String,
+        value: String,
+    },
+    MissingQuery,
+    MissingPath,
+    Conflict(&'static str),
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::Help => write!(f, "help requested"),
+            ArgsError::UnknownFlag(flag) => write!(f, "Unknown flag {flag}"),
+            ArgsError::MissingValue(flag) => write!(f, "Didn't get a value for {flag}"),
+            ArgsError::InvalidValue { flag, value } => {
+                write!(f, "Invalid value '{value}' for {flag}")
+            }
+            ArgsError::MissingQuery => write!(f, "Didn't get a query string"),
+            ArgsError::MissingPath => write!(f, "Didn't get a file path"),
+            ArgsError::Conflict(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+// a flag the parser knows about, `value` names its argument if it takes one
+struct Flag {
+    short: Option<char>,
+    long: &'static str,
+    value: Option<&'static str>,
+    help: &'static str,
+}
+
+const FLAGS: &[Flag] = &[
+    Flag {
+        short: Some('i'),
+        long: "ignore-case",
+        value: None,
+        help: "match without regard to case (also set by the IGNORE_CASE variable)",
+    },
+    Flag {
+        short: None,
+        long: "turkic",
+        value: None,
+        help: "with -i, fold I to ı and İ to i as in Turkish and Azerbaijani (fixed strings only)",
+    },
+    Flag {
+        short: Some('w'),
+        long: "word-regexp",
+        value: None,
+        help: "only match whole words",
+    },
+    Flag {
+        short: Some('E'),
+        long: "extended-regexp",
+        value: None,
+        help: "treat patterns as regular expressions",
+    },
+    Flag {
+        short: Some('F'),
+        long: "fixed-strings",
+        value: None,
+        help: "treat patterns as literal text (the default), overriding an earlier -E",
+    },
+    Flag {
+        short: Some('e'),
+        long: "regexp",
+        value: Some("PATTERN"),
+        help: "search for PATTERN, can be given more than once",
+    },
+    Flag {
+        short: Some('f'),
+        long: "file",
+        value: Some("FILE"),
+        help: "read patterns from FILE, one per line",
+    },
+    Flag {
+        short: None,
+        long: "filter",
+        value: Some("EXPR"),
+        help: "match lines by a boolean expression of patterns, e.g. '(error & !timeout) | panic'",
+    },
+    Flag {
+        short: None,
+        long: "lines",
+        value: Some("RANGE"),
+        help: "only search lines in RANGE, e.g. 100-500, 100- or 42, can be given more than once",
+    },
+    Flag {
+        short: Some('U'),
+        long: "multiline",
+        value: None,
+        help: "let matches span lines, printing every line of each match after its line number",
+    },
+    Flag {
+        short: Some('o'),
+        long: "only-matching",
+        value: None,
+        help: "print only the matched parts of each line",
+    },
+    Flag {
+        short: Some('b'),
+        long: "byte-offset",
+        value: None,
+        help: "print the byte offset of each matching line, or of each match with -o",
+    },
+    Flag {
+        short: Some('m'),
+        long: "max-count",
+        value: Some("NUM"),
+        help: "stop searching a file after NUM matching lines",
+    },
+    Flag {
+        short: Some('l'),
+        long: "files-with-matches",
+        value: None,
+        help: "print only the names of files with a match",
+    },
+    Flag {
+        short: Some('L'),
+        long: "files-without-match",
+        value: None,
+        help: "print only the names of files without a match",
+    },
+    Flag {
+        short: Some('Z'),
+        long: "null",
+        value: None,
+        help: "end file names with a NUL byte instead of a newline or colon",
+    },
+    Flag {
+        short: None,
+        long: "format",
+        value: Some("TEMPLATE"),
+        help: "print each match as TEMPLATE, using {path} {line} {column} {offset} {match} {text}",
+    },
+    Flag {
+        short: Some('q'),
+        long: "quiet",
+        value: None,
+        help: "print nothing and stop at the first match, only the exit code tells the result",
+    },
+    Flag {
+        short: Some('r'),
+        long: "recursive",
+        value: None,
+        help: "search directories and everything under them",
+    },
+    Flag {
+        short: None,
+        long: "follow",
+        value: None,
+        help: "with -r, follow symbolic links inside directories (loops are detected)",
+    },
+    Flag {
+        short: None,
+        long: "max-depth",
+        value: Some("NUM"),
+        help: "with -r, descend at most NUM directories below the given paths",
+    },
+    Flag {
+        short: Some('z'),
+        long: "decompress",
+        value: None,
+        help: "transparently search gzip compressed files",
+    },
+    Flag {
+        short: None,
+        long: "encoding",
+        value: Some("NAME"),
+        help: "decode files from NAME (utf-8, utf-16le, utf-16be, latin1)",
+    },
+    Flag {
+        short: None,
+        long: "replace",
+        value: Some("TEXT"),
+        help: "print matching lines with every match replaced by TEXT",
+    },
+    Flag {
+        short: None,
+        long: "write",
+        value: None,
+        help: "with --replace, rewrite the files in place instead of printing",
+    },
+    Flag {
+        short: Some('t'),
+        long: "type",
+        value: Some("TYPE"),
+        help:
+            "with -r, only search files of TYPE (rust, md, toml, ...), can be given more than once",
+    },
+    Flag {
+        short: Some('T'),
+        long: "type-not",
+        value: Some("TYPE"),
+        help: "with -r, don't search files of TYPE, can be given more than once",
+    },
+    Flag {
+        short: None,
+        long: "type-add",
+        value: Some("NAME:GLOB"),
+        help: "add files matching GLOB to the type NAME, creating it if needed",
+    },
+    Flag {
+        short: Some('j'),
+        long: "threads",
+        value: Some("NUM"),
+        help: "search NUM files at a time, still printing them in the order they were found",
+    },
+    Flag {
+        short: None,
+        long: "no-sort",
+        value: None,
+        help: "with -j, print each file as soon as it is searched instead of in order",
+    },
+    Flag {
+        short: None,
+        long: "watch",
+        value: None,
+        help: "keep checking the files for new lines, printing only matches added since the last check",
+    },
+    Flag {
+        short: None,
+        long: "stats",
+        value: None,
+        help: "print counts of files, lines and matches and the time taken at the end",
+    },
+    Flag {
+        short: Some('h'),
+        long: "help",
+        value: None,
+        help: "print this message",
+    },
+];
+
+/// the text printed for `--help`
+pub fn usage() -> String {
+    let mut usage = String::from(
+        "usage: minigrep [flags] <query> <file path>...\n       \
+         minigrep [flags] -e <pattern>... <file path>...\n\n\
+         exits with 0 if a line matched, 1 if nothing matched and 2 on errors\n\nflags:\n",
+    );
+
+    for flag in FLAGS {
+        let short = match flag.short {
+            Some(c) => format!("-{c}, "),
+            None => String::from("    "),
+        };
+        let long = match flag.value {
+            Some(value) => format!("--{} {value}", flag.long),
+            None => format!("--{}", flag.long),
+        };
+        usage.push_str(&format!("  {short}{long:<28} {}\n", flag.help));
+    }
+
+    usage
+}
+
+impl Config {
+    /// parses the command line, where the first argument is the program name
+    ///
+    /// flags can come before, after or between positional arguments, short flags can be
+    /// combined (`-iw`), values can be attached (`-m5`, `--max-count=5`) or passed as the next
+    /// argument, and everything after `--` is treated as positional
+    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, ArgsError> {
+        args.next(); // skip the first argument which is the program name
+
+        let mut config = Config {
+            ignore_case: env::var("IGNORE_CASE").is_ok(),
+            ..Config::default()
+        };
+        let mut positional = Vec::new();
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                positional.extend(args.by_ref());
+                break;
+            }
+
+            if let Some(long) = arg.strip_prefix("--") {
+                let (name, attached) = match long.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (long, None),
+                };
+                let flag = FLAGS
+                    .iter()
+                    .find(|flag| flag.long == name)
+                    .ok_or_else(|| ArgsError::UnknownFlag(arg.clone()))?;
+
+                let value = match (flag.value, attached) {
+                    (Some(_), Some(value)) => Some(value),
+                    (Some(_), None) => Some(
+                        args.next()
+                            .ok_or_else(|| ArgsError::MissingValue(arg.clone()))?,
+                    ),
+                    (None, Some(_)) => return Err(ArgsError::UnknownFlag(arg.clone())),
+                    (None, None) => None,
+                };
+                config.apply(flag, value)?;
+            } else if arg.len() > 1 && arg.starts_with('-') {
+                for (i, c) in arg[1..].char_indices() {
+                    let flag = FLAGS
+                        .iter()
+                        .find(|flag| flag.short == Some(c))
+                        .ok_or_else(|| ArgsError::UnknownFlag(format!("-{c}")))?;
+
+                    if flag.value.is_none() {
+                        config.apply(flag, None)?;
+                        continue;
+                    }
+
+                    // a flag that takes a value swallows the rest of the group, if there is any
+                    let rest = &arg[1 + i + c.len_utf8()..];
+                    let value = if rest.is_empty() {
+                        args.next()
+                            .ok_or_else(|| ArgsError::MissingValue(format!("-{c}")))?
+                    } else {
+                        rest.to_string()
+                    };
+                    config.apply(flag, Some(value))?;
+                    break;
+                }
+            } else {
+                positional.push(arg);
+            }
+        }
+
+        let mut positional = positional.into_iter();
+
+        // with -e, -f or --filter the patterns are already known,
+        // so every positional argument is a path
+        if config.patterns.is_empty() && config.pattern_files.is_empty() && config.filter.is_none()
+        {
+            config
+                .patterns
+                .push(positional.next().ok_or(ArgsError::MissingQuery)?);
+        }
+
+        config.file_paths = positional.collect();
+        if config.file_paths.is_empty() {
+            return Err(ArgsError::MissingPath);
+        }
+
+        // types can be defined after they are used, so they can only be checked once all are in
+        if let Some(name) = config.file_types.unknown() {
+            return Err(ArgsError::InvalidValue {
+                flag: "--type".to_string(),
+                value: name.to_string(),
+            });
+        }
+
+        if config.write && config.replace.is_none() {
+            return Err(ArgsError::Conflict("--write needs --replace"));
+        }
+        if config.write && config.decompress {
+            return Err(ArgsError::Conflict("--write can't be combined with -z"));
+        }
+        if config.turkic && config.matcher == Matcher::Regex {
+            return Err(ArgsError::Conflict(
+                "--turkic only works with fixed strings, not -E",
+            ));
+        }
+        if config.watch && config.line_ranges != LineRanges::default() {
+            return Err(ArgsError::Conflict(
+                "--watch can't be combined with --lines",
+            ));
+        }
+        if config.watch && (config.write || config.decompress || config.multiline || config.quiet) {
+            return Err(ArgsError::Conflict(
+                "--watch can't be combined with --write, -z, -U or -q",
+            ));
+        }
+        if config.multiline && config.write {
+            return Err(ArgsError::Conflict("--write can't be combined with -U"));
+        }
+        if config.multiline && config.filter.is_some() {
+            return Err(ArgsError::Conflict("--filter can't be combined with -U"));
+        }
+        if config.filter.is_some() && config.replace.is_some() {
+            return Err(ArgsError::Conflict(
+                "--replace can't be combined with --filter",
+            ));
+        }
+
+        Ok(config)
+    }
+
+    // records a single flag, `value` is always present for flags that take one
+    fn apply(&mut self, flag: &Flag, value: Option<String>) -> Result<(), ArgsError> {
+        let invalid = |value: &str| ArgsError::InvalidValue {
+            flag: format!("--{}", flag.long),
+            value: value.to_string(),
+        };
+        let value = value.unwrap_or_default();
+
+        match flag.long {
+            "ignore-case" => self.ignore_case = true,
+            "turkic" => self.turkic = true,
+            "word-regexp" => self.whole_word = true,
+            "extended-regexp" => self.matcher = Matcher::Regex,
+            "fixed-strings" => self.matcher = Matcher::Literal,
+            "regexp" => self.patterns.push(value),
+            "file" => self.pattern_files.push(value),
+            "filter" => self.filter = Some(value),
+            "lines" => self
+                .line_ranges
+                .add(&value)
+                .ok_or_else(|| invalid(&value))?,
+            "multiline" => self.multiline = true,
+            "only-matching" => self.only_matching = true,
+            "byte-offset" => self.byte_offset = true,
+            "max-count" => self.max_count = Some(value.parse().map_err(|_| invalid(&value))?),
+            "files-with-matches" => self.list_files = Some(ListFiles::WithMatches),
+            "files-without-match" => self.list_files = Some(ListFiles::WithoutMatch),
+            "null" => self.null_separated = true,
+            "format" => self.format = Some(Template::parse(&value).ok_or_else(|| invalid(&value))?),
+            "quiet" => self.quiet = true,
+            "decompress" => self.decompress = true,
+            "recursive" => self.recursive = true,
+            "follow" => self.follow_links = true,
+            "max-depth" => self.max_depth = Some(value.parse().map_err(|_| invalid(&value))?),
+            "encoding" => {
+                self.encoding = Some(Encoding::from_label(&value).ok_or_else(|| invalid(&value))?)
+            }
+            "replace" => self.replace = Some(value),
+            "write" => self.write = true,
+            "type" => self.file_types.select(&value),
+            "type-not" => self.file_types.negate(&value),
+            "type-add" => self.file_types.add(&value).ok_or_else(|| invalid(&value))?,
+            "threads" => match value.parse() {
+                Ok(threads) if threads > 0 => self.threads = Some(threads),
+                _ => return Err(invalid(&value)),
+            },
+            "no-sort" => self.no_sort = true,
+            "watch" => self.watch = true,
+            "stats" => self.stats = true,
+            "help" => return Err(ArgsError::Help),
+            _ => unreachable!("every flag in FLAGS is handled"),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(args: &[&str]) -> Result<Config, ArgsError> {
+        Config::build(
+            std::iter::once("minigrep")
+                .chain(args.iter().copied())
+                .map(String::from),
+        )
+    }
+
+    #[test]
+    fn parses_combined_and_attached_flags() {
+        let config = build(&["-iwm5", "--encoding=latin1", "query", "a.txt", "b.txt"]).unwrap();
+
+        assert!(config.ignore_case && config.whole_word);
+        assert_eq!(Some(5), config.max_count);
+        assert_eq!(Some(Encoding::Latin1), config.encoding);
+        assert_eq!(vec!["query"], config.patterns);
+        assert_eq!(vec!["a.txt", "b.txt"], config.file_paths);
+    }
+
+    #[test]
+    fn last_matcher_flag_wins() {
+        assert_eq!(Matcher::Regex, build(&["-FE", "q", "a"]).unwrap().matcher);
+        assert_eq!(Matcher::Literal, build(&["-EF", "q", "a"]).unwrap().matcher);
+    }
+
+    #[test]
+    fn double_dash_ends_flags() {
+        let config = build(&["--", "-w", "-file.txt"]).unwrap();
+
+        assert!(!config.whole_word);
+        assert_eq!(vec!["-w"], config.patterns);
+        assert_eq!(vec!["-file.txt"], config.file_paths);
+    }
+
+    #[test]
+    fn reports_bad_flags() {
+        assert_eq!(
+            Some(ArgsError::UnknownFlag("-x".into())),
+            build(&["-x", "query", "a.txt"]).err()
+        );
+        assert_eq!(
+            Some(ArgsError::MissingValue("--max-count".into())),
+            build(&["query", "a.txt", "--max-count"]).err()
+        );
+        assert_eq!(Some(ArgsError::Help), build(&["-h"]).err());
+    }
+
+    #[test]
+    fn types_can_be_added_after_use() {
+        let config = build(&["-t", "notes", "--type-add", "notes:*.notes", "q", "."]).unwrap();
+        assert!(config.file_types.is_match("todo.notes"));
+        assert_eq!(
+            Some(ArgsError::InvalidValue {
+                flag: "--type".into(),
+                value: "notes".into()
+            }),
+            build(&["-t", "notes", "q", "."]).err()
+        );
+    }
+}
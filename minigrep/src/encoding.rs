@@ -0,0 +1,218 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+/// text encodings that files can be transcoded from before searching
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    /// parses the name given to `--encoding`, accepting the common spellings
+    pub fn from_label(label: &str) -> Option<Encoding> {
+        match label.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    // looks at the start of a file for a byte order mark, returning the encoding and its length
+    fn from_bom(bytes: &[u8]) -> Option<(Encoding, usize)> {
+        match bytes {
+            [0xEF, 0xBB, 0xBF, ..] => Some((Encoding::Utf8, 3)),
+            [0xFF, 0xFE, ..] => Some((Encoding::Utf16Le, 2)),
+            [0xFE, 0xFF, ..] => Some((Encoding::Utf16Be, 2)),
+            _ => None,
+        }
+    }
+
+    // best guess for files without a BOM:
+    // ASCII text stored as UTF-16 is full of NUL bytes on one side of each code unit,
+    // and anything else that isn't valid UTF-8 is most likely a single byte encoding
+    fn sniff(bytes: &[u8]) -> Encoding {
+        let even_nuls = bytes.iter().step_by(2).filter(|&&b| b == 0).count();
+        let odd_nuls = bytes.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+        let half = bytes.len() / 4;
+
+        if half > 0 && odd_nuls > half && even_nuls == 0 {
+            return Encoding::Utf16Le;
+        }
+        if half > 0 && even_nuls > half && odd_nuls == 0 {
+            return Encoding::Utf16Be;
+        }
+
+        match std::str::from_utf8(bytes) {
+            Ok(_) => Encoding::Utf8,
+            // the buffer may just end partway through a multi-byte char
+            Err(err) if err.error_len().is_none() => Encoding::Utf8,
+            Err(_) => Encoding::Latin1,
+        }
+    }
+}
+
+/// works out which encoding a reader is in the same way `decode` does, without consuming
+/// anything, returning it with the length of the byte order mark it starts with, if any
+pub fn detect<R: BufRead>(
+    reader: &mut R,
+    encoding: Option<Encoding>,
+) -> io::Result<(Encoding, usize)> {
+    let start = reader.fill_buf()?;
+
+    Ok(match Encoding::from_bom(start) {
+        Some((detected, bom_len)) if encoding.is_none() || encoding == Some(detected) => {
+            (detected, bom_len)
+        }
+        _ => (encoding.unwrap_or_else(|| Encoding::sniff(start)), 0),
+    })
+}
+
+/// wraps a reader so that it always produces UTF-8
+///
+/// when no encoding is given it is detected from the byte order mark, or guessed from the
+/// first block of the file
+pub fn decode<'a, R: BufRead + 'a>(
+    mut reader: R,
+    encoding: Option<Encoding>,
+) -> io::Result<Box<dyn BufRead + 'a>> {
+    let (encoding, bom_len) = detect(&mut reader, encoding)?;
+    reader.consume(bom_len);
+
+    if encoding == Encoding::Utf8 {
+        return Ok(Box::new(reader));
+    }
+
+    Ok(Box::new(BufReader::new(Transcoder {
+        inner: reader,
+        encoding,
+        pending: Vec::new(),
+        out: Vec::new(),
+        out_pos: 0,
+    })))
+}
+
+// converts a non UTF-8 byte stream into UTF-8 as it is read
+struct Transcoder<R> {
+    inner: R,
+    encoding: Encoding,
+    // raw bytes that couldn't be decoded yet, e.g. half of a UTF-16 code unit
+    pending: Vec<u8>,
+    // decoded UTF-8 that hasn't been handed out yet
+    out: Vec<u8>,
+    out_pos: usize,
+}
+
+impl<R: BufRead> Transcoder<R> {
+    fn push_char(&mut self, c: char) {
+        let mut buf = [0; 4];
+        self.out
+            .extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+
+    fn decode_pending(&mut self) {
+        let used = match self.encoding {
+            Encoding::Latin1 => {
+                // every Latin-1 byte is the Unicode code point with the same value
+                for i in 0..self.pending.len() {
+                    self.push_char(self.pending[i] as char);
+                }
+                self.pending.len()
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let little_endian = self.encoding == Encoding::Utf16Le;
+                let mut units: Vec<u16> = self
+                    .pending
+                    .chunks_exact(2)
+                    .map(|pair| {
+                        let pair = [pair[0], pair[1]];
+                        if little_endian {
+                            u16::from_le_bytes(pair)
+                        } else {
+                            u16::from_be_bytes(pair)
+                        }
+                    })
+                    .collect();
+
+                // hold back a trailing high surrogate, its partner may be in the next block
+                if units
+                    .last()
+                    .is_some_and(|unit| (0xD800..0xDC00).contains(unit))
+                {
+                    units.pop();
+                }
+
+                for c in char::decode_utf16(units.iter().copied()) {
+                    self.push_char(c.unwrap_or(char::REPLACEMENT_CHARACTER));
+                }
+                units.len() * 2
+            }
+            Encoding::Utf8 => unreachable!("UTF-8 input is never transcoded"),
+        };
+
+        self.pending.drain(..used);
+    }
+}
+
+impl<R: BufRead> Read for Transcoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.out_pos == self.out.len() {
+            self.out.clear();
+            self.out_pos = 0;
+
+            let input = self.inner.fill_buf()?;
+            if input.is_empty() {
+                if self.pending.is_empty() {
+                    return Ok(0);
+                }
+                // the file ended partway through a character
+                self.pending.clear();
+                self.push_char(char::REPLACEMENT_CHARACTER);
+                break;
+            }
+
+            let len = input.len();
+            self.pending.extend_from_slice(input);
+            self.inner.consume(len);
+            self.decode_pending();
+        }
+
+        let len = buf.len().min(self.out.len() - self.out_pos);
+        buf[..len].copy_from_slice(&self.out[self.out_pos..self.out_pos + len]);
+        self.out_pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(bytes: &[u8], encoding: Option<Encoding>) -> String {
+        let mut decoded = String::new();
+        decode(bytes, encoding)
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+        decoded
+    }
+
+    #[test]
+    fn decodes_utf16_from_bom() {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend("Trust 🦀\n".encode_utf16().flat_map(u16::to_le_bytes));
+
+        assert_eq!("Trust 🦀\n", decode_all(&bytes, None));
+    }
+
+    #[test]
+    fn decodes_latin1() {
+        let bytes = b"caf\xe9\n";
+
+        assert_eq!("café\n", decode_all(bytes, Some(Encoding::Latin1)));
+        assert_eq!("café\n", decode_all(bytes, None));
+    }
+}
@@ -0,0 +1,138 @@
+use std::error::Error;
+
+use logical_expression::Expression;
+
+use crate::{LineMatcher, Matcher, Pattern, PatternSet};
+
+// characters that the logical evaluator understands as operators or grouping
+const OPERATORS: &str = "&|!()<>=";
+
+// a filter expression split up into operators and references to named patterns
+enum Piece {
+    Operator(char),
+    Name(usize),
+}
+
+/// a boolean combination of patterns, such as `(error & !timeout) | panic`
+///
+/// every name in the expression is a pattern that is checked against the line, then the
+/// resulting truth values are combined with the logical expression evaluator.
+/// names containing spaces or operators can be wrapped in double quotes
+pub struct Filter {
+    pieces: Vec<Piece>,
+    patterns: Vec<Pattern>,
+}
+
+impl Filter {
+    pub fn new(
+        expr: &str,
+        ignore_case: bool,
+        whole_word: bool,
+        matcher: Matcher,
+    ) -> Result<Filter, Box<dyn Error>> {
+        let mut names: Vec<String> = Vec::new();
+        let mut pieces = Vec::new();
+        let mut chars = expr.chars().peekable();
+
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if OPERATORS.contains(c) {
+                chars.next();
+                pieces.push(Piece::Operator(c));
+                continue;
+            }
+
+            let mut name = String::new();
+            if c == '"' {
+                chars.next();
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    name.push(c);
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || OPERATORS.contains(c) {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+            }
+
+            // the same name showing up twice only needs to be matched once per line
+            let index = match names.iter().position(|existing| *existing == name) {
+                Some(index) => index,
+                None => {
+                    names.push(name);
+                    names.len() - 1
+                }
+            };
+            pieces.push(Piece::Name(index));
+        }
+
+        let patterns = names
+            .iter()
+            .map(|name| Pattern::build(name, matcher, ignore_case, whole_word))
+            .collect::<Result<_, _>>()?;
+
+        let filter = Filter { pieces, patterns };
+
+        // catch syntax errors once up front, rather than on every line
+        Expression::new(&filter.substitute(&vec![false; names.len()])).eval()?;
+
+        Ok(filter)
+    }
+
+    /// applies `Pattern::turkic` to every pattern in the expression
+    pub fn turkic(mut self, turkic: bool) -> Filter {
+        self.patterns = self
+            .patterns
+            .into_iter()
+            .map(|pattern| pattern.turkic(turkic))
+            .collect();
+        self
+    }
+
+    // rewrites the expression with each name replaced by its truth value
+    fn substitute(&self, values: &[bool]) -> String {
+        self.pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Operator(c) => *c,
+                Piece::Name(index) if values[*index] => 'T',
+                Piece::Name(_) => 'F',
+            })
+            .collect()
+    }
+}
+
+impl LineMatcher for Filter {
+    fn is_match(&self, line: &str) -> bool {
+        let values: Vec<bool> = self
+            .patterns
+            .iter()
+            .map(|pattern| pattern.is_match(line))
+            .collect();
+
+        // the expression was already checked in `new`, so it can't fail to evaluate here
+        Expression::new(&self.substitute(&values))
+            .eval()
+            .unwrap_or(false)
+    }
+
+    /// the spans of every named pattern that occurs in the line, even negated ones
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut spans: Vec<(usize, usize)> = self
+            .patterns
+            .iter()
+            .flat_map(|pattern| pattern.find_spans(line))
+            .collect();
+        PatternSet::merge_spans(&mut spans);
+        spans
+    }
+}
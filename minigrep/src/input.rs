@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use flate2::bufread::MultiGzDecoder;
+
+/// opens a file for streaming search
+///
+/// with the `mmap` feature the reader walks the memory map directly, so lines are only copied
+/// out one at a time as they are read
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+
+    #[cfg(feature = "mmap")]
+    {
+        if file.metadata()?.len() > 0 {
+            // SAFETY: the map is only ever read as bytes, we just can't stop another process
+            // from truncating the file underneath us, which is the usual caveat for mmap
+            let map = unsafe { memmap2::Mmap::map(&file)? };
+            return Ok(Box::new(io::Cursor::new(map)));
+        }
+    }
+
+    Ok(Box::new(BufReader::new(file)))
+}
+
+// every gzip stream starts with these two bytes
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// decompresses the reader on the fly if it holds gzip data, otherwise passes it through
+///
+/// the check is done on the magic bytes rather than the `.gz` extension, so rotated logs are
+/// handled whatever they are named. concatenated gzip members are read one after another
+pub fn decompress_gzip<'a>(mut reader: Box<dyn BufRead + 'a>) -> io::Result<Box<dyn BufRead + 'a>> {
+    if reader.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        return Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))));
+    }
+
+    Ok(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Read;
+
+    // run with `--features mmap` as well, which reads through the memory map instead
+    #[test]
+    fn open_reads_the_whole_file() {
+        let empty = std::env::temp_dir().join(format!("minigrep-open-{}", std::process::id()));
+        fs::write(&empty, "").unwrap();
+
+        let read = |path: &Path| {
+            let mut contents = Vec::new();
+            open(path).unwrap().read_to_end(&mut contents).unwrap();
+            contents
+        };
+        let poem = read(Path::new("files/poem.txt"));
+        let nothing = read(&empty);
+        fs::remove_file(&empty).unwrap();
+
+        assert_eq!(fs::read("files/poem.txt").unwrap(), poem);
+        assert!(nothing.is_empty());
+        assert!(open("files/missing.txt").is_err());
+    }
+}
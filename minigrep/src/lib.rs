@@ -1,72 +1,422 @@
-use std::env;
 use std::error::Error;
 use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-pub struct Config {
-    pub query: String,
-    pub file_path: String,
-    pub ignore_case: bool,
+mod config;
+mod encoding;
+mod filter;
+mod input;
+mod parallel;
+mod pattern;
+mod ranges;
+mod replace;
+mod search;
+mod stats;
+mod stream;
+mod template;
+mod types;
+mod walk;
+mod watch;
+
+pub use config::{usage, ArgsError, Config, ListFiles};
+pub use encoding::{decode, Encoding};
+pub use filter::Filter;
+pub use pattern::{LineMatcher, Matcher, Pattern, PatternSet};
+pub use ranges::LineRanges;
+pub use replace::replace_in_file;
+pub use search::{search_iter, search_matches, search_multiline, Match};
+pub use stats::Stats;
+pub use stream::{search_reader, SearchReader};
+pub use template::{Fields, Template};
+pub use types::FileTypes;
+pub use walk::{WalkOptions, Walker};
+
+/// searches every file in the config, returning whether anything matched
+pub fn run(config: Config) -> Result<bool, Box<dyn Error>> {
+    run_to(config, &mut io::stdout().lock())
+}
+
+// `run`, writing the results to `out` instead of stdout
+fn run_to(config: Config, out: &mut dyn Write) -> Result<bool, Box<dyn Error>> {
+    let started = Instant::now();
+
+    let mut queries = config.patterns.clone();
+    for path in &config.pattern_files {
+        queries.extend(fs::read_to_string(path)?.lines().map(String::from));
+    }
+
+    let pattern = PatternSet::build(
+        &queries,
+        config.matcher,
+        config.ignore_case,
+        config.whole_word,
+    )?
+    .turkic(config.turkic);
+
+    let filter = match &config.filter {
+        Some(expr) => Some(
+            Filter::new(expr, config.ignore_case, config.whole_word, config.matcher)?
+                .turkic(config.turkic),
+        ),
+        None => None,
+    };
+
+    // a filter expression takes the place of the plain patterns when deciding which lines match
+    let matcher: &(dyn LineMatcher + Sync) = match &filter {
+        Some(filter) => filter,
+        None => &pattern,
+    };
+
+    let searcher = Searcher {
+        config: &config,
+        pattern: &pattern,
+        matcher,
+        // like grep, only prefix lines with their file once there is more than one to tell apart
+        show_path: config.file_paths.len() > 1 || config.recursive,
+    };
+
+    let list_paths = || -> Box<dyn Iterator<Item = io::Result<PathBuf>> + Send + '_> {
+        if config.recursive {
+            let options = WalkOptions {
+                follow_links: config.follow_links,
+                max_depth: config.max_depth,
+                types: config.file_types.clone(),
+            };
+            Box::new(Walker::new(&config.file_paths, options))
+        } else {
+            Box::new(config.file_paths.iter().map(|path| Ok(PathBuf::from(path))))
+        }
+    };
+
+    if config.watch {
+        return Ok(watch::watch(&searcher, list_paths, out));
+    }
+
+    let paths = list_paths();
+    let mut progress = Progress::default();
+
+    match config.threads {
+        Some(threads) if threads > 1 => {
+            let sorted = !config.no_sort;
+            parallel::search(
+                &searcher,
+                paths,
+                threads,
+                sorted,
+                |output, result, stats| {
+                    progress.stats += stats;
+                    let result = out.write_all(&output).map_err(|err| err.into()).and(result);
+                    progress.record(result, config.quiet)
+                },
+            );
+        }
+        _ => {
+            for path in paths {
+                let result = path
+                    .map_err(|err| err.into())
+                    .and_then(|path| searcher.search_file(&path, out, &mut progress.stats));
+                if !progress.record(result, config.quiet) {
+                    break;
+                }
+            }
+        }
+    }
+
+    let Progress {
+        found,
+        mut stats,
+        pipe_closed,
+    } = progress;
+    if pipe_closed || (config.quiet && found) {
+        return Ok(found);
+    }
+
+    if config.stats {
+        stats.elapsed = started.elapsed();
+        writeln!(out, "\n{stats}")?;
+    }
+
+    // like grep, -q only hides a skipped file when something else already matched, which
+    // returned above
+    if stats.files_skipped > 0 {
+        return Err(format!("{} files could not be searched", stats.files_skipped).into());
+    }
+
+    Ok(found)
+}
+
+// how a run is going, updated as each file is finished with
+#[derive(Default)]
+struct Progress {
+    found: bool,
+    stats: Stats,
+    // nobody is reading the output anymore, e.g. it was piped into `head`
+    pipe_closed: bool,
+}
+
+impl Progress {
+    // records how a file went, returning whether it's worth searching any more of them
+    fn record(&mut self, result: Result<bool, Box<dyn Error>>, quiet: bool) -> bool {
+        match result {
+            Ok(file_found) => self.found |= file_found,
+            Err(err) if is_broken_pipe(err.as_ref()) => {
+                self.pipe_closed = true;
+                return false;
+            }
+            // a file that can't be searched shouldn't stop the rest from being searched
+            Err(err) => {
+                eprintln!("minigrep: {err}");
+                self.stats.files_skipped += 1;
+            }
+        }
+
+        // the answer is already known after the first match, no matter how many files are left
+        !(quiet && self.found)
+    }
+}
+
+fn is_broken_pipe(err: &(dyn Error + 'static)) -> bool {
+    err.downcast_ref::<io::Error>()
+        .is_some_and(|err| err.kind() == io::ErrorKind::BrokenPipe)
+}
+
+// everything needed to search one file, shared across all the files of a run
+struct Searcher<'a> {
+    config: &'a Config,
+    pattern: &'a PatternSet,
+    matcher: &'a (dyn LineMatcher + Sync),
+    show_path: bool,
 }
 
-impl Config {
-    pub fn build(mut args: impl Iterator<Item = String>) -> Result<Config, &'static str> {
-        args.next(); // skip the first argument which is the program name
+impl Searcher<'_> {
+    // searches a single file, writing results to `out` and returning whether anything matched
+    fn search_file(
+        &self,
+        path: &Path,
+        out: &mut dyn Write,
+        stats: &mut Stats,
+    ) -> Result<bool, Box<dyn Error>> {
+        let config = self.config;
+        let name = path.display();
+
+        if let (Some(replacement), true) = (&config.replace, config.write) {
+            let changed = replace_in_file(self.pattern, path, replacement, config.encoding)
+                .map_err(|err| format!("{name}: {err}"))?;
+            if !config.quiet {
+                eprintln!("{name}: replaced matches on {changed} lines");
+            }
+            stats.files_searched += 1;
+            stats.matches += changed as u64;
+            return Ok(changed > 0);
+        }
 
-        let query = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a query string"),
+        let open = || -> io::Result<_> {
+            let mut reader = input::open(path)?;
+            if config.decompress {
+                reader = input::decompress_gzip(reader)?;
+            }
+            encoding::decode(reader, config.encoding)
         };
+        // io errors don't say which file they came from, so add that before passing them on
+        let with_name = |err: io::Error| format!("{name}: {err}");
+        let mut reader = open().map_err(with_name)?;
+        stats.files_searched += 1;
+
+        if config.multiline {
+            // a match can cross lines, so the whole file has to be read before searching it
+            let mut contents = String::new();
+            reader.read_to_string(&mut contents).map_err(with_name)?;
+            stats.lines_scanned += contents.lines().count() as u64;
+
+            let mut matches = search_multiline(self.matcher, &contents)
+                .into_iter()
+                .filter(|found| config.line_ranges.contains(found.line_number))
+                .map(Ok);
+            return self.write_matches(path, &mut matches, out, stats);
+        }
+
+        let mut lines = search_reader(self.matcher, reader);
+        if let Some(last_line) = config.line_ranges.last() {
+            lines = lines.stop_after(last_line);
+        }
+        let mut in_range = lines.by_ref().filter(|found| match found {
+            Ok(found) => config.line_ranges.contains(found.line_number),
+            Err(_) => true,
+        });
+        let found = self.write_matches(path, &mut in_range, out, stats);
+        stats.lines_scanned += lines.lines_read();
+        found
+    }
+
+    // reports the matches found in a file according to the output flags
+    fn write_matches<'m>(
+        &self,
+        path: &Path,
+        matches: &mut dyn Iterator<Item = io::Result<Match<'m>>>,
+        out: &mut dyn Write,
+        stats: &mut Stats,
+    ) -> Result<bool, Box<dyn Error>> {
+        let config = self.config;
+        let name = path.display();
+        let with_name = |err: io::Error| format!("{name}: {err}");
+
+        // with -Z a NUL byte ends every file name instead of the usual newline or colon,
+        // since those are the only separators that can't appear in a path
+        let path_end = if config.null_separated { "\0" } else { "\n" };
+        let path_sep = if config.null_separated { "\0" } else { ":" };
+
+        // only the first match matters for these modes, so stop reading right there
+        if config.quiet || config.list_files.is_some() {
+            let file_found = matches.next().transpose().map_err(with_name)?.is_some();
+            stats.matches += file_found as u64;
 
-        let file_path = match args.next() {
-            Some(arg) => arg,
-            None => return Err("Didn't get a file path"),
+            if let Some(list_files) = config.list_files {
+                if file_found == (list_files == ListFiles::WithMatches) && !config.quiet {
+                    write!(out, "{name}{path_end}")?;
+                    return Ok(true);
+                }
+                return Ok(false);
+            }
+            return Ok(file_found);
+        }
+
+        let prefix = if self.show_path {
+            format!("{name}{path_sep}")
+        } else {
+            String::new()
         };
 
-        let ignore_case = env::var("IGNORE_CASE").is_ok();
+        // lines are read lazily, so stopping after the Nth hit stops reading the file too
+        let max_count = config.max_count.unwrap_or(usize::MAX);
+        let mut count = 0;
 
-        Ok(Config {
-            query,
-            file_path,
-            ignore_case,
-        })
+        while count < max_count {
+            let Some(found) = matches.next().transpose().map_err(with_name)? else {
+                break;
+            };
+            count += 1;
+
+            let line = &found.line;
+            let offset = found.byte_offset;
+            // a multiline match can start anywhere, so say which line that was
+            let line_number = if config.multiline {
+                format!("{}:", found.line_number)
+            } else {
+                String::new()
+            };
+            let offset_prefix = |start: usize| {
+                let offset = if config.byte_offset {
+                    format!("{}:", offset + start as u64)
+                } else {
+                    String::new()
+                };
+                format!("{line_number}{offset}")
+            };
+
+            // the replacement, if any, stands in for the line itself
+            let replaced;
+            let text: &str = match &config.replace {
+                Some(replacement) => {
+                    replaced = self.pattern.replace(line, replacement);
+                    &replaced
+                }
+                None => line,
+            };
+
+            if let Some(template) = &config.format {
+                let fields = |(start, end): (usize, usize)| Fields {
+                    path: &name,
+                    line_number: found.line_number,
+                    column: start + 1,
+                    offset: offset + start as u64,
+                    matched: &line[start..end],
+                    text,
+                };
+                // with -o every match gets its own line, otherwise the first one stands for it
+                if config.only_matching {
+                    for &span in &found.spans {
+                        template.render(out, &fields(span))?;
+                    }
+                } else {
+                    let first = found.spans.first().copied().unwrap_or((0, 0));
+                    template.render(
+                        out,
+                        &Fields {
+                            offset,
+                            ..fields(first)
+                        },
+                    )?;
+                }
+            } else if config.replace.is_some() {
+                writeln!(out, "{prefix}{}{}", offset_prefix(0), text)?;
+            } else if config.only_matching {
+                // each match gets its own offset rather than the line's
+                for &(start, end) in &found.spans {
+                    writeln!(out, "{prefix}{}{}", offset_prefix(start), &line[start..end])?;
+                }
+            } else {
+                writeln!(out, "{prefix}{}{line}", offset_prefix(0))?;
+            }
+        }
+
+        stats.matches += count as u64;
+        Ok(count > 0)
     }
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(config.file_path)?;
+fn matching_lines<'a: 'p, 'p>(
+    pattern: &'p PatternSet,
+    contents: &'a str,
+) -> impl Iterator<Item = &'a str> + 'p {
+    search::matching_lines(pattern, contents).map(|(_, _, line)| line)
+}
 
-    let results = if config.ignore_case {
-        search_case_insensitive(&config.query, &contents)
-    } else {
-        search(&config.query, &contents)
-    };
+pub fn search_pattern<'a>(pattern: &PatternSet, contents: &'a str) -> Vec<&'a str> {
+    matching_lines(pattern, contents).collect()
+}
 
-    for line in results {
-        println!("{line}");
-    }
+/// like `search_pattern`, but stops scanning after `max_count` matching lines
+pub fn search_max_count<'a>(
+    pattern: &PatternSet,
+    contents: &'a str,
+    max_count: usize,
+) -> Vec<&'a str> {
+    matching_lines(pattern, contents).take(max_count).collect()
+}
 
-    Ok(())
+/// checks whether any line matches, stopping at the first hit
+///
+/// this backs both `-l` (files with a match) and `-L` (files without one)
+pub fn has_match(pattern: &PatternSet, contents: &str) -> bool {
+    matching_lines(pattern, contents).next().is_some()
 }
 
-pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    contents
-        .lines()
-        .filter(|line| line.contains(query))
+/// returns each matched substring rather than the whole line, in the order they appear
+pub fn search_only_matching<'a>(pattern: &PatternSet, contents: &'a str) -> Vec<&'a str> {
+    matching_lines(pattern, contents)
+        .flat_map(|line| {
+            pattern
+                .find_spans(line)
+                .into_iter()
+                .map(move |(start, end)| &line[start..end])
+        })
         .collect()
 }
 
-pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-    let query = query.to_lowercase();
+pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    search_pattern(&Pattern::new(query, false, false).into(), contents)
+}
 
-    contents
-        .lines()
-        .filter(|line| line.to_lowercase().contains(&query))
-        .collect()
+pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
+    search_pattern(&Pattern::new(query, true, false).into(), contents)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::Cell;
 
     #[test]
     fn search_case_sensitive_returns_one_result() {
@@ -118,4 +468,451 @@ Trust me.";
             search_case_insensitive(query, contents)
         );
     }
+
+    #[test]
+    fn search_multiline_spans_lines() {
+        let pattern: PatternSet = Pattern::regex(r"fast,\s+\w+|me\.", false, false)
+            .unwrap()
+            .into();
+        let contents = "\
+Rust:
+safe, fast,
+productive. Pick three.
+Trust me.";
+
+        let matches = search_multiline(&pattern, contents);
+        assert_eq!(2, matches.len());
+        assert_eq!(2, matches[0].line_number);
+        assert_eq!(6, matches[0].byte_offset);
+        assert_eq!("safe, fast,\nproductive. Pick three.", matches[0].line);
+        assert_eq!(
+            vec!["fast,\nproductive"],
+            matches[0].matched_text().collect::<Vec<_>>()
+        );
+        assert_eq!(4, matches[1].line_number);
+        assert_eq!("Trust me.", matches[1].line);
+        assert_eq!(7, matches[1].column);
+    }
+
+    #[test]
+    fn case_insensitive_uses_full_case_folding() {
+        let pattern: PatternSet = Pattern::new("straße", true, false).into();
+        let contents = "\
+STRASSE
+Strasse
+strase";
+
+        assert_eq!(
+            vec!["STRASSE", "Strasse"],
+            search_pattern(&pattern, contents)
+        );
+        assert_eq!(vec![(0, 7)], pattern.find_spans("STRASSE"));
+
+        let pattern: PatternSet = Pattern::new("ss", true, false).into();
+        assert_eq!(vec![(4, 6)], pattern.find_spans("Straße"));
+    }
+
+    #[test]
+    fn turkic_folding_pairs_dotless_i() {
+        let contents = "\
+ILIK
+ılık
+ilik";
+
+        let pattern: PatternSet = Pattern::new("ılık", true, false).into();
+        assert_eq!(vec!["ılık"], search_pattern(&pattern, contents));
+
+        let pattern = pattern.turkic(true);
+        assert_eq!(vec!["ILIK", "ılık"], search_pattern(&pattern, contents));
+    }
+
+    #[test]
+    fn search_whole_word_skips_partial_matches() {
+        let pattern: PatternSet = Pattern::new("rust", true, true).into();
+        let contents = "\
+Rust:
+Trust me.
+rusty nails
+über_rust
+(rust)";
+
+        assert_eq!(vec!["Rust:", "(rust)"], search_pattern(&pattern, contents));
+    }
+
+    #[test]
+    fn search_whole_word_uses_unicode_boundaries() {
+        let pattern: PatternSet = Pattern::new("art", false, true).into();
+        let contents = "\
+émart
+art déco";
+
+        assert_eq!(vec!["art déco"], search_pattern(&pattern, contents));
+    }
+
+    #[test]
+    fn search_only_matching_returns_original_text() {
+        let pattern: PatternSet = Pattern::new("rust", true, false).into();
+        let contents = "\
+Rust and rust:
+safe, fast, productive.
+Trust me.";
+
+        assert_eq!(
+            vec!["Rust", "rust", "rust"],
+            search_only_matching(&pattern, contents)
+        );
+    }
+
+    #[test]
+    fn search_max_count_stops_after_n_lines() {
+        let pattern: PatternSet = Pattern::new("t", false, false).into();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(
+            vec!["Rust:", "safe, fast, productive."],
+            search_max_count(&pattern, contents, 2)
+        );
+    }
+
+    #[test]
+    fn has_match_finds_any_matching_line() {
+        let pattern: PatternSet = Pattern::new("three", false, false).into();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        assert!(has_match(&pattern, contents));
+        assert!(!has_match(&pattern, "Rust:\nTrust me."));
+    }
+
+    #[test]
+    fn pattern_set_matches_any_pattern() {
+        let pattern = PatternSet::from_queries(&["fast".into(), "Pick".into()], false, false);
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        assert_eq!(
+            vec!["safe, fast, productive.", "Pick three."],
+            search_pattern(&pattern, contents)
+        );
+    }
+
+    #[test]
+    fn pattern_set_merges_overlapping_spans() {
+        let pattern = PatternSet::from_queries(&["pro".into(), "productive".into()], false, false);
+
+        assert_eq!(
+            vec![(12, 22)],
+            pattern.find_spans("safe, fast, productive.")
+        );
+    }
+
+    #[test]
+    fn literal_matcher_ignores_regex_syntax() {
+        let contents = "\
+version 1.1
+version 121";
+
+        let literal = PatternSet::build(&["1.1".into()], Matcher::Literal, false, false).unwrap();
+        assert_eq!(vec!["version 1.1"], search_pattern(&literal, contents));
+
+        let regex = PatternSet::build(&["1.1".into()], Matcher::Regex, false, false).unwrap();
+        assert_eq!(
+            vec!["version 1.1", "version 121"],
+            search_pattern(&regex, contents)
+        );
+    }
+
+    #[test]
+    fn replace_swaps_every_match() {
+        let pattern: PatternSet = Pattern::new("fast", false, false).into();
+
+        assert_eq!(
+            "safe, quick, productive, quick.",
+            pattern.replace("safe, fast, productive, fast.", "quick")
+        );
+    }
+
+    #[test]
+    fn replace_expands_regex_captures() {
+        let pattern = PatternSet::from_regexes(&[r"(\w+), (\w+)".into()], false, false).unwrap();
+
+        assert_eq!(
+            "fast, safe, productive.",
+            pattern.replace("safe, fast, productive.", "$2, $1")
+        );
+    }
+
+    #[test]
+    fn filter_combines_patterns() {
+        let filter =
+            Filter::new("(error & !timeout) | panic", false, false, Matcher::Literal).unwrap();
+        let contents = "\
+error: disk full
+error: timeout waiting for lock
+thread panicked
+all good";
+
+        let results: Vec<String> = search_reader(&filter, contents.as_bytes())
+            .map(|found| found.unwrap().line.into_owned())
+            .collect();
+        assert_eq!(vec!["error: disk full", "thread panicked"], results);
+    }
+
+    #[test]
+    fn filter_rejects_bad_expression() {
+        assert!(Filter::new("error & | panic", false, false, Matcher::Literal).is_err());
+    }
+
+    #[test]
+    fn search_reader_streams_matching_lines() {
+        let pattern: PatternSet = Pattern::new("t", false, false).into();
+        let contents = "Rust:\r\nsafe, fast, productive.\nPick three.\nno match here";
+
+        let results: Vec<String> = search_reader(&pattern, contents.as_bytes())
+            .take(2)
+            .map(|found| found.unwrap().line.into_owned())
+            .collect();
+        assert_eq!(vec!["Rust:", "safe, fast, productive."], results);
+    }
+
+    #[test]
+    fn decompress_gzip_detects_magic_bytes() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::{Read, Write};
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"Rust:\nTrust me.\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decompressed = String::new();
+        input::decompress_gzip(Box::new(&compressed[..]))
+            .unwrap()
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!("Rust:\nTrust me.\n", decompressed);
+
+        let mut plain = String::new();
+        input::decompress_gzip(Box::new(&b"Pick three."[..]))
+            .unwrap()
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!("Pick three.", plain);
+    }
+
+    #[test]
+    fn search_reader_tracks_line_offsets() {
+        let pattern: PatternSet = Pattern::new("three", false, false).into();
+        let contents = "Rust:\r\nsafe, fast, productive.\nPick three.";
+
+        let found = search_reader(&pattern, contents.as_bytes())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!("Pick three.", found.line);
+        assert_eq!(31, found.byte_offset);
+    }
+
+    #[test]
+    fn literal_scan_agrees_with_line_by_line() {
+        let literal: PatternSet = Pattern::new("needle", false, false).into();
+        // a second pattern turns the whole-buffer scan off
+        let per_line = PatternSet::from_queries(&["needle".into(), "\u{0}".into()], false, false);
+        assert_eq!(Some("needle"), literal.required_literal());
+        assert_eq!(None, per_line.required_literal());
+
+        let contents: String = (0..200)
+            .map(|i| match i % 7 {
+                0 => format!("line {i} has a needle\n"),
+                3 => format!("line {i} has a needl\r\n"),
+                _ => format!("line {i}\n"),
+            })
+            .collect();
+
+        let describe = |found: Match| {
+            (
+                found.line_number,
+                found.byte_offset,
+                found.line.into_owned(),
+            )
+        };
+        let scanned: Vec<_> = search_iter(&literal, &contents).map(describe).collect();
+        assert_eq!(29, scanned.len());
+        assert_eq!(
+            scanned,
+            search_iter(&per_line, &contents)
+                .map(describe)
+                .collect::<Vec<_>>()
+        );
+
+        // a tiny buffer makes the needle straddle the end of it now and then
+        let reader = io::BufReader::with_capacity(16, contents.as_bytes());
+        let mut lines = search_reader(&literal, reader);
+        let streamed: Vec<_> = lines
+            .by_ref()
+            .map(|found| describe(found.unwrap()))
+            .collect();
+        assert_eq!(scanned, streamed);
+        assert_eq!(200, lines.lines_read());
+    }
+
+    #[test]
+    fn search_reader_stops_after_last_line() {
+        let pattern: PatternSet = Pattern::new("Rust", false, false).into();
+        let contents = "Rust 1\nRust 2\nRust 3\nRust 4\n";
+
+        let mut lines = search_reader(&pattern, contents.as_bytes()).stop_after(2);
+        assert_eq!(2, lines.by_ref().count());
+        assert_eq!(2, lines.lines_read());
+    }
+
+    #[test]
+    fn search_iter_is_lazy() {
+        struct Counting(Cell<usize>, PatternSet);
+
+        impl LineMatcher for Counting {
+            fn is_match(&self, line: &str) -> bool {
+                self.0.set(self.0.get() + 1);
+                self.1.is_match(line)
+            }
+
+            fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+                self.1.find_spans(line)
+            }
+        }
+
+        let matcher = Counting(Cell::new(0), Pattern::new("a", false, false).into());
+        let contents = "a\nb\na\nb\na";
+
+        let first: Vec<Match> = search_iter(&matcher, contents).take(2).collect();
+        assert_eq!(
+            vec![1, 3],
+            first.iter().map(|m| m.line_number).collect::<Vec<_>>()
+        );
+        assert_eq!(3, matcher.0.get());
+    }
+
+    #[test]
+    fn search_matches_describes_each_line() {
+        let pattern: PatternSet = Pattern::new("rust", true, false).into();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Trust me, rust.";
+
+        let matches = search_matches(&pattern, contents);
+        assert_eq!(2, matches.len());
+        assert_eq!(
+            Match {
+                line_number: 3,
+                byte_offset: 30,
+                column: 2,
+                line: "Trust me, rust.".into(),
+                spans: vec![(1, 5), (10, 14)],
+            },
+            matches[1]
+        );
+        assert_eq!(
+            vec!["rust", "rust"],
+            matches[1].matched_text().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn quiet_run_reports_skipped_files_unless_something_matched() {
+        let quiet = |pattern: &str| {
+            let args = [
+                "minigrep",
+                "-q",
+                pattern,
+                "files/poem.txt",
+                "files/missing.txt",
+            ];
+            run(Config::build(args.into_iter().map(String::from)).unwrap())
+        };
+
+        assert!(quiet("frog").unwrap());
+        assert!(quiet("monomorphization").is_err());
+    }
+
+    #[test]
+    fn null_ends_file_names_with_nul() {
+        let output = |flags: &[&str]| {
+            let args = ["minigrep"].iter().chain(flags).chain(&[
+                "frog",
+                "files/poem.txt",
+                "files/poem.txt",
+            ]);
+            let config = Config::build(args.map(|arg| arg.to_string())).unwrap();
+            let mut out = Vec::new();
+            assert!(run_to(config, &mut out).unwrap());
+            String::from_utf8(out).unwrap()
+        };
+
+        let null = |flag: &str| {
+            let args = ["minigrep", flag, "frog", "files/poem.txt"];
+            Config::build(args.into_iter().map(String::from))
+                .unwrap()
+                .null_separated
+        };
+        assert!(null("-Z") && null("--null") && !null("-l"));
+
+        assert_eq!("files/poem.txt\0files/poem.txt\0", output(&["-lZ"]));
+        assert_eq!(
+            "files/poem.txt\0files/poem.txt\0",
+            output(&["-l", "--null"])
+        );
+        assert_eq!(
+            "files/poem.txt\0How public, like a frog\n".repeat(2),
+            output(&["-Z"])
+        );
+        assert_eq!("files/poem.txt\nfiles/poem.txt\n", output(&["-l"]));
+    }
+
+    #[test]
+    fn stats_count_the_whole_run() {
+        let stats = |threads: &str| {
+            let args = [
+                "minigrep",
+                "--stats",
+                "-j",
+                threads,
+                "you",
+                "files/poem.txt",
+                "files/missing.txt",
+                "files/poem.txt",
+            ];
+            let mut out = Vec::new();
+            let result = run_to(
+                Config::build(args.into_iter().map(String::from)).unwrap(),
+                &mut out,
+            );
+            assert!(result.is_err());
+            let out = String::from_utf8(out).unwrap();
+            // the summary after the matching lines, but not the time it took, which changes
+            // from run to run
+            let (_, summary) = out.split_once("\n\n").unwrap();
+            summary
+                .lines()
+                .filter(|line| !line.ends_with("seconds elapsed"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+
+        let expected = "\
+2 files searched
+1 files skipped
+18 lines scanned
+8 matches found";
+        assert_eq!(expected, stats("1"));
+        // the same again when each file's counts are added up from the worker threads
+        assert_eq!(expected, stats("3"));
+    }
 }
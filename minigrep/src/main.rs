@@ -1,16 +1,32 @@
 use std::env;
 use std::process;
 
+use minigrep::ArgsError;
+
+// exit codes follow grep, so minigrep can be used in shell conditionals
+const EXIT_MATCH: i32 = 0;
+const EXIT_NO_MATCH: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
 fn main() {
     let config = minigrep::Config::build(env::args()).unwrap_or_else(|err| {
+        if err == ArgsError::Help {
+            print!("{}", minigrep::usage());
+            process::exit(EXIT_MATCH);
+        }
         eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
+        eprintln!("Run with --help to see the available flags");
+        process::exit(EXIT_ERROR);
     });
 
-    // Search for config.query in config.file_path
+    // Search for the patterns in every file in config.file_paths
 
-    if let Err(e) = minigrep::run(config) {
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    match minigrep::run(config) {
+        Ok(true) => process::exit(EXIT_MATCH),
+        Ok(false) => process::exit(EXIT_NO_MATCH),
+        Err(e) => {
+            eprintln!("Application error: {e}");
+            process::exit(EXIT_ERROR);
+        }
     }
 }
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use crate::{Searcher, Stats};
+
+// a file that a worker has finished with, waiting to be printed
+struct Searched {
+    // where the file came in the list of paths
+    index: usize,
+    output: Vec<u8>,
+    // errors are turned into strings since `Box<dyn Error>` can't be sent between threads
+    result: Result<bool, String>,
+    stats: Stats,
+}
+
+/// searches the paths on `threads` worker threads, handing each file's buffered output,
+/// result and stats to `emit` on the calling thread
+///
+/// when `sorted` is set, files are emitted in the order they were listed, which for a
+/// recursive search is sorted by path, so the output is the same on every run. otherwise
+/// they are emitted as soon as they are done. `emit` returns false to stop the search early
+pub(crate) fn search<F>(
+    searcher: &Searcher,
+    paths: Box<dyn Iterator<Item = io::Result<PathBuf>> + Send + '_>,
+    threads: usize,
+    sorted: bool,
+    mut emit: F,
+) where
+    F: FnMut(Vec<u8>, Result<bool, Box<dyn Error>>, Stats) -> bool,
+{
+    // the walk itself happens under the lock, one path at a time, as workers ask for them
+    let paths = Mutex::new(paths.enumerate());
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (paths, stop) = (&paths, &stop);
+
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let next = paths.lock().unwrap().next();
+                    let Some((index, path)) = next else {
+                        break;
+                    };
+
+                    let mut output = Vec::new();
+                    let mut stats = Stats::default();
+                    let result = path
+                        .map_err(|err| err.into())
+                        .and_then(|path| searcher.search_file(&path, &mut output, &mut stats))
+                        .map_err(|err| err.to_string());
+
+                    let searched = Searched {
+                        index,
+                        output,
+                        result,
+                        stats,
+                    };
+                    // the receiver only goes away once the search has been stopped
+                    if sender.send(searched).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // otherwise the receiver would wait forever for this last sender to go away
+        drop(sender);
+
+        let mut emit = |searched: Searched| {
+            let result = searched.result.map_err(|err| err.into());
+            emit(searched.output, result, searched.stats)
+        };
+
+        // files that finished before an earlier one, held back until it is printed
+        let mut pending = BTreeMap::new();
+        let mut next_index = 0;
+
+        for searched in &receiver {
+            let keep_going = if sorted {
+                pending.insert(searched.index, searched);
+                let mut keep_going = true;
+                while let Some(searched) = pending.remove(&next_index) {
+                    next_index += 1;
+                    keep_going = emit(searched);
+                    if !keep_going {
+                        break;
+                    }
+                }
+                keep_going
+            } else {
+                emit(searched)
+            };
+
+            if !keep_going {
+                stop.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+        // dropping the receiver here makes any worker still sending give up
+        drop(receiver);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Pattern, PatternSet};
+    use std::fs;
+
+    #[test]
+    fn sorted_output_follows_path_order() {
+        let root = std::env::temp_dir().join(format!("minigrep-parallel-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+
+        // bigger files first, so they tend to finish last
+        let paths: Vec<PathBuf> = (0..8)
+            .map(|i| {
+                let path = root.join(format!("{i}.txt"));
+                fs::write(&path, "Rust\n".repeat((8 - i) * 2000)).unwrap();
+                path
+            })
+            .collect();
+
+        let config = Config::default();
+        let pattern: PatternSet = Pattern::new("Rust", false, false).into();
+        let searcher = Searcher {
+            config: &config,
+            pattern: &pattern,
+            matcher: &pattern,
+            show_path: true,
+        };
+
+        let mut emitted = Vec::new();
+        let listed = Box::new(paths.clone().into_iter().map(Ok));
+        search(&searcher, listed, 4, true, |output, result, stats| {
+            assert!(result.unwrap());
+            let first = String::from_utf8(output).unwrap();
+            emitted.push(PathBuf::from(first.split(':').next().unwrap()));
+            assert_eq!(1, stats.files_searched);
+            true
+        });
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(paths, emitted);
+    }
+}
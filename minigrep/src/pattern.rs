@@ -0,0 +1,452 @@
+use memchr::memmem::Finder;
+use regex::{Regex, RegexBuilder};
+
+/// anything that can decide whether a line matches and where
+pub trait LineMatcher {
+    fn is_match(&self, line: &str) -> bool;
+
+    /// the sorted, non-overlapping (start, end) byte spans of the matches in the line
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)>;
+
+    /// text that every matching line is known to contain
+    ///
+    /// searches use it to skip straight over lines that can't match, by scanning the whole
+    /// buffer for it at once rather than checking one line at a time. lines that do contain it
+    /// are still checked with `is_match`
+    fn required_literal(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// how the text of a pattern is interpreted
+///
+/// `Literal` never touches the regex engine, so it is both the fastest option and safe for
+/// queries full of characters like `.` or `(` that would otherwise need escaping
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Matcher {
+    #[default]
+    Literal,
+    Regex,
+}
+
+/// a single query along with the options that control how it is matched against a line
+///
+/// case-insensitive literal patterns use full Unicode case folding, so `STRASSE` matches
+/// `straße`, while regex patterns get the simple one-char-to-one-char folding of the regex crate
+pub struct Pattern {
+    kind: Kind,
+    ignore_case: bool,
+    whole_word: bool,
+    turkic: bool,
+}
+
+enum Kind {
+    Literal {
+        query: String,
+        // folded once up front so case-insensitive matching doesn't redo it for every line
+        query_folded: Vec<char>,
+        // a prebuilt memmem searcher for the case-sensitive query
+        finder: Box<Finder<'static>>,
+    },
+    Regex(Regex),
+}
+
+impl Pattern {
+    pub fn new(query: &str, ignore_case: bool, whole_word: bool) -> Pattern {
+        Pattern {
+            kind: Kind::Literal {
+                query: query.to_string(),
+                query_folded: fold_str(query, false),
+                finder: Box::new(Finder::new(query).into_owned()),
+            },
+            ignore_case,
+            whole_word,
+            turkic: false,
+        }
+    }
+
+    /// folds the dotted and dotless i the Turkish and Azerbaijani way when ignoring case,
+    /// so `I` pairs with `ı` and `İ` with `i`
+    ///
+    /// only literal patterns are affected
+    pub fn turkic(mut self, turkic: bool) -> Pattern {
+        self.turkic = turkic;
+        if let Kind::Literal {
+            query,
+            query_folded,
+            ..
+        } = &mut self.kind
+        {
+            *query_folded = fold_str(query, turkic);
+        }
+        self
+    }
+
+    /// builds a pattern with the given semantics, only `Matcher::Regex` can fail
+    pub fn build(
+        query: &str,
+        matcher: Matcher,
+        ignore_case: bool,
+        whole_word: bool,
+    ) -> Result<Pattern, regex::Error> {
+        match matcher {
+            Matcher::Literal => Ok(Pattern::new(query, ignore_case, whole_word)),
+            Matcher::Regex => Pattern::regex(query, ignore_case, whole_word),
+        }
+    }
+
+    /// compiles the query as a regular expression
+    pub fn regex(
+        query: &str,
+        ignore_case: bool,
+        whole_word: bool,
+    ) -> Result<Pattern, regex::Error> {
+        let regex = RegexBuilder::new(query)
+            .case_insensitive(ignore_case)
+            .build()?;
+
+        Ok(Pattern {
+            kind: Kind::Regex(regex),
+            ignore_case,
+            whole_word,
+            turkic: false,
+        })
+    }
+
+    /// which semantics this pattern was built with
+    pub fn matcher(&self) -> Matcher {
+        match self.kind {
+            Kind::Literal { .. } => Matcher::Literal,
+            Kind::Regex(_) => Matcher::Regex,
+        }
+    }
+
+    /// checks whether the pattern occurs anywhere in the line
+    pub fn is_match(&self, line: &str) -> bool {
+        self.find_at(line, 0).is_some()
+    }
+
+    /// returns the (start, end) byte spans of every non-overlapping match in the line
+    ///
+    /// spans always index into the original line, even when matching case-insensitively
+    pub fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+        let mut pos = 0;
+
+        while let Some((start, end)) = self.find_at(line, pos) {
+            if start == end {
+                // empty matches are allowed, but there is nothing to print for them
+                match line[end..].chars().next() {
+                    Some(c) => pos = end + c.len_utf8(),
+                    None => break,
+                }
+                continue;
+            }
+            spans.push((start, end));
+            pos = end;
+        }
+
+        spans
+    }
+
+    /// writes the replacement for the match at `span` onto `out`
+    ///
+    /// regex replacements can refer to capture groups with `$1` or `${name}`
+    fn expand(&self, line: &str, span: (usize, usize), replacement: &str, out: &mut String) {
+        match &self.kind {
+            Kind::Literal { .. } => out.push_str(replacement),
+            Kind::Regex(regex) => match regex.captures_at(line, span.0) {
+                Some(caps) => caps.expand(replacement, out),
+                None => out.push_str(replacement),
+            },
+        }
+    }
+
+    // finds the leftmost match starting at or after byte position `pos`
+    fn find_at(&self, line: &str, pos: usize) -> Option<(usize, usize)> {
+        match &self.kind {
+            Kind::Literal {
+                query,
+                query_folded,
+                finder,
+            } => self.find_literal_at(line, pos, query, query_folded, finder),
+            Kind::Regex(regex) => self.find_regex_at(line, pos, regex),
+        }
+    }
+
+    fn find_regex_at(&self, line: &str, mut pos: usize, regex: &Regex) -> Option<(usize, usize)> {
+        loop {
+            let found = regex.find_at(line, pos)?;
+            if !self.whole_word || is_word_bounded(line, found.start(), found.end()) {
+                return Some((found.start(), found.end()));
+            }
+            // try again from the next char, a shorter or later match may still be a whole word
+            pos = found.start() + line[found.start()..].chars().next()?.len_utf8();
+        }
+    }
+
+    fn find_literal_at(
+        &self,
+        line: &str,
+        pos: usize,
+        query: &str,
+        query_folded: &[char],
+        finder: &Finder,
+    ) -> Option<(usize, usize)> {
+        if query.is_empty() {
+            return if self.whole_word {
+                None
+            } else {
+                Some((pos, pos))
+            };
+        }
+
+        if self.ignore_case {
+            return line[pos..]
+                .char_indices()
+                .map(|(i, _)| pos + i)
+                .filter_map(|start| {
+                    let len = match_len_ignore_case(&line[start..], query_folded, self.turkic)?;
+                    Some((start, start + len))
+                })
+                .find(|&(start, end)| !self.whole_word || is_word_bounded(line, start, end));
+        }
+
+        let mut pos = pos;
+        loop {
+            let start = pos + finder.find(&line.as_bytes()[pos..])?;
+            let end = start + query.len();
+            if !self.whole_word || is_word_bounded(line, start, end) {
+                return Some((start, end));
+            }
+            // the next occurrence may still be a whole word
+            pos = start + line[start..].chars().next()?.len_utf8();
+        }
+    }
+
+    /// the query itself, if every line this pattern matches has to contain it exactly
+    pub fn required_literal(&self) -> Option<&str> {
+        match &self.kind {
+            Kind::Literal { query, .. } if !self.ignore_case && !query.is_empty() => Some(query),
+            _ => None,
+        }
+    }
+}
+
+// word characters follow the usual \w definition, but over all of Unicode rather than just ASCII
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// true if the span is not glued to a word character on either side
+fn is_word_bounded(line: &str, start: usize, end: usize) -> bool {
+    let before = line[..start].chars().next_back();
+    let after = line[end..].chars().next();
+
+    !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+}
+
+// the case folding of a single char, which may be more than one char long
+enum Fold {
+    Lower(std::char::ToLowercase),
+    Special(std::str::Chars<'static>),
+}
+
+impl Iterator for Fold {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self {
+            Fold::Lower(chars) => chars.next(),
+            Fold::Special(chars) => chars.next(),
+        }
+    }
+}
+
+// full case folding is lowercasing, except for the chars where CaseFolding.txt says otherwise
+fn fold(c: char, turkic: bool) -> Fold {
+    let special = match c {
+        'I' if turkic => "ı",
+        'İ' if turkic => "i",
+        'ß' | 'ẞ' => "ss",
+        'ſ' => "s",
+        'ς' => "σ",
+        'ϐ' => "β",
+        'ϑ' => "θ",
+        'ϕ' => "φ",
+        'ϖ' => "π",
+        'ϰ' => "κ",
+        'ϱ' => "ρ",
+        'ϵ' => "ε",
+        '\u{1fbe}' => "ι",
+        'ẛ' => "ṡ",
+        'ŉ' => "ʼn",
+        'ﬀ' => "ff",
+        'ﬁ' => "fi",
+        'ﬂ' => "fl",
+        'ﬃ' => "ffi",
+        'ﬄ' => "ffl",
+        'ﬅ' | 'ﬆ' => "st",
+        _ => return Fold::Lower(c.to_lowercase()),
+    };
+    Fold::Special(special.chars())
+}
+
+fn fold_str(text: &str, turkic: bool) -> Vec<char> {
+    text.chars().flat_map(|c| fold(c, turkic)).collect()
+}
+
+// returns how many bytes of the haystack were consumed matching the (already folded) query,
+// compared one folded char at a time so the result can be sliced from the original text
+fn match_len_ignore_case(haystack: &str, query_folded: &[char], turkic: bool) -> Option<usize> {
+    let mut query = query_folded.iter();
+
+    for (i, c) in haystack.char_indices() {
+        for folded in fold(c, turkic) {
+            match query.next() {
+                Some(&q) if q == folded => (),
+                // either a mismatch or the query ended halfway through this char
+                _ => return None,
+            }
+        }
+        if query.len() == 0 {
+            return Some(i + c.len_utf8());
+        }
+    }
+
+    None
+}
+
+/// a group of patterns where a line matches if any one of them does
+pub struct PatternSet {
+    patterns: Vec<Pattern>,
+}
+
+impl PatternSet {
+    pub fn new(patterns: Vec<Pattern>) -> PatternSet {
+        PatternSet { patterns }
+    }
+
+    /// builds a set from raw queries, all sharing the same matching options
+    pub fn from_queries(queries: &[String], ignore_case: bool, whole_word: bool) -> PatternSet {
+        let patterns = queries
+            .iter()
+            .map(|query| Pattern::new(query, ignore_case, whole_word))
+            .collect();
+
+        PatternSet { patterns }
+    }
+
+    /// like `from_queries`, but every query is compiled as a regular expression
+    pub fn from_regexes(
+        queries: &[String],
+        ignore_case: bool,
+        whole_word: bool,
+    ) -> Result<PatternSet, regex::Error> {
+        PatternSet::build(queries, Matcher::Regex, ignore_case, whole_word)
+    }
+
+    /// builds a set where every query is interpreted according to `matcher`
+    pub fn build(
+        queries: &[String],
+        matcher: Matcher,
+        ignore_case: bool,
+        whole_word: bool,
+    ) -> Result<PatternSet, regex::Error> {
+        let patterns = queries
+            .iter()
+            .map(|query| Pattern::build(query, matcher, ignore_case, whole_word))
+            .collect::<Result<_, _>>()?;
+
+        Ok(PatternSet { patterns })
+    }
+
+    /// applies `Pattern::turkic` to every pattern in the set
+    pub fn turkic(self, turkic: bool) -> PatternSet {
+        let patterns = self
+            .patterns
+            .into_iter()
+            .map(|pattern| pattern.turkic(turkic))
+            .collect();
+
+        PatternSet { patterns }
+    }
+
+    /// checks whether any of the patterns occurs in the line
+    pub fn is_match(&self, line: &str) -> bool {
+        self.patterns.iter().any(|pattern| pattern.is_match(line))
+    }
+
+    /// returns the spans matched by any pattern, sorted and with overlaps removed
+    pub fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        let mut spans: Vec<(usize, usize)> = self
+            .patterns
+            .iter()
+            .flat_map(|pattern| pattern.find_spans(line))
+            .collect();
+        PatternSet::merge_spans(&mut spans);
+        spans
+    }
+
+    // sorts spans and drops any that overlap an earlier one,
+    // so when two matches start at the same place the longer one wins
+    pub(crate) fn merge_spans(spans: &mut Vec<(usize, usize)>) {
+        spans.sort_by_key(|&(start, end)| (start, std::cmp::Reverse(end)));
+
+        let mut last_end = 0;
+        spans.retain(|&(start, end)| {
+            if start < last_end {
+                return false;
+            }
+            last_end = end;
+            true
+        });
+    }
+
+    /// returns the line with every match swapped out for the replacement text
+    pub fn replace(&self, line: &str, replacement: &str) -> String {
+        let mut replaced = String::with_capacity(line.len());
+        let mut last = 0;
+
+        for span in self.find_spans(line) {
+            replaced.push_str(&line[last..span.0]);
+            // the first pattern that produces exactly this span decides how it is expanded
+            let pattern = self
+                .patterns
+                .iter()
+                .find(|pattern| pattern.find_at(line, span.0) == Some(span));
+            match pattern {
+                Some(pattern) => pattern.expand(line, span, replacement, &mut replaced),
+                None => replaced.push_str(replacement),
+            }
+            last = span.1;
+        }
+
+        replaced.push_str(&line[last..]);
+        replaced
+    }
+}
+
+impl LineMatcher for PatternSet {
+    fn is_match(&self, line: &str) -> bool {
+        PatternSet::is_match(self, line)
+    }
+
+    fn find_spans(&self, line: &str) -> Vec<(usize, usize)> {
+        PatternSet::find_spans(self, line)
+    }
+
+    // with more than one pattern a line only has to contain one of them
+    fn required_literal(&self) -> Option<&str> {
+        match self.patterns.as_slice() {
+            [pattern] => pattern.required_literal(),
+            _ => None,
+        }
+    }
+}
+
+impl From<Pattern> for PatternSet {
+    fn from(pattern: Pattern) -> PatternSet {
+        PatternSet::new(vec![pattern])
+    }
+}
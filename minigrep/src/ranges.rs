@@ -0,0 +1,74 @@
+/// the line numbers a search is restricted to, from one or more `--lines` ranges
+///
+/// with no ranges at all every line is included
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct LineRanges {
+    // inclusive and 1-based, an open ended range runs to `u64::MAX`
+    ranges: Vec<(u64, u64)>,
+}
+
+impl LineRanges {
+    /// adds a range written as `N-M`, `N-`, `-M` or just `N`, returning `None` if it is malformed
+    pub fn add(&mut self, range: &str) -> Option<()> {
+        let parse = |bound: &str, default| match bound {
+            "" => Some(default),
+            bound => bound.parse().ok(),
+        };
+
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse(start, 1)?, parse(end, u64::MAX)?),
+            None => {
+                let line = range.parse().ok()?;
+                (line, line)
+            }
+        };
+        if start == 0 || start > end {
+            return None;
+        }
+
+        self.ranges.push((start, end));
+        Some(())
+    }
+
+    pub fn contains(&self, line_number: u64) -> bool {
+        self.ranges.is_empty()
+            || self
+                .ranges
+                .iter()
+                .any(|&(start, end)| (start..=end).contains(&line_number))
+    }
+
+    /// the last line any range includes, past which there is no point reading further
+    pub fn last(&self) -> Option<u64> {
+        self.ranges
+            .iter()
+            .map(|&(_, end)| end)
+            .max()
+            .filter(|&end| end != u64::MAX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ranges() {
+        let mut ranges = LineRanges::default();
+        assert!(ranges.contains(7) && ranges.last().is_none());
+
+        ranges.add("3-5").unwrap();
+        ranges.add("9").unwrap();
+        ranges.add("-1").unwrap();
+        let included: Vec<u64> = (1..=12).filter(|&line| ranges.contains(line)).collect();
+        assert_eq!(vec![1, 3, 4, 5, 9], included);
+        assert_eq!(Some(9), ranges.last());
+
+        ranges.add("11-").unwrap();
+        assert!(ranges.contains(1000) && ranges.last().is_none());
+
+        for bad in ["0-3", "5-2", "a-b", "", "1-2-3"] {
+            assert_eq!(None, LineRanges::default().add(bad), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+
+use crate::encoding::{self, Encoding};
+use crate::{input, PatternSet};
+
+/// rewrites a file with every match replaced, returning how many lines changed
+///
+/// the new contents go to a temporary file next to the original which is then renamed over it,
+/// so the file is never left half written if something goes wrong partway through.
+/// a file with nothing to replace is left exactly as it was.
+/// only UTF-8 files can be rewritten, anything else is refused rather than converted
+pub fn replace_in_file<P: AsRef<Path>>(
+    pattern: &PatternSet,
+    path: P,
+    replacement: &str,
+    encoding: Option<Encoding>,
+) -> io::Result<usize> {
+    let original = path.as_ref();
+    let mut reader = input::open(original)?;
+    let (detected, bom_len) = encoding::detect(&mut reader, encoding)?;
+    if detected != Encoding::Utf8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("can only rewrite UTF-8 files, not {detected:?}"),
+        ));
+    }
+
+    let file_name = original
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let temp_path = original.with_file_name(format!(
+        ".{}.minigrep-{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let result = write_replaced(pattern, &mut reader, bom_len, &temp_path, replacement);
+    // release the reader before renaming, some platforms won't replace a file that is open
+    drop(reader);
+
+    match result {
+        Ok(0) => {
+            fs::remove_file(&temp_path)?;
+            Ok(0)
+        }
+        Ok(changed) => {
+            fs::set_permissions(&temp_path, fs::metadata(original)?.permissions())?;
+            fs::rename(&temp_path, original)?;
+            Ok(changed)
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(err)
+        }
+    }
+}
+
+fn write_replaced(
+    pattern: &PatternSet,
+    reader: &mut dyn BufRead,
+    bom_len: usize,
+    temp_path: &Path,
+    replacement: &str,
+) -> io::Result<usize> {
+    let mut writer = BufWriter::new(File::create(temp_path)?);
+    // a byte order mark isn't part of the first line, but is kept
+    writer.write_all(&reader.fill_buf()?[..bom_len])?;
+    reader.consume(bom_len);
+    let mut line = String::new();
+    let mut changed = 0;
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+
+        // keep the original line ending untouched and only substitute in the text before it
+        let text = line.trim_end_matches(['\n', '\r']);
+        let ending = &line[text.len()..];
+
+        if pattern.is_match(text) {
+            writer.write_all(pattern.replace(text, replacement).as_bytes())?;
+            changed += 1;
+        } else {
+            writer.write_all(text.as_bytes())?;
+        }
+        writer.write_all(ending.as_bytes())?;
+    }
+
+    writer
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pattern;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("minigrep-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn replaces_matches_keeping_the_bom() {
+        let dir = temp_dir("replace");
+        let path = dir.join("notes.txt");
+        fs::write(&path, "\u{FEFF}safe\r\nfast\n").unwrap();
+        let pattern: PatternSet = Pattern::new("fast", false, false).into();
+
+        let changed = replace_in_file(&pattern, &path, "quick", None).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(1, changed);
+        assert_eq!("\u{FEFF}safe\r\nquick\n", contents);
+    }
+
+    #[test]
+    fn leaves_files_without_matches_alone() {
+        let dir = temp_dir("unchanged");
+        let path = dir.join("notes.txt");
+        fs::write(&path, "safe\n").unwrap();
+        let link = dir.join("link.txt");
+        fs::hard_link(&path, &link).unwrap();
+        let pattern: PatternSet = Pattern::new("fast", false, false).into();
+
+        let changed = replace_in_file(&pattern, &path, "quick", None).unwrap();
+        // still the same file as the link, rather than a new one renamed over it
+        fs::write(&link, "linked\n").unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        let leftovers = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(0, changed);
+        assert_eq!("linked\n", contents);
+        assert_eq!(2, leftovers);
+    }
+
+    #[test]
+    fn refuses_to_rewrite_other_encodings() {
+        let dir = temp_dir("latin1");
+        let path = dir.join("notes.txt");
+        fs::write(&path, b"caf\xe9 fast\n").unwrap();
+        let pattern: PatternSet = Pattern::new("fast", false, false).into();
+
+        let result = replace_in_file(&pattern, &path, "quick", None);
+        let explicit = replace_in_file(&pattern, &path, "quick", Some(Encoding::Latin1));
+        let contents = fs::read(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+        assert_eq!(io::ErrorKind::InvalidData, explicit.unwrap_err().kind());
+        assert_eq!(b"caf\xe9 fast\n", &contents[..]);
+    }
+}
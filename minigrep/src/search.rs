@@ -0,0 +1,185 @@
+use std::borrow::Cow;
+use std::iter;
+
+use memchr::memmem::Finder;
+use memchr::{memchr, memchr_iter, memrchr};
+
+use crate::LineMatcher;
+
+/// a matching line along with where it was found
+#[derive(PartialEq, Debug, Clone)]
+pub struct Match<'a> {
+    /// 1-based number of the line
+    pub line_number: u64,
+    /// offset of the start of the line from the start of the text, in bytes
+    pub byte_offset: u64,
+    /// 1-based byte column of the first match in the line, or 1 if nothing in the line can be
+    /// pointed at (e.g. a filter that only matched because a pattern was absent)
+    pub column: usize,
+    /// the line itself without its line ending, borrowed when searching a `&str`
+    pub line: Cow<'a, str>,
+    /// (start, end) byte spans of every match within `line`
+    pub spans: Vec<(usize, usize)>,
+}
+
+impl<'a> Match<'a> {
+    pub(crate) fn new<M: LineMatcher + ?Sized>(
+        matcher: &M,
+        line: Cow<'a, str>,
+        line_number: u64,
+        byte_offset: u64,
+    ) -> Match<'a> {
+        let spans = matcher.find_spans(&line);
+        let column = spans.first().map_or(1, |&(start, _)| start + 1);
+
+        Match {
+            line_number,
+            byte_offset,
+            column,
+            line,
+            spans,
+        }
+    }
+
+    /// the matched parts of the line, in order
+    pub fn matched_text(&self) -> impl Iterator<Item = &str> {
+        self.spans
+            .iter()
+            .map(|&(start, end)| &self.line[start..end])
+    }
+}
+
+/// lazily searches the contents, describing each matching line in full
+///
+/// nothing is searched until the iterator is advanced, so taking the first few matches only
+/// scans as far as the last one taken
+pub fn search_iter<'a, 'p, M: LineMatcher + ?Sized>(
+    matcher: &'p M,
+    contents: &'a str,
+) -> impl Iterator<Item = Match<'a>> + 'p
+where
+    'a: 'p,
+{
+    matching_lines(matcher, contents).map(|(line_number, byte_offset, line)| {
+        Match::new(matcher, Cow::Borrowed(line), line_number, byte_offset)
+    })
+}
+
+/// the number, byte offset and text of each matching line, without its line ending
+///
+/// when the matcher has a required literal the whole buffer is scanned for it with memmem,
+/// and only the lines it turns up in are checked, so lines that can't match are never split
+/// out one by one
+pub(crate) fn matching_lines<'a, 'p, M: LineMatcher + ?Sized>(
+    matcher: &'p M,
+    contents: &'a str,
+) -> impl Iterator<Item = (u64, u64, &'a str)> + 'p
+where
+    'a: 'p,
+{
+    let finder = matcher.required_literal().map(Finder::new);
+    let bytes = contents.as_bytes();
+    // the start of the next line to look at, and its number
+    let mut pos = 0;
+    let mut line_number = 1;
+
+    iter::from_fn(move || {
+        while pos < bytes.len() {
+            if let Some(finder) = &finder {
+                // no more hits means no more matching lines
+                let hit = pos + finder.find(&bytes[pos..])?;
+                let line_start = memrchr(b'\n', &bytes[pos..hit]).map_or(pos, |i| pos + i + 1);
+                line_number += memchr_iter(b'\n', &bytes[pos..line_start]).count() as u64;
+                pos = line_start;
+            }
+
+            let line_end = memchr(b'\n', &bytes[pos..]).map_or(bytes.len(), |i| pos + i + 1);
+            let (number, offset) = (line_number, pos);
+            let line = &contents[pos..line_end];
+            pos = line_end;
+            line_number += 1;
+
+            let line = line.strip_suffix('\n').unwrap_or(line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if matcher.is_match(line) {
+                return Some((number, offset as u64, line));
+            }
+        }
+
+        None
+    })
+}
+
+/// searches the contents, describing each matching line in full
+pub fn search_matches<'a, M: LineMatcher + ?Sized>(
+    matcher: &M,
+    contents: &'a str,
+) -> Vec<Match<'a>> {
+    search_iter(matcher, contents).collect()
+}
+
+/// searches the contents as a whole, so a single match can span several lines
+///
+/// each match describes the full lines it touches, starting at the line where it begins.
+/// matches that share a line are reported together, with a span for each of them
+pub fn search_multiline<'a, M: LineMatcher + ?Sized>(
+    matcher: &M,
+    contents: &'a str,
+) -> Vec<Match<'a>> {
+    let mut matches: Vec<Match<'a>> = Vec::new();
+    // the byte range of the last region, and how many lines come before it
+    let mut region = (0, 0);
+    let mut line_number = 1;
+    let mut counted_to = 0;
+
+    for (start, end) in matcher.find_spans(contents) {
+        // a match ending in a newline shouldn't drag the following line in with it
+        let last = if contents[start..end].ends_with('\n') {
+            end - 1
+        } else {
+            end
+        };
+
+        if let Some(current) = matches.last_mut().filter(|_| start < region.1) {
+            region.1 = region.1.max(line_end(contents, last));
+            current.line = Cow::Borrowed(&contents[region.0..region.1]);
+            current.spans.push(clamp((start, end), region));
+            continue;
+        }
+
+        let line_start = contents[..start].rfind('\n').map_or(0, |i| i + 1);
+        region = (line_start, line_end(contents, last));
+        line_number += contents[counted_to..line_start].matches('\n').count() as u64;
+        counted_to = line_start;
+
+        matches.push(Match {
+            line_number,
+            byte_offset: line_start as u64,
+            column: start - line_start + 1,
+            line: Cow::Borrowed(&contents[region.0..region.1]),
+            spans: vec![clamp((start, end), region)],
+        });
+    }
+
+    matches
+}
+
+// makes a span relative to its region, cutting off any line ending the region leaves out
+fn clamp(span: (usize, usize), region: (usize, usize)) -> (usize, usize) {
+    (
+        span.0.min(region.1) - region.0,
+        span.1.min(region.1) - region.0,
+    )
+}
+
+// the end of the line containing `pos`, not counting its line ending
+fn line_end(contents: &str, pos: usize) -> usize {
+    let end = contents[pos..]
+        .find('\n')
+        .map_or(contents.len(), |i| pos + i);
+    if contents[..end].ends_with('\r') {
+        end - 1
+    } else {
+        end
+    }
+}
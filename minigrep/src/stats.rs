@@ -0,0 +1,35 @@
+use std::fmt;
+use std::ops::AddAssign;
+use std::time::Duration;
+
+/// counters collected over a whole run, printed by `--stats`
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub files_searched: u64,
+    /// files that couldn't be opened or read, or that the directory walk couldn't reach
+    pub files_skipped: u64,
+    pub lines_scanned: u64,
+    /// matching lines, only counting those that were actually looked at (see `-m`, `-l`)
+    pub matches: u64,
+    pub elapsed: Duration,
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Stats) {
+        self.files_searched += other.files_searched;
+        self.files_skipped += other.files_skipped;
+        self.lines_scanned += other.lines_scanned;
+        self.matches += other.matches;
+        self.elapsed += other.elapsed;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} files searched", self.files_searched)?;
+        writeln!(f, "{} files skipped", self.files_skipped)?;
+        writeln!(f, "{} lines scanned", self.lines_scanned)?;
+        writeln!(f, "{} matches found", self.matches)?;
+        write!(f, "{:.3} seconds elapsed", self.elapsed.as_secs_f64())
+    }
+}
@@ -0,0 +1,132 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead};
+
+use memchr::memmem::Finder;
+use memchr::{memchr_iter, memrchr};
+
+use crate::{LineMatcher, Match};
+
+/// an iterator over the matching lines of a reader, pulling one line at a time
+///
+/// the same line buffer is reused for every read, so memory use only depends on the longest
+/// line rather than the size of the input. when the pattern has a required literal, the
+/// reader's buffer is scanned for it directly and lines without it are never copied out
+pub struct SearchReader<'p, R, M: ?Sized> {
+    pattern: &'p M,
+    finder: Option<Finder<'p>>,
+    reader: R,
+    line: String,
+    // byte offset of the next line to be read
+    next_offset: u64,
+    lines_read: u64,
+    // nothing past this line number is read
+    last_line: u64,
+}
+
+impl<R, M: ?Sized> SearchReader<'_, R, M> {
+    /// how many lines have been read so far, matching or not
+    pub fn lines_read(&self) -> u64 {
+        self.lines_read
+    }
+
+    /// stops reading once the given line number has been read
+    pub fn stop_after(mut self, last_line: u64) -> Self {
+        self.last_line = last_line;
+        self
+    }
+}
+
+impl<R: BufRead, M: ?Sized> SearchReader<'_, R, M> {
+    // consumes whole lines from the reader's buffer up to the first one that could match,
+    // returning false once there is nothing left to read
+    fn skip_lines(&mut self) -> io::Result<bool> {
+        let Some(finder) = &self.finder else {
+            return Ok(true);
+        };
+
+        loop {
+            let buf = self.reader.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(false);
+            }
+
+            let hit = finder.find(buf);
+            // without a hit the last line is kept, the literal could continue past the buffer
+            let end = hit.unwrap_or(buf.len());
+            let skip = memrchr(b'\n', &buf[..end]).map_or(0, |i| i + 1);
+
+            self.lines_read += memchr_iter(b'\n', &buf[..skip]).count() as u64;
+            self.next_offset += skip as u64;
+            self.reader.consume(skip);
+
+            // a line with no newline in the buffer is left for `read_line` to put together
+            if hit.is_some() || skip == 0 {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<R: BufRead, M: LineMatcher + ?Sized> Iterator for SearchReader<'_, R, M> {
+    type Item = io::Result<Match<'static>>;
+
+    /// offsets in the returned matches count bytes of the UTF-8 text being searched,
+    /// which for transcoded files is the decoded text rather than the raw file
+    fn next(&mut self) -> Option<io::Result<Match<'static>>> {
+        loop {
+            self.line.clear();
+
+            match self.skip_lines() {
+                Ok(true) => (),
+                Ok(false) => return None,
+                Err(err) => return Some(Err(err)),
+            }
+            if self.lines_read >= self.last_line {
+                return None;
+            }
+
+            let offset = self.next_offset;
+            match self.reader.read_line(&mut self.line) {
+                Ok(0) => return None,
+                Ok(len) => {
+                    self.next_offset += len as u64;
+                    self.lines_read += 1;
+                }
+                Err(err) => return Some(Err(err)),
+            }
+
+            // strip the line ending the same way `str::lines` does
+            if self.line.ends_with('\n') {
+                self.line.pop();
+                if self.line.ends_with('\r') {
+                    self.line.pop();
+                }
+            }
+
+            if self.pattern.is_match(&self.line) {
+                return Some(Ok(Match::new(
+                    self.pattern,
+                    Cow::Owned(self.line.clone()),
+                    self.lines_read,
+                    offset,
+                )));
+            }
+        }
+    }
+}
+
+/// searches a reader line by line, yielding each matching line as soon as it is read
+pub fn search_reader<R: BufRead, M: LineMatcher + ?Sized>(
+    pattern: &M,
+    reader: R,
+) -> SearchReader<'_, R, M> {
+    SearchReader {
+        pattern,
+        finder: pattern.required_literal().map(Finder::new),
+        reader,
+        line: String::new(),
+        next_offset: 0,
+        lines_read: 0,
+        last_line: u64::MAX,
+    }
+}
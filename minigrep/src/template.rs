@@ -0,0 +1,135 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+
+/// a user supplied layout for each line of output, like `{path}:{line}:{text}`
+///
+/// the fields are `{path}`, `{line}` (the line number), `{column}` (in bytes, of the match),
+/// `{offset}` (in bytes, of the line like `-b`), `{match}` (the matched text) and `{text}`
+/// (the whole line). `{{` and `}}` stand for literal braces
+#[derive(PartialEq, Debug, Clone)]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+enum Piece {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum Field {
+    Path,
+    Line,
+    Column,
+    Offset,
+    Match,
+    Text,
+}
+
+/// the values a template can refer to, for a single line or match
+pub struct Fields<'a> {
+    pub path: &'a dyn Display,
+    pub line_number: u64,
+    /// 1-based
+    pub column: usize,
+    pub offset: u64,
+    pub matched: &'a str,
+    pub text: &'a str,
+}
+
+impl Template {
+    /// parses a template, returning `None` for unknown fields or unbalanced braces
+    pub fn parse(template: &str) -> Option<Template> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next()? {
+                            '}' => break,
+                            c => name.push(c),
+                        }
+                    }
+                    let field = match name.as_str() {
+                        "path" => Field::Path,
+                        "line" => Field::Line,
+                        "column" => Field::Column,
+                        "offset" => Field::Offset,
+                        "match" => Field::Match,
+                        "text" => Field::Text,
+                        _ => return None,
+                    };
+                    if !text.is_empty() {
+                        pieces.push(Piece::Text(std::mem::take(&mut text)));
+                    }
+                    pieces.push(Piece::Field(field));
+                }
+                '}' => return None,
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Some(Template { pieces })
+    }
+
+    /// writes the template with every field filled in, followed by a newline
+    pub fn render(&self, out: &mut dyn Write, fields: &Fields) -> io::Result<()> {
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => write!(out, "{text}")?,
+                Piece::Field(Field::Path) => write!(out, "{}", fields.path)?,
+                Piece::Field(Field::Line) => write!(out, "{}", fields.line_number)?,
+                Piece::Field(Field::Column) => write!(out, "{}", fields.column)?,
+                Piece::Field(Field::Offset) => write!(out, "{}", fields.offset)?,
+                Piece::Field(Field::Match) => write!(out, "{}", fields.matched)?,
+                Piece::Field(Field::Text) => write!(out, "{}", fields.text)?,
+            }
+        }
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_fields_and_braces() {
+        let template = Template::parse("{path}:{line}:{column}: {{{match}}} in {text}").unwrap();
+        let fields = Fields {
+            path: &"poem.txt",
+            line_number: 2,
+            column: 9,
+            offset: 33,
+            matched: "nobody",
+            text: "Are you nobody, too?",
+        };
+
+        let mut out = Vec::new();
+        template.render(&mut out, &fields).unwrap();
+        assert_eq!(
+            "poem.txt:2:9: {nobody} in Are you nobody, too?\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        assert_eq!(None, Template::parse("{file}"));
+        assert_eq!(None, Template::parse("{line"));
+        assert_eq!(None, Template::parse("line}"));
+    }
+}
@@ -0,0 +1,166 @@
+/// the file types known without any `--type-add`, as a name and the globs its files match
+const DEFAULT_TYPES: &[(&str, &[&str])] = &[
+    ("c", &["*.c", "*.h"]),
+    ("cargo", &["Cargo.toml", "Cargo.lock"]),
+    ("cpp", &["*.cpp", "*.cc", "*.cxx", "*.hpp", "*.hh", "*.h"]),
+    ("css", &["*.css"]),
+    ("go", &["*.go"]),
+    ("html", &["*.htm", "*.html"]),
+    ("java", &["*.java"]),
+    ("js", &["*.js", "*.mjs", "*.cjs"]),
+    ("json", &["*.json"]),
+    ("md", &["*.md", "*.markdown"]),
+    ("py", &["*.py"]),
+    ("rust", &["*.rs"]),
+    ("sh", &["*.sh", "*.bash"]),
+    ("toml", &["*.toml"]),
+    ("ts", &["*.ts", "*.tsx"]),
+    ("txt", &["*.txt"]),
+    ("wgsl", &["*.wgsl"]),
+    ("yaml", &["*.yaml", "*.yml"]),
+];
+
+/// decides which files a recursive search looks at, based on named groups of file name globs
+///
+/// with no types selected every file is searched, otherwise only files matching at least one
+/// selected type are. negated types always win over selected ones
+#[derive(Debug, Clone)]
+pub struct FileTypes {
+    // every known type and its globs, later definitions of a name add to earlier ones
+    defs: Vec<(String, Vec<String>)>,
+    selected: Vec<String>,
+    negated: Vec<String>,
+}
+
+impl Default for FileTypes {
+    fn default() -> FileTypes {
+        let defs = DEFAULT_TYPES
+            .iter()
+            .map(|(name, globs)| {
+                let globs = globs.iter().map(|glob| glob.to_string()).collect();
+                (name.to_string(), globs)
+            })
+            .collect();
+
+        FileTypes {
+            defs,
+            selected: Vec::new(),
+            negated: Vec::new(),
+        }
+    }
+}
+
+impl FileTypes {
+    /// adds a glob to a type from a `name:glob` definition, creating the type if it is new
+    ///
+    /// returns `None` if the definition is malformed
+    pub fn add(&mut self, def: &str) -> Option<()> {
+        let (name, glob) = def.split_once(':')?;
+        if name.is_empty() || glob.is_empty() {
+            return None;
+        }
+
+        match self.defs.iter_mut().find(|(existing, _)| existing == name) {
+            Some((_, globs)) => globs.push(glob.to_string()),
+            None => self.defs.push((name.to_string(), vec![glob.to_string()])),
+        }
+        Some(())
+    }
+
+    /// only search files of this type (or of any other selected one)
+    pub fn select(&mut self, name: &str) {
+        self.selected.push(name.to_string());
+    }
+
+    /// never search files of this type
+    pub fn negate(&mut self, name: &str) {
+        self.negated.push(name.to_string());
+    }
+
+    /// the first selected or negated name that isn't a known type, if there is one
+    pub fn unknown(&self) -> Option<&str> {
+        self.selected
+            .iter()
+            .chain(&self.negated)
+            .find(|name| self.globs(name).is_none())
+            .map(String::as_str)
+    }
+
+    /// whether a file with this name should be searched
+    pub fn is_match(&self, file_name: &str) -> bool {
+        let matches = |names: &[String]| {
+            names.iter().any(|name| {
+                self.globs(name)
+                    .is_some_and(|globs| globs.iter().any(|glob| glob_match(glob, file_name)))
+            })
+        };
+
+        (self.selected.is_empty() || matches(&self.selected)) && !matches(&self.negated)
+    }
+
+    fn globs(&self, name: &str) -> Option<&[String]> {
+        self.defs
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, globs)| globs.as_slice())
+    }
+}
+
+// matches a file name against a glob where `*` is any run of chars and `?` is any single one
+fn glob_match(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // the usual greedy algorithm, backtracking to just after the last `*` on a mismatch
+    let (mut g, mut n) = (0, 0);
+    let mut star = None;
+
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, n));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_g, star_n)) => {
+                    g = star_g + 1;
+                    n = star_n + 1;
+                    star = Some((star_g, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_and_negates_types() {
+        let mut types = FileTypes::default();
+        types.add("notes:*.notes").unwrap();
+        types.add("rust:build.rs.in").unwrap();
+        types.select("rust");
+        types.select("notes");
+        types.negate("cargo");
+
+        assert!(types.is_match("main.rs"));
+        assert!(types.is_match("build.rs.in"));
+        assert!(types.is_match("todo.notes"));
+        assert!(!types.is_match("README.md"));
+        assert!(!types.is_match("Cargo.toml"));
+        assert_eq!(None, types.unknown());
+
+        types.negate("nope");
+        assert_eq!(Some("nope"), types.unknown());
+        assert_eq!(None, types.add("no glob"));
+    }
+}
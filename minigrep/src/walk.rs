@@ -0,0 +1,189 @@
+use std::collections::HashSet;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::FileTypes;
+
+/// settings for expanding directories into the files inside them
+#[derive(Default)]
+pub struct WalkOptions {
+    /// follow symbolic links found inside directories, instead of skipping them
+    pub follow_links: bool,
+    /// how many levels below the given paths to descend, `Some(0)` only searches the paths
+    /// themselves and `Some(1)` also the files directly inside them
+    pub max_depth: Option<usize>,
+    /// which files found while walking get yielded, paths given directly always are
+    pub types: FileTypes,
+}
+
+// what makes a directory unique, so that a symlink loop is noticed when we come back around
+#[cfg(unix)]
+type DirId = (u64, u64);
+#[cfg(not(unix))]
+type DirId = PathBuf;
+
+#[cfg(unix)]
+fn dir_id(_path: &Path, metadata: &Metadata) -> io::Result<DirId> {
+    use std::os::unix::fs::MetadataExt;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn dir_id(path: &Path, _metadata: &Metadata) -> io::Result<DirId> {
+    fs::canonicalize(path)
+}
+
+/// walks a list of paths depth first, yielding every file found under them
+///
+/// paths given directly are always followed even if they are symlinks, like grep does,
+/// but links found while walking are only followed with `follow_links`.
+/// directories are listed in sorted order so the output is the same on every run
+pub struct Walker {
+    options: WalkOptions,
+    // paths still to visit along with their depth, the next one is at the end
+    stack: Vec<(PathBuf, usize)>,
+    visited: HashSet<DirId>,
+}
+
+impl Walker {
+    pub fn new<P: AsRef<Path>>(roots: &[P], options: WalkOptions) -> Walker {
+        Walker {
+            options,
+            stack: roots
+                .iter()
+                .rev()
+                .map(|root| (root.as_ref().into(), 0))
+                .collect(),
+            visited: HashSet::new(),
+        }
+    }
+
+    // pushes the entries of a directory so they come out in name order
+    fn push_dir(&mut self, path: &Path, depth: usize) -> io::Result<()> {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+
+        self.stack
+            .extend(entries.into_iter().rev().map(|entry| (entry, depth + 1)));
+        Ok(())
+    }
+}
+
+impl Iterator for Walker {
+    type Item = io::Result<PathBuf>;
+
+    fn next(&mut self) -> Option<io::Result<PathBuf>> {
+        while let Some((path, depth)) = self.stack.pop() {
+            // the top level paths are not subject to the symlink rule
+            let is_root = depth == 0;
+
+            let link_metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) => return Some(Err(with_path(err, &path))),
+            };
+            if link_metadata.file_type().is_symlink() && !is_root && !self.options.follow_links {
+                continue;
+            }
+
+            // resolves the link if there is one
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) => return Some(Err(with_path(err, &path))),
+            };
+            if !metadata.is_dir() {
+                let wanted = is_root
+                    || path
+                        .file_name()
+                        .is_some_and(|name| self.options.types.is_match(&name.to_string_lossy()));
+                if wanted {
+                    return Some(Ok(path));
+                }
+                continue;
+            }
+
+            if self.options.max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+
+            match dir_id(&path, &metadata) {
+                Ok(id) if !self.visited.insert(id) => {
+                    let message = format!("{}: directory loop, skipping", path.display());
+                    return Some(Err(io::Error::other(message)));
+                }
+                Ok(_) => (),
+                Err(err) => return Some(Err(with_path(err, &path))),
+            }
+
+            if let Err(err) = self.push_dir(&path, depth) {
+                return Some(Err(with_path(err, &path)));
+            }
+        }
+
+        None
+    }
+}
+
+// io errors don't say which file they came from, which isn't much help halfway through a walk
+fn with_path(err: io::Error, path: &Path) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {err}", path.display()))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follow_detects_symlink_loops() {
+        let root = std::env::temp_dir().join(format!("minigrep-walk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/file.txt"), "Rust").unwrap();
+        std::os::unix::fs::symlink("..", root.join("a/up")).unwrap();
+
+        let skipped: Vec<_> = Walker::new(&[&root], WalkOptions::default()).collect();
+        let followed: Vec<_> = Walker::new(
+            &[&root],
+            WalkOptions {
+                follow_links: true,
+                ..WalkOptions::default()
+            },
+        )
+        .collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(1, skipped.len());
+        assert_eq!(root.join("a/file.txt"), *skipped[0].as_ref().unwrap());
+        // the file once, then the loop back to the root is reported instead of walked forever
+        assert_eq!(2, followed.len());
+        assert!(followed[1].is_err());
+    }
+
+    #[test]
+    fn max_depth_limits_descent() {
+        let root = std::env::temp_dir().join(format!("minigrep-depth-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("top.txt"), "Rust").unwrap();
+        fs::write(root.join("a/mid.txt"), "Rust").unwrap();
+        fs::write(root.join("a/b/deep.txt"), "Rust").unwrap();
+
+        let walk = |max_depth| {
+            let options = WalkOptions {
+                max_depth: Some(max_depth),
+                ..WalkOptions::default()
+            };
+            Walker::new(&[&root], options)
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        };
+        let shallow = walk(1);
+        let deeper = walk(2);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(vec![root.join("top.txt")], shallow);
+        assert_eq!(vec![root.join("a/mid.txt"), root.join("top.txt")], deeper);
+    }
+}
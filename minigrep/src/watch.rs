@@ -0,0 +1,267 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::{is_broken_pipe, search_iter, Match, Searcher, Stats};
+
+/// how long to wait between checking the files for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// keeps track of how much of each file has already been searched, so that every pass only
+/// looks at what was added since the last one, like `tail -f`
+///
+/// a file that shrinks is taken to have been truncated or replaced and is searched from the
+/// start again. a line is only searched once its newline has been written
+pub(crate) struct Watcher<'a> {
+    searcher: &'a Searcher<'a>,
+    // how much of each file has already been searched
+    offsets: HashMap<PathBuf, Searched>,
+    // errors already printed, so a missing file isn't complained about on every pass
+    reported: HashSet<String>,
+    found: bool,
+}
+
+// the part of a file already searched, always ending on a line boundary
+#[derive(Clone, Copy, Default)]
+struct Searched {
+    bytes: u64,
+    // the lines in those bytes, so that matches after them get the right line numbers
+    lines: u64,
+}
+
+impl<'a> Watcher<'a> {
+    pub(crate) fn new(searcher: &'a Searcher<'a>) -> Watcher<'a> {
+        Watcher {
+            searcher,
+            offsets: HashMap::new(),
+            reported: HashSet::new(),
+            found: false,
+        }
+    }
+
+    /// searches whatever was added to the files since the last pass
+    ///
+    /// problems with the files themselves are reported and retried on the next pass, so the
+    /// only error returned is the output being closed
+    pub(crate) fn pass(
+        &mut self,
+        paths: impl Iterator<Item = io::Result<PathBuf>>,
+        out: &mut dyn Write,
+    ) -> Result<(), Box<dyn Error>> {
+        for path in paths {
+            let result = path
+                .map_err(|err| err.into())
+                .and_then(|path| self.search_added(&path, out));
+
+            match result {
+                Ok(file_found) => self.found |= file_found,
+                Err(err) if is_broken_pipe(err.as_ref()) => return Err(err),
+                Err(err) => {
+                    let message = err.to_string();
+                    if !self.reported.contains(&message) {
+                        eprintln!("minigrep: {message}");
+                        self.reported.insert(message);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // searches the complete lines added to a file since it was last looked at
+    fn search_added(&mut self, path: &Path, out: &mut dyn Write) -> Result<bool, Box<dyn Error>> {
+        let with_name = |err: io::Error| format!("{}: {err}", path.display());
+
+        let mut file = File::open(path).map_err(with_name)?;
+        let len = file.metadata().map_err(with_name)?.len();
+        let searched = match self.offsets.get(path) {
+            Some(&searched) if searched.bytes <= len => searched,
+            _ => Searched::default(),
+        };
+        let offset = searched.bytes;
+
+        let mut added = Vec::new();
+        file.seek(SeekFrom::Start(offset)).map_err(with_name)?;
+        file.take(len - offset)
+            .read_to_end(&mut added)
+            .map_err(with_name)?;
+
+        // a line still being written is left for the next pass
+        let complete = added.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        added.truncate(complete);
+        let lines = added.iter().filter(|&&b| b == b'\n').count() as u64;
+        self.offsets.insert(
+            path.to_path_buf(),
+            Searched {
+                bytes: offset + complete as u64,
+                lines: searched.lines + lines,
+            },
+        );
+
+        let text = String::from_utf8_lossy(&added);
+        let mut matches = search_iter(self.searcher.matcher, &text).map(|found| {
+            Ok(Match {
+                line_number: searched.lines + found.line_number,
+                byte_offset: offset + found.byte_offset,
+                ..found
+            })
+        });
+        self.searcher
+            .write_matches(path, &mut matches, out, &mut Stats::default())
+    }
+}
+
+/// searches the files over and over, printing only the matches added since the last time
+/// around, until the output is closed
+///
+/// `paths` is called before every pass, so files created in a watched directory are picked up.
+/// returns whether anything was ever found
+pub(crate) fn watch<F, I>(searcher: &Searcher, mut paths: F, out: &mut dyn Write) -> bool
+where
+    F: FnMut() -> I,
+    I: Iterator<Item = io::Result<PathBuf>>,
+{
+    let mut watcher = Watcher::new(searcher);
+
+    while watcher.pass(paths(), out).is_ok() && out.flush().is_ok() {
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    watcher.found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Pattern, PatternSet, Template};
+    use std::fs::{self, OpenOptions};
+
+    #[test]
+    fn only_prints_added_lines() {
+        let path = std::env::temp_dir().join(format!("minigrep-watch-{}.log", std::process::id()));
+        fs::write(&path, "Rust: old\nnothing\n").unwrap();
+
+        let config = Config::default();
+        let pattern: PatternSet = Pattern::new("Rust", false, false).into();
+        let searcher = Searcher {
+            config: &config,
+            pattern: &pattern,
+            matcher: &pattern,
+            show_path: false,
+        };
+        let mut watcher = Watcher::new(&searcher);
+        let mut pass = |append: &str| {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(append.as_bytes()).unwrap();
+
+            let mut out = Vec::new();
+            watcher
+                .pass([Ok(path.clone())].into_iter(), &mut out)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!("Rust: old\n", pass(""));
+        assert_eq!("", pass("still nothing\nRust: half"));
+        assert_eq!("Rust: half done\nRust: new\n", pass(" done\nRust: new\n"));
+
+        // a truncated file is searched from the start again
+        fs::write(&path, "Rust: rotated\n").unwrap();
+        assert_eq!("Rust: rotated\n", pass(""));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn numbers_added_lines_from_the_start_of_the_file() {
+        let path = std::env::temp_dir().join(format!("minigrep-lines-{}.log", std::process::id()));
+        fs::write(
+            &path,
+            "Rust one
+nothing
+",
+        )
+        .unwrap();
+
+        let config = Config {
+            format: Some(Template::parse("{line}:{offset}:{text}").unwrap()),
+            ..Config::default()
+        };
+        let pattern: PatternSet = Pattern::new("Rust", false, false).into();
+        let searcher = Searcher {
+            config: &config,
+            pattern: &pattern,
+            matcher: &pattern,
+            show_path: false,
+        };
+        let mut watcher = Watcher::new(&searcher);
+        let mut pass = |append: &str| {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(append.as_bytes()).unwrap();
+
+            let mut out = Vec::new();
+            watcher
+                .pass([Ok(path.clone())].into_iter(), &mut out)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!("1:0:Rust one\n", pass(""));
+        assert_eq!("", pass("nothing\nRust th"));
+        assert_eq!(
+            "4:25:Rust three\n5:36:Rust four\n",
+            pass("ree\nRust four\n")
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn format_fields_follow_the_file_as_it_grows() {
+        let path = std::env::temp_dir().join(format!("minigrep-format-{}.log", std::process::id()));
+        fs::write(&path, "say Rust\n").unwrap();
+        let name = path.display().to_string();
+
+        let args = [
+            "minigrep",
+            "--watch",
+            "-o",
+            "--format",
+            "{path}:{line}:{column}:{match}",
+        ];
+        let config = Config::build(
+            args.into_iter()
+                .chain(["Rust", name.as_str()])
+                .map(String::from),
+        )
+        .unwrap();
+        let pattern: PatternSet = Pattern::new("Rust", false, false).into();
+        let searcher = Searcher {
+            config: &config,
+            pattern: &pattern,
+            matcher: &pattern,
+            show_path: true,
+        };
+        let mut watcher = Watcher::new(&searcher);
+        let mut pass = |append: &str| {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(append.as_bytes()).unwrap();
+
+            let mut out = Vec::new();
+            watcher
+                .pass([Ok(path.clone())].into_iter(), &mut out)
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let first = pass("");
+        let added = pass("nothing\nRust and Rust\n");
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(format!("{name}:1:5:Rust\n"), first);
+        assert_eq!(format!("{name}:3:1:Rust\n{name}:3:10:Rust\n"), added);
+    }
+}
@@ -0,0 +1,70 @@
+use std::{hint::black_box, thread};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use mvcc::{ColumnType, Schema, Value, MVCC};
+
+const TRANSACTIONS: u64 = 4_000;
+const ROWS: u32 = 1_024;
+const READS: u32 = 16;
+
+// Runs `TRANSACTIONS` transactions split evenly over `threads` threads. Each reads
+// `READS` rows and writes one, keeping to rows of its own thread so that none of them
+// conflict. With a single shard every read and write waits on the same lock, so only
+// with more shards can more threads get through more transactions.
+fn run_transactions(store: &MVCC, threads: u32) {
+    let per_thread = TRANSACTIONS / threads as u64;
+    thread::scope(|scope| {
+        for thread in 0..threads {
+            scope.spawn(move || {
+                for n in 0..per_thread as u32 {
+                    let transaction = store.begin_transaction();
+                    let base = (n * 7) % (ROWS / threads - READS);
+                    for offset in 0..READS {
+                        black_box(transaction.get("rows", (base + offset) * threads + thread));
+                    }
+                    transaction
+                        .set("rows", base * threads + thread, vec![Value::Int(n.into())])
+                        .unwrap();
+                    transaction.commit().unwrap();
+                }
+            });
+        }
+    });
+}
+
+fn store(shards: usize) -> MVCC {
+    let store = MVCC::new().with_shards(shards);
+    store.create_table("rows", Schema::new().column("value", ColumnType::Int));
+    let setup = store.begin_transaction();
+    let rows = (0..ROWS).map(|id| (id, Some(vec![Value::Int(id.into())])));
+    setup.write_batch("rows", rows.collect()).unwrap();
+    setup.commit().unwrap();
+    store
+}
+
+fn scaling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("transactions");
+    group.throughput(Throughput::Elements(TRANSACTIONS));
+
+    for shards in [1, 16] {
+        for threads in [1, 2, 4, 8] {
+            let store = store(shards);
+            group.bench_function(
+                BenchmarkId::new(format!("{}_shards", shards), threads),
+                |b| {
+                    b.iter(|| {
+                        run_transactions(&store, threads);
+                        // Keeps the old versions from piling up between iterations.
+                        store.vacuum();
+                    })
+                },
+            );
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, scaling);
+criterion_main!(benches);
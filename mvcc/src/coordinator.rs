@@ -0,0 +1,188 @@
+use super::{CommitError, CommitInfo, Transaction, MVCC};
+use std::error::Error;
+use std::fmt;
+
+// A transaction spanning several stores, made up of one transaction in each, which
+// either commits in every store or in none of them. Committing takes two phases: first
+// every transaction is prepared, which is where any of them can fail, and only once all
+// of them have been is each one committed, which none of them can then fail to do. If
+// one can't be prepared, they are all rolled back.
+//
+// The transactions are read and written as usual through `transaction`, but only the
+// coordinator should commit or roll them back. Dropping it rolls back any that haven't
+// finished.
+pub struct Coordinator {
+    transactions: Vec<Transaction>,
+}
+
+impl Coordinator {
+    // Begin a transaction in each of `stores`, at the store's isolation level.
+    pub fn begin(stores: &[&MVCC]) -> Self {
+        Self::new(
+            stores
+                .iter()
+                .map(|store| store.begin_transaction())
+                .collect(),
+        )
+    }
+
+    // Coordinate transactions that have already begun, each in a different store.
+    pub fn new(transactions: Vec<Transaction>) -> Self {
+        Self { transactions }
+    }
+
+    // The transaction in the store at `store`, in the order the stores were given.
+    //
+    // Panics if there is no store there.
+    pub fn transaction(&self, store: usize) -> &Transaction {
+        &self.transactions[store]
+    }
+
+    // Commit the transaction in every store, returning what each of them did, in order.
+    // If any of them can't be prepared, every one is rolled back instead, and the error
+    // says which store it was and why.
+    pub fn commit(&self) -> Result<Vec<CommitInfo>, CoordinatorError> {
+        for (store, transaction) in self.transactions.iter().enumerate() {
+            if let Err(error) = transaction.prepare() {
+                self.rollback();
+                return Err(CoordinatorError { store, error });
+            }
+        }
+        Ok(self
+            .transactions
+            .iter()
+            .map(|transaction| {
+                transaction
+                    .commit()
+                    .expect("prepared transactions can always commit")
+            })
+            .collect())
+    }
+
+    // Roll back the transaction in every store.
+    pub fn rollback(&self) {
+        for transaction in &self.transactions {
+            transaction.rollback();
+        }
+    }
+}
+
+// Returned when a coordinated transaction can't commit, which has left it rolled back in
+// every store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoordinatorError {
+    // Where the store whose transaction couldn't be prepared was in the order given.
+    pub store: usize,
+    // Why it couldn't be.
+    pub error: CommitError,
+}
+
+impl fmt::Display for CoordinatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "store {} can't commit: {}", self.store, self.error)
+    }
+}
+
+impl Error for CoordinatorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ColumnType, Row, Schema, TransactionError, Value};
+
+    // A store with an empty table of stock, each row holding how many of an item there
+    // are.
+    fn warehouse() -> MVCC {
+        let mvcc = MVCC::new();
+        mvcc.create_table("stock", Schema::new().column("count", ColumnType::Int));
+        mvcc
+    }
+
+    fn count(count: i64) -> Row {
+        vec![Value::Int(count)]
+    }
+
+    #[test]
+    fn commit_commits_in_every_store() {
+        let first = warehouse();
+        let second = warehouse();
+        let coordinator = Coordinator::begin(&[&first, &second]);
+        coordinator
+            .transaction(0)
+            .set("stock", 1, count(4))
+            .unwrap();
+        coordinator
+            .transaction(1)
+            .set("stock", 1, count(6))
+            .unwrap();
+        assert_eq!(None, first.begin_read_only().get("stock", 1));
+
+        let infos = coordinator.commit().unwrap();
+        assert_eq!(
+            vec![1, 1],
+            infos.iter().map(|info| info.writes).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(count(4)), first.begin_read_only().get("stock", 1));
+        assert_eq!(Some(count(6)), second.begin_read_only().get("stock", 1));
+    }
+
+    #[test]
+    fn one_store_failing_to_prepare_rolls_back_every_store() {
+        let first = warehouse();
+        let second = warehouse().optimistic();
+        let coordinator = Coordinator::begin(&[&first, &second]);
+        coordinator
+            .transaction(0)
+            .set("stock", 1, count(4))
+            .unwrap();
+        coordinator
+            .transaction(1)
+            .set("stock", 1, count(6))
+            .unwrap();
+
+        // Gets in first in the second store, so the coordinated transaction fails
+        // validation there.
+        let other = second.begin_transaction();
+        other.set("stock", 1, count(1)).unwrap();
+        other.commit().unwrap();
+
+        assert_eq!(
+            Err(CoordinatorError {
+                store: 1,
+                error: CommitError::Validation {
+                    table: "stock".to_string(),
+                    id: 1,
+                },
+            }),
+            coordinator.commit()
+        );
+        assert_eq!(None, first.begin_read_only().get("stock", 1));
+        assert_eq!(Some(count(1)), second.begin_read_only().get("stock", 1));
+        assert!(first.versions("stock").is_empty());
+    }
+
+    #[test]
+    fn prepared_transactions_can_only_commit_or_roll_back() {
+        let mvcc = warehouse().with_max_transaction_age(std::time::Duration::ZERO);
+        let transaction = mvcc.begin_transaction();
+        transaction.set("stock", 1, count(4)).unwrap();
+        transaction.prepare().unwrap();
+
+        assert_eq!(
+            Err(TransactionError::Prepared),
+            transaction.set("stock", 2, count(1))
+        );
+        assert_eq!(Some(count(4)), transaction.get("stock", 1));
+        // Others still can't write what it wrote, and it isn't aborted for its age.
+        assert!(mvcc.begin_transaction().set("stock", 1, count(1)).is_err());
+        assert_eq!(0, mvcc.abort_expired());
+
+        transaction.prepare().unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(Some(count(4)), mvcc.begin_read_only().get("stock", 1));
+    }
+}
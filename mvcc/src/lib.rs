@@ -0,0 +1,3196 @@
+mod coordinator;
+mod query;
+mod schema;
+mod ssi;
+mod wal;
+
+pub use coordinator::{Coordinator, CoordinatorError};
+pub use query::{Column, Filter, Output, QueryError, Statement};
+pub use schema::{ColumnType, Row, Schema, SchemaError, Value};
+use ssi::SsiTracker;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockWriteGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use wal::RowWrite;
+pub use wal::{SyncMode, Wal, WalRecord};
+
+// One version of a row, as written by a single transaction.
+struct RowVersion {
+    values: Row,
+    // The transaction that wrote this version.
+    created_by: usize,
+    // The transaction that overwrote or deleted this version, if any.
+    deleted_by: Option<usize>,
+}
+
+// How many shards each table is split into, unless the store says otherwise.
+const DEFAULT_SHARDS: usize = 16;
+
+// Every table in a store, by name.
+//
+// The store sits behind a read-write lock. Reading and writing rows only takes it for
+// reading, along with the lock on the shard of the table each row is in, so transactions
+// working on rows in different shards don't hold each other up. Anything that changes a
+// transaction's state, like committing or rolling back, takes it for writing, so it can't
+// happen halfway through one of the transaction's reads or writes.
+struct Database {
+    tables: BTreeMap<String, Table>,
+    // How many shards new tables are split into.
+    shards: usize,
+    // Where to send the changes transactions commit.
+    subscribers: Vec<Sender<Change>>,
+}
+
+impl Database {
+    // The table with the given name. Panics if there is none.
+    fn table(&self, table: &str) -> &Table {
+        self.tables
+            .get(table)
+            .unwrap_or_else(|| panic!("there is no table named {:?}", table))
+    }
+
+    // The table with the given name, or `UnknownTable` if there is none.
+    fn try_table(&self, table: &str) -> Result<&Table, TransactionError> {
+        self.tables
+            .get(table)
+            .ok_or_else(|| TransactionError::UnknownTable(table.to_string()))
+    }
+
+    // Check that `table` exists, and that `row`, if there is one, fits its schema.
+    fn check_write(&self, table: &str, id: u32, row: Option<&Row>) -> Result<(), TransactionError> {
+        let schema = &self.try_table(table)?.schema;
+        match row.map(|row| schema.check(row)) {
+            Some(Err(error)) => Err(TransactionError::InvalidRow {
+                table: table.to_string(),
+                id,
+                error,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // Vacuum every table, returning how many versions were dropped in all. Each shard is
+    // only locked while it is being vacuumed.
+    fn vacuum(&self, horizon: usize) -> usize {
+        self.tables
+            .values()
+            .flat_map(|table| &table.shards)
+            .map(|shard| shard.lock().unwrap().vacuum(horizon))
+            .sum()
+    }
+
+    // Undo a transaction's writes, given oldest first.
+    fn undo(&mut self, records: Vec<UndoRecord>) {
+        // Newest first, so each one finds the row the way it left it.
+        for record in records.into_iter().rev() {
+            if let Some(table) = self.tables.get_mut(&record.table) {
+                table.shard_mut(record.id).undo(&record);
+            }
+        }
+    }
+
+    // Send every subscriber the changes transaction `version` made to the rows in its
+    // `undo_log`, as it commits. Subscribers that have gone away are dropped.
+    fn publish(&mut self, version: usize, undo_log: &[UndoRecord]) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let mut seen = HashSet::new();
+        let changes: Vec<Change> = undo_log
+            .iter()
+            .filter(|record| seen.insert((&record.table, record.id)))
+            .filter_map(|record| {
+                let shard = self.table(&record.table).shard(record.id);
+                let (old, new) = shard.change(version, record.id);
+                // A row the transaction added and then deleted again never changed.
+                (old.is_some() || new.is_some()).then(|| Change {
+                    table: record.table.clone(),
+                    id: record.id,
+                    old,
+                    new,
+                    version,
+                })
+            })
+            .collect();
+        for change in changes {
+            self.subscribers
+                .retain(|subscriber| subscriber.send(change.clone()).is_ok());
+        }
+    }
+
+    // Give up the row locks of a transaction that has finished.
+    fn release(&mut self, version: usize) {
+        for table in self.tables.values_mut() {
+            for shard in &mut table.shards {
+                let shard = shard.get_mut().unwrap();
+                shard.locks.retain(|_, holder| *holder != version);
+            }
+        }
+    }
+}
+
+// A table, with its rows spread over shards by ID.
+struct Table {
+    // The columns every row has, which never change once the table is created.
+    schema: Schema,
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl Table {
+    // Create an empty table with the given schema, split into `shards` shards.
+    fn new(schema: Schema, shards: usize) -> Self {
+        Self {
+            schema,
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    fn shard_index(&self, id: u32) -> usize {
+        id as usize % self.shards.len()
+    }
+
+    // Lock the shard row `id` is in.
+    fn shard(&self, id: u32) -> MutexGuard<'_, Shard> {
+        self.shards[self.shard_index(id)].lock().unwrap()
+    }
+
+    // The shard row `id` is in, with the whole store locked.
+    fn shard_mut(&mut self, id: u32) -> &mut Shard {
+        let index = self.shard_index(id);
+        self.shards[index].get_mut().unwrap()
+    }
+
+    // Lock every shard, in order. Anything that holds more than one shard at a time takes
+    // them in this order, so nothing can deadlock over them.
+    fn lock_all(&self) -> Vec<MutexGuard<'_, Shard>> {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap())
+            .collect()
+    }
+
+    // Lock the shards rows `ids` are in, each once and in order.
+    fn lock_rows(&self, ids: &[u32]) -> LockedShards<'_> {
+        let indexes: BTreeSet<usize> = ids.iter().map(|&id| self.shard_index(id)).collect();
+        LockedShards {
+            table: self,
+            shards: indexes
+                .into_iter()
+                .map(|index| (index, self.shards[index].lock().unwrap()))
+                .collect(),
+        }
+    }
+}
+
+// The shards of a table holding some of its rows, locked with `Table::lock_rows`.
+struct LockedShards<'a> {
+    table: &'a Table,
+    shards: BTreeMap<usize, MutexGuard<'a, Shard>>,
+}
+
+impl LockedShards<'_> {
+    // The shard row `id` is in. Panics if it wasn't one of the rows the shards were
+    // locked for.
+    fn get(&mut self, id: u32) -> &mut Shard {
+        self.shards
+            .get_mut(&self.table.shard_index(id))
+            .expect("the row's shard is locked")
+    }
+}
+
+// The rows of one shard of a table, along with their entries in the table's indexes.
+#[derive(Default)]
+struct Shard {
+    // Every version of each row, keyed by row ID and ordered oldest to newest.
+    rows: BTreeMap<u32, Vec<RowVersion>>,
+    // The secondary indexes, by name.
+    indexes: HashMap<String, Index>,
+    // The rows locked with `Transaction::get_for_update`, each with the transaction that
+    // holds the lock.
+    locks: HashMap<u32, usize>,
+}
+
+// A secondary index, which finds rows by their value in one of the table's columns.
+struct Index {
+    // Where the column is in a row.
+    column: usize,
+    // The rows with at least one version that has each key. Versions no snapshot sees
+    // are indexed too, since some transaction may still see them, so a lookup has to
+    // check which of the rows it finds actually match.
+    entries: BTreeMap<Value, BTreeSet<u32>>,
+}
+
+impl Index {
+    // A row's key in the index.
+    fn key<'a>(&self, row: &'a [Value]) -> &'a Value {
+        &row[self.column]
+    }
+
+    // The rows with a version that has `key`, or had it.
+    fn rows<'a>(
+        &'a self,
+        rows: &'a BTreeMap<u32, Vec<RowVersion>>,
+        key: &Value,
+    ) -> impl Iterator<Item = (&'a u32, &'a Vec<RowVersion>)> {
+        self.entries
+            .get(key)
+            .into_iter()
+            .flatten()
+            .filter_map(move |id| rows.get_key_value(id))
+    }
+}
+
+impl Shard {
+    // Drop every version that was deleted by a transaction before `horizon`, which no
+    // snapshot can see any more, returning how many were dropped. Rows left without any
+    // versions are removed entirely.
+    fn vacuum(&mut self, horizon: usize) -> usize {
+        let mut removed = Vec::new();
+        self.rows.retain(|&id, versions| {
+            let (dead, live) = versions
+                .drain(..)
+                .partition(|row| matches!(row.deleted_by, Some(deleter) if deleter < horizon));
+            *versions = live;
+            removed.extend(dead.into_iter().map(|row: RowVersion| (id, row.values)));
+            !versions.is_empty()
+        });
+
+        for (id, row) in &removed {
+            self.unindex(*id, row);
+        }
+        removed.len()
+    }
+
+    // Write a new version of a row for the transaction `version`, or delete the row if
+    // `row` is `None`, returning whether that ended the row's previous version and
+    // whether it created a new one.
+    fn write(&mut self, version: usize, id: u32, row: Option<Row>) -> (bool, bool) {
+        let versions = self.rows.entry(id).or_default();
+
+        // Only the newest version can still be live.
+        let ended_previous = match versions.last_mut() {
+            Some(latest) if latest.deleted_by.is_none() => {
+                latest.deleted_by = Some(version);
+                true
+            }
+            _ => false,
+        };
+        let created = row.is_some();
+        if let Some(values) = row {
+            versions.push(RowVersion {
+                values,
+                created_by: version,
+                deleted_by: None,
+            });
+        }
+
+        if versions.is_empty() {
+            // There was nothing to delete.
+            self.rows.remove(&id);
+        } else if created {
+            self.index_latest(id);
+        }
+        (ended_previous, created)
+    }
+
+    // Row `id` as it was before transaction `version` wrote it, and as the transaction has
+    // left it, or `None` where it didn't exist.
+    fn change(&self, version: usize, id: u32) -> (Option<Row>, Option<Row>) {
+        let versions = self.rows.get(&id).map(Vec::as_slice).unwrap_or_default();
+        // The version it ended that someone else created, if it ended one.
+        let old = versions
+            .iter()
+            .find(|row| row.deleted_by == Some(version) && row.created_by != version);
+        let new = versions
+            .last()
+            .filter(|row| row.created_by == version && row.deleted_by.is_none());
+        (
+            old.map(|row| row.values.clone()),
+            new.map(|row| row.values.clone()),
+        )
+    }
+
+    // Undo a single write, which has to be the last one made to its row.
+    fn undo(&mut self, record: &UndoRecord) {
+        let Some(versions) = self.rows.get_mut(&record.id) else {
+            return;
+        };
+        let created = if record.created { versions.pop() } else { None };
+        if record.ended_previous {
+            if let Some(previous) = versions.last_mut() {
+                previous.deleted_by = None;
+            }
+        }
+        if versions.is_empty() {
+            self.rows.remove(&record.id);
+        }
+        if let Some(row) = created {
+            self.unindex(record.id, &row.values);
+        }
+    }
+
+    // The index with the given name. Panics if there is none.
+    fn index(&self, index: &str) -> &Index {
+        self.indexes
+            .get(index)
+            .unwrap_or_else(|| panic!("there is no index named {:?}", index))
+    }
+
+    // Add the newest version of a row to every index.
+    fn index_latest(&mut self, id: u32) {
+        let Some(row) = self.rows.get(&id).and_then(|versions| versions.last()) else {
+            return;
+        };
+        for index in self.indexes.values_mut() {
+            index
+                .entries
+                .entry(index.key(&row.values).clone())
+                .or_default()
+                .insert(id);
+        }
+    }
+
+    // Take a row out of the indexes for a version with the values `row` that has been
+    // removed, wherever none of the row's remaining versions has the same key.
+    fn unindex(&mut self, id: u32, row: &[Value]) {
+        let versions = self.rows.get(&id).map_or(&[][..], Vec::as_slice);
+        for index in self.indexes.values_mut() {
+            let key = index.key(row);
+            if versions.iter().any(|other| index.key(&other.values) == key) {
+                continue;
+            }
+            if let Some(ids) = index.entries.get_mut(key) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    index.entries.remove(key);
+                }
+            }
+        }
+    }
+}
+
+// Returned when a transaction can't do what it was asked to. Unless it names a missing
+// savepoint or table or a row that doesn't fit its table, or the transaction has been
+// prepared, the transaction has to roll back and try again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+    // The transaction wrote a row that a concurrent transaction had already written. The
+    // first transaction to write a row wins, or the other would silently overwrite a
+    // change it never saw.
+    WriteConflict {
+        // The table and row both transactions wrote.
+        table: String,
+        id: u32,
+        // The transaction that wrote it first.
+        version: usize,
+    },
+    // The transaction is serializable, and what it read and wrote alongside concurrent
+    // transactions could have had an outcome that no serial order of them would.
+    Serialization,
+    // The transaction ran for longer than the store allows, and has been aborted.
+    TimedOut,
+    // The transaction has already committed or rolled back.
+    Finished,
+    // The transaction has been prepared to commit, so it can't change anything more.
+    Prepared,
+    // The transaction has no savepoint with the given name to roll back to.
+    UnknownSavepoint(String),
+    // The store has no table with the given name.
+    UnknownTable(String),
+    // Another transaction still in progress has locked the row, and the store doesn't
+    // wait for locks.
+    WouldBlock {
+        // The table and row that are locked.
+        table: String,
+        id: u32,
+        // The transaction holding the lock.
+        version: usize,
+    },
+    // Waiting for the row would have left transactions waiting for each other forever.
+    Deadlock,
+    // The values the transaction tried to set a row to don't fit the table's schema.
+    InvalidRow {
+        table: String,
+        id: u32,
+        error: SchemaError,
+    },
+    // There is no reading the store as of the given version, since no transaction has
+    // had it yet.
+    FutureVersion(usize),
+    // There is no reading the store as of the given version any more, since vacuum has
+    // removed rows that were current then.
+    Vacuumed(usize),
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionError::WriteConflict { table, id, version } => write!(
+                f,
+                "row {} of {} was already written by concurrent transaction {}",
+                id, table, version
+            ),
+            TransactionError::Serialization => write!(
+                f,
+                "transaction can't be serialized with the transactions running alongside it"
+            ),
+            TransactionError::TimedOut => {
+                write!(f, "transaction ran for too long and has been aborted")
+            }
+            TransactionError::Finished => write!(f, "transaction has already finished"),
+            TransactionError::Prepared => {
+                write!(
+                    f,
+                    "transaction has been prepared to commit and can't change anything more"
+                )
+            }
+            TransactionError::UnknownSavepoint(name) => write!(f, "no savepoint named {:?}", name),
+            TransactionError::UnknownTable(table) => write!(f, "no table named {:?}", table),
+            TransactionError::WouldBlock { table, id, version } => write!(
+                f,
+                "row {} of {} is locked by concurrent transaction {}",
+                id, table, version
+            ),
+            TransactionError::Deadlock => write!(
+                f,
+                "waiting for the row would deadlock with the transactions it is waiting for"
+            ),
+            TransactionError::InvalidRow { table, id, error } => {
+                write!(
+                    f,
+                    "row {} of {} doesn't fit the table: {}",
+                    id, table, error
+                )
+            }
+            TransactionError::FutureVersion(version) => {
+                write!(f, "no transaction has had version {} yet", version)
+            }
+            TransactionError::Vacuumed(version) => write!(
+                f,
+                "rows as of version {} have since been removed by vacuum",
+                version
+            ),
+        }
+    }
+}
+
+impl Error for TransactionError {}
+
+// What a store is up to, as reported by `MVCC::stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    // How many transactions are running, not counting read-only ones.
+    pub active_transactions: usize,
+    // How many read-only transactions are running.
+    pub active_readers: usize,
+    // The oldest transaction whose writes some running transaction can't see, if any
+    // are running. Vacuum keeps every version deleted from then on.
+    pub oldest_snapshot: Option<usize>,
+    // How many row versions the tables hold in all.
+    pub versions: usize,
+    // How many of those no snapshot can see any more, which the next vacuum removes.
+    pub dead_versions: usize,
+    // How many transactions have committed since the store was created.
+    pub commits: usize,
+    // How many transactions have rolled back or been aborted since the store was created,
+    // including ones that failed to commit.
+    pub aborts: usize,
+}
+
+// What a transaction did, once it has committed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitInfo {
+    // The transaction's version number, which its writes are stamped with.
+    pub version: usize,
+    // How many writes it made to the store's tables.
+    pub writes: usize,
+}
+
+// A row a transaction changed, sent to subscribers once it has committed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    // The table and ID of the row.
+    pub table: String,
+    pub id: u32,
+    // The row before the transaction changed it, or `None` if it didn't exist.
+    pub old: Option<Row>,
+    // The row as the transaction left it, or `None` if it deleted it.
+    pub new: Option<Row>,
+    // The version the transaction committed as.
+    pub version: usize,
+}
+
+// One version of a row, as `MVCC::versions` reports it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    // The ID of the row.
+    pub id: u32,
+    pub row: Row,
+    // The transaction that wrote this version.
+    pub created_by: usize,
+    // The transaction that overwrote or deleted this version, if any.
+    pub deleted_by: Option<usize>,
+}
+
+// Returned when a transaction can't commit. Unless it had already finished, it has been
+// rolled back, and can be tried again from the start.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommitError {
+    // The transaction is serializable, and conflicted with concurrent transactions in a
+    // way that could have had an outcome no serial order of them would.
+    Serialization,
+    // The transaction is optimistic, and a transaction that committed while it was
+    // running wrote a row it read or wrote.
+    Validation {
+        // The table and row the other transaction wrote.
+        table: String,
+        id: u32,
+    },
+    // The transaction is optimistic, and wrote a row another transaction still in
+    // progress has locked.
+    Locked {
+        // The table and row that are locked.
+        table: String,
+        id: u32,
+    },
+    // The transaction ran for longer than the store allows, and has been aborted.
+    TimedOut,
+    // The transaction has already committed or rolled back.
+    Finished,
+}
+
+impl fmt::Display for CommitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommitError::Serialization => write!(
+                f,
+                "transaction can't be serialized with the transactions running alongside it"
+            ),
+            CommitError::Validation { table, id } => write!(
+                f,
+                "row {} of {} was changed by a transaction that committed after this one began",
+                id, table
+            ),
+            CommitError::Locked { table, id } => write!(
+                f,
+                "row {} of {} is locked by a transaction that is still in progress",
+                id, table
+            ),
+            CommitError::TimedOut => {
+                write!(f, "transaction ran for too long and has been aborted")
+            }
+            CommitError::Finished => write!(f, "transaction has already finished"),
+        }
+    }
+}
+
+impl Error for CommitError {}
+
+// Hands out version numbers to transactions and keeps track of which are still active.
+// Each MVCC instance has its own, so separate instances never see each other's
+// transactions.
+pub struct TransactionManager {
+    state: Mutex<ManagerState>,
+    // What the serializable transactions read and write.
+    ssi: SsiTracker,
+    // Each transaction waiting for a row, with the transaction it is waiting for.
+    waits: Mutex<HashMap<usize, usize>>,
+    // Signalled whenever a transaction finishes, for the ones waiting for its rows. Waited
+    // on with `waits`.
+    released: Condvar,
+}
+
+struct ManagerState {
+    // The version number the next transaction will get.
+    next_version: usize,
+    // The currently active transactions, by ID.
+    active: HashMap<usize, ActiveTransaction>,
+    // The ID the next read-only transaction will get. They don't have version numbers,
+    // since they never write anything.
+    next_reader: usize,
+    // The currently active read-only transactions, each with the oldest transaction whose
+    // writes it can't see.
+    readers: HashMap<usize, usize>,
+    // How long a transaction may run before it is aborted, if there is a limit.
+    max_age: Option<Duration>,
+    // The horizon vacuum has gone up to. Versions deleted by transactions before it may
+    // be gone.
+    vacuumed: usize,
+    // How many transactions have committed, and how many have rolled back or been aborted.
+    commits: usize,
+    aborts: usize,
+}
+
+struct ActiveTransaction {
+    // The oldest transaction whose writes this one can't see.
+    oldest_unseen: usize,
+    // When the transaction began.
+    started: Instant,
+    // The transaction's writes, so that it can be rolled back if it runs for too long.
+    undo_log: Arc<Mutex<Vec<UndoRecord>>>,
+    // Whether the transaction has been prepared to commit, which keeps it from being
+    // aborted for running too long.
+    prepared: bool,
+}
+
+impl TransactionManager {
+    // Create a manager with no transactions yet.
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(ManagerState {
+                next_version: 1,
+                active: HashMap::new(),
+                next_reader: 1,
+                readers: HashMap::new(),
+                max_age: None,
+                vacuumed: 0,
+                commits: 0,
+                aborts: 0,
+            }),
+            ssi: SsiTracker::default(),
+            waits: Mutex::new(HashMap::new()),
+            released: Condvar::new(),
+        }
+    }
+
+    // Register a new transaction, which records its writes in `undo_log`, returning its
+    // version number along with its snapshot. Both are taken under the same lock, so a
+    // transaction can never miss one that got an earlier version but hadn't been
+    // registered yet.
+    fn begin(&self, undo_log: Arc<Mutex<Vec<UndoRecord>>>) -> (usize, Snapshot) {
+        let mut state = self.state.lock().unwrap();
+        let version = state.next_version;
+        state.next_version += 1;
+
+        let active_xids: HashSet<usize> = state.active.keys().copied().collect();
+        let oldest_unseen = active_xids.iter().copied().min().unwrap_or(version);
+        state.active.insert(
+            version,
+            ActiveTransaction {
+                oldest_unseen,
+                started: Instant::now(),
+                undo_log,
+                prepared: false,
+            },
+        );
+        (
+            version,
+            Snapshot {
+                xmax: version,
+                active_xids,
+                own: Some(version),
+            },
+        )
+    }
+
+    // Register a new read-only transaction, returning its ID along with its snapshot,
+    // which sees every transaction that has committed so far. Writers don't need to know
+    // about it, so it is left out of their snapshots.
+    fn begin_read_only(&self) -> (usize, Snapshot) {
+        let mut state = self.state.lock().unwrap();
+        let reader = state.next_reader;
+        state.next_reader += 1;
+
+        let xmax = state.next_version;
+        let active_xids: HashSet<usize> = state.active.keys().copied().collect();
+        let oldest_unseen = active_xids.iter().copied().min().unwrap_or(xmax);
+        state.readers.insert(reader, oldest_unseen);
+        (
+            reader,
+            Snapshot {
+                xmax,
+                active_xids,
+                own: None,
+            },
+        )
+    }
+
+    // Register a new read-only transaction that sees the store as of `version`, returning
+    // its ID along with its snapshot, which sees every transaction up to and including
+    // `version` that has committed so far. Fails if `version` hasn't been handed out yet,
+    // since a transaction that gets it later would turn up in the snapshot, or if vacuum
+    // has removed versions the snapshot would see.
+    fn begin_at(&self, version: usize) -> Result<(usize, Snapshot), TransactionError> {
+        let mut state = self.state.lock().unwrap();
+        if version >= state.next_version {
+            return Err(TransactionError::FutureVersion(version));
+        }
+        // Vacuum keeps every version deleted by a transaction at or after its horizon.
+        if state.vacuumed > version + 1 {
+            return Err(TransactionError::Vacuumed(version));
+        }
+        let reader = state.next_reader;
+        state.next_reader += 1;
+
+        let xmax = version + 1;
+        let active_xids: HashSet<usize> = state.active.keys().copied().collect();
+        let oldest_unseen = active_xids.iter().copied().min().unwrap_or(xmax).min(xmax);
+        state.readers.insert(reader, oldest_unseen);
+        Ok((
+            reader,
+            Snapshot {
+                xmax,
+                active_xids,
+                own: None,
+            },
+        ))
+    }
+
+    // A new snapshot for the active transaction `version`, which sees every transaction
+    // that has committed so far, for a read committed transaction's next statement.
+    // Vacuum only has to keep what the new one sees from then on.
+    fn refresh(&self, version: usize) -> Snapshot {
+        let mut state = self.state.lock().unwrap();
+        let xmax = state.next_version;
+        let active_xids: HashSet<usize> = state.active.keys().copied().collect();
+        // Never past the transaction itself, whose writes other snapshots may not see yet.
+        let oldest_unseen = active_xids.iter().copied().min().unwrap_or(version);
+        if let Some(active) = state.active.get_mut(&version) {
+            active.oldest_unseen = oldest_unseen;
+        }
+        Snapshot {
+            xmax,
+            active_xids,
+            own: Some(version),
+        }
+    }
+
+    // Whether a transaction is still active, and hasn't committed, rolled back or been
+    // aborted.
+    fn is_active(&self, version: usize) -> bool {
+        self.state.lock().unwrap().active.contains_key(&version)
+    }
+
+    // The active transactions that have run for longer than the maximum age, with their
+    // undo logs.
+    fn expired(&self) -> Vec<(usize, Arc<Mutex<Vec<UndoRecord>>>)> {
+        let state = self.state.lock().unwrap();
+        let Some(max_age) = state.max_age else {
+            return Vec::new();
+        };
+        state
+            .active
+            .iter()
+            .filter(|(_, active)| !active.prepared && active.started.elapsed() > max_age)
+            .map(|(&version, active)| (version, active.undo_log.clone()))
+            .collect()
+    }
+
+    // Note that a transaction has been prepared to commit.
+    fn prepare(&self, version: usize) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(active) = state.active.get_mut(&version) {
+            active.prepared = true;
+        }
+    }
+
+    // Remove a committed or rolled back transaction from the active set, and wake up the
+    // transactions that may be waiting for it.
+    fn finish(&self, version: usize, committed: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.active.remove(&version);
+        if committed {
+            state.commits += 1;
+        } else {
+            state.aborts += 1;
+        }
+        drop(state);
+        self.ssi.prune(self.horizon());
+        // Under the lock, so that no transaction can miss it between finding this one
+        // holds the row it wants and starting to wait.
+        let _waits = self.waits.lock().unwrap();
+        self.released.notify_all();
+    }
+
+    // Remove a finished read-only transaction.
+    fn finish_read_only(&self, reader: usize) {
+        self.state.lock().unwrap().readers.remove(&reader);
+    }
+
+    // The oldest transaction whose writes some active transaction can't see. Every
+    // transaction before it has finished, and is seen by all snapshots there are now or
+    // will be from here on.
+    fn horizon(&self) -> usize {
+        self.state.lock().unwrap().horizon()
+    }
+
+    // The horizon to vacuum up to, noted so that `begin_at` knows which versions may be
+    // gone.
+    fn vacuum_horizon(&self) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let horizon = state.horizon();
+        state.vacuumed = state.vacuumed.max(horizon);
+        horizon
+    }
+}
+
+impl ManagerState {
+    // See `TransactionManager::horizon`.
+    fn horizon(&self) -> usize {
+        self.active
+            .values()
+            .map(|active| &active.oldest_unseen)
+            .chain(self.readers.values())
+            .copied()
+            .min()
+            .unwrap_or(self.next_version)
+    }
+}
+
+impl Default for TransactionManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// What a single write did to a row's version chain, so that rollback can undo it.
+struct UndoRecord {
+    table: String,
+    id: u32,
+    // Whether the write marked the row's previous version as deleted.
+    ended_previous: bool,
+    // Whether the write added a new version to the end of the chain.
+    created: bool,
+}
+
+// Append a record to the write-ahead log, if there is one.
+fn log(wal: &Option<Arc<Wal>>, record: WalRecord) {
+    if let Some(wal) = wal {
+        // A store that can't write to its log can no longer promise that what it commits
+        // will survive a restart, so it doesn't carry on as if it could.
+        wal.append(&record)
+            .expect("failed to write to the write-ahead log");
+    }
+}
+
+// Which transactions' writes a transaction can see: its own, and those of transactions
+// that began before it and had finished by the time it began. Rolled-back transactions
+// leave no versions behind, so any such transaction has committed.
+struct Snapshot {
+    // The first transaction that began after this snapshot was taken.
+    xmax: usize,
+    // The transactions that were still active when the snapshot was taken.
+    active_xids: HashSet<usize>,
+    // The transaction the snapshot belongs to, unless it is read-only.
+    own: Option<usize>,
+}
+
+impl Snapshot {
+    // Determine whether the writes of the transaction with the given version are part of
+    // the snapshot. A transaction's own writes always are, though they aren't part of any
+    // other snapshot until it has committed.
+    fn is_visible(&self, version: usize) -> bool {
+        if Some(version) == self.own {
+            return true;
+        }
+        version < self.xmax && !self.active_xids.contains(&version)
+    }
+
+    // The rows the snapshot sees among `rows`, with their values.
+    fn rows<'a>(
+        &self,
+        rows: impl Iterator<Item = (&'a u32, &'a Vec<RowVersion>)>,
+    ) -> Vec<(u32, Row)> {
+        rows.filter_map(|(&id, versions)| Some((id, self.find(versions)?.values.clone())))
+            .collect()
+    }
+
+    // The rows in `shard` the snapshot sees whose key in `index` is `key`, with their
+    // values. The index may have a row under the key for a version the snapshot doesn't
+    // see, so each one is checked again.
+    fn indexed(&self, shard: &Shard, index: &str, key: &Value) -> Vec<(u32, Row)> {
+        let index = shard.index(index);
+        self.rows(index.rows(&shard.rows, key))
+            .into_iter()
+            .filter(|(_, row)| index.key(row) == key)
+            .collect()
+    }
+
+    // Find the version of a row the snapshot sees. The newest version whose writer is
+    // visible is the one, unless a visible transaction has since deleted it. Writes made
+    // after the snapshot was taken are ignored, even once they commit.
+    fn find<'a>(&self, versions: &'a [RowVersion]) -> Option<&'a RowVersion> {
+        let row = versions
+            .iter()
+            .rev()
+            .find(|row| self.is_visible(row.created_by))?;
+        match row.deleted_by {
+            Some(deleter) if self.is_visible(deleter) => None,
+            _ => Some(row),
+        }
+    }
+}
+
+// How far a transaction is kept from seeing what the transactions running alongside it
+// do. Each transaction gets one when it begins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IsolationLevel {
+    // Every read sees whatever has committed by the time it is made, so reading the same
+    // row twice can give two different answers. Writing a row another transaction has
+    // committed since this one began isn't a conflict, only writing one it hasn't
+    // committed yet is.
+    ReadCommitted,
+    // Every read sees the snapshot taken when the transaction began, and writing a row
+    // any transaction outside it has written is a conflict. Anomalies like write skew are
+    // still possible.
+    #[default]
+    SnapshotIsolation,
+    // Snapshot isolation, and on top of that the transaction is aborted when what it reads
+    // and writes alongside other serializable transactions could have an outcome that no
+    // serial order of them would. Transactions at the other levels aren't tracked, so they
+    // may still see or cause such anomalies.
+    Serializable,
+}
+
+// Definition of an MVCC (Multi-Version Concurrency Control) transaction.
+pub struct MVCC {
+    database: Arc<RwLock<Database>>,
+    transactions: Arc<TransactionManager>,
+    // Where transactions record what they do, if anywhere.
+    wal: Option<Arc<Wal>>,
+    // The isolation level of transactions that don't ask for one.
+    isolation: IsolationLevel,
+    // Whether transactions hold on to their writes until they commit.
+    optimistic: bool,
+    // Whether a transaction that wants a row another one holds waits for it to finish.
+    wait_for_locks: bool,
+}
+
+impl Default for MVCC {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MVCC {
+    // Constructor for creating a new MVCC instance, with no tables yet.
+    pub fn new() -> Self {
+        Self {
+            database: Arc::new(RwLock::new(Database {
+                tables: BTreeMap::new(),
+                shards: DEFAULT_SHARDS,
+                subscribers: Vec::new(),
+            })),
+            transactions: Arc::new(TransactionManager::new()),
+            wal: None,
+            isolation: IsolationLevel::default(),
+            optimistic: false,
+            wait_for_locks: false,
+        }
+    }
+
+    // Split the tables created from now on into `shards` shards, rather than the default
+    // of 16. Transactions reading and writing single rows only hold up the others working
+    // in the same shard, while scans and index lookups go through every shard.
+    //
+    // Panics if `shards` is zero.
+    pub fn with_shards(self, shards: usize) -> Self {
+        assert!(shards > 0, "a table needs at least one shard");
+        self.database.write().unwrap().shards = shards;
+        self
+    }
+
+    // Record every transaction's begin, writes, and commit or rollback in a write-ahead
+    // log, from which the committed state can be rebuilt after a restart.
+    pub fn with_wal(mut self, wal: Wal) -> Self {
+        self.wal = Some(Arc::new(wal));
+        self
+    }
+
+    // Rebuild the store from the write-ahead log at `path`, as an earlier run left it, so
+    // that it carries on from the last transaction that committed. Transactions that
+    // rolled back, or hadn't committed by the time that run stopped, are left out, as are
+    // writes undone by rolling back to a savepoint. A log that doesn't exist yet is taken
+    // to be empty.
+    //
+    // Neither tables nor indexes are logged, so every table written to in the log has to
+    // be created again, with the same schema, first; the log is rejected as invalid if it
+    // writes to one that doesn't exist, or a row that doesn't fit. Indexes are best
+    // created afterwards, so they index the recovered rows in one go. To carry on logging
+    // to the same file, open it and pass it to `with_wal`.
+    pub fn recover(self, path: impl AsRef<Path>) -> io::Result<Self> {
+        let records = match Wal::read(path) {
+            Ok(records) => records,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(self),
+            Err(err) => return Err(err),
+        };
+        let last_version = records.iter().map(WalRecord::version).max();
+
+        {
+            let mut database = self.database.write().unwrap();
+            for transaction in wal::committed(records) {
+                for (table, id, row) in transaction.writes {
+                    database
+                        .check_write(&table, id, row.as_ref())
+                        .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+                    database
+                        .tables
+                        .get_mut(&table)
+                        .unwrap()
+                        .shard_mut(id)
+                        .write(transaction.version, id, row);
+                }
+            }
+        }
+
+        // No version in the log is handed out again, not even one whose transaction never
+        // committed, or a later recovery would mix up its records with a new transaction's.
+        if let Some(last_version) = last_version {
+            let mut state = self.transactions.state.lock().unwrap();
+            state.next_version = state.next_version.max(last_version + 1);
+        }
+        Ok(self)
+    }
+
+    // Abort transactions that have been running for longer than `max_age`, next time the
+    // store is vacuumed or `abort_expired` is called, so that one that has been forgotten
+    // about can't hold up vacuum or keep others from writing its rows forever. Read-only
+    // transactions are never aborted, since they have no way of finding out, and nor are
+    // prepared ones, which have promised they can commit.
+    pub fn with_max_transaction_age(self, max_age: Duration) -> Self {
+        self.transactions.state.lock().unwrap().max_age = Some(max_age);
+        self
+    }
+
+    // Make transactions serializable unless they ask otherwise: on top of the conflicts
+    // between their writes, a transaction is also aborted when what it reads and writes
+    // alongside concurrent transactions could lead to anomalies like write skew, which
+    // snapshots alone allow.
+    pub fn serializable(mut self) -> Self {
+        self.isolation = IsolationLevel::Serializable;
+        self
+    }
+
+    // Make transactions optimistic: instead of writing to the tables straight away, a
+    // transaction keeps its writes to itself until it commits. Committing checks that no
+    // transaction that committed in the meantime wrote any row it read or wrote, and only
+    // then applies its writes, or fails with `CommitError::Validation` if one did.
+    // Nothing a transaction does holds up the others until it commits, at the cost of
+    // finding out about conflicts only then.
+    //
+    // Since everything an optimistic transaction read is still current as it commits, it
+    // is serializable without the tracking `serializable` does, whether it asked for
+    // snapshot isolation or to be serializable. A read committed one only has its writes
+    // checked.
+    pub fn optimistic(mut self) -> Self {
+        self.optimistic = true;
+        self
+    }
+
+    // Make a transaction that wants to write or lock a row another one has locked, or
+    // written without committing yet, wait for that one to finish rather than failing
+    // straight away. If the other one commits, the row has changed under this one's
+    // snapshot and it gets a `WriteConflict` then; if it rolls back, this one carries on.
+    // A transaction that would end up waiting for itself through the others fails with
+    // `TransactionError::Deadlock` instead.
+    pub fn wait_for_locks(mut self) -> Self {
+        self.wait_for_locks = true;
+        self
+    }
+
+    // Create an empty table called `table`, which transactions can then read and write
+    // by name. Besides its ID, every row has a value for each of the columns in `schema`,
+    // which writes are checked against. Every table shares the store's transactions, so
+    // one transaction can write to several and have its writes to all of them commit or
+    // roll back together. Creating a table that already exists leaves it as it is, schema
+    // and all.
+    pub fn create_table(&self, table: &str, schema: Schema) {
+        let mut database = self.database.write().unwrap();
+        let shards = database.shards;
+        database
+            .tables
+            .entry(table.to_string())
+            .or_insert_with(|| Table::new(schema, shards));
+    }
+
+    // The schema of `table`, if there is such a table.
+    pub fn schema(&self, table: &str) -> Option<Schema> {
+        let database = self.database.read().unwrap();
+        database.tables.get(table).map(|table| table.schema.clone())
+    }
+
+    // Declare a secondary index called `index` on `table`, which finds rows by their
+    // value in `column`. Rows already in the table are indexed straight away, and writes
+    // keep the index up to date from then on. Declaring an index with the name of an
+    // existing one replaces it.
+    //
+    // Panics if there is no such table, or it has no such column.
+    pub fn create_index(&self, table: &str, index: &str, column: &str) {
+        let mut database = self.database.write().unwrap();
+        let table = database
+            .tables
+            .get_mut(table)
+            .unwrap_or_else(|| panic!("there is no table named {:?}", table));
+        let column = table
+            .schema
+            .position(column)
+            .unwrap_or_else(|| panic!("there is no column named {:?}", column));
+        for shard in &mut table.shards {
+            let shard = shard.get_mut().unwrap();
+            let mut entries: BTreeMap<Value, BTreeSet<u32>> = BTreeMap::new();
+            for (&id, versions) in &shard.rows {
+                for row in versions {
+                    entries
+                        .entry(row.values[column].clone())
+                        .or_default()
+                        .insert(id);
+                }
+            }
+            shard
+                .indexes
+                .insert(index.to_string(), Index { column, entries });
+        }
+    }
+
+    // Every version of every row of `table`, in order of ID and oldest first, including
+    // the ones that have been overwritten or deleted, whoever wrote them. Meant for seeing
+    // how the store keeps its rows rather than for reading them, which is what
+    // transactions are for.
+    //
+    // Panics if there is no such table.
+    pub fn versions(&self, table: &str) -> Vec<VersionInfo> {
+        let database = self.database.read().unwrap();
+        let shards = database.table(table).lock_all();
+        let mut rows: Vec<_> = shards.iter().flat_map(|shard| &shard.rows).collect();
+        rows.sort_unstable_by_key(|(id, _)| **id);
+        rows.into_iter()
+            .flat_map(|(&id, versions)| {
+                versions.iter().map(move |version| VersionInfo {
+                    id,
+                    row: version.values.clone(),
+                    created_by: version.created_by,
+                    deleted_by: version.deleted_by,
+                })
+            })
+            .collect()
+    }
+
+    // Subscribe to the changes transactions commit from now on, so that another system can
+    // keep a copy of the store up to date. Every transaction that commits sends one
+    // `Change` for each row it changed, and the changes arrive in the order the
+    // transactions committed. Dropping the receiver unsubscribes.
+    pub fn subscribe(&self) -> Receiver<Change> {
+        let (sender, receiver) = mpsc::channel();
+        self.database.write().unwrap().subscribers.push(sender);
+        receiver
+    }
+
+    // Begin a new transaction, at the store's isolation level.
+    pub fn begin_transaction(&self) -> Transaction {
+        Transaction::begin(self)
+    }
+
+    // Begin a new transaction at the given isolation level, whatever the store's is.
+    pub fn begin_with_isolation(&self, isolation: IsolationLevel) -> Transaction {
+        Transaction::begin_with_isolation(self, isolation)
+    }
+
+    // Begin a transaction that only reads. It sees a snapshot like any other, but has
+    // nothing to undo or log, and writers don't have to keep it out of their snapshots.
+    // It ends when it is dropped.
+    //
+    // Its reads aren't tracked by a serializable store either, so while everything it
+    // sees was committed together, that isn't always a state some serial order of the
+    // writers would have gone through.
+    pub fn begin_read_only(&self) -> ReadOnlyTransaction {
+        let (reader, snapshot) = self.transactions.begin_read_only();
+        ReadOnlyTransaction {
+            database: self.database.clone(),
+            transactions: self.transactions.clone(),
+            reader,
+            snapshot,
+        }
+    }
+
+    // Begin a read-only transaction that sees the store as it was at an earlier version,
+    // like the one `commit` returns: with the writes of every transaction up to and
+    // including that version that has committed, and none after it. Vacuum keeps what it
+    // sees for as long as it runs, just like any other snapshot.
+    //
+    // Fails with `TransactionError::FutureVersion` if no transaction has had the version
+    // yet, or `TransactionError::Vacuumed` if vacuum has already removed rows it would see.
+    pub fn begin_at(&self, version: usize) -> Result<ReadOnlyTransaction, TransactionError> {
+        let (reader, snapshot) = self.transactions.begin_at(version)?;
+        Ok(ReadOnlyTransaction {
+            database: self.database.clone(),
+            transactions: self.transactions.clone(),
+            reader,
+            snapshot,
+        })
+    }
+
+    // Roll back every transaction that has been running for longer than the store's
+    // maximum transaction age, returning how many there were. Anything they try to do
+    // from then on fails with `TransactionError::TimedOut`.
+    pub fn abort_expired(&self) -> usize {
+        abort_expired(&self.database, &self.transactions, &self.wal)
+    }
+
+    // Report how many transactions are running and have finished, and how many row
+    // versions the store holds, for monitoring. Every table is locked in turn to count
+    // its versions, so this is best called every so often rather than constantly.
+    pub fn stats(&self) -> Stats {
+        let database = self.database.read().unwrap();
+        let state = self.transactions.state.lock().unwrap();
+        let oldest_snapshot = state
+            .active
+            .values()
+            .map(|active| active.oldest_unseen)
+            .chain(state.readers.values().copied())
+            .min();
+        let horizon = state.horizon();
+        let mut stats = Stats {
+            active_transactions: state.active.len(),
+            active_readers: state.readers.len(),
+            oldest_snapshot,
+            versions: 0,
+            dead_versions: 0,
+            commits: state.commits,
+            aborts: state.aborts,
+        };
+        drop(state);
+
+        for table in database.tables.values() {
+            for shard in &table.shards {
+                for row in shard.lock().unwrap().rows.values().flatten() {
+                    stats.versions += 1;
+                    // The same versions `Shard::vacuum` removes.
+                    if matches!(row.deleted_by, Some(deleter) if deleter < horizon) {
+                        stats.dead_versions += 1;
+                    }
+                }
+            }
+        }
+        stats
+    }
+
+    // Remove the row versions that no transaction can see any more, so that a store
+    // which is written to for a long time doesn't keep every version it ever had.
+    // Transactions that have run for too long are aborted first. Returns how many
+    // versions were removed.
+    pub fn vacuum(&self) -> usize {
+        vacuum(&self.database, &self.transactions, &self.wal)
+    }
+
+    // Run `vacuum` on a background thread every `interval`, until the returned handle
+    // is dropped.
+    pub fn vacuum_every(&self, interval: Duration) -> Vacuum {
+        let (stop, stopped) = mpsc::channel();
+        let database = self.database.clone();
+        let transactions = self.transactions.clone();
+        let wal = self.wal.clone();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                vacuum(&database, &transactions, &wal);
+            }
+        });
+
+        Vacuum {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+fn abort_expired(
+    database: &RwLock<Database>,
+    transactions: &TransactionManager,
+    wal: &Option<Arc<Wal>>,
+) -> usize {
+    // The transactions check they are still active under the store lock before doing
+    // anything, so they either finish before this or find they have been aborted.
+    let mut database = database.write().unwrap();
+    let expired = transactions.expired();
+    for (version, undo_log) in &expired {
+        database.undo(undo_log.lock().unwrap().drain(..).collect());
+        database.release(*version);
+        log(wal, WalRecord::Abort { version: *version });
+        transactions.ssi.forget(*version);
+        transactions.finish(*version, false);
+    }
+    expired.len()
+}
+
+fn vacuum(
+    database: &RwLock<Database>,
+    transactions: &TransactionManager,
+    wal: &Option<Arc<Wal>>,
+) -> usize {
+    abort_expired(database, transactions, wal);
+
+    // Taken before locking the store. Transactions that begin in the meantime can only
+    // move the horizon forward, so it is still safe to prune up to.
+    let horizon = transactions.vacuum_horizon();
+    database.read().unwrap().vacuum(horizon)
+}
+
+// A background vacuum started with `MVCC::vacuum_every`, which stops when dropped.
+pub struct Vacuum {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Vacuum {
+    fn drop(&mut self) {
+        // Dropping the sender wakes the thread up and tells it to stop.
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+// Representation of an MVCC transaction.
+pub struct Transaction {
+    // The underlying tables.
+    database: Arc<RwLock<Database>>,
+    // The manager of the MVCC instance the transaction belongs to.
+    transactions: Arc<TransactionManager>,
+    // The MVCC instance's write-ahead log, if it has one.
+    wal: Option<Arc<Wal>>,
+    // The version number assigned to this transaction.
+    version: usize,
+    // The writes the transaction can see. A read committed transaction takes a new one
+    // for each statement.
+    snapshot: Mutex<Snapshot>,
+    // How isolated the transaction is from the others.
+    isolation: IsolationLevel,
+    // Whether the transaction's reads and writes are tracked to keep it serializable.
+    serializable: bool,
+    // Whether the transaction holds on to its writes until it commits.
+    optimistic: bool,
+    // Whether the transaction waits for rows other transactions hold.
+    wait_for_locks: bool,
+    // Every write made by the transaction, oldest first. The manager has it too, so
+    // that it can roll the transaction back if it runs for too long.
+    undo_log: Arc<Mutex<Vec<UndoRecord>>>,
+    // The writes an optimistic transaction has yet to apply, oldest first, each with
+    // the table it is to.
+    buffered: Mutex<Vec<RowWrite>>,
+    // The ranges of rows an optimistic transaction has read, single rows included, each
+    // with the table they are in, which have to be unchanged when it commits.
+    reads: Mutex<Vec<(String, IdRange)>>,
+    // The transaction's savepoints, oldest first, each with how many writes it had made
+    // when it was made.
+    savepoints: Mutex<Vec<(String, usize)>>,
+    // Whether the transaction has committed or rolled back.
+    finished: AtomicBool,
+    // Whether the transaction has been prepared to commit, and can't write any more.
+    prepared: AtomicBool,
+}
+
+impl Transaction {
+    // Start a new transaction, at the store's isolation level.
+    pub fn begin(mvcc: &MVCC) -> Self {
+        Self::begin_with_isolation(mvcc, mvcc.isolation)
+    }
+
+    // Start a new transaction at the given isolation level.
+    pub fn begin_with_isolation(mvcc: &MVCC, isolation: IsolationLevel) -> Self {
+        // Obtain a version number for the transaction, and the IDs of the transactions
+        // that are still active and so are left out of its snapshot.
+        let undo_log = Arc::new(Mutex::new(Vec::new()));
+        let (version, snapshot) = mvcc.transactions.begin(undo_log.clone());
+        let serializable = isolation == IsolationLevel::Serializable && !mvcc.optimistic;
+        if serializable {
+            mvcc.transactions.ssi.register(version);
+        }
+
+        let transaction = Self {
+            database: mvcc.database.clone(),
+            transactions: mvcc.transactions.clone(),
+            wal: mvcc.wal.clone(),
+            version,
+            snapshot: Mutex::new(snapshot),
+            isolation,
+            serializable,
+            optimistic: mvcc.optimistic,
+            wait_for_locks: mvcc.wait_for_locks,
+            undo_log,
+            buffered: Mutex::new(Vec::new()),
+            reads: Mutex::new(Vec::new()),
+            savepoints: Mutex::new(Vec::new()),
+            finished: AtomicBool::new(false),
+            prepared: AtomicBool::new(false),
+        };
+        transaction.log(WalRecord::Begin { version });
+
+        // Return the initialized transaction.
+        transaction
+    }
+
+    // Write data to a table within the scope of the transaction. Fails with
+    // `TransactionError::InvalidRow` if `row` doesn't have a value of the right type for
+    // each of the table's columns.
+    pub fn set(&self, table: &str, id: u32, row: Row) -> Result<(), TransactionError> {
+        self.write(table, id, Some(row))
+    }
+
+    // Delete data from a table within the scope of the transaction.
+    pub fn delete(&self, table: &str, id: u32) -> Result<(), TransactionError> {
+        self.write(table, id, None)
+    }
+
+    // Internal method to perform write operations. An optimistic transaction only takes
+    // note of the write, to apply when it commits.
+    fn write(&self, table: &str, id: u32, row: Option<Row>) -> Result<(), TransactionError> {
+        let database = self.database.read().unwrap();
+        self.check_writable()?;
+        database.check_write(table, id, row.as_ref())?;
+        if self.optimistic {
+            self.buffered
+                .lock()
+                .unwrap()
+                .push((table.to_string(), id, row));
+            return Ok(());
+        }
+        // Tables never change their schemas, so the row still fits once the shard is locked.
+        drop(database);
+
+        self.with_row(table, id, |shard| {
+            // Checked under the shard's lock, so that any transaction reading the row
+            // either does so before this write and is found here, or finds this write itself.
+            if self.serializable
+                && !self
+                    .transactions
+                    .ssi
+                    .write(self.version, table, id, |reader| !self.is_visible(reader))
+            {
+                return Err(TransactionError::Serialization);
+            }
+
+            self.apply(shard, table, id, row, &mut self.undo_log.lock().unwrap())
+        })
+    }
+
+    // Write many rows of `table` at once: set each to its values, or delete it where they
+    // are `None`. The shards the rows are in are locked once for the whole batch, and
+    // every row is checked before any is written, so either the whole batch is written or,
+    // if any row can't be, none of it is. A row written more than once ends up as the
+    // last write leaves it.
+    pub fn write_batch(
+        &self,
+        table: &str,
+        writes: Vec<(u32, Option<Row>)>,
+    ) -> Result<(), TransactionError> {
+        let database = self.database.read().unwrap();
+        self.check_writable()?;
+        for (id, row) in &writes {
+            database.check_write(table, *id, row.as_ref())?;
+        }
+        if self.optimistic {
+            let writes = writes
+                .into_iter()
+                .map(|(id, row)| (table.to_string(), id, row));
+            self.buffered.lock().unwrap().extend(writes);
+            return Ok(());
+        }
+        drop(database);
+
+        let ids: Vec<u32> = writes.iter().map(|(id, _)| *id).collect();
+        self.with_rows(table, &ids, |shards| {
+            for &id in &ids {
+                if let Some(versions) = shards.get(id).rows.get(&id) {
+                    self.check_conflict(table, id, versions)?;
+                }
+            }
+            // As for a single write, checked with the shards locked.
+            if self.serializable {
+                for &id in &ids {
+                    let reader_unseen = |reader| !self.is_visible(reader);
+                    if !self
+                        .transactions
+                        .ssi
+                        .write(self.version, table, id, reader_unseen)
+                    {
+                        return Err(TransactionError::Serialization);
+                    }
+                }
+            }
+
+            let mut undo_log = self.undo_log.lock().unwrap();
+            for (id, row) in writes {
+                self.apply(shards.get(id), table, id, row, &mut undo_log)
+                    .expect("checked writes don't conflict");
+            }
+            Ok(())
+        })
+    }
+
+    // Apply a write to a table, noting how to undo it in `undo_log`. Nothing is
+    // overwritten in place: the row's current version is marked as deleted by this
+    // transaction, and a set adds a new version on top, so older versions stay readable
+    // by the transactions that can see them.
+    fn apply(
+        &self,
+        shard: &mut Shard,
+        table: &str,
+        id: u32,
+        row: Option<Row>,
+        undo_log: &mut Vec<UndoRecord>,
+    ) -> Result<(), TransactionError> {
+        match shard.rows.get(&id) {
+            Some(versions) => self.check_conflict(table, id, versions)?,
+            // There is nothing to delete.
+            None if row.is_none() => return Ok(()),
+            None => {}
+        }
+
+        // Logged ahead of the change itself, now that it is known to go ahead.
+        self.log(WalRecord::Write {
+            version: self.version,
+            table: table.to_string(),
+            id,
+            row: row.clone(),
+        });
+
+        let (ended_previous, created) = shard.write(self.version, id, row);
+        if ended_previous || created {
+            undo_log.push(UndoRecord {
+                table: table.to_string(),
+                id,
+                ended_previous,
+                created,
+            });
+        }
+        Ok(())
+    }
+
+    // Whether the store has a table called `table`.
+    pub fn has_table(&self, table: &str) -> bool {
+        self.database.read().unwrap().tables.contains_key(table)
+    }
+
+    // The schema of `table`, if the store has such a table.
+    pub fn schema(&self, table: &str) -> Option<Schema> {
+        let database = self.database.read().unwrap();
+        database.tables.get(table).map(|table| table.schema.clone())
+    }
+
+    // Fail with a write conflict if the newest of a row's versions was written by a
+    // transaction outside this one's snapshot, either one still in progress or one that
+    // committed after the snapshot. A store that waits for locks has already waited for
+    // one still in progress to finish by now.
+    fn check_conflict(
+        &self,
+        table: &str,
+        id: u32,
+        versions: &[RowVersion],
+    ) -> Result<(), TransactionError> {
+        let Some(latest) = versions.last() else {
+            return Ok(());
+        };
+        let writers = [Some(latest.created_by), latest.deleted_by];
+        match writers.into_iter().flatten().find(|v| !self.is_visible(*v)) {
+            Some(version) => Err(TransactionError::WriteConflict {
+                table: table.to_string(),
+                id,
+                version,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // Run `f` on the shard row `id` of `table` is in, once no other transaction has the
+    // row locked, or has written it without committing yet, so that this one can write or
+    // lock it. Unless the store waits for locks, a locked row fails with `WouldBlock`
+    // straight away, and an uncommitted write is left for `check_conflict` to find.
+    fn with_row<T>(
+        &self,
+        table: &str,
+        id: u32,
+        f: impl FnOnce(&mut Shard) -> Result<T, TransactionError>,
+    ) -> Result<T, TransactionError> {
+        self.with_rows(table, &[id], |shards| f(shards.get(id)))
+    }
+
+    // Like `with_row`, for every one of the rows `ids` at once. Their shards are locked
+    // together, and `f` only runs once none of the rows is held by another transaction.
+    fn with_rows<T>(
+        &self,
+        table: &str,
+        ids: &[u32],
+        f: impl FnOnce(&mut LockedShards) -> Result<T, TransactionError>,
+    ) -> Result<T, TransactionError> {
+        loop {
+            let database = self.database.read().unwrap();
+            self.check_writable()?;
+            // Again after waiting, so a read committed transaction sees what it waited for.
+            self.refresh_snapshot();
+            let mut shards = database.try_table(table)?.lock_rows(ids);
+            let held = ids
+                .iter()
+                .find_map(|&id| Some((id, self.holder(shards.get(id), id)?)));
+            let Some((id, holder)) = held else {
+                return f(&mut shards);
+            };
+            if !self.wait_for_locks {
+                return Err(TransactionError::WouldBlock {
+                    table: table.to_string(),
+                    id,
+                    version: holder,
+                });
+            }
+
+            // Taken before letting go of the store, which the holder needs to finish, so
+            // that it can't signal that it has before this is waiting.
+            let waits = self.transactions.waits.lock().unwrap();
+            drop(shards);
+            drop(database);
+            self.wait_for(waits, holder)?;
+            // Whatever happened while waiting, the rows are looked at again from the start.
+        }
+    }
+
+    // The other transaction holding row `id` of `shard`, if any: one that has locked it,
+    // or, if the store waits for locks, one still in progress that has written it.
+    fn holder(&self, shard: &Shard, id: u32) -> Option<usize> {
+        match shard.locks.get(&id) {
+            Some(&holder) if holder != self.version => Some(holder),
+            _ if self.wait_for_locks => shard
+                .rows
+                .get(&id)
+                .and_then(|versions| versions.last())
+                .and_then(|latest| {
+                    [Some(latest.created_by), latest.deleted_by]
+                        .into_iter()
+                        .flatten()
+                        .find(|&writer| {
+                            !self.is_visible(writer) && self.transactions.is_active(writer)
+                        })
+                }),
+            _ => None,
+        }
+    }
+
+    // Wait for the transaction `holder` to finish, unless that would close a cycle of
+    // transactions waiting for each other.
+    fn wait_for(
+        &self,
+        mut waits: MutexGuard<'_, HashMap<usize, usize>>,
+        holder: usize,
+    ) -> Result<(), TransactionError> {
+        // Each transaction waits for at most one other, so following them on from the
+        // holder finds any cycle this one would close.
+        let mut next = Some(holder);
+        while let Some(waiter) = next {
+            if waiter == self.version {
+                return Err(TransactionError::Deadlock);
+            }
+            next = waits.get(&waiter).copied();
+        }
+
+        waits.insert(self.version, holder);
+        let mut waits = self.transactions.released.wait(waits).unwrap();
+        waits.remove(&self.version);
+        Ok(())
+    }
+
+    // Read data from a table as of this transaction's snapshot.
+    //
+    // Panics if there is no such table.
+    pub fn get(&self, table: &str, id: u32) -> Option<Row> {
+        self.try_get(table, id)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    // Like `get`, but fails with `UnknownTable` rather than panicking if there is no such
+    // table.
+    pub fn try_get(&self, table: &str, id: u32) -> Result<Option<Row>, TransactionError> {
+        let database = self.database.read().unwrap();
+        self.refresh_snapshot();
+        let shard = database.try_table(table)?.shard(id);
+        Ok(self.read(&shard, table, id))
+    }
+
+    // Read a row like `get`, and lock it so that no other transaction can write or lock
+    // it until this one finishes. This one's own writes to it can't conflict from then
+    // on. If another transaction has the row locked, this fails with `WouldBlock`, or
+    // waits for it to finish if the store waits for locks; a row written by another
+    // transaction that is still in progress, or that committed after this one's snapshot,
+    // is a `WriteConflict`, as it would be for a write.
+    pub fn get_for_update(&self, table: &str, id: u32) -> Result<Option<Row>, TransactionError> {
+        self.with_row(table, id, |shard| {
+            let versions = shard.rows.get(&id).map_or(&[][..], Vec::as_slice);
+            self.check_conflict(table, id, versions)?;
+            shard.locks.insert(id, self.version);
+            Ok(self.read(shard, table, id))
+        })
+    }
+
+    // Read a row as of this transaction's snapshot, with the shard it is in locked.
+    fn read(&self, shard: &Shard, table: &str, id: u32) -> Option<Row> {
+        let versions = shard.rows.get(&id).map_or(&[][..], Vec::as_slice);
+        if self.optimistic {
+            let row = (Bound::Included(id), Bound::Included(id));
+            self.track_read(table, row);
+            let buffered = self.buffered.lock().unwrap();
+            if let Some((_, _, row)) = buffered
+                .iter()
+                .rev()
+                .find(|(written, write, _)| written == table && *write == id)
+            {
+                return row.clone();
+            }
+        }
+
+        if self.serializable {
+            // Any write to the row this transaction can't see puts it before the writer.
+            let unseen = self.unseen_writers(versions);
+            self.transactions.ssi.read(self.version, table, id, unseen);
+        }
+
+        let snapshot = self.snapshot.lock().unwrap();
+        snapshot.find(versions).map(|row| row.values.clone())
+    }
+
+    // The rows of a table this transaction's snapshot sees, in order of ID.
+    //
+    // Panics if there is no such table.
+    pub fn scan(&self, table: &str) -> impl Iterator<Item = (u32, Row)> {
+        self.range(table, ..)
+    }
+
+    // Like `scan`, but fails with `UnknownTable` rather than panicking if there is no such
+    // table.
+    pub fn try_scan(
+        &self,
+        table: &str,
+    ) -> Result<impl Iterator<Item = (u32, Row)>, TransactionError> {
+        self.try_range(table, ..)
+    }
+
+    // The rows of a table this transaction's snapshot sees with IDs in `range`, in order
+    // of ID. Only the rows in the range are looked at, not the whole table. They are read
+    // all at once, so the store isn't left locked while the caller goes through them.
+    //
+    // Panics if there is no such table, or if the range starts after it ends, like
+    // `BTreeMap::range`.
+    pub fn range(
+        &self,
+        table: &str,
+        range: impl RangeBounds<u32>,
+    ) -> impl Iterator<Item = (u32, Row)> {
+        self.try_range(table, range)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    // Like `range`, but fails with `UnknownTable` rather than panicking if there is no
+    // such table.
+    pub fn try_range(
+        &self,
+        table: &str,
+        range: impl RangeBounds<u32>,
+    ) -> Result<impl Iterator<Item = (u32, Row)>, TransactionError> {
+        let range = bounds(&range);
+        let database = self.database.read().unwrap();
+        self.refresh_snapshot();
+        // Every shard is held at once, so the scan sees them all at the same point.
+        let shards = database.try_table(table)?.lock_all();
+        if self.serializable {
+            let unseen = shards
+                .iter()
+                .flat_map(|shard| shard.rows.range(range))
+                .flat_map(|(_, versions)| self.unseen_writers(versions));
+            self.transactions
+                .ssi
+                .scan(self.version, table, range, unseen);
+        }
+        if self.optimistic {
+            self.track_read(table, range);
+        }
+
+        let snapshot = self.snapshot.lock().unwrap();
+        let found = merge(
+            shards
+                .iter()
+                .map(|shard| snapshot.rows(shard.rows.range(range))),
+        );
+        drop(snapshot);
+        Ok(self.overlay(found, table, range, |_| true).into_iter())
+    }
+
+    // The rows of a table this transaction's snapshot sees whose key in the table's
+    // secondary index `index` is `key`, in order of ID.
+    //
+    // Panics if there is no such table or index.
+    pub fn get_by_index(
+        &self,
+        table: &str,
+        index: &str,
+        key: impl Into<Value>,
+    ) -> impl Iterator<Item = (u32, Row)> {
+        self.try_get_by_index(table, index, key)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    // Like `get_by_index`, but fails with `UnknownTable` rather than panicking if there is
+    // no such table. It still panics if the table has no such index.
+    pub fn try_get_by_index(
+        &self,
+        table: &str,
+        index: &str,
+        key: impl Into<Value>,
+    ) -> Result<impl Iterator<Item = (u32, Row)>, TransactionError> {
+        let key = &key.into();
+        let database = self.database.read().unwrap();
+        self.refresh_snapshot();
+        let shards = database.try_table(table)?.lock_all();
+        if self.serializable {
+            // A row could be given the key by any later write, so the lookup counts as
+            // having read the whole table.
+            let unseen = shards
+                .iter()
+                .flat_map(|shard| shard.index(index).rows(&shard.rows, key))
+                .flat_map(|(_, versions)| self.unseen_writers(versions));
+            self.transactions.ssi.scan(self.version, table, .., unseen);
+        }
+        if self.optimistic {
+            self.track_read(table, bounds(&..));
+        }
+
+        let snapshot = self.snapshot.lock().unwrap();
+        let rows = merge(
+            shards
+                .iter()
+                .map(|shard| snapshot.indexed(shard, index, key)),
+        );
+        drop(snapshot);
+        let index = shards[0].index(index);
+        Ok(self
+            .overlay(rows, table, bounds(&..), |row| index.key(row) == key)
+            .into_iter())
+    }
+
+    // Apply the writes an optimistic transaction has yet to make to rows read from its
+    // snapshot, for the rows of `table` with IDs in `range` and values `matches` accepts.
+    fn overlay(
+        &self,
+        rows: Vec<(u32, Row)>,
+        table: &str,
+        range: IdRange,
+        matches: impl Fn(&[Value]) -> bool,
+    ) -> Vec<(u32, Row)> {
+        let buffered = self.buffered.lock().unwrap();
+        if buffered.is_empty() {
+            return rows;
+        }
+
+        let mut rows: BTreeMap<u32, Row> = rows.into_iter().collect();
+        let writes = buffered
+            .iter()
+            .filter(|(written, id, _)| written == table && range.contains(id));
+        for (_, id, row) in writes {
+            match row {
+                Some(row) if matches(row) => rows.insert(*id, row.clone()),
+                _ => rows.remove(id),
+            };
+        }
+        rows.into_iter().collect()
+    }
+
+    // Check that no transaction that committed while this optimistic one was running
+    // wrote a row it read or is about to write. Other optimistic transactions only write
+    // to the tables as they commit, so any write this one can't see is such a transaction's.
+    // Called with the whole store locked.
+    fn validate(&self, database: &Database) -> Result<(), CommitError> {
+        let reads = self.reads.lock().unwrap();
+        let buffered = self.buffered.lock().unwrap();
+        let writes = buffered
+            .iter()
+            .map(|(table, id, _)| (table.clone(), (Bound::Included(*id), Bound::Included(*id))));
+
+        // Rows locked by another transaction can't be written either.
+        for (table, id, _) in buffered.iter() {
+            let shard = database.table(table).shard(*id);
+            if matches!(shard.locks.get(id), Some(&holder) if holder != self.version) {
+                return Err(CommitError::Locked {
+                    table: table.clone(),
+                    id: *id,
+                });
+            }
+        }
+
+        for (table, range) in reads.iter().cloned().chain(writes) {
+            for shard in database.table(&table).lock_all() {
+                for (&id, versions) in shard.rows.range(range) {
+                    if self.unseen_writers(versions).next().is_some() {
+                        return Err(CommitError::Validation { table, id });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Apply every write an optimistic transaction has held on to, oldest first, once
+    // they have been validated.
+    fn apply_buffered(&self, database: &mut Database) {
+        let mut undo_log = self.undo_log.lock().unwrap();
+        for (table, id, row) in mem::take(&mut *self.buffered.lock().unwrap()) {
+            let shard = database.tables.get_mut(&table).unwrap().shard_mut(id);
+            // Validation checked that nothing this transaction can't see has written
+            // to the row, which is all that would make the write conflict.
+            self.apply(shard, &table, id, row, &mut undo_log)
+                .expect("validated writes don't conflict");
+        }
+    }
+
+    // The transactions that wrote any of `versions` without this one being able to see it.
+    fn unseen_writers<'a>(
+        &'a self,
+        versions: &'a [RowVersion],
+    ) -> impl Iterator<Item = usize> + 'a {
+        versions
+            .iter()
+            .flat_map(|row| [Some(row.created_by), row.deleted_by])
+            .flatten()
+            .filter(|&writer| !self.is_visible(writer))
+    }
+
+    // Commit the transaction, removing it from the list of active transactions. A
+    // serializable transaction that has been chosen to abort, or an optimistic one that
+    // fails validation, is rolled back instead.
+    pub fn commit(&self) -> Result<CommitInfo, CommitError> {
+        // Held until the transaction has finished, so it can't be aborted halfway through,
+        // and so no other transaction commits between validating and applying its writes.
+        let database = self.database.write().unwrap();
+        let mut database = self.prepare_locked(database)?;
+
+        self.log(WalRecord::Commit {
+            version: self.version,
+        });
+        // While the store is still locked, so no other transaction's changes get in first.
+        database.publish(self.version, &self.undo_log.lock().unwrap());
+        self.finished.store(true, Ordering::SeqCst);
+        database.release(self.version);
+        self.transactions.finish(self.version, true);
+        Ok(CommitInfo {
+            version: self.version,
+            writes: self.undo_log.lock().unwrap().len(),
+        })
+    }
+
+    // Do everything committing the transaction involves that can fail, so that once this
+    // succeeds, `commit` can't: an optimistic transaction is validated and its writes
+    // applied, and a serializable one checked against the others for the last time. One
+    // that fails is rolled back, just as if it had failed to commit. This is the first
+    // phase of committing with a `Coordinator`.
+    //
+    // A prepared transaction can still read, but can't write or roll back to a savepoint,
+    // and isn't aborted for running too long. It still has to be committed or rolled
+    // back, and until then holds on to the rows it wrote like any other. Neither is
+    // logged, so a store recovered from its log leaves out transactions that were only
+    // prepared. Preparing a transaction again does nothing.
+    pub fn prepare(&self) -> Result<(), CommitError> {
+        let database = self.database.write().unwrap();
+        let _database = self.prepare_locked(database)?;
+        self.prepared.store(true, Ordering::SeqCst);
+        self.transactions.prepare(self.version);
+        Ok(())
+    }
+
+    // What `prepare` does, given the store locked for writing, which is handed back
+    // unless the transaction had to roll back.
+    fn prepare_locked<'a>(
+        &self,
+        mut database: RwLockWriteGuard<'a, Database>,
+    ) -> Result<RwLockWriteGuard<'a, Database>, CommitError> {
+        match self.check_active() {
+            Err(TransactionError::Finished) => return Err(CommitError::Finished),
+            Err(_) => return Err(CommitError::TimedOut),
+            Ok(()) => {}
+        }
+        if self.prepared.load(Ordering::SeqCst) {
+            return Ok(database);
+        }
+        if self.optimistic {
+            // Read committed transactions write over whatever has committed by now.
+            self.refresh_snapshot();
+            if let Err(err) = self.validate(&database) {
+                drop(database);
+                self.rollback();
+                return Err(err);
+            }
+            self.apply_buffered(&mut database);
+        } else if self.serializable && !self.transactions.ssi.commit(self.version) {
+            drop(database);
+            self.rollback();
+            return Err(CommitError::Serialization);
+        }
+        Ok(database)
+    }
+
+    // Mark the transaction's writes so far as a savepoint called `name`, which
+    // `rollback_to` can later return to. A savepoint with the same name as an earlier one
+    // hides it.
+    pub fn savepoint(&self, name: &str) -> Result<(), TransactionError> {
+        let _database = self.database.read().unwrap();
+        self.check_writable()?;
+
+        let length = if self.optimistic {
+            self.buffered.lock().unwrap().len()
+        } else {
+            self.undo_log.lock().unwrap().len()
+        };
+        self.savepoints
+            .lock()
+            .unwrap()
+            .push((name.to_string(), length));
+        self.log(WalRecord::Savepoint {
+            version: self.version,
+            name: name.to_string(),
+        });
+        Ok(())
+    }
+
+    // Undo the writes made since the savepoint called `name`, leaving the transaction
+    // active with the ones made before it. Savepoints made after it are dropped, but it
+    // stays, so the transaction can roll back to it again.
+    pub fn rollback_to(&self, name: &str) -> Result<(), TransactionError> {
+        let mut database = self.database.write().unwrap();
+        self.check_writable()?;
+
+        let mut savepoints = self.savepoints.lock().unwrap();
+        let Some(position) = savepoints
+            .iter()
+            .rposition(|(savepoint, _)| savepoint == name)
+        else {
+            return Err(TransactionError::UnknownSavepoint(name.to_string()));
+        };
+        let length = savepoints[position].1;
+        savepoints.truncate(position + 1);
+
+        if self.optimistic {
+            self.buffered.lock().unwrap().truncate(length);
+        } else {
+            let undone = self.undo_log.lock().unwrap().split_off(length);
+            database.undo(undone);
+        }
+        self.log(WalRecord::RollbackTo {
+            version: self.version,
+            name: name.to_string(),
+        });
+        Ok(())
+    }
+
+    // Rollback the transaction, undoing any writes made during the transaction. One that
+    // has already finished, or been aborted for running too long, has nothing left to undo.
+    pub fn rollback(&self) {
+        let mut database = self.database.write().unwrap();
+        if self.check_active().is_err() {
+            return;
+        }
+        self.finished.store(true, Ordering::SeqCst);
+        database.undo(self.undo_log.lock().unwrap().drain(..).collect());
+        database.release(self.version);
+        self.buffered.lock().unwrap().clear();
+
+        self.log(WalRecord::Abort {
+            version: self.version,
+        });
+        self.transactions.ssi.forget(self.version);
+        // Only once its versions are gone, or other transactions would take them for
+        // committed ones.
+        self.transactions.finish(self.version, false);
+    }
+
+    // Check that the transaction can still do anything, which it can't once it has
+    // committed, rolled back or been aborted. Called with the store locked, which all of
+    // those lock for writing.
+    fn check_active(&self) -> Result<(), TransactionError> {
+        if self.finished.load(Ordering::SeqCst) {
+            return Err(TransactionError::Finished);
+        }
+        if !self.transactions.is_active(self.version) {
+            return Err(TransactionError::TimedOut);
+        }
+        Ok(())
+    }
+
+    // Check that the transaction can still write, which on top of being active means it
+    // hasn't been prepared to commit.
+    fn check_writable(&self) -> Result<(), TransactionError> {
+        self.check_active()?;
+        if self.prepared.load(Ordering::SeqCst) {
+            return Err(TransactionError::Prepared);
+        }
+        Ok(())
+    }
+
+    // Append a record to the write-ahead log, if there is one.
+    fn log(&self, record: WalRecord) {
+        log(&self.wal, record);
+    }
+
+    // Determine whether the writes of the transaction with the given version are part of
+    // this transaction's snapshot.
+    fn is_visible(&self, version: usize) -> bool {
+        self.snapshot.lock().unwrap().is_visible(version)
+    }
+
+    // Take a new snapshot if the transaction is read committed, so that the statement it
+    // is about to run sees everything that has committed before it. Called with the store
+    // locked, so nothing commits partway through the statement.
+    fn refresh_snapshot(&self) {
+        if self.isolation == IsolationLevel::ReadCommitted {
+            *self.snapshot.lock().unwrap() = self.transactions.refresh(self.version);
+        }
+    }
+
+    // Note that an optimistic transaction read rows of `table` in `range`, which have to
+    // be unchanged when it commits. A read committed one doesn't mind if they changed.
+    fn track_read(&self, table: &str, range: IdRange) {
+        if self.isolation != IsolationLevel::ReadCommitted {
+            self.reads.lock().unwrap().push((table.to_string(), range));
+        }
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        // A transaction that was never committed or rolled back is rolled back, so it
+        // doesn't stay active and hold up vacuum. If the lock is poisoned the store is
+        // unusable anyway, and rolling back would panic again.
+        if !self.database.is_poisoned() {
+            self.rollback();
+        }
+    }
+}
+
+// A transaction started with `MVCC::begin_read_only`, which can read but not write.
+pub struct ReadOnlyTransaction {
+    // The underlying tables.
+    database: Arc<RwLock<Database>>,
+    // The manager of the MVCC instance the transaction belongs to.
+    transactions: Arc<TransactionManager>,
+    // The ID the manager knows the transaction by.
+    reader: usize,
+    // The writes the transaction can see.
+    snapshot: Snapshot,
+}
+
+impl ReadOnlyTransaction {
+    // Read data from a table as of this transaction's snapshot.
+    //
+    // Panics if there is no such table.
+    pub fn get(&self, table: &str, id: u32) -> Option<Row> {
+        self.try_get(table, id)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    // Like `get`, but fails with `UnknownTable` rather than panicking if there is no such
+    // table.
+    pub fn try_get(&self, table: &str, id: u32) -> Result<Option<Row>, TransactionError> {
+        let database = self.database.read().unwrap();
+        let shard = database.try_table(table)?.shard(id);
+        let Some(versions) = shard.rows.get(&id) else {
+            return Ok(None);
+        };
+        Ok(self.snapshot.find(versions).map(|row| row.values.clone()))
+    }
+
+    // The rows of a table this transaction's snapshot sees, in order of ID.
+    //
+    // Panics if there is no such table.
+    pub fn scan(&self, table: &str) -> impl Iterator<Item = (u32, Row)> {
+        self.range(table, ..)
+    }
+
+    // Like `scan`, but fails with `UnknownTable` rather than panicking if there is no such
+    // table.
+    pub fn try_scan(
+        &self,
+        table: &str,
+    ) -> Result<impl Iterator<Item = (u32, Row)>, TransactionError> {
+        self.try_range(table, ..)
+    }
+
+    // The rows of a table this transaction's snapshot sees with IDs in `range`, in order
+    // of ID.
+    //
+    // Panics if there is no such table, or if the range starts after it ends, like
+    // `BTreeMap::range`.
+    pub fn range(
+        &self,
+        table: &str,
+        range: impl RangeBounds<u32>,
+    ) -> impl Iterator<Item = (u32, Row)> {
+        self.try_range(table, range)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    // Like `range`, but fails with `UnknownTable` rather than panicking if there is no
+    // such table.
+    pub fn try_range(
+        &self,
+        table: &str,
+        range: impl RangeBounds<u32>,
+    ) -> Result<impl Iterator<Item = (u32, Row)>, TransactionError> {
+        let range = bounds(&range);
+        let database = self.database.read().unwrap();
+        let shards = database.try_table(table)?.lock_all();
+        Ok(merge(
+            shards
+                .iter()
+                .map(|shard| self.snapshot.rows(shard.rows.range(range))),
+        )
+        .into_iter())
+    }
+
+    // The rows of a table this transaction's snapshot sees whose key in the table's
+    // secondary index `index` is `key`, in order of ID.
+    //
+    // Panics if there is no such table or index.
+    pub fn get_by_index(
+        &self,
+        table: &str,
+        index: &str,
+        key: impl Into<Value>,
+    ) -> impl Iterator<Item = (u32, Row)> {
+        self.try_get_by_index(table, index, key)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    // Like `get_by_index`, but fails with `UnknownTable` rather than panicking if there is
+    // no such table. It still panics if the table has no such index.
+    pub fn try_get_by_index(
+        &self,
+        table: &str,
+        index: &str,
+        key: impl Into<Value>,
+    ) -> Result<impl Iterator<Item = (u32, Row)>, TransactionError> {
+        let key = &key.into();
+        let database = self.database.read().unwrap();
+        let shards = database.try_table(table)?.lock_all();
+        Ok(merge(
+            shards
+                .iter()
+                .map(|shard| self.snapshot.indexed(shard, index, key)),
+        )
+        .into_iter())
+    }
+}
+
+// Put together the rows read from each shard of a table, in order of ID.
+fn merge(shards: impl Iterator<Item = Vec<(u32, Row)>>) -> Vec<(u32, Row)> {
+    let mut rows: Vec<(u32, Row)> = shards.flatten().collect();
+    rows.sort_unstable_by_key(|(id, _)| *id);
+    rows
+}
+
+// A range of row IDs, in a form that can be copied around.
+pub type IdRange = (Bound<u32>, Bound<u32>);
+
+// The bounds of a range of row IDs as an `IdRange`.
+fn bounds(range: &impl RangeBounds<u32>) -> IdRange {
+    (range.start_bound().cloned(), range.end_bound().cloned())
+}
+
+impl Drop for ReadOnlyTransaction {
+    fn drop(&mut self) {
+        // Let vacuum remove the versions only this transaction could still see.
+        self.transactions.finish_read_only(self.reader);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Barrier;
+
+    // A store with an empty table of users, each with a name.
+    fn users() -> MVCC {
+        let mvcc = MVCC::new();
+        mvcc.create_table("users", Schema::new().column("name", ColumnType::Text));
+        mvcc
+    }
+
+    // A store with `count` accounts, each holding `balance`.
+    fn accounts(count: u32, balance: i64) -> MVCC {
+        let mvcc = MVCC::new();
+        mvcc.create_table("accounts", Schema::new().column("balance", ColumnType::Int));
+        let setup = mvcc.begin_transaction();
+        let rows = (0..count).map(|id| (id, Some(vec![Value::Int(balance)])));
+        setup.write_batch("accounts", rows.collect()).unwrap();
+        setup.commit().unwrap();
+        mvcc
+    }
+
+    fn name(name: &str) -> Row {
+        vec![name.into()]
+    }
+
+    fn balance(row: &[Value]) -> i64 {
+        match row[0] {
+            Value::Int(balance) => balance,
+            _ => panic!("a balance is an int"),
+        }
+    }
+
+    #[test]
+    fn commits_are_visible_to_later_transactions() {
+        let mvcc = users();
+        let writer = mvcc.begin_transaction();
+        writer.set("users", 1, name("Alice")).unwrap();
+        writer.set("users", 2, name("Bob")).unwrap();
+        writer.delete("users", 2).unwrap();
+        assert_eq!(1, writer.commit().unwrap().version);
+
+        let reader = mvcc.begin_transaction();
+        assert_eq!(Some(name("Alice")), reader.get("users", 1));
+        assert_eq!(None, reader.get("users", 2));
+        assert_eq!(
+            vec![(1, name("Alice"))],
+            mvcc.begin_read_only().scan("users").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn uncommitted_writes_are_invisible_to_others() {
+        let mvcc = users();
+        let writer = mvcc.begin_transaction();
+        writer.set("users", 1, name("Alice")).unwrap();
+
+        let concurrent = mvcc.begin_transaction();
+        let reader = mvcc.begin_read_only();
+        assert_eq!(None, concurrent.get("users", 1));
+        assert_eq!(None, reader.get("users", 1));
+
+        // Committing doesn't change what snapshots taken before it see.
+        writer.commit().unwrap();
+        assert_eq!(None, concurrent.get("users", 1));
+        assert_eq!(None, reader.get("users", 1));
+        assert_eq!(Some(name("Alice")), mvcc.begin_read_only().get("users", 1));
+    }
+
+    // Write over, add and delete rows on top of committed ones in a table of users in
+    // `mvcc`, and check that the transaction reads back its own writes while nobody else
+    // sees any of them.
+    fn own_writes_are_visible_only_to_the_writer(mvcc: MVCC, isolation: IsolationLevel) {
+        mvcc.create_table("users", Schema::new().column("name", ColumnType::Text));
+        mvcc.create_index("users", "name", "name");
+        let setup = mvcc.begin_transaction();
+        setup.set("users", 1, name("Alice")).unwrap();
+        setup.set("users", 2, name("Bob")).unwrap();
+        setup.commit().unwrap();
+
+        let writer = mvcc.begin_with_isolation(isolation);
+        let concurrent = mvcc.begin_with_isolation(isolation);
+        let reader = mvcc.begin_read_only();
+        writer.set("users", 1, name("Alicia")).unwrap();
+        writer.delete("users", 2).unwrap();
+        writer.set("users", 3, name("Charlie")).unwrap();
+        writer.set("users", 3, name("Chuck")).unwrap();
+        writer
+            .write_batch(
+                "users",
+                vec![(4, Some(name("Dan"))), (5, Some(name("Eve")))],
+            )
+            .unwrap();
+        writer.delete("users", 5).unwrap();
+
+        let own = vec![(1, name("Alicia")), (3, name("Chuck")), (4, name("Dan"))];
+        assert_eq!(Some(name("Alicia")), writer.get("users", 1));
+        assert_eq!(None, writer.get("users", 2));
+        assert_eq!(Some(name("Chuck")), writer.get("users", 3));
+        assert_eq!(None, writer.get("users", 5));
+        assert_eq!(own, writer.scan("users").collect::<Vec<_>>());
+        assert_eq!(own[1..], writer.range("users", 2..).collect::<Vec<_>>());
+        assert_eq!(
+            vec![(3, name("Chuck"))],
+            writer
+                .get_by_index("users", "name", "Chuck")
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(0, writer.get_by_index("users", "name", "Bob").count());
+
+        let committed = vec![(1, name("Alice")), (2, name("Bob"))];
+        assert_eq!(committed, concurrent.scan("users").collect::<Vec<_>>());
+        assert_eq!(Some(name("Bob")), concurrent.get("users", 2));
+        assert_eq!(None, concurrent.get("users", 3));
+        assert_eq!(committed, reader.scan("users").collect::<Vec<_>>());
+        assert_eq!(
+            committed,
+            mvcc.begin_read_only().scan("users").collect::<Vec<_>>()
+        );
+
+        // Rolling back to a savepoint takes back the writes made since, for the writer
+        // too.
+        writer.savepoint("before_frank").unwrap();
+        writer.set("users", 6, name("Frank")).unwrap();
+        writer.delete("users", 1).unwrap();
+        assert_eq!(None, writer.get("users", 1));
+        writer.rollback_to("before_frank").unwrap();
+        assert_eq!(own, writer.scan("users").collect::<Vec<_>>());
+
+        writer.commit().unwrap();
+        assert_eq!(
+            own,
+            mvcc.begin_read_only().scan("users").collect::<Vec<_>>()
+        );
+        // Except at read committed, those that began before it still don't see them.
+        if isolation != IsolationLevel::ReadCommitted {
+            assert_eq!(committed, concurrent.scan("users").collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn own_writes_are_visible_only_to_the_writer_at_every_isolation_level() {
+        for isolation in [
+            IsolationLevel::ReadCommitted,
+            IsolationLevel::SnapshotIsolation,
+            IsolationLevel::Serializable,
+        ] {
+            own_writes_are_visible_only_to_the_writer(MVCC::new(), isolation);
+            own_writes_are_visible_only_to_the_writer(MVCC::new().with_shards(1), isolation);
+        }
+    }
+
+    #[test]
+    fn own_writes_are_visible_only_to_the_writer_when_optimistic() {
+        for isolation in [
+            IsolationLevel::ReadCommitted,
+            IsolationLevel::SnapshotIsolation,
+            IsolationLevel::Serializable,
+        ] {
+            own_writes_are_visible_only_to_the_writer(MVCC::new().optimistic(), isolation);
+        }
+    }
+
+    #[test]
+    fn rollback_undoes_writes() {
+        let mvcc = users();
+        let setup = mvcc.begin_transaction();
+        setup.set("users", 1, name("Alice")).unwrap();
+        setup.commit().unwrap();
+
+        let writer = mvcc.begin_transaction();
+        writer.set("users", 1, name("Alicia")).unwrap();
+        writer.set("users", 2, name("Bob")).unwrap();
+        writer.rollback();
+        assert_eq!(Err(TransactionError::Finished), writer.delete("users", 1));
+
+        // A transaction dropped without committing rolls back too.
+        {
+            let dropped = mvcc.begin_transaction();
+            dropped.delete("users", 1).unwrap();
+        }
+
+        assert_eq!(
+            vec![(1, name("Alice"))],
+            mvcc.begin_read_only().scan("users").collect::<Vec<_>>()
+        );
+        let versions = mvcc.versions("users");
+        assert_eq!(1, versions.len());
+        assert_eq!(None, versions[0].deleted_by);
+    }
+
+    #[test]
+    fn rollback_to_keeps_writes_made_before_the_savepoint() {
+        let mvcc = users();
+        let writer = mvcc.begin_transaction();
+        writer.set("users", 1, name("Alice")).unwrap();
+        writer.savepoint("after_alice").unwrap();
+        writer.set("users", 1, name("Alicia")).unwrap();
+        writer.set("users", 2, name("Bob")).unwrap();
+        writer.rollback_to("after_alice").unwrap();
+        assert_eq!(
+            Err(TransactionError::UnknownSavepoint("missing".to_string())),
+            writer.rollback_to("missing")
+        );
+        writer.commit().unwrap();
+
+        assert_eq!(
+            vec![(1, name("Alice"))],
+            mvcc.begin_read_only().scan("users").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn the_first_writer_of_a_row_wins() {
+        let mvcc = users();
+        let first = mvcc.begin_transaction();
+        let second = mvcc.begin_transaction();
+        first.set("users", 1, name("Alice")).unwrap();
+
+        // Still in progress, and once it has committed, outside the second's snapshot.
+        let conflict = Err(TransactionError::WriteConflict {
+            table: "users".to_string(),
+            id: 1,
+            version: first.version,
+        });
+        assert_eq!(conflict, second.set("users", 1, name("Bob")));
+        first.commit().unwrap();
+        assert_eq!(conflict, second.set("users", 1, name("Bob")));
+    }
+
+    #[test]
+    fn locked_rows_turn_other_transactions_away() {
+        let mvcc = users();
+        let first = mvcc.begin_transaction();
+        let second = mvcc.begin_transaction();
+        assert_eq!(Ok(None), first.get_for_update("users", 1));
+        assert_eq!(
+            Err(TransactionError::WouldBlock {
+                table: "users".to_string(),
+                id: 1,
+                version: first.version,
+            }),
+            second.set("users", 1, name("Bob"))
+        );
+
+        first.commit().unwrap();
+        second.set("users", 1, name("Bob")).unwrap();
+    }
+
+    #[test]
+    fn waiting_for_locks_catches_deadlocks() {
+        let mvcc = users().wait_for_locks();
+        let first = mvcc.begin_transaction();
+        let second = mvcc.begin_transaction();
+        first.get_for_update("users", 1).unwrap();
+        second.get_for_update("users", 2).unwrap();
+
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| first.get_for_update("users", 2));
+            // Give the first time to start waiting for the second.
+            thread::sleep(Duration::from_millis(50));
+            assert_eq!(
+                Err(TransactionError::Deadlock),
+                second.get_for_update("users", 1)
+            );
+            second.rollback();
+            assert_eq!(Ok(None), waiter.join().unwrap());
+        });
+        first.commit().unwrap();
+    }
+
+    #[test]
+    fn locked_rows_can_only_be_locked_once() {
+        let mvcc = users();
+        let setup = mvcc.begin_transaction();
+        setup.set("users", 1, name("Alice")).unwrap();
+        setup.commit().unwrap();
+
+        let first = mvcc.begin_transaction();
+        let second = mvcc.begin_transaction();
+        assert_eq!(Ok(Some(name("Alice"))), first.get_for_update("users", 1));
+        // Locking it again is fine for the holder, but not for anyone else.
+        assert_eq!(Ok(Some(name("Alice"))), first.get_for_update("users", 1));
+        assert_eq!(
+            Err(TransactionError::WouldBlock {
+                table: "users".to_string(),
+                id: 1,
+                version: first.version,
+            }),
+            second.get_for_update("users", 1)
+        );
+
+        first.rollback();
+        assert_eq!(Ok(Some(name("Alice"))), second.get_for_update("users", 1));
+    }
+
+    #[test]
+    fn waiting_for_locks_waits_until_the_holder_rolls_back() {
+        let mvcc = users().wait_for_locks();
+        let setup = mvcc.begin_transaction();
+        setup.set("users", 1, name("Alice")).unwrap();
+        setup.commit().unwrap();
+
+        let first = mvcc.begin_transaction();
+        let second = mvcc.begin_transaction();
+        first.set("users", 1, name("Alicia")).unwrap();
+
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| second.get_for_update("users", 1));
+            thread::sleep(Duration::from_millis(50));
+            assert!(!waiter.is_finished());
+            first.rollback();
+            assert_eq!(Ok(Some(name("Alice"))), waiter.join().unwrap());
+        });
+        second.set("users", 1, name("Bob")).unwrap();
+        second.commit().unwrap();
+        assert_eq!(Some(name("Bob")), mvcc.begin_read_only().get("users", 1));
+    }
+
+    #[test]
+    fn two_writers_waiting_for_each_other_deadlock() {
+        let mvcc = users().wait_for_locks();
+        let first = mvcc.begin_transaction();
+        let second = mvcc.begin_transaction();
+        first.set("users", 1, name("Alice")).unwrap();
+        second.set("users", 2, name("Bob")).unwrap();
+
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| first.set("users", 2, name("Alice")));
+            // Give the first time to start waiting for the second.
+            thread::sleep(Duration::from_millis(50));
+            assert_eq!(
+                Err(TransactionError::Deadlock),
+                second.set("users", 1, name("Bob"))
+            );
+            second.rollback();
+            assert_eq!(Ok(()), waiter.join().unwrap());
+        });
+        first.commit().unwrap();
+        let reader = mvcc.begin_read_only();
+        assert_eq!(Some(name("Alice")), reader.get("users", 1));
+        assert_eq!(Some(name("Alice")), reader.get("users", 2));
+    }
+
+    #[test]
+    fn serializable_transactions_avoid_write_skew() {
+        let mvcc = users().serializable();
+        let setup = mvcc.begin_transaction();
+        setup.set("users", 1, name("on call")).unwrap();
+        setup.set("users", 2, name("on call")).unwrap();
+        setup.commit().unwrap();
+
+        // Each goes off call having seen the other still on call.
+        let first = mvcc.begin_transaction();
+        let second = mvcc.begin_transaction();
+        assert_eq!(Some(name("on call")), first.get("users", 2));
+        assert_eq!(Some(name("on call")), second.get("users", 1));
+        first.set("users", 1, name("off call")).unwrap();
+        let second_done = second
+            .set("users", 2, name("off call"))
+            .map_err(|_| ())
+            .and_then(|()| second.commit().map_err(|_| ()));
+        let first_done = first.commit().map_err(|_| ());
+        assert!(first_done.is_err() || second_done.is_err());
+
+        // Snapshot isolation alone lets both through.
+        let mvcc = users();
+        let first = mvcc.begin_transaction();
+        let second = mvcc.begin_transaction();
+        first.get("users", 2);
+        second.get("users", 1);
+        first.set("users", 1, name("off call")).unwrap();
+        second.set("users", 2, name("off call")).unwrap();
+        first.commit().unwrap();
+        second.commit().unwrap();
+    }
+
+    #[test]
+    fn optimistic_transactions_fail_validation_on_commit() {
+        let mvcc = users().optimistic();
+        let first = mvcc.begin_transaction();
+        let second = mvcc.begin_transaction();
+        first.set("users", 1, name("Alice")).unwrap();
+        second.set("users", 1, name("Bob")).unwrap();
+        assert_eq!(Some(name("Bob")), second.get("users", 1));
+        assert_eq!(None, mvcc.begin_read_only().get("users", 1));
+
+        first.commit().unwrap();
+        assert_eq!(
+            Err(CommitError::Validation {
+                table: "users".to_string(),
+                id: 1,
+            }),
+            second.commit()
+        );
+        assert_eq!(Some(name("Alice")), mvcc.begin_read_only().get("users", 1));
+    }
+
+    #[test]
+    fn read_committed_sees_each_commit() {
+        let mvcc = users();
+        let read_committed = mvcc.begin_with_isolation(IsolationLevel::ReadCommitted);
+        let snapshot = mvcc.begin_with_isolation(IsolationLevel::SnapshotIsolation);
+        let writer = mvcc.begin_transaction();
+        writer.set("users", 1, name("Alice")).unwrap();
+        assert_eq!(None, read_committed.get("users", 1));
+        writer.commit().unwrap();
+
+        assert_eq!(Some(name("Alice")), read_committed.get("users", 1));
+        assert_eq!(None, snapshot.get("users", 1));
+        // Writing over the commit isn't a conflict at read committed.
+        read_committed.set("users", 1, name("Alicia")).unwrap();
+        read_committed.commit().unwrap();
+        assert!(snapshot.set("users", 1, name("Ally")).is_err());
+    }
+
+    #[test]
+    fn begin_at_reads_the_store_as_of_a_version() {
+        let mvcc = users();
+        let first = mvcc.begin_transaction();
+        first.set("users", 1, name("Alice")).unwrap();
+        let version = first.commit().unwrap().version;
+        let second = mvcc.begin_transaction();
+        second.set("users", 1, name("Alicia")).unwrap();
+        second.commit().unwrap();
+
+        let history = mvcc.begin_at(version).unwrap();
+        assert_eq!(Some(name("Alice")), history.get("users", 1));
+        assert_eq!(
+            Some(TransactionError::FutureVersion(10)),
+            mvcc.begin_at(10).err()
+        );
+
+        drop(history);
+        mvcc.vacuum();
+        assert_eq!(
+            Some(TransactionError::Vacuumed(version)),
+            mvcc.begin_at(version).err()
+        );
+    }
+
+    #[test]
+    fn vacuum_keeps_versions_a_snapshot_can_see() {
+        let mvcc = users();
+        let setup = mvcc.begin_transaction();
+        setup.set("users", 1, name("Alice")).unwrap();
+        setup.commit().unwrap();
+
+        let reader = mvcc.begin_read_only();
+        let writer = mvcc.begin_transaction();
+        writer.set("users", 1, name("Alicia")).unwrap();
+        writer.commit().unwrap();
+        assert_eq!(0, mvcc.vacuum());
+        assert_eq!(Some(name("Alice")), reader.get("users", 1));
+
+        drop(reader);
+        assert_eq!(1, mvcc.vacuum());
+        assert_eq!(1, mvcc.stats().versions);
+        assert_eq!(Some(name("Alicia")), mvcc.begin_read_only().get("users", 1));
+    }
+
+    #[test]
+    fn rows_keep_a_chain_of_versions() {
+        let mvcc = users();
+        let version = |id, row: &str, created_by, deleted_by| VersionInfo {
+            id,
+            row: name(row),
+            created_by,
+            deleted_by,
+        };
+        let create = mvcc.begin_transaction();
+        create.set("users", 1, name("Alice")).unwrap();
+        create.commit().unwrap();
+        let before_update = mvcc.begin_read_only();
+        let update = mvcc.begin_transaction();
+        update.set("users", 1, name("Alicia")).unwrap();
+        update.commit().unwrap();
+        let before_delete = mvcc.begin_read_only();
+        let delete = mvcc.begin_transaction();
+        delete.delete("users", 1).unwrap();
+        delete.commit().unwrap();
+
+        assert_eq!(
+            vec![
+                version(1, "Alice", create.version, Some(update.version)),
+                version(1, "Alicia", update.version, Some(delete.version)),
+            ],
+            mvcc.versions("users")
+        );
+        // Every snapshot still finds its own version in the chain.
+        assert_eq!(Some(name("Alice")), before_update.get("users", 1));
+        assert_eq!(Some(name("Alicia")), before_delete.get("users", 1));
+        assert_eq!(None, mvcc.begin_read_only().get("users", 1));
+
+        // Writing it again starts a new version after the deleted ones.
+        let recreate = mvcc.begin_transaction();
+        recreate.set("users", 1, name("Bob")).unwrap();
+        recreate.commit().unwrap();
+        assert_eq!(
+            version(1, "Bob", recreate.version, None),
+            mvcc.versions("users")[2]
+        );
+
+        drop(before_update);
+        drop(before_delete);
+        assert_eq!(2, mvcc.vacuum());
+        assert_eq!(
+            vec![version(1, "Bob", recreate.version, None)],
+            mvcc.versions("users")
+        );
+    }
+
+    #[test]
+    fn rollback_restores_the_version_chain() {
+        let mvcc = users();
+        let setup = mvcc.begin_transaction();
+        setup.set("users", 1, name("Alice")).unwrap();
+        setup.commit().unwrap();
+        let chain = mvcc.versions("users");
+
+        // Overwrite it twice and then delete it, which ends the transaction's own versions
+        // as well as the committed one.
+        let transaction = mvcc.begin_transaction();
+        transaction.set("users", 1, name("Alicia")).unwrap();
+        transaction.set("users", 1, name("Ali")).unwrap();
+        transaction.delete("users", 1).unwrap();
+        transaction.set("users", 2, name("Bob")).unwrap();
+        assert_eq!(4, mvcc.versions("users").len());
+        transaction.rollback();
+
+        assert_eq!(chain, mvcc.versions("users"));
+        assert_eq!(Some(name("Alice")), mvcc.begin_read_only().get("users", 1));
+    }
+
+    #[test]
+    fn indexes_find_rows_by_value() {
+        let mvcc = users();
+        let setup = mvcc.begin_transaction();
+        setup.set("users", 1, name("Alice")).unwrap();
+        setup.set("users", 2, name("Bob")).unwrap();
+        setup.commit().unwrap();
+        mvcc.create_index("users", "name", "name");
+
+        let writer = mvcc.begin_transaction();
+        writer.set("users", 3, name("Alice")).unwrap();
+        writer.set("users", 1, name("Alicia")).unwrap();
+        assert_eq!(
+            vec![(3, name("Alice"))],
+            writer
+                .get_by_index("users", "name", "Alice")
+                .collect::<Vec<_>>()
+        );
+        let reader = mvcc.begin_read_only();
+        assert_eq!(
+            vec![(1, name("Alice"))],
+            reader
+                .get_by_index("users", "name", "Alice")
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rows_have_to_fit_their_table() {
+        let mvcc = users();
+        let writer = mvcc.begin_transaction();
+        assert_eq!(
+            Err(TransactionError::InvalidRow {
+                table: "users".to_string(),
+                id: 1,
+                error: SchemaError::Type {
+                    column: "name".to_string(),
+                    expected: ColumnType::Text,
+                    found: ColumnType::Int,
+                },
+            }),
+            writer.set("users", 1, vec![Value::Int(1)])
+        );
+        assert_eq!(
+            Err(TransactionError::UnknownTable("orders".to_string())),
+            writer.set("orders", 1, name("Alice"))
+        );
+        // Neither stops the transaction from carrying on.
+        writer.set("users", 1, name("Alice")).unwrap();
+        writer.commit().unwrap();
+    }
+
+    #[test]
+    fn reading_an_unknown_table_is_an_error() {
+        let mvcc = users();
+        mvcc.create_index("users", "name", "name");
+        let setup = mvcc.begin_transaction();
+        setup.set("users", 1, name("Alice")).unwrap();
+        setup.commit().unwrap();
+
+        let unknown = Some(TransactionError::UnknownTable("orders".to_string()));
+        let transaction = mvcc.begin_transaction();
+        let reader = mvcc.begin_read_only();
+        assert_eq!(unknown, transaction.try_get("orders", 1).err());
+        assert_eq!(unknown, transaction.try_scan("orders").err());
+        assert_eq!(unknown, transaction.try_range("orders", 1..).err());
+        assert_eq!(
+            unknown,
+            transaction
+                .try_get_by_index("orders", "name", "Alice")
+                .err()
+        );
+        assert_eq!(unknown, reader.try_get("orders", 1).err());
+        assert_eq!(unknown, reader.try_scan("orders").err());
+        assert_eq!(unknown, reader.try_range("orders", 1..).err());
+        assert_eq!(
+            unknown,
+            reader.try_get_by_index("orders", "name", "Alice").err()
+        );
+
+        // Tables that do exist read the same as without `try_`.
+        assert_eq!(Ok(Some(name("Alice"))), transaction.try_get("users", 1));
+        assert_eq!(Ok(None), reader.try_get("users", 2));
+        assert_eq!(
+            vec![(1, name("Alice"))],
+            reader.try_scan("users").unwrap().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(1, name("Alice"))],
+            transaction
+                .try_get_by_index("users", "name", "Alice")
+                .unwrap()
+                .collect::<Vec<_>>()
+        );
+        // And the transaction carries on.
+        transaction.set("users", 2, name("Bob")).unwrap();
+        transaction.commit().unwrap();
+    }
+
+    #[test]
+    fn write_batch_writes_all_or_nothing() {
+        let mvcc = accounts(4, 100);
+        let blocker = mvcc.begin_transaction();
+        blocker.get_for_update("accounts", 3).unwrap();
+
+        let batch = mvcc.begin_transaction();
+        let writes = vec![
+            (0, Some(vec![Value::Int(0)])),
+            (1, None),
+            (3, Some(vec![Value::Int(0)])),
+        ];
+        assert!(batch.write_batch("accounts", writes.clone()).is_err());
+        assert_eq!(Some(vec![Value::Int(100)]), batch.get("accounts", 0));
+
+        blocker.commit().unwrap();
+        batch.write_batch("accounts", writes).unwrap();
+        batch.commit().unwrap();
+        assert_eq!(
+            vec![
+                (0, vec![Value::Int(0)]),
+                (2, vec![Value::Int(100)]),
+                (3, vec![Value::Int(0)])
+            ],
+            mvcc.begin_read_only().scan("accounts").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn subscribers_receive_committed_changes() {
+        let mvcc = users();
+        let changes = mvcc.subscribe();
+        let first = mvcc.begin_transaction();
+        first.set("users", 1, name("Alice")).unwrap();
+        first.commit().unwrap();
+        let rolled_back = mvcc.begin_transaction();
+        rolled_back.set("users", 2, name("Bob")).unwrap();
+        rolled_back.rollback();
+        let second = mvcc.begin_transaction();
+        second.delete("users", 1).unwrap();
+        second.commit().unwrap();
+
+        let change = |old: Option<&str>, new: Option<&str>, version| Change {
+            table: "users".to_string(),
+            id: 1,
+            old: old.map(name),
+            new: new.map(name),
+            version,
+        };
+        assert_eq!(
+            vec![
+                change(None, Some("Alice"), first.version),
+                change(Some("Alice"), None, second.version),
+            ],
+            changes.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn recover_rebuilds_what_committed() {
+        let path = std::env::temp_dir().join(format!("mvcc-test-{}.wal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mvcc = users().with_wal(Wal::open(&path, SyncMode::OnCommit).unwrap());
+        let committed = mvcc.begin_transaction();
+        committed.set("users", 1, name("Alice")).unwrap();
+        committed.savepoint("alice").unwrap();
+        committed.set("users", 2, name("Bob")).unwrap();
+        committed.rollback_to("alice").unwrap();
+        committed.commit().unwrap();
+        let unfinished = mvcc.begin_transaction();
+        unfinished.set("users", 3, name("Charlie")).unwrap();
+
+        let recovered = users().recover(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            vec![(1, name("Alice"))],
+            recovered
+                .begin_read_only()
+                .scan("users")
+                .collect::<Vec<_>>()
+        );
+        // New transactions carry on after every version in the log.
+        assert!(recovered.begin_transaction().version > unfinished.version);
+    }
+
+    #[test]
+    fn expired_transactions_are_aborted() {
+        let mvcc = users().with_max_transaction_age(Duration::from_millis(10));
+        let abandoned = mvcc.begin_transaction();
+        abandoned.set("users", 1, name("Alice")).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(1, mvcc.abort_expired());
+        assert_eq!(Err(CommitError::TimedOut), abandoned.commit());
+
+        let next = mvcc.begin_transaction();
+        next.set("users", 1, name("Bob")).unwrap();
+        next.commit().unwrap();
+    }
+
+    // Move money between accounts on several threads, retrying whenever a transfer
+    // conflicts, while others keep checking that no snapshot ever sees money appear or
+    // disappear halfway through a transfer.
+    fn transfers_keep_the_total(mvcc: MVCC) {
+        const ACCOUNTS: u32 = 8;
+        const TRANSFERS: u32 = 100;
+        let mvcc = &mvcc;
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let writers: Vec<_> = (0..4)
+                .map(|thread| {
+                    scope.spawn(move || {
+                        for n in 0..TRANSFERS {
+                            let from = (thread + n) % ACCOUNTS;
+                            let to = (from + 1 + n % (ACCOUNTS - 1)) % ACCOUNTS;
+                            loop {
+                                let transfer = mvcc.begin_transaction();
+                                let moved = (|| {
+                                    let from_row = transfer.get_for_update("accounts", from)?;
+                                    let to_row = transfer.get_for_update("accounts", to)?;
+                                    let from_balance = balance(&from_row.unwrap()) - 1;
+                                    let to_balance = balance(&to_row.unwrap()) + 1;
+                                    transfer.set("accounts", from, vec![from_balance.into()])?;
+                                    transfer.set("accounts", to, vec![to_balance.into()])
+                                })();
+                                if moved.is_ok() && transfer.commit().is_ok() {
+                                    break;
+                                }
+                                transfer.rollback();
+                                thread::yield_now();
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            let readers: Vec<_> = (0..2)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut checks = 0;
+                        while !done.load(Ordering::SeqCst) || checks == 0 {
+                            let reader = mvcc.begin_read_only();
+                            let total: i64 =
+                                reader.scan("accounts").map(|(_, row)| balance(&row)).sum();
+                            assert_eq!(ACCOUNTS as i64 * 100, total);
+                            let transaction = mvcc.begin_transaction();
+                            let total: i64 = (0..ACCOUNTS)
+                                .map(|id| balance(&transaction.get("accounts", id).unwrap()))
+                                .sum();
+                            assert_eq!(ACCOUNTS as i64 * 100, total);
+                            // Only reading, it has nothing to commit, and an optimistic
+                            // one would fail validation if a transfer got in first.
+                            transaction.rollback();
+                            checks += 1;
+                        }
+                    })
+                })
+                .collect();
+
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            done.store(true, Ordering::SeqCst);
+            for reader in readers {
+                reader.join().unwrap();
+            }
+        });
+
+        let stats = mvcc.stats();
+        assert_eq!(0, stats.active_transactions);
+        assert!(stats.commits >= 4 * TRANSFERS as usize);
+    }
+
+    #[test]
+    fn concurrent_transfers_keep_every_snapshot_consistent() {
+        transfers_keep_the_total(accounts(8, 100));
+    }
+
+    #[test]
+    fn concurrent_transfers_keep_every_snapshot_consistent_when_waiting_for_locks() {
+        transfers_keep_the_total(accounts(8, 100).wait_for_locks());
+    }
+
+    #[test]
+    fn concurrent_transfers_keep_every_snapshot_consistent_when_optimistic() {
+        transfers_keep_the_total(accounts(8, 100).optimistic());
+    }
+
+    #[test]
+    fn concurrent_increments_are_never_lost() {
+        const THREADS: usize = 4;
+        const INCREMENTS: i64 = 50;
+        let mvcc = accounts(1, 0);
+
+        thread::scope(|scope| {
+            for _ in 0..THREADS {
+                scope.spawn(|| {
+                    for _ in 0..INCREMENTS {
+                        loop {
+                            let increment = mvcc.begin_transaction();
+                            let count = balance(&increment.get("accounts", 0).unwrap());
+                            if increment
+                                .set("accounts", 0, vec![(count + 1).into()])
+                                .is_ok()
+                                && increment.commit().is_ok()
+                            {
+                                break;
+                            }
+                            increment.rollback();
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+        });
+
+        let count = balance(&mvcc.begin_read_only().get("accounts", 0).unwrap());
+        assert_eq!(THREADS as i64 * INCREMENTS, count);
+    }
+
+    #[test]
+    fn a_transaction_on_another_thread_is_seen_all_at_once() {
+        const ROWS: u32 = 64;
+        let mvcc = users();
+        let written = Barrier::new(2);
+        let checked = Barrier::new(2);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let writer = mvcc.begin_transaction();
+                for id in 0..ROWS {
+                    writer.set("users", id, name("new")).unwrap();
+                }
+                written.wait();
+                checked.wait();
+                writer.commit().unwrap();
+            });
+
+            written.wait();
+            let before = mvcc.begin_read_only();
+            assert_eq!(0, before.scan("users").count());
+            checked.wait();
+
+            // Every later snapshot sees either none of the rows or all of them.
+            loop {
+                let seen = mvcc.begin_read_only().scan("users").count() as u32;
+                assert!(seen == 0 || seen == ROWS, "saw {} of the rows", seen);
+                if seen == ROWS {
+                    break;
+                }
+                thread::yield_now();
+            }
+            assert_eq!(0, before.scan("users").count());
+        });
+    }
+}
@@ -1,192 +1,89 @@
-use lazy_static::lazy_static;
-use std::collections::{HashMap, HashSet};
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Arc, Mutex};
-
-struct TableRow {
-    id: u32,
-    name: String,
-}
-
-pub struct TableStore {
-    rows: Vec<TableRow>,
-}
-
-// A globally incrementing version number.
-static VERSION: AtomicUsize = AtomicUsize::new(1);
-
-// Function to acquire the next version number.
-fn acquire_next_version() -> usize {
-    VERSION.fetch_add(1, Ordering::SeqCst)
-}
+use mvcc::{
+    ColumnType, Coordinator, IsolationLevel, Schema, Statement, SyncMode, Transaction, Value, Wal,
+    MVCC,
+};
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
 
-lazy_static! {
-    // Stores the currently active transaction IDs along with the versions they have written.
-    static ref ACTIVE_TXN: Arc<Mutex<HashMap<usize, Vec<(u32, String)>>>> = Arc::new(Mutex::new(HashMap::new()));
-}
-
-// Definition of an MVCC (Multi-Version Concurrency Control) transaction.
-pub struct MVCC {
-    table: Arc<Mutex<TableStore>>,
-}
-
-impl MVCC {
-    // Constructor for creating a new MVCC instance.
-    pub fn new(table: TableStore) -> Self {
-        Self {
-            table: Arc::new(Mutex::new(table)),
-        }
-    }
-
-    // Begin a new transaction.
-    pub fn begin_transaction(&self) -> Transaction {
-        Transaction::begin(self.table.clone())
-    }
-}
-
-// Representation of an MVCC transaction.
-pub struct Transaction {
-    // The underlying table store.
-    table: Arc<Mutex<TableStore>>,
-    // The version number assigned to this transaction.
-    version: usize,
-    // A list of active transaction IDs at the time the transaction was started.
-    active_xids: HashSet<usize>,
-}
-
-impl Transaction {
-    // Start a new transaction.
-    pub fn begin(table: Arc<Mutex<TableStore>>) -> Self {
-        // Obtain a global version number for the transaction.
-        let version = acquire_next_version();
-
-        let mut active_txns = ACTIVE_TXN.lock().unwrap();
-        // Collect all currently active transaction IDs.
-        let active_xids = active_txns.keys().cloned().collect();
-
-        // Add the current transaction ID to the list of active transactions.
-        active_txns.insert(version, Vec::new());
-
-        // Return the initialized transaction.
-        Self {
-            table,
-            version,
-            active_xids,
-        }
-    }
-
-    // Write data to the database within the scope of the transaction.
-    pub fn set(&self, id: u32, name: String) {
-        self.write(id, Some(name));
-    }
-
-    // Delete data from the database within the scope of the transaction.
-    pub fn delete(&self, id: u32) {
-        self.write(id, None);
-    }
-
-    // Internal method to perform write operations.
-    fn write(&self, id: u32, name: Option<String>) {
-        let mut table = self.table.lock().unwrap();
-        match name {
-            Some(n) => {
-                // Find the index of the row with the given ID.
-                let idx = table.rows.iter().position(|r| r.id == id);
-                if let Some(idx) = idx {
-                    // Replace the existing row with the new name.
-                    table.rows[idx] = TableRow { id, name: n };
-                } else {
-                    // Insert a new row if the ID doesn't exist.
-                    table.rows.push(TableRow { id, name: n });
-                }
-            }
-            None => {
-                // Remove the row with the given ID.
-                table.rows.retain(|r| r.id != id);
-            }
-        }
-    }
-
-    // Read data from the database, starting from the most recent version and stopping at the first visible one.
-    pub fn get(&self, id: u32) -> Option<String> {
-        let table = self.table.lock().unwrap();
-        for row in &table.rows {
-            if row.id == id && self.is_visible(version) {
-                return Some(row.name.clone());
-            }
-        }
-        None
+fn main() {
+    // `mvcc shell` reads queries from the terminal instead of running the demo.
+    if std::env::args().nth(1).as_deref() == Some("shell") {
+        shell();
+        return;
     }
 
-    // Commit the transaction, removing it from the list of active transactions.
-    pub fn commit(&self) {
-        let mut active_txns = ACTIVE_TXN.lock().unwrap();
-        active_txns.remove(&self.version);
-    }
+    // Log everything to a fresh write-ahead log in the temporary directory.
+    let wal_path = std::env::temp_dir().join("mvcc-demo.wal");
+    let _ = std::fs::remove_file(&wal_path);
+    let wal = Wal::open(&wal_path, SyncMode::OnCommit).unwrap();
 
-    // Rollback the transaction, undoing any writes made during the transaction.
-    pub fn rollback(&self) {
-        let mut active_txns = ACTIVE_TXN.lock().unwrap();
-        if let Some(entries) = active_txns.get(&self.version) {
-            let mut table = self.table.lock().unwrap();
-            for (id, name) in entries {
-                // Restore the state of the table to before the transaction.
-                table.rows.retain(|r| r.id != *id);
-            }
-        }
-        active_txns.remove(&self.version);
-    }
+    // Create an instance of the MVCC system with a table of users.
+    let mvcc = MVCC::new().with_wal(wal);
+    mvcc.create_table("users", Schema::new().column("name", ColumnType::Text));
 
-    // Determine whether a version of data is visible to the current transaction.
-    fn is_visible(&self, version: usize) -> bool {
-        if self.active_xids.contains(&version) {
-            return false;
-        }
-        version <= self.version
-    }
-}
+    // Index the users by name, so they can be looked up by it as well as by ID.
+    mvcc.create_index("users", "name", "name");
 
-fn main() {
-    // Initialize the table store.
-    let table_store = TableStore { rows: Vec::new() };
-
-    // Create an instance of the MVCC system using the initialized table store.
-    let mvcc = MVCC::new(table_store);
+    // Follow every change committed from here on, as a copy of the store elsewhere would.
+    let changes = mvcc.subscribe();
 
     // Start a new transaction.
     let transaction1 = mvcc.begin_transaction();
 
     // Perform set operations within the transaction.
-    transaction1.set(1, "Alice".into());
-    transaction1.set(2, "Bob".into());
-    transaction1.set(3, "Charlie".into());
+    transaction1.set("users", 1, vec!["Alice".into()]).unwrap();
+    transaction1.set("users", 2, vec!["Bob".into()]).unwrap();
+    transaction1
+        .set("users", 3, vec!["Charlie".into()])
+        .unwrap();
+
+    // Add a fourth row as well, then think better of it.
+    transaction1.savepoint("before_dan").unwrap();
+    transaction1.set("users", 4, vec!["Dan".into()]).unwrap();
+    transaction1.set("users", 1, vec!["Alicia".into()]).unwrap();
+    transaction1.rollback_to("before_dan").unwrap();
 
     // Print the current state of the table store to verify the set operations.
     println!("After Transaction1 sets:");
-    for row in &mvcc.table.lock().unwrap().rows {
-        println!("ID: {}, Name: {}", row.id, row.name);
+    print_versions(&mvcc, "users");
+
+    // Commit the first transaction, making its rows visible to later transactions.
+    let info = transaction1.commit().unwrap();
+    println!(
+        "Transaction{} committed {} write(s)",
+        info.version, info.writes
+    );
+
+    // Once it has committed, it can't write anything else.
+    if let Err(err) = transaction1.set("users", 4, vec!["Dan".into()]) {
+        println!("Transaction1 can't set ID 4: {}", err);
     }
 
+    // A read-only transaction keeps seeing the rows as Transaction1 left them.
+    let reader = mvcc.begin_read_only();
+
     // Start another transaction.
     let transaction2 = mvcc.begin_transaction();
 
     // Perform a delete operation within the second transaction.
-    transaction2.delete(2);
+    transaction2.delete("users", 2).unwrap();
 
     // Print the current state of the table store to verify the delete operation.
     println!("After Transaction2 deletes ID 2:");
-    for row in &mvcc.table.lock().unwrap().rows {
-        println!("ID: {}, Name: {}", row.id, row.name);
-    }
-
-    // Commit the first transaction.
-    transaction1.commit();
+    print_versions(&mvcc, "users");
 
-    // Verify that the commit makes the changes visible to subsequent transactions.
+    // Transaction2 hasn't committed, so Transaction3 still sees the row it deleted.
     let transaction3 = mvcc.begin_transaction();
-    println!("After Transaction1 commits, Transaction3 sees:");
-    for row in &mvcc.table.lock().unwrap().rows {
-        println!("ID: {}, Name: {}", row.id, row.name);
+    println!("Transaction3 sees:");
+    print_snapshot(&transaction3, "users");
+    println!("Transaction2 sees:");
+    print_snapshot(&transaction2, "users");
+
+    // Transaction2 deleted ID 2 first, so Transaction3 can't change it until Transaction2
+    // has finished.
+    if let Err(err) = transaction3.set("users", 2, vec!["Robert".into()]) {
+        println!("Transaction3 can't rename ID 2: {}", err);
     }
 
     // Attempt to roll back the second transaction.
@@ -194,10 +91,464 @@ fn main() {
 
     // Verify that the rollback undoes the delete operation.
     println!("After Transaction2 rolls back, the table state is:");
-    for row in &mvcc.table.lock().unwrap().rows {
-        println!("ID: {}, Name: {}", row.id, row.name);
+    print_versions(&mvcc, "users");
+
+    // With Transaction2 out of the way, Transaction3 can rename ID 2 after all. The old
+    // version stays behind until no transaction can see it, and vacuum cleans it up.
+    transaction3.set("users", 2, vec!["Robert".into()]).unwrap();
+    transaction3.commit().unwrap();
+
+    // A transaction that is dropped without committing is rolled back.
+    {
+        let forgotten = mvcc.begin_transaction();
+        forgotten.set("users", 4, vec!["Dan".into()]).unwrap();
+    }
+    println!(
+        "After Transaction3 renames ID 2, vacuum removes {} version(s):",
+        mvcc.vacuum()
+    );
+    print_versions(&mvcc, "users");
+
+    // The reader still sees the old name, which vacuum keeps until it is done.
+    println!("The reader still sees ID 2 as {:?}", reader.get("users", 2));
+    println!(
+        "and IDs 2 and up as {:?}",
+        reader.range("users", 2..).collect::<Vec<_>>()
+    );
+    println!(
+        "and still finds {:?} by the name Bob",
+        reader
+            .get_by_index("users", "name", "Bob")
+            .collect::<Vec<_>>()
+    );
+    drop(reader);
+    let stats = mvcc.stats();
+    println!(
+        "After {} commit(s) and {} abort(s), {} of the {} version(s) stored are dead",
+        stats.commits, stats.aborts, stats.dead_versions, stats.versions
+    );
+    println!(
+        "Once the reader is done, vacuum removes {} version(s):",
+        mvcc.vacuum()
+    );
+    print_versions(&mvcc, "users");
+    let latest = mvcc.begin_read_only();
+    println!(
+        "Now the name Bob finds {:?}, and Robert finds {:?}",
+        latest
+            .get_by_index("users", "name", "Bob")
+            .collect::<Vec<_>>(),
+        latest
+            .get_by_index("users", "name", "Robert")
+            .collect::<Vec<_>>()
+    );
+    drop(latest);
+
+    // One transaction can write to several tables, and its writes to all of them commit
+    // or roll back together. Each table has its own columns.
+    mvcc.create_table(
+        "orders",
+        Schema::new()
+            .column("customer", ColumnType::Text)
+            .column("item", ColumnType::Text)
+            .column("quantity", ColumnType::Int)
+            .column("paid", ColumnType::Bool),
+    );
+    let checkout = mvcc.begin_transaction();
+    checkout
+        .set(
+            "orders",
+            1,
+            vec!["Alice".into(), "books".into(), Value::Int(2), false.into()],
+        )
+        .unwrap();
+    checkout
+        .set("users", 1, vec!["Alice (customer)".into()])
+        .unwrap();
+    checkout.rollback();
+    let checkout = mvcc.begin_transaction();
+    checkout
+        .set(
+            "orders",
+            1,
+            vec!["Charlie".into(), "lamp".into(), Value::Int(1), true.into()],
+        )
+        .unwrap();
+    // Rows that don't fit the table are turned away.
+    if let Err(err) = checkout.set("orders", 2, vec!["Charlie".into(), "lamp".into()]) {
+        println!("The checkout can't add a second order: {}", err);
+    }
+    checkout
+        .set("users", 3, vec!["Charlie (customer)".into()])
+        .unwrap();
+    checkout.commit().unwrap();
+    let latest = mvcc.begin_read_only();
+    println!(
+        "After one checkout rolls back and another commits, the orders are {:?} and the users {:?}",
+        latest.scan("orders").collect::<Vec<_>>(),
+        latest.scan("users").collect::<Vec<_>>()
+    );
+    drop(latest);
+
+    // Only what committed reached the subscriber, in the order it committed.
+    println!("The subscriber has received:");
+    for change in changes.try_iter() {
+        println!("{:?}", change);
     }
 
+    // Everything above was recorded in the write-ahead log.
+    println!("The write-ahead log holds:");
+    for record in Wal::read(&wal_path).unwrap() {
+        println!("{:?}", record);
+    }
+
+    // A transaction still running when the process stops never committed. Recovering a
+    // fresh store from the log, as after a crash, leaves it out and brings back
+    // everything else as it was committed. Neither tables nor indexes are logged, so
+    // they have to be created again, the tables before recovering.
+    let unfinished = mvcc.begin_transaction();
+    unfinished
+        .set(
+            "orders",
+            2,
+            vec!["Bob".into(), "pens".into(), Value::Int(3), false.into()],
+        )
+        .unwrap();
+    let recovered = MVCC::new();
+    for table in ["users", "orders"] {
+        recovered.create_table(table, mvcc.schema(table).unwrap());
+    }
+    let recovered = recovered.recover(&wal_path).unwrap();
+    recovered.create_index("users", "name", "name");
+    let latest = recovered.begin_read_only();
+    println!(
+        "Recovered from the log, the orders are {:?} and the users {:?}, with {:?} named Robert",
+        latest.scan("orders").collect::<Vec<_>>(),
+        latest.scan("users").collect::<Vec<_>>(),
+        latest
+            .get_by_index("users", "name", "Robert")
+            .collect::<Vec<_>>()
+    );
+    drop(latest);
+    drop(unfinished);
+
+    // The recovered store hasn't been vacuumed, so it can still be read as it was when
+    // Transaction1 committed. The original store has vacuumed those rows away.
+    let history = recovered.begin_at(info.version).unwrap();
+    println!(
+        "As of Transaction{}, the users were {:?}",
+        info.version,
+        history.scan("users").collect::<Vec<_>>()
+    );
+    if let Err(err) = mvcc.begin_at(info.version) {
+        println!("The original store can't go back that far: {}", err);
+    }
+
+    // Snapshots alone allow write skew. Two doctors are on call, and each checks that the
+    // other still is before going off call themselves, so neither sees the other leave.
+    // A serializable store lets only one of them go.
+    let rota = MVCC::new().serializable();
+    rota.create_table("doctors", Schema::new().column("status", ColumnType::Text));
+    let setup = rota.begin_transaction();
+    setup.set("doctors", 1, vec!["on call".into()]).unwrap();
+    setup.set("doctors", 2, vec!["on call".into()]).unwrap();
+    setup.commit().unwrap();
+
+    let doctor1 = rota.begin_transaction();
+    let doctor2 = rota.begin_transaction();
+    println!("Doctor 1 sees doctor 2 {:?}", doctor1.get("doctors", 2));
+    println!("Doctor 2 sees doctor 1 {:?}", doctor2.get("doctors", 1));
+    doctor1.set("doctors", 1, vec!["off call".into()]).unwrap();
+    if let Err(err) = doctor2.set("doctors", 2, vec!["off call".into()]) {
+        println!("Doctor 2 can't go off call: {}", err);
+        doctor2.rollback();
+    }
+    doctor1.commit().unwrap();
+    println!("The rota ends up as:");
+    for (id, status) in rota.begin_read_only().scan("doctors") {
+        println!("Doctor {} is {}", id, status[0]);
+    }
+
+    // Each transaction can choose how isolated it is. A read committed one sees every
+    // commit as soon as it happens, while one with snapshot isolation keeps seeing the
+    // rows as they were when it began.
+    let shop = MVCC::new();
+    shop.create_table("prices", Schema::new().column("price", ColumnType::Int));
+    let setup = shop.begin_transaction();
+    setup.set("prices", 1, vec![Value::Int(10)]).unwrap();
+    setup.commit().unwrap();
+    let read_committed = shop.begin_with_isolation(IsolationLevel::ReadCommitted);
+    let snapshot = shop.begin_with_isolation(IsolationLevel::SnapshotIsolation);
+    println!(
+        "Read committed sees the price as {:?}, and so does snapshot isolation: {:?}",
+        read_committed.get("prices", 1),
+        snapshot.get("prices", 1)
+    );
+    let update = shop.begin_transaction();
+    update.set("prices", 1, vec![Value::Int(12)]).unwrap();
+    update.commit().unwrap();
+    println!(
+        "Once it goes up, read committed sees {:?}, while snapshot isolation still sees {:?}",
+        read_committed.get("prices", 1),
+        snapshot.get("prices", 1)
+    );
+    if let Err(err) = read_committed.set("prices", 1, vec!["15".into()]) {
+        println!("Read committed can't set the price to text: {}", err);
+    }
+    read_committed
+        .set("prices", 1, vec![Value::Int(15)])
+        .unwrap();
+    read_committed.commit().unwrap();
+    if let Err(err) = snapshot.set("prices", 1, vec![Value::Int(11)]) {
+        println!("Snapshot isolation can't change the price: {}", err);
+    }
+    drop(snapshot);
+
+    // A transaction that is left open for too long gets aborted, so it can't keep others
+    // from writing the rows it wrote.
+    let store = MVCC::new().with_max_transaction_age(Duration::from_millis(10));
+    store.create_table("users", Schema::new().column("name", ColumnType::Text));
+    let abandoned = store.begin_transaction();
+    abandoned.set("users", 1, vec!["Dave".into()]).unwrap();
+    thread::sleep(Duration::from_millis(20));
+    println!(
+        "{} transaction(s) ran for too long and were aborted",
+        store.abort_expired()
+    );
+    if let Err(err) = abandoned.commit() {
+        println!("The abandoned transaction can't commit: {}", err);
+    }
+    let next = store.begin_transaction();
+    next.set("users", 1, vec!["Erin".into()]).unwrap();
+    next.commit().unwrap();
+    print_versions(&store, "users");
+
+    // Optimistic transactions keep their writes to themselves until they commit, and
+    // only find out about conflicts then.
+    let accounts = MVCC::new().optimistic();
+    accounts.create_table("accounts", Schema::new().column("owner", ColumnType::Text));
+    let setup = accounts.begin_transaction();
+    setup.set("accounts", 1, vec!["Alice".into()]).unwrap();
+    setup.commit().unwrap();
+
+    let clerk1 = accounts.begin_transaction();
+    let clerk2 = accounts.begin_transaction();
+    clerk1
+        .set(
+            "accounts",
+            1,
+            vec![format!("{} Smith", clerk1.get("accounts", 1).unwrap()[0]).into()],
+        )
+        .unwrap();
+    clerk2
+        .set(
+            "accounts",
+            1,
+            vec![format!("{} Jones", clerk2.get("accounts", 1).unwrap()[0]).into()],
+        )
+        .unwrap();
+    println!(
+        "Clerk 1 sees {:?}, clerk 2 sees {:?}, and everyone else still sees {:?}",
+        clerk1.get("accounts", 1),
+        clerk2.get("accounts", 1),
+        accounts.begin_read_only().get("accounts", 1)
+    );
+    clerk1.commit().unwrap();
+    if let Err(err) = clerk2.commit() {
+        println!("Clerk 2 can't commit: {}", err);
+    }
+    print_versions(&accounts, "accounts");
+
+    // A transaction can lock a row it is about to change, so nobody else can change it
+    // first. By default anyone else who wants the row is turned away.
+    let seats = MVCC::new();
+    seats.create_table("seats", Schema::new().column("status", ColumnType::Text));
+    // Many rows can be written in one batch, which is written in full or not at all.
+    let setup = seats.begin_transaction();
+    let free = || Some(vec![Value::from("free")]);
+    setup
+        .write_batch("seats", vec![(1, free()), (2, free())])
+        .unwrap();
+    if let Err(err) = setup.write_batch("seats", vec![(3, free()), (4, Some(vec![4.into()]))]) {
+        println!("Can't add seats 3 and 4: {}", err);
+    }
+    println!("Seats: {:?}", setup.scan("seats").collect::<Vec<_>>());
+    setup.commit().unwrap();
+
+    let booking1 = seats.begin_transaction();
+    let booking2 = seats.begin_transaction();
+    println!(
+        "Booking 1 locks seat 1, which is {:?}",
+        booking1.get_for_update("seats", 1).unwrap()
+    );
+    if let Err(err) = booking2.get_for_update("seats", 1) {
+        println!("Booking 2 can't lock seat 1: {}", err);
+    }
+    booking1.set("seats", 1, vec!["taken".into()]).unwrap();
+    booking1.commit().unwrap();
+    booking2.rollback();
+
+    // A store can have them wait for the row instead, and catches them waiting for each
+    // other.
+    let seats = MVCC::new().wait_for_locks();
+    seats.create_table("seats", Schema::new().column("status", ColumnType::Text));
+    let booking1 = seats.begin_transaction();
+    let booking2 = seats.begin_transaction();
+    booking1.get_for_update("seats", 1).unwrap();
+    booking2.get_for_update("seats", 2).unwrap();
+    thread::scope(|scope| {
+        let waiter = scope.spawn(|| booking1.get_for_update("seats", 2));
+        // Give booking 1 time to start waiting for booking 2.
+        thread::sleep(Duration::from_millis(50));
+        if let Err(err) = booking2.get_for_update("seats", 1) {
+            println!("Booking 2 can't lock seat 1: {}", err);
+        }
+        booking2.rollback();
+        println!(
+            "Once booking 2 rolls back, booking 1 locks seat 2: {:?}",
+            waiter.join().unwrap()
+        );
+    });
+    booking1.commit().unwrap();
+
+    // A coordinator commits transactions in several stores together: a transfer between
+    // two banks either leaves both accounts changed or neither.
+    let banks = [MVCC::new(), MVCC::new().optimistic()];
+    for bank in &banks {
+        bank.create_table("accounts", Schema::new().column("balance", ColumnType::Int));
+        let setup = bank.begin_transaction();
+        setup.set("accounts", 1, vec![Value::Int(100)]).unwrap();
+        setup.commit().unwrap();
+    }
+    let transfer = |amount: i64| {
+        let coordinator = Coordinator::begin(&[&banks[0], &banks[1]]);
+        for (bank, change) in [(0, -amount), (1, amount)] {
+            let transaction = coordinator.transaction(bank);
+            let Value::Int(balance) = transaction.get("accounts", 1).unwrap()[0] else {
+                unreachable!("balances are ints");
+            };
+            transaction
+                .set("accounts", 1, vec![Value::Int(balance + change)])
+                .unwrap();
+        }
+        coordinator
+    };
+    transfer(30).commit().unwrap();
+    // A deposit commits in the second bank while the next transfer is still running, so
+    // that one fails there and is rolled back in the first bank too.
+    let second = transfer(20);
+    let deposit = banks[1].begin_transaction();
+    deposit.set("accounts", 1, vec![Value::Int(200)]).unwrap();
+    deposit.commit().unwrap();
+    if let Err(err) = second.commit() {
+        println!("The second transfer can't commit: {}", err);
+    }
+    println!(
+        "The balances are {:?} and {:?}",
+        banks[0].begin_read_only().get("accounts", 1),
+        banks[1].begin_read_only().get("accounts", 1)
+    );
+
+    // Queries can also be written out, and run within a transaction like anything else.
+    let transaction = mvcc.begin_transaction();
+    for query in [
+        "INSERT INTO users VALUES (4, 'Dan'), (5, 'Eve')",
+        "DELETE FROM users WHERE id = 1",
+        "SELECT name FROM users WHERE id >= 3",
+        "SELECT * FROM users WHERE name = 'Robert'",
+        "SELECT age FROM users",
+        "SELECT * FROM customers",
+        "INSERT INTO orders VALUES (2, 'Dan', 'pens', 3, false), (3, 'Eve', 'mug', 1, true)",
+        "SELECT customer, quantity FROM orders WHERE paid = true",
+        "INSERT INTO orders VALUES (4, 'Eve', 'lamp', 'two', false)",
+    ] {
+        println!("> {}", query);
+        match Statement::parse(query).and_then(|statement| statement.execute(&transaction)) {
+            Ok(output) => println!("{}", output),
+            Err(err) => println!("Error: {}", err),
+        }
+    }
+    transaction.commit().unwrap();
+
     // Clean up the MVCC instance.
     drop(mvcc);
 }
+
+// Run the queries typed in, one per line, against a store with an empty table of users.
+// Each query runs in a transaction of its own, unless one has been started with BEGIN,
+// in which case they all run in it until COMMIT or ROLLBACK.
+fn shell() {
+    let mvcc = MVCC::new();
+    mvcc.create_table("users", Schema::new().column("name", ColumnType::Text));
+    let mut open: Option<Transaction> = None;
+
+    for line in std::io::stdin().lines() {
+        let line = line.unwrap();
+        let command = line.trim().trim_end_matches(';').trim();
+        if command.is_empty() {
+            continue;
+        }
+
+        if command.eq_ignore_ascii_case("BEGIN") {
+            if open.is_some() {
+                println!("Error: a transaction is already open");
+            } else {
+                open = Some(mvcc.begin_transaction());
+            }
+        } else if command.eq_ignore_ascii_case("COMMIT") {
+            match open.take().map(|transaction| transaction.commit()) {
+                Some(Ok(info)) => println!("Committed {} write(s)", info.writes),
+                Some(Err(err)) => println!("Error: {}", err),
+                None => println!("Error: no transaction is open"),
+            }
+        } else if command.eq_ignore_ascii_case("ROLLBACK") {
+            match open.take() {
+                Some(transaction) => transaction.rollback(),
+                None => println!("Error: no transaction is open"),
+            }
+        } else {
+            let statement = match Statement::parse(command) {
+                Ok(statement) => statement,
+                Err(err) => {
+                    println!("Error: {}", err);
+                    continue;
+                }
+            };
+            let output: Result<_, Box<dyn Error>> = match &open {
+                Some(transaction) => statement.execute(transaction).map_err(Into::into),
+                None => {
+                    let transaction = mvcc.begin_transaction();
+                    statement
+                        .execute(&transaction)
+                        .map_err(Into::into)
+                        .and_then(|output| {
+                            transaction.commit()?;
+                            Ok(output)
+                        })
+                }
+            };
+            match output {
+                Ok(output) => println!("{}", output),
+                Err(err) => println!("Error: {}", err),
+            }
+        }
+    }
+}
+
+// Print the rows of a table a transaction can see.
+fn print_snapshot(transaction: &Transaction, table: &str) {
+    for (id, row) in transaction.scan(table) {
+        println!("ID: {}, Row: {:?}", id, row);
+    }
+}
+
+// Print every version of every row of a table, including those that have been overwritten
+// or deleted.
+fn print_versions(mvcc: &MVCC, table: &str) {
+    for version in mvcc.versions(table) {
+        println!(
+            "ID: {}, Row: {:?}, Created by: {}, Deleted by: {:?}",
+            version.id, version.row, version.created_by, version.deleted_by
+        );
+    }
+}
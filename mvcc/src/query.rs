@@ -0,0 +1,576 @@
+use super::schema::{Row, Schema, Value};
+use super::{IdRange, Transaction, TransactionError};
+use std::error::Error;
+use std::fmt;
+use std::iter::Peekable;
+use std::ops::Bound;
+use std::str::Chars;
+
+// Why a query couldn't be run.
+#[derive(Debug)]
+pub enum QueryError {
+    // The query isn't one this front end understands.
+    Parsing(String),
+    // The query names a column its table doesn't have.
+    UnknownColumn { table: String, column: String },
+    // The query was understood, but the transaction couldn't carry it out.
+    Transaction(TransactionError),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::Parsing(description) => f.write_str(description),
+            QueryError::UnknownColumn { table, column } => {
+                write!(f, "{} has no column named {:?}", table, column)
+            }
+            QueryError::Transaction(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for QueryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            QueryError::Parsing(_) | QueryError::UnknownColumn { .. } => None,
+            QueryError::Transaction(err) => Some(err),
+        }
+    }
+}
+
+impl From<TransactionError> for QueryError {
+    fn from(err: TransactionError) -> Self {
+        QueryError::Transaction(err)
+    }
+}
+
+// The tokens a query is made up of.
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    // A keyword, or the name of a table or column.
+    Word(String),
+    Number(i64),
+    // A string in single quotes, with any doubled quotes inside it made single.
+    Text(String),
+    Star,
+    Comma,
+    Equals,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    LeftParenthesis,
+    RightParenthesis,
+    Semicolon,
+    // A character no token starts with, a number too large for an int, or a string that
+    // is never closed.
+    Invalid(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Text(text) => write!(f, "'{}'", text.replace('\'', "''")),
+            Token::Star => write!(f, "*"),
+            Token::Comma => write!(f, ","),
+            Token::Equals => write!(f, "="),
+            Token::Less => write!(f, "<"),
+            Token::LessOrEqual => write!(f, "<="),
+            Token::Greater => write!(f, ">"),
+            Token::GreaterOrEqual => write!(f, ">="),
+            Token::LeftParenthesis => write!(f, "("),
+            Token::RightParenthesis => write!(f, ")"),
+            Token::Semicolon => write!(f, ";"),
+            Token::Invalid(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+// Splits a query into tokens, peeking at each character before taking it so a token can
+// end without the character after it being lost.
+struct Tokenizer<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        match self.chars.peek()? {
+            c if c.is_ascii_digit() => Some(self.scan_number()),
+            c if c.is_alphabetic() || *c == '_' => Some(self.scan_word()),
+            '\'' => Some(self.scan_text()),
+            _ => Some(self.scan_symbol()),
+        }
+    }
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(query: &'a str) -> Self {
+        Self {
+            chars: query.chars().peekable(),
+        }
+    }
+
+    fn scan_number(&mut self) -> Token {
+        let mut digits = String::new();
+        while let Some(c) = self.chars.next_if(char::is_ascii_digit) {
+            digits.push(c);
+        }
+        // Only too many digits can make this fail.
+        match digits.parse() {
+            Ok(n) => Token::Number(n),
+            Err(_) => Token::Invalid(digits),
+        }
+    }
+
+    fn scan_word(&mut self) -> Token {
+        let mut word = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+            word.push(c);
+        }
+        Token::Word(word)
+    }
+
+    fn scan_text(&mut self) -> Token {
+        // The opening quote.
+        self.chars.next();
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                // Two quotes in a row stand for one inside the string.
+                Some('\'') if self.chars.next_if_eq(&'\'').is_some() => text.push('\''),
+                Some('\'') => return Token::Text(text),
+                Some(c) => text.push(c),
+                None => return Token::Invalid(format!("'{}", text)),
+            }
+        }
+    }
+
+    fn scan_symbol(&mut self) -> Token {
+        match self.chars.next().unwrap() {
+            '*' => Token::Star,
+            ',' => Token::Comma,
+            '=' => Token::Equals,
+            '<' if self.chars.next_if_eq(&'=').is_some() => Token::LessOrEqual,
+            '<' => Token::Less,
+            '>' if self.chars.next_if_eq(&'=').is_some() => Token::GreaterOrEqual,
+            '>' => Token::Greater,
+            '(' => Token::LeftParenthesis,
+            ')' => Token::RightParenthesis,
+            ';' => Token::Semicolon,
+            c => Token::Invalid(c.to_string()),
+        }
+    }
+}
+
+// A column of a table, as a query names it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Column {
+    // The row's ID, which every table has.
+    Id,
+    // One of the columns in the table's schema, whose name is matched case insensitively.
+    Named(String),
+    // `*`: the ID, followed by every column in the schema.
+    All,
+}
+
+// Which rows a query applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    // The rows with IDs in a range, which is every row if there was no WHERE clause.
+    Ids(IdRange),
+    // The rows whose value in the given column is the given value.
+    Equals(String, Value),
+}
+
+// A parsed query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    // SELECT id, column, ... FROM table WHERE ...
+    Select {
+        columns: Vec<Column>,
+        table: String,
+        filter: Filter,
+    },
+    // INSERT INTO table VALUES (id, value, ...), ...
+    Insert {
+        table: String,
+        rows: Vec<(u32, Row)>,
+    },
+    // DELETE FROM table WHERE ...
+    Delete {
+        table: String,
+        filter: Filter,
+    },
+}
+
+// What running a query gave back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    // The rows a SELECT found, in order of ID, with just the columns it asked for.
+    Rows(Vec<Vec<String>>),
+    // How many rows an INSERT or DELETE wrote.
+    Written(usize),
+}
+
+impl fmt::Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Output::Rows(rows) => {
+                for row in rows {
+                    writeln!(f, "{}", row.join(" | "))?;
+                }
+                write!(f, "({} row(s))", rows.len())
+            }
+            Output::Written(count) => write!(f, "{} row(s) written", count),
+        }
+    }
+}
+
+impl Statement {
+    // Parse a single query. Keywords and column names are case insensitive, text is
+    // written in single quotes, bools as true or false, and a trailing semicolon is
+    // optional.
+    pub fn parse(query: &str) -> Result<Statement, QueryError> {
+        let mut parser = Parser {
+            iter: Tokenizer::new(query).peekable(),
+        };
+        let statement = parser.parse_statement()?;
+        parser.iter.next_if_eq(&Token::Semicolon);
+        // If there are still tokens left over, the query didn't end where it should have.
+        match parser.iter.next() {
+            Some(token) => Err(unexpected(Some(token), "the end of the query")),
+            None => Ok(statement),
+        }
+    }
+
+    // Run the query within `transaction`. INSERT sets rows whether or not they exist
+    // already, and fails if one doesn't fit the table's schema.
+    pub fn execute(&self, transaction: &Transaction) -> Result<Output, QueryError> {
+        match self {
+            Statement::Select {
+                columns,
+                table,
+                filter,
+            } => {
+                // Where each column is in a row, or `None` for the ID.
+                let schema = schema(transaction, table)?;
+                let mut positions = Vec::new();
+                for column in columns {
+                    match column {
+                        Column::Id => positions.push(None),
+                        Column::Named(name) => {
+                            positions.push(Some(position(&schema, table, name)?))
+                        }
+                        Column::All => {
+                            positions.push(None);
+                            positions.extend((0..schema.columns().len()).map(Some));
+                        }
+                    }
+                }
+                let rows = matching(transaction, table, filter)?
+                    .into_iter()
+                    .map(|(id, row)| {
+                        positions
+                            .iter()
+                            .map(|position| match position {
+                                Some(position) => row[*position].to_string(),
+                                None => id.to_string(),
+                            })
+                            .collect()
+                    })
+                    .collect();
+                Ok(Output::Rows(rows))
+            }
+            Statement::Insert { table, rows } => {
+                for (id, row) in rows {
+                    transaction.set(table, *id, row.clone())?;
+                }
+                Ok(Output::Written(rows.len()))
+            }
+            Statement::Delete { table, filter } => {
+                let rows = matching(transaction, table, filter)?;
+                for (id, _) in &rows {
+                    transaction.delete(table, *id)?;
+                }
+                Ok(Output::Written(rows.len()))
+            }
+        }
+    }
+}
+
+// The rows of `table` that `filter` picks out, as `transaction` sees them.
+fn matching(
+    transaction: &Transaction,
+    table: &str,
+    filter: &Filter,
+) -> Result<Vec<(u32, Row)>, QueryError> {
+    // Reading a table that doesn't exist panics, which a mistyped query shouldn't.
+    let schema = schema(transaction, table)?;
+    Ok(match filter {
+        Filter::Ids(range) => transaction.range(table, *range).collect(),
+        Filter::Equals(column, wanted) => {
+            let position = position(&schema, table, column)?;
+            transaction
+                .scan(table)
+                .filter(|(_, row)| row[position] == *wanted)
+                .collect()
+        }
+    })
+}
+
+// The schema of `table`, which fails the query if there is no such table.
+fn schema(transaction: &Transaction, table: &str) -> Result<Schema, QueryError> {
+    transaction
+        .schema(table)
+        .ok_or_else(|| TransactionError::UnknownTable(table.to_string()).into())
+}
+
+// Where the column called `column` is in the rows of `table`, which has `schema`.
+fn position(schema: &Schema, table: &str, column: &str) -> Result<usize, QueryError> {
+    schema
+        .columns()
+        .iter()
+        .position(|(name, _)| name.eq_ignore_ascii_case(column))
+        .ok_or_else(|| QueryError::UnknownColumn {
+            table: table.to_string(),
+            column: column.to_string(),
+        })
+}
+
+// The error for finding `token` where `expected` should have been.
+fn unexpected(token: Option<Token>, expected: &str) -> QueryError {
+    let found = match token {
+        Some(token) => format!("\"{}\"", token),
+        None => "the end of the query".to_string(),
+    };
+    QueryError::Parsing(format!("expected {}, found {}", expected, found))
+}
+
+struct Parser<'a> {
+    iter: Peekable<Tokenizer<'a>>,
+}
+
+impl Parser<'_> {
+    fn parse_statement(&mut self) -> Result<Statement, QueryError> {
+        match self.iter.next() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("SELECT") => {
+                let columns = self.parse_columns()?;
+                self.expect_keyword("FROM")?;
+                let table = self.expect_word("a table name")?;
+                let filter = self.parse_filter()?;
+                Ok(Statement::Select {
+                    columns,
+                    table,
+                    filter,
+                })
+            }
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("INSERT") => {
+                self.expect_keyword("INTO")?;
+                let table = self.expect_word("a table name")?;
+                self.expect_keyword("VALUES")?;
+                let mut rows = vec![self.parse_row()?];
+                while self.iter.next_if_eq(&Token::Comma).is_some() {
+                    rows.push(self.parse_row()?);
+                }
+                Ok(Statement::Insert { table, rows })
+            }
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("DELETE") => {
+                self.expect_keyword("FROM")?;
+                let table = self.expect_word("a table name")?;
+                let filter = self.parse_filter()?;
+                Ok(Statement::Delete { table, filter })
+            }
+            token => Err(unexpected(token, "SELECT, INSERT or DELETE")),
+        }
+    }
+
+    // `*`, or one or more columns separated by commas.
+    fn parse_columns(&mut self) -> Result<Vec<Column>, QueryError> {
+        if self.iter.next_if_eq(&Token::Star).is_some() {
+            return Ok(vec![Column::All]);
+        }
+        let mut columns = vec![self.parse_column()?];
+        while self.iter.next_if_eq(&Token::Comma).is_some() {
+            columns.push(self.parse_column()?);
+        }
+        Ok(columns)
+    }
+
+    fn parse_column(&mut self) -> Result<Column, QueryError> {
+        match self.iter.next() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("id") => Ok(Column::Id),
+            Some(Token::Word(word)) => Ok(Column::Named(word)),
+            token => Err(unexpected(token, "a column name")),
+        }
+    }
+
+    // An optional `WHERE id <op> <number>` or `WHERE <column> = <value>`, where the
+    // operator is one of =, <, <=, > and >=.
+    fn parse_filter(&mut self) -> Result<Filter, QueryError> {
+        let has_where = self
+            .iter
+            .next_if(
+                |token| matches!(token, Token::Word(word) if word.eq_ignore_ascii_case("WHERE")),
+            )
+            .is_some();
+        if !has_where {
+            return Ok(Filter::Ids((Bound::Unbounded, Bound::Unbounded)));
+        }
+
+        match self.parse_column()? {
+            Column::Id => {
+                let operator = self.iter.next();
+                let id = self.parse_id()?;
+                let range = match operator {
+                    Some(Token::Equals) => (Bound::Included(id), Bound::Included(id)),
+                    Some(Token::Less) => (Bound::Unbounded, Bound::Excluded(id)),
+                    Some(Token::LessOrEqual) => (Bound::Unbounded, Bound::Included(id)),
+                    Some(Token::Greater) => (Bound::Excluded(id), Bound::Unbounded),
+                    Some(Token::GreaterOrEqual) => (Bound::Included(id), Bound::Unbounded),
+                    token => return Err(unexpected(token, "=, <, <=, > or >=")),
+                };
+                Ok(Filter::Ids(range))
+            }
+            Column::Named(column) => {
+                self.expect(Token::Equals)?;
+                Ok(Filter::Equals(column, self.parse_value()?))
+            }
+            // `parse_column` never gives back `*`.
+            Column::All => unreachable!(),
+        }
+    }
+
+    // `(<id>, <value>, ...)`
+    fn parse_row(&mut self) -> Result<(u32, Row), QueryError> {
+        self.expect(Token::LeftParenthesis)?;
+        let id = self.parse_id()?;
+        let mut row = Vec::new();
+        while self.iter.next_if_eq(&Token::Comma).is_some() {
+            row.push(self.parse_value()?);
+        }
+        self.expect(Token::RightParenthesis)?;
+        Ok((id, row))
+    }
+
+    fn parse_id(&mut self) -> Result<u32, QueryError> {
+        match self.iter.next() {
+            Some(Token::Number(id)) if u32::try_from(id).is_ok() => Ok(id as u32),
+            token => Err(unexpected(token, "a row ID")),
+        }
+    }
+
+    // A number, text in single quotes, or true or false.
+    fn parse_value(&mut self) -> Result<Value, QueryError> {
+        match self.iter.next() {
+            Some(Token::Number(n)) => Ok(Value::Int(n)),
+            Some(Token::Text(text)) => Ok(Value::Text(text)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("true") => Ok(Value::Bool(true)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("false") => Ok(Value::Bool(false)),
+            token => Err(unexpected(token, "a value")),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), QueryError> {
+        match self.iter.next() {
+            Some(token) if token == expected => Ok(()),
+            token => Err(unexpected(token, &format!("\"{}\"", expected))),
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), QueryError> {
+        match self.iter.next() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => Ok(()),
+            token => Err(unexpected(token, keyword)),
+        }
+    }
+
+    fn expect_word(&mut self, expected: &str) -> Result<String, QueryError> {
+        match self.iter.next() {
+            Some(Token::Word(word)) => Ok(word),
+            token => Err(unexpected(token, expected)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ColumnType, MVCC};
+
+    fn run(transaction: &Transaction, query: &str) -> Result<Output, QueryError> {
+        Statement::parse(query).and_then(|statement| statement.execute(transaction))
+    }
+
+    #[test]
+    fn parse_reads_every_kind_of_statement() {
+        assert_eq!(
+            Statement::Select {
+                columns: vec![Column::Id, Column::Named("name".to_string())],
+                table: "users".to_string(),
+                filter: Filter::Ids((Bound::Included(2), Bound::Unbounded)),
+            },
+            Statement::parse("select id, name from users where id >= 2;").unwrap()
+        );
+        assert_eq!(
+            Statement::Insert {
+                table: "users".to_string(),
+                rows: vec![(1, vec!["O'Brien".into(), true.into()])],
+            },
+            Statement::parse("INSERT INTO users VALUES (1, 'O''Brien', true)").unwrap()
+        );
+        assert_eq!(
+            Statement::Delete {
+                table: "users".to_string(),
+                filter: Filter::Equals("name".to_string(), "Bob".into()),
+            },
+            Statement::parse("DELETE FROM users WHERE name = 'Bob'").unwrap()
+        );
+        assert!(matches!(
+            Statement::parse("SELECT FROM users"),
+            Err(QueryError::Parsing(_))
+        ));
+    }
+
+    #[test]
+    fn execute_runs_queries_in_the_transaction() {
+        let mvcc = MVCC::new();
+        mvcc.create_table(
+            "users",
+            Schema::new()
+                .column("name", ColumnType::Text)
+                .column("age", ColumnType::Int),
+        );
+        let transaction = mvcc.begin_transaction();
+        assert_eq!(
+            Output::Written(3),
+            run(
+                &transaction,
+                "INSERT INTO users VALUES (1, 'Alice', 30), (2, 'Bob', 25), (3, 'Carol', 30)"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            Output::Written(1),
+            run(&transaction, "DELETE FROM users WHERE id = 1").unwrap()
+        );
+        assert_eq!(
+            Output::Rows(vec![vec!["3".to_string(), "Carol".to_string()]]),
+            run(&transaction, "SELECT id, NAME FROM users WHERE age = 30").unwrap()
+        );
+        assert!(matches!(
+            run(&transaction, "SELECT height FROM users"),
+            Err(QueryError::UnknownColumn { .. })
+        ));
+        assert!(matches!(
+            run(&transaction, "INSERT INTO users VALUES (4, 'Dan')"),
+            Err(QueryError::Transaction(TransactionError::InvalidRow { .. }))
+        ));
+        transaction.commit().unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+// A single value in a row.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Value {
+    Int(i64),
+    Text(String),
+    Bool(bool),
+}
+
+// The values of a row, one for each column of its table's schema, in the same order.
+pub type Row = Vec<Value>;
+
+impl Value {
+    // The type of column the value fits in.
+    pub fn column_type(&self) -> ColumnType {
+        match self {
+            Value::Int(_) => ColumnType::Int,
+            Value::Text(_) => ColumnType::Text,
+            Value::Bool(_) => ColumnType::Bool,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Text(text) => write!(f, "{}", text),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+// Written as the literal it would be in code, so that a row prints as `["Alice", 30]`.
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => n.fmt(f),
+            Value::Text(text) => text.fmt(f),
+            Value::Bool(b) => b.fmt(f),
+        }
+    }
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::Text(text.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::Text(text)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+// The type of the values a column holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Int,
+    Text,
+    Bool,
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColumnType::Int => write!(f, "int"),
+            ColumnType::Text => write!(f, "text"),
+            ColumnType::Bool => write!(f, "bool"),
+        }
+    }
+}
+
+// The columns every row of a table has, besides its ID, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schema {
+    columns: Vec<(String, ColumnType)>,
+}
+
+impl Schema {
+    // A schema with no columns yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add a column called `name` holding values of type `column_type` after the others.
+    //
+    // Panics if there is already a column with that name.
+    pub fn column(mut self, name: &str, column_type: ColumnType) -> Self {
+        assert!(
+            self.position(name).is_none(),
+            "there is already a column named {:?}",
+            name
+        );
+        self.columns.push((name.to_string(), column_type));
+        self
+    }
+
+    // The columns, in order, each with the type of its values.
+    pub fn columns(&self) -> &[(String, ColumnType)] {
+        &self.columns
+    }
+
+    // Where in a row the column called `name` is, if there is one.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|(column, _)| column == name)
+    }
+
+    // Check that `row` has a value of the right type for every column.
+    pub fn check(&self, row: &[Value]) -> Result<(), SchemaError> {
+        if row.len() != self.columns.len() {
+            return Err(SchemaError::ColumnCount {
+                expected: self.columns.len(),
+                found: row.len(),
+            });
+        }
+        for ((column, expected), value) in self.columns.iter().zip(row) {
+            if value.column_type() != *expected {
+                return Err(SchemaError::Type {
+                    column: column.clone(),
+                    expected: *expected,
+                    found: value.column_type(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// Why a row doesn't fit a table's schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+    // The row has more or fewer values than the table has columns.
+    ColumnCount {
+        expected: usize,
+        found: usize,
+    },
+    // The row's value for a column is of the wrong type.
+    Type {
+        column: String,
+        expected: ColumnType,
+        found: ColumnType,
+    },
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaError::ColumnCount { expected, found } => {
+                write!(f, "expected {} column(s), found {}", expected, found)
+            }
+            SchemaError::Type {
+                column,
+                expected,
+                found,
+            } => write!(f, "column {} holds {}, not {}", column, expected, found),
+        }
+    }
+}
+
+impl Error for SchemaError {}
@@ -0,0 +1,176 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::{Bound, RangeBounds};
+use std::sync::Mutex;
+
+// Keeps track of the rw-antidependencies between concurrent serializable transactions,
+// following serializable snapshot isolation. A transaction T1 has one on T2 when T1 reads
+// a row that T2 writes but T1's snapshot doesn't include T2's write, so T1 has to come
+// before T2 in any serial order. Snapshot isolation on its own only goes wrong when some
+// transaction has one of these both coming in and going out, so whenever a new one would
+// make such a pivot, the transaction that caused it is aborted.
+//
+// Transactions are only tracked by the flags saying whether they have any coming in or
+// going out, not by the dependencies themselves. This aborts some transactions that
+// didn't need to be, but never lets through one that did.
+#[derive(Default)]
+pub struct SsiTracker {
+    transactions: Mutex<HashMap<usize, Tracked>>,
+}
+
+// A range of row IDs.
+type IdRange = (Bound<u32>, Bound<u32>);
+
+#[derive(Default)]
+struct Tracked {
+    // The rows the transaction has read, including ones it found missing, each with the
+    // table it is in.
+    reads: HashSet<(String, u32)>,
+    // The ranges of rows the transaction has scanned, each with the table they are in,
+    // so that rows added to them later count as read too.
+    scans: Vec<(String, IdRange)>,
+    // Whether some concurrent transaction read a row this one wrote.
+    conflict_in: bool,
+    // Whether this transaction read a row some concurrent transaction wrote.
+    conflict_out: bool,
+    // Whether the transaction has committed. Committed transactions are kept until every
+    // transaction that ran alongside them has finished.
+    committed: bool,
+    // Whether the transaction has been chosen to abort, and can't commit.
+    doomed: bool,
+}
+
+impl SsiTracker {
+    // Start tracking a new transaction.
+    pub fn register(&self, version: usize) {
+        self.transactions
+            .lock()
+            .unwrap()
+            .insert(version, Tracked::default());
+    }
+
+    // Record that `reader` read row `id` of `table`, which the transactions in `writers`
+    // wrote without the reader being able to see it.
+    pub fn read(
+        &self,
+        reader: usize,
+        table: &str,
+        id: u32,
+        writers: impl IntoIterator<Item = usize>,
+    ) {
+        let mut transactions = self.transactions.lock().unwrap();
+        if let Some(tracked) = transactions.get_mut(&reader) {
+            tracked.reads.insert((table.to_string(), id));
+        }
+        for writer in writers {
+            add_dependency(&mut transactions, reader, writer, reader);
+        }
+    }
+
+    // Record that `reader` scanned the rows of `table` in `range`, which the transactions
+    // in `writers` wrote without the reader being able to see it.
+    pub fn scan(
+        &self,
+        reader: usize,
+        table: &str,
+        range: impl RangeBounds<u32>,
+        writers: impl IntoIterator<Item = usize>,
+    ) {
+        let mut transactions = self.transactions.lock().unwrap();
+        if let Some(tracked) = transactions.get_mut(&reader) {
+            let range = (range.start_bound().cloned(), range.end_bound().cloned());
+            tracked.scans.push((table.to_string(), range));
+        }
+        for writer in writers {
+            add_dependency(&mut transactions, reader, writer, reader);
+        }
+    }
+
+    // Record that `writer` wrote row `id` of `table`, returning false if it has to abort
+    // instead. `overlaps` tells whether a committed transaction ran alongside the writer,
+    // which is so when the writer's snapshot doesn't include it.
+    pub fn write(
+        &self,
+        writer: usize,
+        table: &str,
+        id: u32,
+        overlaps: impl Fn(usize) -> bool,
+    ) -> bool {
+        let row = (table.to_string(), id);
+        let mut transactions = self.transactions.lock().unwrap();
+        let readers: Vec<usize> = transactions
+            .iter()
+            .filter(|(&version, tracked)| {
+                version != writer
+                    && (tracked.reads.contains(&row)
+                        || tracked
+                            .scans
+                            .iter()
+                            .any(|(scanned, range)| scanned == table && range.contains(&id)))
+                    && (!tracked.committed || overlaps(version))
+            })
+            .map(|(&version, _)| version)
+            .collect();
+        for reader in readers {
+            add_dependency(&mut transactions, reader, writer, writer);
+        }
+        !transactions
+            .get(&writer)
+            .is_some_and(|tracked| tracked.doomed)
+    }
+
+    // Mark a transaction as committed, returning false if it has to abort instead.
+    pub fn commit(&self, version: usize) -> bool {
+        let mut transactions = self.transactions.lock().unwrap();
+        match transactions.get_mut(&version) {
+            Some(tracked) if tracked.doomed => false,
+            Some(tracked) => {
+                tracked.committed = true;
+                true
+            }
+            None => true,
+        }
+    }
+
+    // Stop tracking a transaction that rolled back.
+    pub fn forget(&self, version: usize) {
+        self.transactions.lock().unwrap().remove(&version);
+    }
+
+    // Stop tracking the committed transactions before `horizon`. Every snapshot includes
+    // them now, so no new dependency can involve them.
+    pub fn prune(&self, horizon: usize) {
+        self.transactions
+            .lock()
+            .unwrap()
+            .retain(|&version, tracked| !tracked.committed || version >= horizon);
+    }
+}
+
+// Record that `reader` has to come before `writer`, aborting `cause`, the one of the two
+// that just made the dependency, if either of them has now become a pivot.
+fn add_dependency(
+    transactions: &mut HashMap<usize, Tracked>,
+    reader: usize,
+    writer: usize,
+    cause: usize,
+) {
+    // Transactions that aren't serializable, or that have already rolled back, are
+    // left out.
+    if reader == writer
+        || !transactions.contains_key(&reader)
+        || !transactions.contains_key(&writer)
+    {
+        return;
+    }
+
+    let reader = transactions.get_mut(&reader).unwrap();
+    reader.conflict_out = true;
+    let reader_is_pivot = reader.conflict_in;
+    let writer = transactions.get_mut(&writer).unwrap();
+    writer.conflict_in = true;
+    let writer_is_pivot = writer.conflict_out;
+
+    if reader_is_pivot || writer_is_pivot {
+        transactions.get_mut(&cause).unwrap().doomed = true;
+    }
+}
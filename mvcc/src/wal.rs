@@ -0,0 +1,207 @@
+use super::schema::Row;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+// One entry in the write-ahead log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalRecord {
+    // A transaction started.
+    Begin {
+        version: usize,
+    },
+    // A transaction set a row of a table, or deleted it if `row` is `None`.
+    Write {
+        version: usize,
+        table: String,
+        id: u32,
+        row: Option<Row>,
+    },
+    // A transaction marked a savepoint it may roll back to.
+    Savepoint {
+        version: usize,
+        name: String,
+    },
+    // A transaction undid its writes since the latest savepoint called `name`.
+    RollbackTo {
+        version: usize,
+        name: String,
+    },
+    // A transaction committed, so its writes are part of the store's state.
+    Commit {
+        version: usize,
+    },
+    // A transaction rolled back, so its writes are to be ignored.
+    Abort {
+        version: usize,
+    },
+}
+
+impl WalRecord {
+    // The transaction the record is about.
+    pub fn version(&self) -> usize {
+        match self {
+            WalRecord::Begin { version }
+            | WalRecord::Write { version, .. }
+            | WalRecord::Savepoint { version, .. }
+            | WalRecord::RollbackTo { version, .. }
+            | WalRecord::Commit { version }
+            | WalRecord::Abort { version } => *version,
+        }
+    }
+}
+
+// A single write: a table, a row ID and the row's new values, or `None` if it deleted
+// the row.
+pub type RowWrite = (String, u32, Option<Row>);
+
+// A transaction that committed, with its writes in the order it made them.
+pub struct Committed {
+    pub version: usize,
+    pub writes: Vec<RowWrite>,
+}
+
+// A transaction whose commit hasn't been found yet, while replaying the log.
+#[derive(Default)]
+struct Pending {
+    writes: Vec<RowWrite>,
+    // Each savepoint, with how many writes had been made by then.
+    savepoints: Vec<(String, usize)>,
+}
+
+// Replay `records`, as read from a log, to find the transactions that committed and
+// what they wrote, in the order they committed. Writes undone by rolling back to a
+// savepoint are left out, as are transactions that rolled back or never finished, like
+// ones cut off by a crash.
+pub fn committed(records: Vec<WalRecord>) -> Vec<Committed> {
+    let mut pending: HashMap<usize, Pending> = HashMap::new();
+    let mut committed = Vec::new();
+
+    for record in records {
+        match record {
+            WalRecord::Begin { version } => {
+                pending.insert(version, Pending::default());
+            }
+            WalRecord::Write {
+                version,
+                table,
+                id,
+                row,
+            } => pending
+                .entry(version)
+                .or_default()
+                .writes
+                .push((table, id, row)),
+            WalRecord::Savepoint { version, name } => {
+                let transaction = pending.entry(version).or_default();
+                let length = transaction.writes.len();
+                transaction.savepoints.push((name, length));
+            }
+            // The same as `Transaction::rollback_to`: later savepoints go, but this one stays.
+            WalRecord::RollbackTo { version, name } => {
+                let transaction = pending.entry(version).or_default();
+                if let Some(position) = transaction
+                    .savepoints
+                    .iter()
+                    .rposition(|(savepoint, _)| *savepoint == name)
+                {
+                    let length = transaction.savepoints[position].1;
+                    transaction.savepoints.truncate(position + 1);
+                    transaction.writes.truncate(length);
+                }
+            }
+            WalRecord::Commit { version } => {
+                let writes = pending.remove(&version).unwrap_or_default().writes;
+                committed.push(Committed { version, writes });
+            }
+            WalRecord::Abort { version } => {
+                pending.remove(&version);
+            }
+        }
+    }
+    committed
+}
+
+// When the log is flushed to disk with fsync. Anything not yet flushed may be lost if
+// the machine crashes, though not if only the process does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    // Never, leaving it to the operating system.
+    Never,
+    // After every commit and rollback, so a committed transaction survives a crash.
+    OnCommit,
+    // After every record.
+    Always,
+}
+
+// An append-only log of everything the store's transactions do, from which the
+// committed state can be rebuilt after a restart.
+//
+// Each record is written as its length, a little-endian u32, followed by the record
+// itself encoded with bincode.
+pub struct Wal {
+    file: Mutex<File>,
+    sync: SyncMode,
+}
+
+impl Wal {
+    // Open the log at `path` for appending, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>, sync: SyncMode) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            sync,
+        })
+    }
+
+    // Append a record to the log, flushing it to disk if the sync mode asks for it.
+    pub fn append(&self, record: &WalRecord) -> io::Result<()> {
+        let encoded = bincode::serialize(record)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        let length =
+            u32::try_from(encoded.len()).map_err(|_| io::Error::from(ErrorKind::InvalidInput))?;
+
+        // Written in one go, so that records from different transactions can't interleave.
+        let mut frame = Vec::with_capacity(4 + encoded.len());
+        frame.extend_from_slice(&length.to_le_bytes());
+        frame.extend_from_slice(&encoded);
+
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&frame)?;
+        let ends_transaction = matches!(record, WalRecord::Commit { .. } | WalRecord::Abort { .. });
+        match self.sync {
+            SyncMode::Always => file.sync_data(),
+            SyncMode::OnCommit if ends_transaction => file.sync_data(),
+            _ => Ok(()),
+        }
+    }
+
+    // Read every record in the log at `path`, oldest first. A record cut short at the
+    // end of the log, as left by a crash partway through writing it, is ignored.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<WalRecord>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut records = Vec::new();
+
+        loop {
+            let mut length = [0; 4];
+            match reader.read_exact(&mut length) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(records),
+                Err(err) => return Err(err),
+            }
+
+            let mut encoded = vec![0; u32::from_le_bytes(length) as usize];
+            match reader.read_exact(&mut encoded) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(records),
+                Err(err) => return Err(err),
+            }
+            let record = bincode::deserialize(&encoded)
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+            records.push(record);
+        }
+    }
+}
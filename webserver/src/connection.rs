@@ -0,0 +1,249 @@
+use std::{
+    io::{self, BufReader, Read, Write},
+    net::IpAddr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    http::{Body, ParseError, Request, Response, Status, Version},
+    rate_limit::{self, RateLimiter},
+    router::Router,
+};
+
+/// how long a kept-alive connection may wait for its next request before it is closed,
+/// set as the stream's read timeout by whoever accepts the connection
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Answers requests on a connection until the client is done with it.
+///
+/// The connection is kept open between requests when the client asks for it, see
+/// `Request::keep_alive`, and closed after a malformed request, since there is no telling
+/// where the next one would start. A read timeout on the stream is what closes idle
+/// connections, so a kept-alive connection holds on to its worker until then.
+///
+/// Once `stopping` is set, the response being worked on is the connection's last.
+///
+/// With a `limit`, every request after the first takes a token from the client's
+/// bucket, and one that finds it empty is answered `429 Too Many Requests`. The first
+/// is left to whoever accepted the connection, who can turn a client away sooner.
+pub fn serve<S: Read + Write>(
+    stream: S,
+    router: &Router,
+    stopping: &AtomicBool,
+    limit: Option<(&RateLimiter, IpAddr)>,
+) {
+    let mut reader = BufReader::new(stream);
+    let mut first = true;
+
+    loop {
+        let (response, keep_alive) = match Request::read_from(&mut reader) {
+            Ok(request) => {
+                let limited = match limit {
+                    Some((limiter, client)) if !first => limiter.check(client).err(),
+                    _ => None,
+                };
+                match limited {
+                    Some(retry_after) => (rate_limit::too_many_requests(retry_after), false),
+                    None => respond(router, request, stopping),
+                }
+            }
+            Err(ParseError::ConnectionClosed) => return,
+            // the client is gone or too slow, either way there is nobody to answer
+            Err(ParseError::Io(err)) => {
+                if !is_timeout(&err) && err.kind() != io::ErrorKind::UnexpectedEof {
+                    eprintln!("couldn't read request: {err}");
+                }
+                return;
+            }
+            Err(err) => {
+                eprintln!("bad request: {err}");
+                let response = Response::new(err.status()).with_header("Connection", "close");
+                (response, false)
+            }
+        };
+
+        // the client may already be gone, and there is nothing more to tell it anyway
+        if let Err(err) = response.write_to(reader.get_mut()) {
+            eprintln!("couldn't send response: {err}");
+            return;
+        }
+        if !keep_alive {
+            return;
+        }
+        first = false;
+    }
+}
+
+// runs the request through the router, returning the response and whether to keep going
+fn respond(router: &Router, request: Request, stopping: &AtomicBool) -> (Response, bool) {
+    let (wants_keep_alive, version) = (request.keep_alive(), request.version);
+    let mut response = router.dispatch(request);
+    let keep_alive = wants_keep_alive && !stopping.load(Ordering::SeqCst);
+
+    // HTTP/1.0 clients don't know chunked encoding, so the body is read in to find its length
+    if version == Version::Http10 && response.body.len().is_none() {
+        let body = std::mem::replace(&mut response.body, Body::empty());
+        match body.into_bytes() {
+            Ok(bytes) => response.body = bytes.into(),
+            Err(err) => {
+                eprintln!("couldn't read response body: {err}");
+                let response = Response::new(Status::INTERNAL_SERVER_ERROR);
+                return (response.with_header("Connection", "close"), false);
+            }
+        }
+    }
+
+    let response = match (keep_alive, version) {
+        (false, _) => response.with_header("Connection", "close"),
+        // keeping the connection is the default in HTTP/1.1 but not before
+        (true, Version::Http10) => response.with_header("Connection", "keep-alive"),
+        (true, Version::Http11) => response,
+    };
+    (response, keep_alive)
+}
+
+// a read timeout shows up as either of these, depending on the platform
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a connection whose client sends `input` all at once
+    struct Fake {
+        input: &'static [u8],
+        output: Vec<u8>,
+    }
+
+    impl Read for Fake {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Fake {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn responses(input: &'static str) -> Vec<String> {
+        responses_while(input, &AtomicBool::new(false), None)
+    }
+
+    fn responses_while(
+        input: &'static str,
+        stopping: &AtomicBool,
+        limit: Option<(&RateLimiter, IpAddr)>,
+    ) -> Vec<String> {
+        let router = Router::new()
+            .get("/", |_, _| Response::new(Status::OK).with_body("hi"))
+            .get("/stream", |_, _| {
+                Response::new(Status::OK).with_body(Body::Chunked(Box::new(&b"streamed"[..])))
+            });
+        let mut connection = Fake {
+            input: input.as_bytes(),
+            output: Vec::new(),
+        };
+        serve(&mut connection, &router, stopping, limit);
+
+        String::from_utf8(connection.output)
+            .unwrap()
+            .split("HTTP/1.1 ")
+            .skip(1)
+            .map(|response| response.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn answers_requests_until_the_client_closes() {
+        let sent = responses(concat!(
+            "GET / HTTP/1.1\r\n\r\n",
+            "GET /nope HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1\r\nConnection: close\r\n\r\n",
+            // never read, the client said it was done
+            "GET / HTTP/1.1\r\n\r\n",
+        ));
+
+        assert_eq!(3, sent.len());
+        assert!(sent[0].starts_with("200 OK") && !sent[0].contains("Connection"));
+        assert!(sent[1].starts_with("404 Not Found"));
+        assert!(sent[2].contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn http_1_0_closes_unless_asked_not_to() {
+        let kept = responses(concat!(
+            "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+            "GET / HTTP/1.0\r\n\r\n",
+            "GET / HTTP/1.0\r\n\r\n",
+        ));
+
+        assert_eq!(2, kept.len());
+        assert!(kept[0].contains("Connection: keep-alive\r\n"));
+        assert!(kept[1].contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn chunked_bodies_are_sent_whole_to_http_1_0() {
+        let sent = responses(concat!(
+            "GET /stream HTTP/1.1\r\n\r\n",
+            "GET /stream HTTP/1.0\r\n\r\n",
+        ));
+
+        assert_eq!(2, sent.len());
+        assert!(sent[0].contains("Transfer-Encoding: chunked\r\n"));
+        assert!(sent[0].ends_with("\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"));
+        assert!(sent[1].contains("Content-Length: 8\r\n"));
+        assert!(sent[1].ends_with("\r\n\r\nstreamed"));
+    }
+
+    #[test]
+    fn stopping_closes_after_the_current_request() {
+        let sent = responses_while(
+            "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+            &AtomicBool::new(true),
+            None,
+        );
+
+        assert_eq!(1, sent.len());
+        assert!(sent[0].starts_with("200 OK") && sent[0].contains("Connection: close"));
+    }
+
+    #[test]
+    fn kept_alive_connections_are_rate_limited() {
+        let limiter = RateLimiter::new(0.001, 2);
+        let client = IpAddr::from([192, 0, 2, 1]);
+        // the first request was already counted when the connection was accepted
+        limiter.check(client).unwrap();
+
+        let sent = responses_while(
+            "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+            &AtomicBool::new(false),
+            Some((&limiter, client)),
+        );
+
+        assert_eq!(3, sent.len());
+        assert!(sent[1].starts_with("200 OK"));
+        assert!(sent[2].starts_with("429 Too Many Requests"));
+        assert!(sent[2].contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn malformed_requests_close_the_connection() {
+        let sent = responses("GET /\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+
+        assert_eq!(1, sent.len());
+        assert!(sent[0].starts_with("400 Bad Request") && sent[0].contains("Connection: close"));
+    }
+}
@@ -0,0 +1,10 @@
+mod form;
+mod headers;
+mod request;
+mod response;
+
+pub(crate) use form::percent_decode;
+pub use form::{DecodeError, Form};
+pub use headers::Headers;
+pub use request::{Method, ParseError, Request, Version};
+pub use response::{Body, Response, Status};
@@ -0,0 +1,150 @@
+use std::{error::Error, fmt};
+
+use super::Status;
+
+/// the `name=value` pairs of a query string or an `application/x-www-form-urlencoded`
+/// body, already decoded and in the order they were sent
+///
+/// A name can come more than once, as with a list of checkboxes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Form(Vec<(String, String)>);
+
+impl Form {
+    /// Decodes `name=value` pairs separated by `&`, where `+` stands for a space and
+    /// `%XX` for any byte. A pair without an `=` has an empty value.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DecodeError::Malformed` if an escape is broken or doesn't make UTF-8.
+    pub fn parse(text: &str) -> Result<Form, DecodeError> {
+        let decode =
+            |part: &str| percent_decode(&part.replace('+', " ")).ok_or(DecodeError::Malformed);
+
+        text.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((decode(name)?, decode(value)?))
+            })
+            .collect::<Result<_, _>>()
+            .map(Form)
+    }
+
+    /// the first value given for `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// every value given for `name`, in order
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// why a request's query string or body couldn't be turned into what the handler wanted,
+/// each maps onto the status code to answer it with
+#[derive(Debug)]
+pub enum DecodeError {
+    /// the body's `Content-Type` isn't the one asked for
+    UnsupportedMediaType,
+    /// the query string or form body wasn't properly encoded
+    Malformed,
+    /// the body wasn't valid JSON, or didn't have the shape asked for
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+}
+
+impl DecodeError {
+    /// the status to answer the request with
+    pub fn status(&self) -> Status {
+        match self {
+            DecodeError::UnsupportedMediaType => Status::UNSUPPORTED_MEDIA_TYPE,
+            _ => Status::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnsupportedMediaType => write!(f, "unsupported Content-Type"),
+            DecodeError::Malformed => write!(f, "malformed form data"),
+            #[cfg(feature = "json")]
+            DecodeError::Json(err) => write!(f, "invalid JSON body: {err}"),
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "json")]
+            DecodeError::Json(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// decodes `%XX` escapes, giving up on ones that are broken or that don't make UTF-8
+pub(crate) fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+
+    while let Some((&byte, after)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = after
+                .get(..2)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            // two hex digits are always ASCII and always fit
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &after[2..];
+        } else {
+            bytes.push(byte);
+            rest = after;
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_pairs_in_order() {
+        let form = Form::parse("q=rust+web%20server&tag=a&tag=b&empty=&flag&&caf%C3%A9=1").unwrap();
+
+        assert_eq!(Some("rust web server"), form.get("q"));
+        assert_eq!(vec!["a", "b"], form.get_all("tag").collect::<Vec<_>>());
+        assert_eq!(Some(""), form.get("empty"));
+        assert_eq!(Some(""), form.get("flag"));
+        assert_eq!(Some("1"), form.get("café"));
+        assert_eq!(None, form.get("missing"));
+        assert_eq!(6, form.len());
+
+        assert!(Form::parse("").unwrap().is_empty());
+        assert!(matches!(Form::parse("a=%zz"), Err(DecodeError::Malformed)));
+        assert!(matches!(Form::parse("a=%ff"), Err(DecodeError::Malformed)));
+    }
+}
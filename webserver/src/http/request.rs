@@ -0,0 +1,697 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Read},
+};
+
+use super::{DecodeError, Form, Headers, Status};
+
+// longest request line we accept, which is mostly the target
+const MAX_REQUEST_LINE: usize = 8 * 1024;
+// longest single header line
+const MAX_HEADER_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+// bodies are read into memory, so they need a limit
+const MAX_BODY: usize = 1024 * 1024;
+// the line giving a chunk's size, which is mostly room for chunk extensions
+const MAX_CHUNK_LINE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Patch,
+    Options,
+    Connect,
+    Trace,
+}
+
+impl Method {
+    fn parse(method: &str) -> Option<Method> {
+        match method {
+            "GET" => Some(Method::Get),
+            "HEAD" => Some(Method::Head),
+            "POST" => Some(Method::Post),
+            "PUT" => Some(Method::Put),
+            "DELETE" => Some(Method::Delete),
+            "PATCH" => Some(Method::Patch),
+            "OPTIONS" => Some(Method::Options),
+            "CONNECT" => Some(Method::Connect),
+            "TRACE" => Some(Method::Trace),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Patch => "PATCH",
+            Method::Options => "OPTIONS",
+            Method::Connect => "CONNECT",
+            Method::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+/// why a request couldn't be read, each maps onto the status code to answer it with
+#[derive(Debug)]
+pub enum ParseError {
+    /// the client closed the connection before sending anything
+    ConnectionClosed,
+    /// reading from the connection failed, or it closed partway through a request
+    Io(io::Error),
+    MalformedRequestLine,
+    UnknownMethod(String),
+    UnsupportedVersion(String),
+    MalformedHeader,
+    RequestLineTooLong,
+    HeadersTooLarge,
+    InvalidContentLength,
+    BodyTooLarge,
+    UnsupportedTransferEncoding,
+    /// a chunked body whose chunks weren't framed properly
+    MalformedChunk,
+}
+
+impl ParseError {
+    /// the status to answer the request with
+    pub fn status(&self) -> Status {
+        match self {
+            ParseError::RequestLineTooLong => Status::URI_TOO_LONG,
+            ParseError::HeadersTooLarge => Status::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            ParseError::BodyTooLarge => Status::CONTENT_TOO_LARGE,
+            ParseError::UnknownMethod(_) | ParseError::UnsupportedTransferEncoding => {
+                Status::NOT_IMPLEMENTED
+            }
+            ParseError::UnsupportedVersion(_) => Status::HTTP_VERSION_NOT_SUPPORTED,
+            _ => Status::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::ConnectionClosed => write!(f, "connection closed before a request"),
+            ParseError::Io(err) => write!(f, "couldn't read request: {err}"),
+            ParseError::MalformedRequestLine => write!(f, "malformed request line"),
+            ParseError::UnknownMethod(method) => write!(f, "unknown method {method:?}"),
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "unsupported HTTP version {version:?}")
+            }
+            ParseError::MalformedHeader => write!(f, "malformed header"),
+            ParseError::RequestLineTooLong => write!(f, "request line too long"),
+            ParseError::HeadersTooLarge => write!(f, "request headers too large"),
+            ParseError::InvalidContentLength => write!(f, "invalid Content-Length"),
+            ParseError::BodyTooLarge => write!(f, "request body too large"),
+            ParseError::UnsupportedTransferEncoding => {
+                write!(f, "unsupported Transfer-Encoding")
+            }
+            ParseError::MalformedChunk => write!(f, "malformed chunk in request body"),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> ParseError {
+        ParseError::Io(err)
+    }
+}
+
+/// an HTTP/1.x request, read with `Request::read_from`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: Method,
+    /// the target as sent, including any query string
+    pub target: String,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads one request off a connection, leaving the reader at the start of the next one.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ParseError::ConnectionClosed` if the connection closed cleanly before the
+    /// request started, and one of the other variants if the request was broken or too big.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Request, ParseError> {
+        let request_line = read_line(reader, MAX_REQUEST_LINE).map_err(|err| match err {
+            ParseError::MalformedHeader => ParseError::MalformedRequestLine,
+            err => err,
+        })?;
+        let request_line = match request_line {
+            Some(line) if line.is_empty() => return Err(ParseError::MalformedRequestLine),
+            Some(line) => line,
+            None => return Err(ParseError::ConnectionClosed),
+        };
+        let (method, target, version) = parse_request_line(&request_line)?;
+
+        let mut headers = Headers::default();
+        loop {
+            let line = read_line(reader, MAX_HEADER_LINE)
+                .map_err(|err| match err {
+                    ParseError::RequestLineTooLong => ParseError::HeadersTooLarge,
+                    err => err,
+                })?
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            let (name, value) = parse_header(&line)?;
+            headers.append(name, value);
+        }
+
+        let body = if is_chunked(&headers)? {
+            read_chunked(reader)?
+        } else {
+            read_body(reader, content_length(&headers)?)?
+        };
+
+        Ok(Request {
+            method,
+            target,
+            version,
+            headers,
+            body,
+        })
+    }
+
+    /// the target without its query string
+    pub fn path(&self) -> &str {
+        match self.target.split_once('?') {
+            Some((path, _)) => path,
+            None => &self.target,
+        }
+    }
+
+    /// the part of the target after the `?`, if there is one
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    /// The query string's `name=value` pairs, none if there is no query string.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DecodeError::Malformed` if the query string isn't properly encoded.
+    pub fn query_params(&self) -> Result<Form, DecodeError> {
+        Form::parse(self.query().unwrap_or(""))
+    }
+
+    /// The fields of a form sent as `application/x-www-form-urlencoded`, which is what
+    /// browsers send HTML forms as unless told otherwise.
+    ///
+    /// ```
+    /// # use webserver::http::Request;
+    /// let raw = "POST /login HTTP/1.1\r\n\
+    ///            Content-Type: application/x-www-form-urlencoded\r\n\
+    ///            Content-Length: 23\r\n\r\n\
+    ///            user=ferris&remember=on";
+    /// let request = Request::read_from(&mut raw.as_bytes()).unwrap();
+    ///
+    /// let form = request.form().unwrap();
+    /// assert_eq!(Some("ferris"), form.get("user"));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `DecodeError::UnsupportedMediaType` if the body is of some other type, and
+    /// `DecodeError::Malformed` if it isn't properly encoded. `DecodeError::status` gives
+    /// the status to answer with in either case.
+    pub fn form(&self) -> Result<Form, DecodeError> {
+        if !self.has_content_type(|media_type| {
+            media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        }) {
+            return Err(DecodeError::UnsupportedMediaType);
+        }
+        let body = std::str::from_utf8(&self.body).map_err(|_| DecodeError::Malformed)?;
+        Form::parse(body)
+    }
+
+    /// Deserializes a JSON body into `T`, which can be a map or any struct that derives
+    /// `serde::Deserialize`. The body has to be sent as `application/json`, or another
+    /// type ending in `+json`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `DecodeError::UnsupportedMediaType` if the body is of some other type, and
+    /// `DecodeError::Json` if it isn't JSON that fits `T`.
+    #[cfg(feature = "json")]
+    pub fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, DecodeError> {
+        if !self.has_content_type(|media_type| {
+            media_type.eq_ignore_ascii_case("application/json")
+                || media_type.to_ascii_lowercase().ends_with("+json")
+        }) {
+            return Err(DecodeError::UnsupportedMediaType);
+        }
+        serde_json::from_slice(&self.body).map_err(DecodeError::Json)
+    }
+
+    // whether the body's media type, without any parameters such as the charset, is
+    // one `accept` takes
+    fn has_content_type(&self, accept: impl Fn(&str) -> bool) -> bool {
+        self.header("Content-Type")
+            .map(|value| value.split(';').next().unwrap_or("").trim())
+            .is_some_and(accept)
+    }
+
+    /// the value of the first header called `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// whether the client wants to send more requests on the same connection, which
+    /// HTTP/1.1 does unless it says `Connection: close`, and HTTP/1.0 only if it asks
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.headers
+                .get_all("Connection")
+                .flat_map(|value| value.split(','))
+                .any(|token| token.trim().eq_ignore_ascii_case(option))
+        };
+
+        match self.version {
+            Version::Http11 => !has_option("close"),
+            Version::Http10 => has_option("keep-alive"),
+        }
+    }
+}
+
+// reads a line without its line ending, or None if the reader was already at the end.
+// `RequestLineTooLong` is returned for any line over `limit` and `MalformedHeader` for
+// one that isn't UTF-8, callers reword them as needed
+fn read_line<R: BufRead>(reader: &mut R, limit: usize) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    // one more than the limit, to tell a line that just fits from one that doesn't
+    let read = reader
+        .by_ref()
+        .take(limit as u64 + 2)
+        .read_until(b'\n', &mut line)?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return Err(if read > limit {
+            ParseError::RequestLineTooLong
+        } else {
+            io::Error::from(io::ErrorKind::UnexpectedEof).into()
+        });
+    }
+    // the spec asks for \r\n, but a bare \n is common enough to let through
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    if line.len() > limit {
+        return Err(ParseError::RequestLineTooLong);
+    }
+
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::MalformedHeader)
+}
+
+fn parse_request_line(line: &str) -> Result<(Method, String, Version), ParseError> {
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ParseError::MalformedRequestLine);
+    };
+    if target.is_empty() {
+        return Err(ParseError::MalformedRequestLine);
+    }
+
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        other if other.starts_with("HTTP/") => {
+            return Err(ParseError::UnsupportedVersion(other.to_string()))
+        }
+        _ => return Err(ParseError::MalformedRequestLine),
+    };
+    let method =
+        Method::parse(method).ok_or_else(|| ParseError::UnknownMethod(method.to_string()))?;
+
+    Ok((method, target.to_string(), version))
+}
+
+// characters allowed in a header name, a "token" in the spec
+fn is_token(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+fn parse_header(line: &str) -> Result<(String, String), ParseError> {
+    let (name, value) = line.split_once(':').ok_or(ParseError::MalformedHeader)?;
+    // no whitespace is allowed before the colon, and no folded continuation lines
+    if name.is_empty() || !name.chars().all(is_token) {
+        return Err(ParseError::MalformedHeader);
+    }
+
+    Ok((
+        name.to_string(),
+        value.trim_matches([' ', '\t']).to_string(),
+    ))
+}
+
+fn content_length(headers: &Headers) -> Result<usize, ParseError> {
+    let mut lengths = headers.get_all("Content-Length");
+    let Some(length) = lengths.next() else {
+        return Ok(0);
+    };
+    // a client sending two different lengths is one we can't agree with about the body
+    if lengths.any(|other| other != length) {
+        return Err(ParseError::InvalidContentLength);
+    }
+    if !length.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ParseError::InvalidContentLength);
+    }
+
+    match length.parse() {
+        Ok(length) if length <= MAX_BODY => Ok(length),
+        // too many digits to parse is too large all the same
+        _ if !length.is_empty() => Err(ParseError::BodyTooLarge),
+        _ => Err(ParseError::InvalidContentLength),
+    }
+}
+
+// whether the body is sent in chunks, the only transfer coding we understand
+fn is_chunked(headers: &Headers) -> Result<bool, ParseError> {
+    let mut codings = headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim);
+    let Some(coding) = codings.next() else {
+        return Ok(false);
+    };
+    if !coding.eq_ignore_ascii_case("chunked") || codings.next().is_some() {
+        return Err(ParseError::UnsupportedTransferEncoding);
+    }
+    // with both, one side of a proxy may go by the other header and read a different
+    // body, which is how requests get smuggled past it
+    if headers.contains("Content-Length") {
+        return Err(ParseError::InvalidContentLength);
+    }
+    Ok(true)
+}
+
+fn read_body<R: BufRead>(reader: &mut R, length: usize) -> Result<Vec<u8>, ParseError> {
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(body)
+}
+
+// reads a body sent as chunks, each a line with its size in hex followed by that many
+// bytes, until one of size zero. trailer fields after it are read and thrown away
+fn read_chunked<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, ParseError> {
+    let line = |reader: &mut R, limit| match read_line(reader, limit) {
+        Ok(Some(line)) => Ok(line),
+        Ok(None) => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        Err(ParseError::Io(err)) => Err(ParseError::Io(err)),
+        Err(_) => Err(ParseError::MalformedChunk),
+    };
+
+    let mut body = Vec::new();
+    loop {
+        let size_line = line(reader, MAX_CHUNK_LINE)?;
+        // chunk extensions after a `;` are allowed and ignored
+        let size = size_line.split(';').next().unwrap_or_default().trim_end();
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseError::MalformedChunk);
+        }
+        // too many digits to parse is too large all the same
+        let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::BodyTooLarge)?;
+        if size == 0 {
+            break;
+        }
+        if size > MAX_BODY - body.len() {
+            return Err(ParseError::BodyTooLarge);
+        }
+
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+        // the data is followed by a line ending and nothing else
+        if !line(reader, 0)?.is_empty() {
+            return Err(ParseError::MalformedChunk);
+        }
+    }
+
+    for _ in 0..=MAX_HEADERS {
+        let trailer = read_line(reader, MAX_HEADER_LINE)
+            .map_err(|err| match err {
+                ParseError::RequestLineTooLong => ParseError::HeadersTooLarge,
+                err => err,
+            })?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        if trailer.is_empty() {
+            return Ok(body);
+        }
+        parse_header(&trailer)?;
+    }
+    Err(ParseError::HeadersTooLarge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::read_from(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn reads_request_line_headers_and_body() {
+        let request = parse(
+            "POST /users?page=2 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 5\r\n\r\nhello",
+        )
+        .unwrap();
+
+        assert_eq!(Method::Post, request.method);
+        assert_eq!("/users", request.path());
+        assert_eq!(Some("page=2"), request.query());
+        assert_eq!(Version::Http11, request.version);
+        assert_eq!(Some("localhost"), request.header("HOST"));
+        assert_eq!(b"hello", &request.body[..]);
+    }
+
+    // a POST to `/submit?page=2` with the given content type and body
+    fn post(content_type: &str, body: &str) -> Request {
+        let raw = format!(
+            "POST /submit?page=2 HTTP/1.1\r\nContent-Type: {content_type}\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        parse(&raw).unwrap()
+    }
+
+    #[test]
+    fn decodes_queries_and_forms() {
+        let request = post(
+            "application/x-www-form-urlencoded; charset=UTF-8",
+            "a=1&b=two+words",
+        );
+
+        assert_eq!(Some("2"), request.query_params().unwrap().get("page"));
+        let form = request.form().unwrap();
+        assert_eq!(Some("1"), form.get("a"));
+        assert_eq!(Some("two words"), form.get("b"));
+
+        // the body has to say it's a form
+        let err = post("text/plain", "a=1").form().unwrap_err();
+        assert_eq!(Status::UNSUPPORTED_MEDIA_TYPE, err.status());
+        let err = post("application/x-www-form-urlencoded", "a=%")
+            .form()
+            .unwrap_err();
+        assert_eq!(Status::BAD_REQUEST, err.status());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn decodes_json_bodies() {
+        use std::collections::HashMap;
+
+        let request = post("application/json", r#"{"a": 1, "b": 2}"#);
+        let values: HashMap<String, u32> = request.json().unwrap();
+        assert_eq!(Some(&2), values.get("b"));
+
+        let err = post("application/json", "{")
+            .json::<HashMap<String, u32>>()
+            .unwrap_err();
+        assert_eq!(Status::BAD_REQUEST, err.status());
+        let err = post("text/plain", "{}")
+            .json::<HashMap<String, u32>>()
+            .unwrap_err();
+        assert_eq!(Status::UNSUPPORTED_MEDIA_TYPE, err.status());
+    }
+
+    #[test]
+    fn reads_chunked_bodies() {
+        let mut raw = concat!(
+            "POST /upload HTTP/1.1\r\nTransfer-Encoding: Chunked\r\n\r\n",
+            "5\r\nhello\r\n",
+            "7;note=ignored\r\n, world\r\n",
+            "0\r\nChecksum: abc\r\n\r\n",
+            "GET / HTTP/1.1\r\n\r\n",
+        )
+        .as_bytes();
+
+        let request = Request::read_from(&mut raw).unwrap();
+        assert_eq!(b"hello, world", &request.body[..]);
+        assert_eq!("/", Request::read_from(&mut raw).unwrap().target);
+    }
+
+    #[test]
+    fn leaves_the_next_request_unread() {
+        let mut raw = "GET / HTTP/1.1\n\nGET /wait HTTP/1.0\n\n".as_bytes();
+
+        assert_eq!("/", Request::read_from(&mut raw).unwrap().target);
+        let second = Request::read_from(&mut raw).unwrap();
+        assert_eq!(
+            ("/wait", Version::Http10),
+            (&second.target[..], second.version)
+        );
+        assert!(matches!(
+            Request::read_from(&mut raw),
+            Err(ParseError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn keep_alive_depends_on_version_and_connection() {
+        let cases = [
+            ("HTTP/1.1", "", true),
+            ("HTTP/1.1", "Connection: Close\r\n", false),
+            ("HTTP/1.1", "Connection: upgrade, close\r\n", false),
+            ("HTTP/1.0", "", false),
+            ("HTTP/1.0", "Connection: keep-alive\r\n", true),
+        ];
+
+        for (version, headers, keep_alive) in cases {
+            let request = parse(&format!("GET / {version}\r\n{headers}\r\n")).unwrap();
+            assert_eq!(keep_alive, request.keep_alive(), "{version} {headers:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_broken_requests() {
+        let cases = [
+            ("GET /\r\n\r\n", 400),
+            ("GET  / HTTP/1.1\r\n\r\n", 400),
+            ("BREW / HTTP/1.1\r\n\r\n", 501),
+            ("GET / HTTP/2.0\r\n\r\n", 505),
+            ("GET / HTTP/1.1\r\nHost localhost\r\n\r\n", 400),
+            ("GET / HTTP/1.1\r\nHost : localhost\r\n\r\n", 400),
+            ("POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n", 400),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n",
+                400,
+            ),
+            (
+                "POST / HTTP/1.1\r\nContent-Length: 99999999999999999999\r\n\r\n",
+                413,
+            ),
+            ("POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n", 501),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n",
+                501,
+            ),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 2\r\n\r\n",
+                400,
+            ),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nhi\r\n0\r\n\r\n",
+                400,
+            ),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nhi\r\n0\r\n\r\n",
+                400,
+            ),
+            (
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nfffffffffffffffffff\r\n",
+                413,
+            ),
+        ];
+
+        for (raw, status) in cases {
+            let err = parse(raw).unwrap_err();
+            assert_eq!(status, err.status().code(), "{raw:?} gave {err}");
+        }
+    }
+
+    #[test]
+    fn limits_sizes() {
+        let long_target = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_REQUEST_LINE));
+        let many_headers = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "A: b\r\n".repeat(MAX_HEADERS + 1)
+        );
+
+        assert!(matches!(
+            parse(&long_target),
+            Err(ParseError::RequestLineTooLong)
+        ));
+        assert!(matches!(
+            parse(&many_headers),
+            Err(ParseError::HeadersTooLarge)
+        ));
+    }
+
+    #[test]
+    fn truncated_requests_are_io_errors() {
+        let truncated = [
+            "GET / HTTP/1.1\r\nHost: a",
+            "POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nhi",
+        ];
+
+        for raw in truncated {
+            match parse(raw) {
+                Err(ParseError::Io(err)) => assert_eq!(io::ErrorKind::UnexpectedEof, err.kind()),
+                other => panic!("{raw:?} gave {other:?}"),
+            }
+        }
+    }
+}
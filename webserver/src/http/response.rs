@@ -0,0 +1,396 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::Headers;
+
+/// an HTTP status code, with constants for the ones this server uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Status(u16);
+
+impl Status {
+    pub const OK: Status = Status(200);
+    pub const NO_CONTENT: Status = Status(204);
+    pub const MOVED_PERMANENTLY: Status = Status(301);
+    pub const NOT_MODIFIED: Status = Status(304);
+    pub const BAD_REQUEST: Status = Status(400);
+    pub const FORBIDDEN: Status = Status(403);
+    pub const NOT_FOUND: Status = Status(404);
+    pub const METHOD_NOT_ALLOWED: Status = Status(405);
+    pub const REQUEST_TIMEOUT: Status = Status(408);
+    pub const CONTENT_TOO_LARGE: Status = Status(413);
+    pub const URI_TOO_LONG: Status = Status(414);
+    pub const UNSUPPORTED_MEDIA_TYPE: Status = Status(415);
+    pub const TOO_MANY_REQUESTS: Status = Status(429);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: Status = Status(431);
+    pub const INTERNAL_SERVER_ERROR: Status = Status(500);
+    pub const NOT_IMPLEMENTED: Status = Status(501);
+    pub const SERVICE_UNAVAILABLE: Status = Status(503);
+    pub const HTTP_VERSION_NOT_SUPPORTED: Status = Status(505);
+
+    /// # Panics
+    ///
+    /// Panics if `code` isn't three digits.
+    pub fn new(code: u16) -> Status {
+        assert!((100..1000).contains(&code), "invalid status code {code}");
+        Status(code)
+    }
+
+    pub fn code(self) -> u16 {
+        self.0
+    }
+
+    /// the standard reason phrase, or an empty one for codes we don't know
+    pub fn reason(self) -> &'static str {
+        match self.0 {
+            200 => "OK",
+            204 => "No Content",
+            301 => "Moved Permanently",
+            304 => "Not Modified",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            413 => "Content Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            503 => "Service Unavailable",
+            505 => "HTTP Version Not Supported",
+            _ => "",
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.0, self.reason())
+    }
+}
+
+/// what a response sends after its headers
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// copied from `reader` as the response is sent, so it never has to fit in memory
+    Stream {
+        reader: Box<dyn Read + Send>,
+        length: u64,
+    },
+    /// copied from the reader until it ends, sent with `Transfer-Encoding: chunked` so
+    /// the length doesn't have to be known up front. Each read becomes a chunk, so a
+    /// reader that generates its content can hand it over a piece at a time
+    Chunked(Box<dyn Read + Send>),
+}
+
+impl Body {
+    pub fn empty() -> Body {
+        Body::Bytes(Vec::new())
+    }
+
+    /// how many bytes will be sent, or `None` for a chunked body that goes on until
+    /// its reader ends
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Stream { length, .. } => Some(*length),
+            Body::Chunked(_) => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// the body if it is already in memory
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            Body::Stream { .. } | Body::Chunked(_) => None,
+        }
+    }
+
+    /// reads the whole body into memory
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Body::Bytes(bytes) => Ok(bytes),
+            Body::Stream { reader, length } => {
+                let mut bytes = Vec::new();
+                reader.take(length).read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+            Body::Chunked(mut reader) => {
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::Stream { length, .. } => {
+                f.debug_struct("Stream").field("length", length).finish()
+            }
+            Body::Chunked(_) => f.debug_tuple("Chunked").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Body {
+        Body::Bytes(text.into())
+    }
+}
+
+/// an HTTP/1.1 response, put together by a handler and sent with `write_to`
+///
+/// ```
+/// use webserver::http::{Response, Status};
+///
+/// let response = Response::new(Status::OK)
+///     .with_header("Content-Type", "text/plain")
+///     .with_body("hello");
+///
+/// let mut sent = Vec::new();
+/// response.write_to(&mut sent).unwrap();
+/// assert!(sent.starts_with(b"HTTP/1.1 200 OK\r\n"));
+/// ```
+#[derive(Debug)]
+pub struct Response {
+    pub status: Status,
+    pub headers: Headers,
+    pub body: Body,
+}
+
+impl Response {
+    /// an empty response with the given status
+    pub fn new(status: Status) -> Response {
+        Response {
+            status,
+            headers: Headers::default(),
+            body: Body::empty(),
+        }
+    }
+
+    /// Adds a header, replacing any others of the same name.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Body>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Sends the response, adding `Content-Length` and `Date` headers unless they were set.
+    /// A chunked body gets `Transfer-Encoding: chunked` instead of a length.
+    ///
+    /// A body in memory is written along with the headers in a single `write_all`, so a
+    /// `TcpStream` sends it in as few packets as it can.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error writing to `writer` or reading a streamed body. A streamed
+    /// body that ends early is an `UnexpectedEof` error, as the client was promised more.
+    pub fn write_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        let framed =
+            self.headers.contains("Content-Length") || self.headers.contains("Transfer-Encoding");
+        match self.body.len() {
+            _ if framed => {}
+            Some(length) => head += &format!("Content-Length: {length}\r\n"),
+            None => head += "Transfer-Encoding: chunked\r\n",
+        }
+        if !self.headers.contains("Date") {
+            head += &format!("Date: {}\r\n", http_date(SystemTime::now()));
+        }
+        for (name, value) in self.headers.iter() {
+            head += &format!("{name}: {value}\r\n");
+        }
+        head += "\r\n";
+
+        let mut message = head.into_bytes();
+        match self.body {
+            Body::Bytes(bytes) => {
+                message.extend_from_slice(&bytes);
+                writer.write_all(&message)
+            }
+            Body::Stream { reader, length } => {
+                writer.write_all(&message)?;
+                let copied = io::copy(&mut reader.take(length), writer)?;
+                if copied < length {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(())
+            }
+            Body::Chunked(reader) => {
+                writer.write_all(&message)?;
+                write_chunks(reader, writer)
+            }
+        }
+    }
+}
+
+// sends each read from `reader` as a chunk, then the empty chunk that ends the body
+fn write_chunks<W: Write>(mut reader: impl Read, writer: &mut W) -> io::Result<()> {
+    let mut buf = vec![0; 8 * 1024];
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => return writer.write_all(b"0\r\n\r\n"),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        // size, data and line ending go out together rather than as three small writes
+        let mut chunk = format!("{read:x}\r\n").into_bytes();
+        chunk.extend_from_slice(&buf[..read]);
+        chunk.extend_from_slice(b"\r\n");
+        writer.write_all(&chunk)?;
+    }
+}
+
+// formats a time the way HTTP headers want it, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+fn http_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let days = secs / 86400;
+    let (hour, minute, second) = (secs % 86400 / 3600, secs % 3600 / 60, secs % 60);
+
+    // converts days since 1970 to a calendar date, counting years from March so that
+    // the leap day falls at the end. see http://howardhinnant.github.io/date_algorithms.html
+    let shifted = days + 719_468;
+    let era = shifted / 146_097;
+    let day_of_era = shifted % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = (month_from_march + 2) % 12;
+    let year = era * 400 + year_of_era + u64::from(month < 2);
+
+    format!(
+        "{}, {day:02} {} {year} {hour:02}:{minute:02}:{second:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        MONTHS[month as usize],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn formats_http_dates() {
+        let at = |secs| http_date(UNIX_EPOCH + Duration::from_secs(secs));
+
+        assert_eq!("Thu, 01 Jan 1970 00:00:00 GMT", at(0));
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", at(784_111_777));
+        assert_eq!("Tue, 29 Feb 2000 23:59:59 GMT", at(951_868_799));
+        assert_eq!("Wed, 01 Mar 2000 00:00:00 GMT", at(951_868_800));
+    }
+
+    #[test]
+    fn adds_length_and_date_unless_set() {
+        let mut sent = Vec::new();
+        Response::new(Status::NOT_FOUND)
+            .with_body("missing")
+            .write_to(&mut sent)
+            .unwrap();
+        let sent = String::from_utf8(sent).unwrap();
+
+        assert!(sent.starts_with("HTTP/1.1 404 Not Found\r\nContent-Length: 7\r\nDate: "));
+        assert!(sent.ends_with(" GMT\r\n\r\nmissing"));
+
+        let mut sent = Vec::new();
+        Response::new(Status::OK)
+            .with_header("Date", "yesterday")
+            .with_header("content-length", "0")
+            .write_to(&mut sent)
+            .unwrap();
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nDate: yesterday\r\ncontent-length: 0\r\n\r\n",
+            String::from_utf8(sent).unwrap()
+        );
+    }
+
+    #[test]
+    fn streams_bodies_of_known_length() {
+        let body = Body::Stream {
+            reader: Box::new(&b"streamed and then some"[..]),
+            length: 8,
+        };
+        let mut sent = Vec::new();
+        Response::new(Status::OK)
+            .with_header("Date", "today")
+            .with_body(body)
+            .write_to(&mut sent)
+            .unwrap();
+
+        assert_eq!(
+            "HTTP/1.1 200 OK\r\nContent-Length: 8\r\nDate: today\r\n\r\nstreamed",
+            String::from_utf8(sent).unwrap()
+        );
+
+        let short = Body::Stream {
+            reader: Box::new(&b"short"[..]),
+            length: 8,
+        };
+        let err = Response::new(Status::OK)
+            .with_body(short)
+            .write_to(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn streams_bodies_of_unknown_length_in_chunks() {
+        // a chain hands over each part with a read of its own
+        let generated = io::Read::chain(&b"hello"[..], &b", world"[..]);
+        let mut sent = Vec::new();
+        Response::new(Status::OK)
+            .with_header("Date", "today")
+            .with_body(Body::Chunked(Box::new(generated)))
+            .write_to(&mut sent)
+            .unwrap();
+
+        assert_eq!(
+            concat!(
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nDate: today\r\n\r\n",
+                "5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n",
+            ),
+            String::from_utf8(sent).unwrap()
+        );
+    }
+}
@@ -0,0 +1,9 @@
+pub mod connection;
+pub mod http;
+pub mod middleware;
+pub mod rate_limit;
+pub mod router;
+pub mod server;
+pub mod static_files;
+#[cfg(feature = "tls")]
+mod tls;
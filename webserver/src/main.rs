@@ -1,45 +1,53 @@
-use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
-    thread,
-    time::Duration,
+#[cfg(feature = "tls")]
+use std::env;
+use std::{fs, thread, time::Duration};
+
+use webserver::{
+    http::{Response, Status},
+    middleware,
+    router::Router,
+    server::Server,
+    static_files::StaticFiles,
 };
-use thread_pool::ThreadPool;
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
-
-    for stream in listener.incoming().take(5) {
-        let stream = stream.unwrap();
+    let files = StaticFiles::new("static");
+    let router = Router::new()
+        .get("/", |_, _| page(Status::OK, "hello.html"))
+        .get("/wait", |_, _| {
+            thread::sleep(Duration::from_secs(10));
+            page(Status::OK, "wait.html")
+        })
+        .get("/static/*path", move |_, params| {
+            files.serve(params.get("path").unwrap_or(""))
+        })
+        .not_found(|_, _| page(Status::NOT_FOUND, "notfound.html"))
+        .wrap(middleware::access_log)
+        .wrap(middleware::request_id)
+        .wrap(middleware::timing);
+
+    let builder = Server::builder()
+        .bind("127.0.0.1:7878")
+        .rate_limit(10.0, 20);
+    #[cfg(feature = "tls")]
+    let builder = match (env::var_os("TLS_CERT"), env::var_os("TLS_KEY")) {
+        (Some(cert_path), Some(key_path)) => builder.tls(cert_path, key_path),
+        _ => builder,
+    };
 
-        pool.execute(|| {
-            handle_connection(stream);
-        });
-    }
+    let server = builder.build(router).unwrap();
+    #[cfg(unix)]
+    server.shutdown_on_signals().unwrap();
+    server.run();
 
-    println!("got 5 requests, shutting down server")
+    println!("server stopped")
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader.lines().next().unwrap().unwrap();
-
-    let (status_line, filename, message) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello.html", "index"),
-        "GET /wait HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(10));
-            ("HTTP/1.1 200 OK", "wait.html", "wait")
-        }
-        _ => ("HTTP/1.1 404 NOT FOUND", "notfound.html", "missing error"),
-    };
-
+fn page(status: Status, filename: &str) -> Response {
     let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
-
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
 
-    stream.write_all(response.as_bytes()).unwrap();
-    println!("served {} page", message);
+    println!("served {filename}");
+    Response::new(status)
+        .with_header("Content-Type", "text/html; charset=utf-8")
+        .with_body(contents)
 }
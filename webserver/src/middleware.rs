@@ -0,0 +1,149 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use thread_pool::current_worker;
+
+use crate::{
+    http::{Request, Response},
+    router::Router,
+};
+
+pub(crate) type Middleware = Box<dyn Fn(Request, Next) -> Response + Send + Sync + 'static>;
+
+/// the rest of the chain after a middleware: the middleware added after it, then the router
+pub struct Next<'a> {
+    rest: &'a [Middleware],
+    router: &'a Router,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(rest: &'a [Middleware], router: &'a Router) -> Next<'a> {
+        Next { rest, router }
+    }
+
+    /// Passes `request` on and returns the response that comes back.
+    pub fn run(self, request: Request) -> Response {
+        match self.rest.split_first() {
+            Some((middleware, rest)) => middleware(request, Next::new(rest, self.router)),
+            None => self.router.handle(&request),
+        }
+    }
+}
+
+/// Prints a line for every request, with the status it got and how long that took,
+/// tagged with the worker that handled it.
+pub fn access_log(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let line = format!("{} {} {}", request.method, request.target, request.version);
+
+    let response = next.run(request);
+    let worker = match current_worker() {
+        Some(worker) => format!("[{worker}] "),
+        None => String::new(),
+    };
+    println!(
+        "{worker}\"{line}\" {} {:?}",
+        response.status.code(),
+        start.elapsed()
+    );
+    response
+}
+
+/// Tags each request with an `X-Request-Id` header, unless the client sent one, and
+/// echoes it on the response so the two can be matched up in logs.
+pub fn request_id(mut request: Request, next: Next) -> Response {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+    let id = match request.header("X-Request-Id") {
+        Some(id) => id.to_string(),
+        None => NEXT_ID.fetch_add(1, Ordering::Relaxed).to_string(),
+    };
+    request.headers.set("X-Request-Id", id.clone());
+
+    next.run(request).with_header("X-Request-Id", id)
+}
+
+/// Adds a `Server-Timing` header with how long the rest of the chain took, in milliseconds.
+pub fn timing(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let response = next.run(request);
+
+    let millis = start.elapsed().as_secs_f64() * 1000.0;
+    response.with_header("Server-Timing", format!("app;dur={millis:.3}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Status;
+
+    use std::sync::{Arc, Mutex};
+
+    fn request(raw: &str) -> Request {
+        Request::read_from(&mut raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn middleware_runs_in_the_order_it_was_added() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let (outer, inner) = (Arc::clone(&seen), Arc::clone(&seen));
+        let router = Router::new()
+            .get("/", |_, _| Response::new(Status::OK))
+            .wrap(move |request, next| {
+                outer.lock().unwrap().push("outer");
+                next.run(request).with_header("X-Layer", "outer")
+            })
+            .wrap(move |request, next| {
+                inner.lock().unwrap().push("inner");
+                next.run(request).with_header("X-Layer", "inner")
+            });
+
+        let response = router.dispatch(request("GET / HTTP/1.1\r\n\r\n"));
+
+        assert_eq!(vec!["outer", "inner"], *seen.lock().unwrap());
+        // the outer one sees the response last, so its header wins
+        assert_eq!(Some("outer"), response.headers.get("X-Layer"));
+    }
+
+    #[test]
+    fn middleware_can_answer_without_the_router() {
+        let router = Router::new()
+            .get("/", |_, _| Response::new(Status::OK))
+            .wrap(|request, next| match request.header("Authorization") {
+                Some(_) => next.run(request),
+                None => Response::new(Status::FORBIDDEN),
+            });
+
+        let denied = router.dispatch(request("GET / HTTP/1.1\r\n\r\n"));
+        let allowed = router.dispatch(request("GET / HTTP/1.1\r\nAuthorization: yes\r\n\r\n"));
+
+        assert_eq!(Status::FORBIDDEN, denied.status);
+        assert_eq!(Status::OK, allowed.status);
+    }
+
+    #[test]
+    fn request_ids_reach_the_handler_and_the_response() {
+        let router = Router::new()
+            .get("/", |request, _| {
+                let id = request.header("X-Request-Id").unwrap_or("none");
+                Response::new(Status::OK).with_body(id.to_string())
+            })
+            .wrap(request_id)
+            .wrap(timing);
+
+        let given = router.dispatch(request("GET / HTTP/1.1\r\nX-Request-Id: abc\r\n\r\n"));
+        let assigned = router.dispatch(request("GET / HTTP/1.1\r\n\r\n"));
+
+        assert_eq!(Some(&b"abc"[..]), given.body.as_bytes());
+        assert_eq!(Some("abc"), given.headers.get("X-Request-Id"));
+        let id = assigned.headers.get("X-Request-Id").unwrap();
+        assert_eq!(Some(id.as_bytes()), assigned.body.as_bytes());
+        assert!(assigned
+            .headers
+            .get("Server-Timing")
+            .unwrap()
+            .starts_with("app;dur="));
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::http::{Response, Status};
+
+// past this many clients, the ones whose buckets have filled back up are forgotten,
+// so a scan from many addresses can't grow the map without bound
+const PRUNE_AT: usize = 10_000;
+
+struct Buckets {
+    clients: HashMap<IpAddr, Bucket>,
+    // twice as many clients as were left by the last prune, so that going through them
+    // all is paid for by the clients added since, however few of them it forgets
+    prune_at: usize,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// a token bucket per client address, see `ServerBuilder::rate_limit`
+///
+/// Each client can make `burst` requests back to back, and after that one every
+/// `1 / per_second` seconds as their bucket refills.
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    /// # Panics
+    ///
+    /// Panics if `per_second` isn't a positive number or `burst` is zero.
+    pub fn new(per_second: f64, burst: u32) -> RateLimiter {
+        assert!(per_second > 0.0 && per_second.is_finite());
+        assert!(burst > 0);
+
+        RateLimiter {
+            per_second,
+            burst: f64::from(burst),
+            buckets: Mutex::new(Buckets {
+                clients: HashMap::new(),
+                prune_at: PRUNE_AT,
+            }),
+        }
+    }
+
+    /// Takes a token from `client`'s bucket, or says how long until there is one.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.clients.len() >= buckets.prune_at {
+            buckets
+                .clients
+                .retain(|_, bucket| self.refill(bucket, now) < self.burst);
+            buckets.prune_at = PRUNE_AT.max(buckets.clients.len() * 2);
+        }
+
+        let bucket = buckets.clients.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let tokens = self.refill(bucket, now);
+        if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - tokens) / self.per_second))
+    }
+
+    // brings the bucket up to date, returning how many tokens it has
+    fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst);
+        bucket.updated = now;
+        bucket.tokens
+    }
+}
+
+/// what a client that is over its rate gets, telling it when to come back
+pub(crate) fn too_many_requests(retry_after: Duration) -> Response {
+    // Retry-After is in whole seconds, rounded up so the client doesn't come back too early
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    Response::new(Status::TOO_MANY_REQUESTS)
+        .with_header("Retry-After", seconds.to_string())
+        .with_header("Connection", "close")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn clients_get_a_burst_then_the_rate() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let (alice, bob) = (
+            Ipv4Addr::new(10, 0, 0, 1).into(),
+            Ipv4Addr::LOCALHOST.into(),
+        );
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(Ok(()), limiter.check_at(alice, start));
+        }
+        assert_eq!(
+            Err(Duration::from_millis(500)),
+            limiter.check_at(alice, start)
+        );
+        // everyone has their own bucket
+        assert_eq!(Ok(()), limiter.check_at(bob, start));
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(Ok(()), limiter.check_at(alice, later));
+        assert!(limiter.check_at(alice, later).is_err());
+    }
+
+    #[test]
+    fn clients_are_forgotten_once_their_buckets_refill() {
+        let limiter = RateLimiter::new(1.0, 1);
+        let start = Instant::now();
+        let clients =
+            |count| (0..count).map(|i: u32| IpAddr::from(Ipv4Addr::from(0x0a00_0000 + i)));
+        let buckets = || {
+            let buckets = limiter.buckets.lock().unwrap();
+            (buckets.clients.len(), buckets.prune_at)
+        };
+
+        for client in clients(PRUNE_AT as u32 + 1) {
+            assert_eq!(Ok(()), limiter.check_at(client, start));
+        }
+        // nobody had refilled, so nothing could go, and the next prune waits for twice as many
+        assert_eq!((PRUNE_AT + 1, PRUNE_AT * 2), buckets());
+
+        let refilled = start + Duration::from_secs(1);
+        for client in clients(PRUNE_AT as u32 * 2).skip(PRUNE_AT + 1) {
+            assert_eq!(Ok(()), limiter.check_at(client, refilled));
+        }
+        assert_eq!((PRUNE_AT * 2, PRUNE_AT * 2), buckets());
+        let late = IpAddr::from(Ipv4Addr::LOCALHOST);
+        assert_eq!(Ok(()), limiter.check_at(late, refilled));
+        // those from the start had refilled, the ones just added hadn't
+        assert_eq!((PRUNE_AT, (PRUNE_AT - 1) * 2), buckets());
+    }
+
+    #[test]
+    fn retry_after_rounds_up() {
+        let response = too_many_requests(Duration::from_millis(1200));
+
+        assert_eq!(Status::TOO_MANY_REQUESTS, response.status);
+        assert_eq!(Some("2"), response.headers.get("Retry-After"));
+    }
+}
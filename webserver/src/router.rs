@@ -0,0 +1,334 @@
+use crate::{
+    http::{percent_decode, Body, Method, Request, Response, Status},
+    middleware::{Middleware, Next},
+};
+
+type Handler = Box<dyn Fn(&Request, &Params) -> Response + Send + Sync + 'static>;
+
+/// the values a route's `:name` and `*name` segments matched, already percent-decoded
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+enum Segment {
+    Literal(String),
+    // `:name`, a single segment
+    Param(String),
+    // `*name`, the rest of the path, which may be empty
+    Rest(String),
+}
+
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut parts = path.strip_prefix('/').unwrap_or(path).split('/');
+        let mut params = Vec::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Rest(name) => {
+                    let rest: Vec<_> = parts.by_ref().collect();
+                    params.push((name.clone(), percent_decode(&rest.join("/"))?));
+                }
+                Segment::Literal(literal) => {
+                    if parts.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => match parts.next()? {
+                    "" => return None,
+                    part => params.push((name.clone(), percent_decode(part)?)),
+                },
+            }
+        }
+
+        match parts.next() {
+            None => Some(Params(params)),
+            Some(_) => None,
+        }
+    }
+}
+
+/// sends each request to the first handler registered for its method and path
+///
+/// Patterns are split on `/`, where a `:name` segment matches any one segment and a
+/// trailing `*name` matches whatever is left. Both are handed to the handler in `Params`.
+///
+/// ```
+/// use webserver::http::{Request, Response, Status};
+/// use webserver::router::Router;
+///
+/// let router = Router::new().get("/users/:id", |_, params| {
+///     Response::new(Status::OK).with_body(format!("user {}", params.get("id").unwrap()))
+/// });
+///
+/// let request = Request::read_from(&mut &b"GET /users/7 HTTP/1.1\r\n\r\n"[..]).unwrap();
+/// assert_eq!(Some(&b"user 7"[..]), router.handle(&request).body.as_bytes());
+/// ```
+///
+/// A path that matches a route for another method gets `405 Method Not Allowed`, and one
+/// that matches nothing gets the `not_found` handler. `HEAD` requests are answered by the
+/// `GET` route if there is no `HEAD` one.
+///
+/// Middleware added with `wrap` runs around every request, matched or not, see
+/// `Router::dispatch`.
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Handler,
+    middleware: Vec<Middleware>,
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_, _| Response::new(Status::NOT_FOUND)),
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Registers `handler` for requests with `method` whose path matches `pattern`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `pattern` doesn't start with `/`, or has a `*name` segment before the end.
+    pub fn route<F>(mut self, method: Method, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        let segments: Vec<_> = pattern
+            .strip_prefix('/')
+            .unwrap_or_else(|| panic!("route {pattern:?} must start with /"))
+            .split('/')
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Rest(name.to_string())
+                } else {
+                    Segment::Literal(segment.to_string())
+                }
+            })
+            .collect();
+        let rest_at = segments
+            .iter()
+            .position(|segment| matches!(segment, Segment::Rest(_)));
+        assert!(
+            rest_at.is_none_or(|at| at == segments.len() - 1),
+            "route {pattern:?} can only have a *name segment at the end"
+        );
+
+        self.routes.push(Route {
+            method,
+            segments,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<F>(self, pattern: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    /// What to answer requests that match no route with, by default an empty 404.
+    pub fn not_found<F>(mut self, handler: F) -> Router
+    where
+        F: Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    /// Wraps every request in `middleware`, which can change the request, answer it
+    /// itself, or pass it on with `next.run(request)` and change the response.
+    ///
+    /// Middleware runs in the order it was added, so the first is the outermost: it sees
+    /// the request first and the response last.
+    ///
+    /// ```
+    /// use webserver::http::{Request, Response, Status};
+    /// use webserver::router::Router;
+    ///
+    /// let router = Router::new()
+    ///     .get("/", |_, _| Response::new(Status::OK))
+    ///     .wrap(|request, next| next.run(request).with_header("X-Frame-Options", "DENY"));
+    ///
+    /// let request = Request::read_from(&mut &b"GET / HTTP/1.1\r\n\r\n"[..]).unwrap();
+    /// assert_eq!(Some("DENY"), router.dispatch(request).headers.get("X-Frame-Options"));
+    /// ```
+    pub fn wrap<F>(mut self, middleware: F) -> Router
+    where
+        F: Fn(Request, Next) -> Response + Send + Sync + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Runs `request` through the middleware and then the matching handler.
+    pub fn dispatch(&self, request: Request) -> Response {
+        Next::new(&self.middleware, self).run(request)
+    }
+
+    /// Runs the handler for `request` and returns its response, skipping the middleware.
+    pub fn handle(&self, request: &Request) -> Response {
+        let path = request.path();
+        let find = |method| {
+            self.routes.iter().find_map(|route| {
+                let params = (route.method == method).then(|| route.matches(path))??;
+                Some((route, params))
+            })
+        };
+
+        if let Some((route, params)) = find(request.method) {
+            return (route.handler)(request, &params);
+        }
+        if request.method == Method::Head {
+            if let Some((route, params)) = find(Method::Get) {
+                // the length of the body that would have been sent is kept, as HEAD asks
+                let mut response = (route.handler)(request, &params);
+                let framed = response.headers.contains("Content-Length")
+                    || response.headers.contains("Transfer-Encoding");
+                match response.body.len() {
+                    _ if framed => {}
+                    Some(length) => response.headers.set("Content-Length", length.to_string()),
+                    None => response.headers.set("Transfer-Encoding", "chunked"),
+                }
+                response.body = Body::empty();
+                return response;
+            }
+        }
+
+        let mut allowed: Vec<_> = self
+            .routes
+            .iter()
+            .filter(|route| route.matches(path).is_some())
+            .map(|route| route.method.as_str())
+            .collect();
+        if allowed.is_empty() {
+            return (self.not_found)(request, &Params::default());
+        }
+        allowed.sort_unstable();
+        allowed.dedup();
+
+        Response::new(Status::METHOD_NOT_ALLOWED).with_header("Allow", allowed.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, target: &str) -> Request {
+        let raw = format!("{method} {target} HTTP/1.1\r\n\r\n");
+        Request::read_from(&mut raw.as_bytes()).unwrap()
+    }
+
+    fn body(response: Response) -> String {
+        String::from_utf8(response.body.into_bytes().unwrap()).unwrap()
+    }
+
+    fn echo(name: &'static str) -> impl Fn(&Request, &Params) -> Response {
+        move |_, params| {
+            let text = params.get(name).unwrap_or("-").to_string();
+            Response::new(Status::OK).with_body(text)
+        }
+    }
+
+    #[test]
+    fn matches_literals_params_and_rest() {
+        let router = Router::new()
+            .get("/", |_, _| Response::new(Status::OK).with_body("index"))
+            .get("/users/:id", echo("id"))
+            .get("/files/*path", echo("path"));
+
+        assert_eq!("index", body(router.handle(&request("GET", "/"))));
+        assert_eq!("42", body(router.handle(&request("GET", "/users/42?x=1"))));
+        assert_eq!("a b", body(router.handle(&request("GET", "/users/a%20b"))));
+        assert_eq!(
+            "css/site.css",
+            body(router.handle(&request("GET", "/files/css/site.css")))
+        );
+        assert_eq!("", body(router.handle(&request("GET", "/files/"))));
+
+        for missing in ["/users", "/users/", "/users/1/posts", "/nope", "/users/%zz"] {
+            let response = router.handle(&request("GET", missing));
+            assert_eq!(Status::NOT_FOUND, response.status, "{missing}");
+        }
+    }
+
+    #[test]
+    fn first_registered_route_wins() {
+        let router = Router::new()
+            .get("/users/me", |_, _| {
+                Response::new(Status::OK).with_body("me")
+            })
+            .get("/users/:id", echo("id"));
+
+        assert_eq!("me", body(router.handle(&request("GET", "/users/me"))));
+        assert_eq!("you", body(router.handle(&request("GET", "/users/you"))));
+    }
+
+    #[test]
+    fn wrong_method_is_not_allowed() {
+        let router = Router::new().post("/users/:id", echo("id")).route(
+            Method::Delete,
+            "/users/:id",
+            echo("id"),
+        );
+
+        let response = router.handle(&request("GET", "/users/1"));
+        assert_eq!(Status::METHOD_NOT_ALLOWED, response.status);
+        assert_eq!(Some("DELETE, POST"), response.headers.get("Allow"));
+    }
+
+    #[test]
+    fn head_falls_back_to_get() {
+        let router = Router::new().get("/", |_, _| Response::new(Status::OK).with_body("index"));
+
+        let response = router.handle(&request("HEAD", "/"));
+        assert_eq!(Status::OK, response.status);
+        assert_eq!(Some("5"), response.headers.get("Content-Length"));
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn not_found_handler_can_be_replaced() {
+        let router = Router::new().not_found(|request, _| {
+            Response::new(Status::NOT_FOUND).with_body(format!("no {}", request.path()))
+        });
+
+        assert_eq!(
+            "no /qwerty",
+            body(router.handle(&request("GET", "/qwerty")))
+        );
+    }
+}
@@ -0,0 +1,572 @@
+#[cfg(feature = "tls")]
+use std::path::PathBuf;
+use std::{
+    io::{self, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use thread_pool::{Executor, ThreadPool};
+
+#[cfg(feature = "tls")]
+use crate::tls;
+use crate::{
+    connection,
+    http::{Response, Status},
+    rate_limit::{self, RateLimiter},
+    router::Router,
+};
+
+#[cfg(feature = "tls")]
+use rustls::ServerConfig as TlsConfig;
+// without the feature there is never a config, this only keeps the signatures the same
+#[cfg(not(feature = "tls"))]
+type TlsConfig = ();
+
+/// sets up a `Server`, see `Server::builder`
+pub struct ServerBuilder {
+    addr: String,
+    threads: Option<usize>,
+    queue_capacity: usize,
+    stop_after: Option<usize>,
+    shutdown_timeout: Duration,
+    rate_limit: Option<(f64, u32)>,
+    max_connections: Option<usize>,
+    #[cfg(feature = "tls")]
+    tls: Option<(PathBuf, PathBuf)>,
+}
+
+impl ServerBuilder {
+    /// The address to listen on, `127.0.0.1:7878` by default.
+    pub fn bind(mut self, addr: impl Into<String>) -> ServerBuilder {
+        self.addr = addr.into();
+        self
+    }
+
+    /// How many connections are handled at once, one per CPU by default.
+    pub fn threads(mut self, threads: usize) -> ServerBuilder {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// How many accepted connections can wait for a free thread, 64 by default.
+    pub fn queue_capacity(mut self, capacity: usize) -> ServerBuilder {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Stops accepting after this many connections, then finishes the ones in progress.
+    pub fn stop_after(mut self, connections: usize) -> ServerBuilder {
+        self.stop_after = Some(connections);
+        self
+    }
+
+    /// How long connections that were accepted but not yet picked up get to start once
+    /// the server is shutting down, 10 seconds by default. Those still waiting are closed.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Limits each client address to `per_second` requests a second, after an initial
+    /// `burst`. Clients over the limit get `429 Too Many Requests`.
+    ///
+    /// A new connection is checked as soon as it is accepted, so a client that is over
+    /// its limit is turned away without tying up a thread.
+    ///
+    /// # Panics
+    ///
+    /// `build` panics if `per_second` isn't a positive number or `burst` is zero.
+    pub fn rate_limit(mut self, per_second: f64, burst: u32) -> ServerBuilder {
+        self.rate_limit = Some((per_second, burst));
+        self
+    }
+
+    /// Limits how many connections can be open at once, counting those waiting for a
+    /// thread as well as those being handled. By default there is no limit.
+    ///
+    /// Past the limit, new connections are answered `503 Service Unavailable` and
+    /// closed as soon as they are accepted, rather than left waiting for a thread.
+    pub fn max_connections(mut self, connections: usize) -> ServerBuilder {
+        self.max_connections = Some(connections);
+        self
+    }
+
+    /// Serves HTTPS with the PEM encoded certificate chain and private key at these paths.
+    #[cfg(feature = "tls")]
+    pub fn tls(
+        mut self,
+        cert_path: impl Into<PathBuf>,
+        key_path: impl Into<PathBuf>,
+    ) -> ServerBuilder {
+        self.tls = Some((cert_path.into(), key_path.into()));
+        self
+    }
+
+    /// Binds the listener and starts the threads, sending every request to `router`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address can't be bound, a thread can't be spawned, or
+    /// the TLS certificate or key can't be loaded.
+    pub fn build(self, router: Router) -> io::Result<Server> {
+        let mut pool = ThreadPool::builder()
+            .thread_name("http-worker")
+            .queue_capacity(self.queue_capacity);
+        if let Some(threads) = self.threads {
+            pool = pool.num_threads(threads);
+        }
+        let pool = pool.build()?;
+        self.build_with(router, pool)
+    }
+
+    /// Like `build`, but connections are handled by `executor` rather than a thread pool
+    /// of the server's own, so `threads` and `queue_capacity` are ignored.
+    ///
+    /// With a `thread_pool::CurrentThread` each connection is handled on the thread
+    /// that calls `Server::run` before the next is accepted, which keeps tests simple.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the address can't be bound, or the TLS certificate or key
+    /// can't be loaded.
+    pub fn build_with<E: Executor>(self, router: Router, executor: E) -> io::Result<Server<E>> {
+        #[cfg(feature = "tls")]
+        let tls = match &self.tls {
+            Some((cert_path, key_path)) => Some(tls::load_config(cert_path, key_path)?),
+            None => None,
+        };
+        #[cfg(not(feature = "tls"))]
+        let tls = None;
+
+        Ok(Server {
+            listener: TcpListener::bind(&self.addr)?,
+            pool: executor,
+            router: Arc::new(router),
+            stop_after: self.stop_after,
+            shutdown_timeout: self.shutdown_timeout,
+            stopping: Arc::new(AtomicBool::new(false)),
+            limiter: self
+                .rate_limit
+                .map(|(per_second, burst)| Arc::new(RateLimiter::new(per_second, burst))),
+            max_connections: self.max_connections,
+            connections: Arc::default(),
+            tls,
+        })
+    }
+}
+
+/// accepts connections and answers their requests on a thread pool, or another
+/// `Executor` given to `ServerBuilder::build_with`
+///
+/// ```no_run
+/// use webserver::{http::{Response, Status}, router::Router, server::Server};
+///
+/// let router = Router::new().get("/", |_, _| Response::new(Status::OK).with_body("hi"));
+/// Server::builder().bind("0.0.0.0:8080").build(router).unwrap().run();
+/// ```
+pub struct Server<E = ThreadPool> {
+    listener: TcpListener,
+    pool: E,
+    router: Arc<Router>,
+    stop_after: Option<usize>,
+    shutdown_timeout: Duration,
+    stopping: Arc<AtomicBool>,
+    limiter: Option<Arc<RateLimiter>>,
+    max_connections: Option<usize>,
+    // how many connections are open, see `ConnectionSlot`
+    connections: Arc<AtomicUsize>,
+    tls: Option<Arc<TlsConfig>>,
+}
+
+// held by each open connection until it closes, or is dropped without being handled
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    // None if `max` connections are already open
+    fn take(connections: &Arc<AtomicUsize>, max: Option<usize>) -> Option<ConnectionSlot> {
+        let max = max.unwrap_or(usize::MAX);
+        connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < max).then_some(open + 1)
+            })
+            .ok()?;
+        Some(ConnectionSlot(Arc::clone(connections)))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// stops a running `Server`, from another thread or a signal handler
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    stopping: Arc<AtomicBool>,
+    addr: SocketAddr,
+}
+
+impl ShutdownHandle {
+    /// Asks the server to stop accepting connections and wind down, see `Server::run`.
+    pub fn shutdown(&self) {
+        if self.stopping.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // accept has no timeout, so the listener is woken up with a connection of its own
+        let mut addr = self.addr;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        if let Err(err) = TcpStream::connect(addr) {
+            eprintln!("couldn't wake the listener: {err}");
+        }
+    }
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            addr: "127.0.0.1:7878".to_string(),
+            threads: None,
+            queue_capacity: 64,
+            stop_after: None,
+            shutdown_timeout: Duration::from_secs(10),
+            rate_limit: None,
+            max_connections: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl<E: Executor> Server<E> {
+    /// The address the server is listening on, handy after binding to port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// A handle that can stop the server once it is running.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the listener's address can't be found.
+    pub fn shutdown_handle(&self) -> io::Result<ShutdownHandle> {
+        Ok(ShutdownHandle {
+            stopping: Arc::clone(&self.stopping),
+            addr: self.local_addr()?,
+        })
+    }
+
+    /// Shuts the server down gracefully on Ctrl-C or `SIGTERM`, a second one exits at once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the signal handlers can't be installed.
+    #[cfg(unix)]
+    pub fn shutdown_on_signals(&self) -> io::Result<()> {
+        use signal_hook::{consts::TERM_SIGNALS, flag, iterator::Signals};
+
+        for &signal in TERM_SIGNALS {
+            // does nothing the first time, as `stopping` is only set once we have seen it
+            flag::register_conditional_shutdown(signal, 1, Arc::clone(&self.stopping))?;
+        }
+        let mut signals = Signals::new(TERM_SIGNALS)?;
+        let handle = self.shutdown_handle()?;
+
+        std::thread::Builder::new()
+            .name("signals".to_string())
+            .spawn(move || {
+                if signals.forever().next().is_some() {
+                    println!("shutting down, press Ctrl-C again to exit now");
+                    handle.shutdown();
+                }
+            })?;
+        Ok(())
+    }
+
+    /// Accepts connections until `stop_after` is reached or the server is shut down,
+    /// then winds down.
+    ///
+    /// Winding down gives connections that are waiting for a thread until the
+    /// `shutdown_timeout` to be picked up, and closes those that aren't. Connections
+    /// already being handled finish the response they are working on, then close.
+    pub fn run(self) {
+        let limit = self.stop_after.unwrap_or(usize::MAX);
+
+        for stream in self.listener.incoming().take(limit) {
+            if self.stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("couldn't accept connection: {err}");
+                    continue;
+                }
+            };
+            // a client we can't tell apart from others can't be limited either
+            let client = stream.peer_addr().ok().map(|addr| addr.ip());
+            let limit = self.limiter.clone().zip(client);
+            // there is no answering a TLS client before the handshake, so one that is
+            // turned away is just hung up on
+            if let Some((limiter, client)) = &limit {
+                if let Err(retry_after) = limiter.check(*client) {
+                    if self.tls.is_none() {
+                        reject(stream, rate_limit::too_many_requests(retry_after));
+                    }
+                    continue;
+                }
+            }
+            let Some(slot) = ConnectionSlot::take(&self.connections, self.max_connections) else {
+                if self.tls.is_none() {
+                    reject(stream, service_unavailable());
+                }
+                continue;
+            };
+
+            let router = Arc::clone(&self.router);
+            let stopping = Arc::clone(&self.stopping);
+            let tls = self.tls.clone();
+
+            let submitted = self.pool.execute(move || {
+                let _slot = slot;
+                let limit = limit
+                    .as_ref()
+                    .map(|(limiter, client)| (&**limiter, *client));
+                handle_connection(stream, &router, &stopping, limit, tls);
+            });
+
+            // the connection is dropped, closing it, rather than taking the whole server down
+            if let Err(err) = submitted {
+                eprintln!("couldn't handle connection: {err}");
+            }
+        }
+
+        // kept-alive connections close after their next response
+        self.stopping.store(true, Ordering::SeqCst);
+        let mut pool = self.pool;
+        let dropped = pool.shutdown_timeout(self.shutdown_timeout);
+        if dropped > 0 {
+            eprintln!("closed {dropped} connections that were still waiting");
+        }
+    }
+}
+
+// what a client gets when every connection the server allows is already open
+fn service_unavailable() -> Response {
+    Response::new(Status::SERVICE_UNAVAILABLE)
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close")
+}
+
+// answers a client that is turned away on the accepting thread, so it never takes up
+// a worker
+fn reject(mut stream: TcpStream, response: Response) {
+    // closing a socket with unread data resets the connection, which can lose the
+    // response, so what the client has sent already is read and thrown away. a client
+    // that keeps sending is reset anyway, rather than holding up the accept loop
+    if stream.set_nonblocking(true).is_ok() {
+        let mut discard = [0; 4096];
+        for _ in 0..4 {
+            if !matches!(stream.read(&mut discard), Ok(1..)) {
+                break;
+            }
+        }
+    }
+
+    let sent = stream
+        .set_nonblocking(false)
+        .and_then(|()| stream.set_write_timeout(Some(Duration::from_millis(100))))
+        .and_then(|()| response.write_to(&mut stream));
+    if let Err(err) = sent {
+        eprintln!("couldn't turn client away: {err}");
+    }
+}
+
+#[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+fn handle_connection(
+    stream: TcpStream,
+    router: &Router,
+    stopping: &AtomicBool,
+    limit: Option<(&RateLimiter, IpAddr)>,
+    tls: Option<Arc<TlsConfig>>,
+) {
+    // idle keep-alive connections are closed by their next read timing out
+    if let Err(err) = stream.set_read_timeout(Some(connection::IDLE_TIMEOUT)) {
+        eprintln!("couldn't set connection timeout: {err}");
+        return;
+    }
+
+    #[cfg(feature = "tls")]
+    if let Some(config) = tls {
+        match tls::accept(config, stream) {
+            Ok(stream) => connection::serve(stream, router, stopping, limit),
+            Err(err) => eprintln!("couldn't start TLS: {err}"),
+        }
+        return;
+    }
+
+    connection::serve(stream, router, stopping, limit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{io::Read, io::Write, thread};
+    use thread_pool::CurrentThread;
+
+    #[test]
+    fn serves_until_stopped() {
+        let router = Router::new().get("/", |_, _| Response::new(Status::OK).with_body("hi"));
+        let server = Server::builder()
+            .bind("127.0.0.1:0")
+            .threads(1)
+            .stop_after(1)
+            .build(router)
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let running = thread::spawn(move || server.run());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        running.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhi"));
+    }
+
+    #[test]
+    fn handlers_can_run_on_the_calling_thread() {
+        let handled_on = Arc::new(std::sync::Mutex::new(None));
+        let handler = Arc::clone(&handled_on);
+        let router = Router::new().get("/", move |_, _| {
+            *handler.lock().unwrap() = Some(thread::current().id());
+            Response::new(Status::OK)
+        });
+        let server = Server::builder()
+            .bind("127.0.0.1:0")
+            .stop_after(1)
+            .build_with(router, CurrentThread::new())
+            .unwrap();
+
+        // waits in the listener's backlog until the server gets to it
+        let mut client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        server.run();
+
+        assert_eq!(Some(thread::current().id()), *handled_on.lock().unwrap());
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn clients_over_the_rate_limit_are_turned_away() {
+        let router = Router::new().get("/", |_, _| Response::new(Status::OK).with_body("hi"));
+        let server = Server::builder()
+            .bind("127.0.0.1:0")
+            .threads(1)
+            .rate_limit(0.001, 1)
+            .stop_after(2)
+            .build(router)
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let running = thread::spawn(move || server.run());
+
+        let statuses: Vec<_> = (0..2)
+            .map(|_| {
+                let mut client = TcpStream::connect(addr).unwrap();
+                client
+                    .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+                    .unwrap();
+                let mut response = String::new();
+                client.read_to_string(&mut response).unwrap();
+                response.lines().next().unwrap().to_string()
+            })
+            .collect();
+        running.join().unwrap();
+
+        assert_eq!(
+            vec!["HTTP/1.1 200 OK", "HTTP/1.1 429 Too Many Requests"],
+            statuses
+        );
+    }
+
+    #[test]
+    fn connections_over_the_limit_are_turned_away() {
+        let router = Router::new().get("/", |_, _| Response::new(Status::OK).with_body("hi"));
+        let server = Server::builder()
+            .bind("127.0.0.1:0")
+            .threads(2)
+            .max_connections(1)
+            .stop_after(2)
+            .build(router)
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let running = thread::spawn(move || server.run());
+
+        // kept open after its response, so it holds on to the only slot
+        let mut kept = TcpStream::connect(addr).unwrap();
+        kept.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut first = [0; 17];
+        kept.read_exact(&mut first).unwrap();
+        assert_eq!(b"HTTP/1.1 200 OK\r\n", &first);
+
+        let mut turned_away = TcpStream::connect(addr).unwrap();
+        turned_away.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        turned_away.read_to_string(&mut response).unwrap();
+        drop(kept);
+        running.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("Retry-After: 1\r\n"));
+    }
+
+    #[test]
+    fn shutdown_closes_kept_alive_connections() {
+        let router = Router::new().get("/", |_, _| Response::new(Status::OK).with_body("hi"));
+        let server = Server::builder()
+            .bind("127.0.0.1:0")
+            .threads(1)
+            .build(router)
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle().unwrap();
+        let running = thread::spawn(move || server.run());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut first = [0; 17];
+        client.read_exact(&mut first).unwrap();
+        assert_eq!(b"HTTP/1.1 200 OK\r\n", &first);
+
+        handle.shutdown();
+        // the connection is still being served, so this is answered and then closed
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut rest = String::new();
+        client.read_to_string(&mut rest).unwrap();
+        running.join().unwrap();
+
+        assert!(rest.contains("HTTP/1.1 200 OK\r\n"));
+        assert!(rest.contains("Connection: close\r\n"));
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::http::{Body, Response, Status};
+
+// served for a directory, if it has one
+const INDEX: &str = "index.html";
+
+/// serves the files under a document root, for a route ending in a `*path` segment
+///
+/// ```no_run
+/// use webserver::{router::Router, static_files::StaticFiles};
+///
+/// let files = StaticFiles::new("static");
+/// let router = Router::new().get("/static/*path", move |_, params| {
+///     files.serve(params.get("path").unwrap_or(""))
+/// });
+/// ```
+pub struct StaticFiles {
+    root: PathBuf,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles { root: root.into() }
+    }
+
+    /// Answers with the file at `path` under the root, streamed rather than read into memory.
+    ///
+    /// `path` is expected to be percent-decoded already, as router params are. Paths that try
+    /// to climb out of the root with `..`, or through a symlink, get `403 Forbidden`.
+    pub fn serve(&self, path: &str) -> Response {
+        let Some(file_path) = self.resolve(path) else {
+            return Response::new(Status::FORBIDDEN);
+        };
+
+        match self.open(&file_path) {
+            Ok(Some((opened, file, length))) => Response::new(Status::OK)
+                .with_header("Content-Type", content_type(&opened))
+                .with_body(Body::Stream {
+                    reader: Box::new(file),
+                    length,
+                }),
+            Ok(None) => Response::new(Status::FORBIDDEN),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Response::new(Status::NOT_FOUND),
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                Response::new(Status::FORBIDDEN)
+            }
+            Err(err) => {
+                eprintln!("couldn't serve {}: {err}", file_path.display());
+                Response::new(Status::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+
+    // joins the path onto the root, or None if any part of it isn't a plain name
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        // a backslash is a separator on windows, so it could sneak a `..` past the check
+        if path.contains(['\\', '\0']) {
+            return None;
+        }
+
+        let mut resolved = self.root.clone();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::CurDir => (),
+                _ => return None,
+            }
+        }
+        Some(resolved)
+    }
+
+    // opens a file, or a directory's index, returning None if it turns out to be outside
+    // the root once symlinks are followed. the path is the one that was actually opened
+    fn open(&self, path: &Path) -> io::Result<Option<(PathBuf, File, u64)>> {
+        let mut path = fs::canonicalize(path)?;
+        if path.is_dir() {
+            // the index can be a symlink too, so it's checked rather than the directory
+            path = fs::canonicalize(path.join(INDEX))?;
+        }
+        if !path.starts_with(fs::canonicalize(&self.root)?) {
+            return Ok(None);
+        }
+
+        let file = File::open(&path)?;
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        Ok(Some((path, file, metadata.len())))
+    }
+}
+
+/// the `Content-Type` to send a file with, going by its extension
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("mp3") => "audio/mpeg",
+        Some("mp4") => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(response: Response) -> String {
+        String::from_utf8(response.body.into_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn serves_files_with_their_type() {
+        let root = std::env::temp_dir().join(format!("webserver-static-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs/css")).unwrap();
+        fs::write(root.join("docs/css/site.css"), "body {}").unwrap();
+        fs::write(root.join("docs/index.html"), "<p>docs</p>").unwrap();
+        fs::write(root.join("secret.txt"), "hunter2").unwrap();
+        // a directory whose index links out of the root
+        fs::create_dir_all(root.join("docs/sub")).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("../../secret.txt", root.join("docs/sub/index.html")).unwrap();
+
+        let files = StaticFiles::new(root.join("docs"));
+        let css = files.serve("css/site.css");
+        let index = files.serve("");
+        let missing = files.serve("nope.js");
+        let linked_index = files.serve("sub").status;
+        let climbing = [
+            "../secret.txt",
+            "css/../../secret.txt",
+            "/etc/passwd",
+            "..\\secret.txt",
+        ]
+        .map(|path| files.serve(path).status);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(Status::OK, css.status);
+        assert_eq!(
+            Some("text/css; charset=utf-8"),
+            css.headers.get("Content-Type")
+        );
+        assert_eq!(Some(7), css.body.len());
+        assert_eq!("body {}", body(css));
+        assert_eq!(
+            Some("text/html; charset=utf-8"),
+            index.headers.get("Content-Type")
+        );
+        assert_eq!("<p>docs</p>", body(index));
+        assert_eq!(Status::NOT_FOUND, missing.status);
+        assert_eq!([Status::FORBIDDEN; 4], climbing);
+        #[cfg(unix)]
+        assert_eq!(Status::FORBIDDEN, linked_index);
+        #[cfg(not(unix))]
+        assert_eq!(Status::NOT_FOUND, linked_index);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_out_of_the_root_are_forbidden() {
+        let root = std::env::temp_dir().join(format!("webserver-links-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("public")).unwrap();
+        fs::write(root.join("secret.txt"), "hunter2").unwrap();
+        std::os::unix::fs::symlink("../secret.txt", root.join("public/leak.txt")).unwrap();
+
+        let status = StaticFiles::new(root.join("public"))
+            .serve("leak.txt")
+            .status;
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(Status::FORBIDDEN, status);
+    }
+
+    #[test]
+    fn guesses_content_types() {
+        assert_eq!("image/png", content_type(Path::new("a/logo.PNG")));
+        assert_eq!(
+            "application/octet-stream",
+            content_type(Path::new("Makefile"))
+        );
+    }
+}
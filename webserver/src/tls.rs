@@ -0,0 +1,39 @@
+use std::{io, net::TcpStream, path::Path, sync::Arc};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection, StreamOwned,
+};
+
+// pem and rustls errors don't say which file they came from
+fn invalid(path: &Path, err: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {err}", path.display()),
+    )
+}
+
+/// reads a PEM certificate chain and private key into a config for accepting connections
+pub(crate) fn load_config(cert_path: &Path, key_path: &Path) -> io::Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| invalid(cert_path, err))?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|err| invalid(key_path, err))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|err| invalid(cert_path, err))?;
+
+    Ok(Arc::new(config))
+}
+
+/// wraps an accepted connection, the handshake happens on the first read or write
+pub(crate) fn accept(
+    config: Arc<ServerConfig>,
+    stream: TcpStream,
+) -> io::Result<StreamOwned<ServerConnection, TcpStream>> {
+    let connection = ServerConnection::new(config).map_err(io::Error::other)?;
+    Ok(StreamOwned::new(connection, stream))
+}
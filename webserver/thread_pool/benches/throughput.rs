@@ -0,0 +1,107 @@
+use std::{
+    hint::black_box,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use thread_pool::ThreadPool;
+
+const THREADS: usize = 4;
+const JOBS: u64 = 10_000;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// how the pool used to hand out jobs: a channel whose receiver every worker takes a
+// lock on to get at, so each job is taken one at a time behind the same mutex
+struct MutexChannelPool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl MutexChannelPool {
+    fn new(size: usize) -> MutexChannelPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+
+        MutexChannelPool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    fn execute(&self, f: impl FnOnce() + Send + 'static) {
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+}
+
+impl Drop for MutexChannelPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+// a job that takes well under a millisecond, so handing it out is most of the cost
+fn tiny_job(n: u64) -> u64 {
+    (0..black_box(n % 64)).fold(n, |acc, i| acc.wrapping_mul(31).wrapping_add(i))
+}
+
+// sends `JOBS` tiny jobs through `execute` and waits for all of them to finish
+fn run_jobs(execute: impl Fn(Job)) {
+    let (done, finished) = mpsc::channel();
+    for n in 0..JOBS {
+        let done = done.clone();
+        execute(Box::new(move || {
+            black_box(tiny_job(n));
+            done.send(()).unwrap();
+        }));
+    }
+    for _ in 0..JOBS {
+        finished.recv().unwrap();
+    }
+}
+
+fn dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(JOBS));
+
+    let channel = MutexChannelPool::new(THREADS);
+    group.bench_function(BenchmarkId::new("mutex_channel", THREADS), |b| {
+        b.iter(|| run_jobs(|job| channel.execute(job)));
+    });
+    drop(channel);
+
+    let pool = ThreadPool::new(THREADS as u32);
+    group.bench_function(BenchmarkId::new("thread_pool", THREADS), |b| {
+        b.iter(|| run_jobs(|job| pool.execute(job).unwrap()));
+    });
+
+    let bounded = ThreadPool::builder()
+        .num_threads(THREADS)
+        .queue_capacity(256)
+        .build()
+        .unwrap();
+    group.bench_function(BenchmarkId::new("thread_pool_bounded", THREADS), |b| {
+        b.iter(|| run_jobs(|job| bounded.execute(job).unwrap()));
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -0,0 +1,97 @@
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+/// how the jobs of a batch went, from `BatchHandle::wait`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSummary {
+    /// jobs that ran to the end
+    pub completed: usize,
+    /// jobs that panicked, which the pool's panic handler has also seen
+    pub panicked: usize,
+    /// jobs that were thrown away without running, e.g. by `ThreadPool::shutdown_now`
+    pub cancelled: usize,
+}
+
+impl BatchSummary {
+    fn finished(&self) -> usize {
+        self.completed + self.panicked + self.cancelled
+    }
+}
+
+#[derive(Default)]
+struct BatchState {
+    summary: Mutex<BatchSummary>,
+    all_done: Condvar,
+}
+
+// owned by each job of a batch, so it is counted whether it ran, panicked or was dropped
+pub(crate) struct Member {
+    state: Arc<BatchState>,
+    ran: bool,
+}
+
+impl Member {
+    pub(crate) fn run(mut self, f: impl FnOnce()) {
+        self.ran = true;
+        f();
+    }
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        let mut summary = self.state.summary.lock().unwrap();
+        if thread::panicking() {
+            summary.panicked += 1;
+        } else if self.ran {
+            summary.completed += 1;
+        } else {
+            summary.cancelled += 1;
+        }
+        self.state.all_done.notify_all();
+    }
+}
+
+/// a handle to wait for every job sent with `ThreadPool::execute_batch`
+pub struct BatchHandle {
+    state: Arc<BatchState>,
+    len: usize,
+}
+
+impl BatchHandle {
+    pub(crate) fn new(len: usize) -> (BatchHandle, impl FnMut() -> Member) {
+        let state = Arc::new(BatchState::default());
+        let member = {
+            let state = Arc::clone(&state);
+            move || Member {
+                state: Arc::clone(&state),
+                ran: false,
+            }
+        };
+        (BatchHandle { state, len }, member)
+    }
+
+    /// How many jobs are in the batch.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether every job in the batch has finished, one way or another.
+    pub fn is_finished(&self) -> bool {
+        self.state.summary.lock().unwrap().finished() == self.len
+    }
+
+    /// Blocks until every job in the batch has finished, and says how they went.
+    pub fn wait(&self) -> BatchSummary {
+        let mut summary = self.state.summary.lock().unwrap();
+        while summary.finished() < self.len {
+            summary = self.state.all_done.wait(summary).unwrap();
+        }
+        *summary
+    }
+}
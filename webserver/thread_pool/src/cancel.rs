@@ -0,0 +1,83 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{JobError, JobHandle};
+
+/// lets a job started with `ThreadPool::execute_cancellable` know it is no longer wanted
+///
+/// Nothing stops the job from outside, it has to check `is_cancelled` every so often and
+/// give up when it sees it. Clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Asks whatever is watching the token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+}
+
+/// a `JobHandle` for a job started with `ThreadPool::execute_cancellable`, which can also
+/// cancel it
+pub struct CancellableHandle<T> {
+    // `None` if the job was cancelled before it started
+    handle: JobHandle<Option<T>>,
+    token: CancellationToken,
+}
+
+impl<T> CancellableHandle<T> {
+    pub(crate) fn new(handle: JobHandle<Option<T>>, token: CancellationToken) -> Self {
+        CancellableHandle { handle, token }
+    }
+
+    /// Cancels the job. If it hasn't started yet it never will, and if it is running it
+    /// sees its token cancelled.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// The job's token, for cancelling it from somewhere the handle can't go.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// whether the job has finished running, successfully or not
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Blocks until the job has finished, returning its value.
+    ///
+    /// A job that was cancelled before it started gives `JobError::Cancelled`. One that
+    /// was cancelled while running gives whatever it returned on seeing that.
+    pub fn join(self) -> Result<T, JobError> {
+        self.handle.join().and_then(started)
+    }
+
+    /// returns the job's value if it has finished, or hands the handle back if it hasn't
+    pub fn try_join(self) -> Result<Result<T, JobError>, CancellableHandle<T>> {
+        match self.handle.try_join() {
+            Ok(result) => Ok(result.and_then(started)),
+            Err(handle) => Err(CancellableHandle {
+                handle,
+                token: self.token,
+            }),
+        }
+    }
+}
+
+fn started<T>(value: Option<T>) -> Result<T, JobError> {
+    value.ok_or(JobError::Cancelled)
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use crate::{PoolClosedError, ThreadPool};
+
+/// something that runs jobs, so code can be written against a `ThreadPool` and be handed
+/// another pool instead, or a `CurrentThread` in tests
+pub trait Executor {
+    /// Runs `f`, now or later, on this thread or another.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the executor has been shut down.
+    fn execute<F>(&self, f: F) -> Result<(), PoolClosedError>
+    where
+        F: FnOnce() + Send + 'static;
+
+    /// Stops taking new jobs, gives the ones waiting to run until `timeout` to get
+    /// started, and waits for those that are running.
+    ///
+    /// Returns how many jobs were thrown away without running.
+    fn shutdown_timeout(&mut self, timeout: Duration) -> usize;
+}
+
+impl<S: 'static> Executor for ThreadPool<S> {
+    fn execute<F>(&self, f: F) -> Result<(), PoolClosedError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        ThreadPool::execute(self, f)
+    }
+
+    fn shutdown_timeout(&mut self, timeout: Duration) -> usize {
+        ThreadPool::shutdown_timeout(self, timeout)
+    }
+}
+
+/// an `Executor` that runs each job straight away on the thread that sent it
+///
+/// `execute` returns once the job has finished, which makes what it did easy to check
+/// in tests. Unlike on a `ThreadPool`, a job that panics takes the caller with it.
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use thread_pool::{CurrentThread, Executor};
+///
+/// let ran = Arc::new(Mutex::new(false));
+/// let job = Arc::clone(&ran);
+/// CurrentThread::new().execute(move || *job.lock().unwrap() = true).unwrap();
+/// assert!(*ran.lock().unwrap());
+/// ```
+#[derive(Debug, Default)]
+pub struct CurrentThread {
+    closed: bool,
+}
+
+impl CurrentThread {
+    pub fn new() -> CurrentThread {
+        CurrentThread::default()
+    }
+}
+
+impl Executor for CurrentThread {
+    fn execute<F>(&self, f: F) -> Result<(), PoolClosedError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.closed {
+            return Err(PoolClosedError);
+        }
+        f();
+        Ok(())
+    }
+
+    fn shutdown_timeout(&mut self, _timeout: Duration) -> usize {
+        // nothing is ever left waiting
+        self.closed = true;
+        0
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use crate::JobError;
+
+// where the job leaves its value for the future to pick up
+struct Slot<T> {
+    result: Option<Result<T, JobError>>,
+    waker: Option<Waker>,
+}
+
+/// the value of a job started with `ThreadPool::submit_async`, for awaiting from async code
+///
+/// Awaiting it doesn't block the executor's thread, the job wakes the task up once it
+/// is done. The job runs whether or not the future is ever awaited.
+pub struct JobFuture<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+// hands the job's value to the future, or `JobError::Cancelled` if it is dropped first
+pub(crate) struct Completer<T> {
+    slot: Option<Arc<Mutex<Slot<T>>>>,
+}
+
+impl<T> JobFuture<T> {
+    pub(crate) fn new() -> (JobFuture<T>, Completer<T>) {
+        let slot = Arc::new(Mutex::new(Slot {
+            result: None,
+            waker: None,
+        }));
+        let completer = Completer {
+            slot: Some(Arc::clone(&slot)),
+        };
+        (JobFuture { slot }, completer)
+    }
+}
+
+impl<T> Future for JobFuture<T> {
+    type Output = Result<T, JobError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock().unwrap();
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                // the task may have moved to another thread since it was last polled
+                match &mut slot.waker {
+                    Some(waker) => waker.clone_from(cx.waker()),
+                    None => slot.waker = Some(cx.waker().clone()),
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Completer<T> {
+    pub(crate) fn complete(mut self, result: Result<T, JobError>) {
+        if let Some(slot) = self.slot.take() {
+            fill(&slot, result);
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            fill(&slot, Err(JobError::Cancelled));
+        }
+    }
+}
+
+fn fill<T>(slot: &Mutex<Slot<T>>, result: Result<T, JobError>) {
+    let mut slot = slot.lock().unwrap();
+    slot.result = Some(result);
+    // woken without the lock held, so the task can poll straight away
+    let waker = slot.waker.take();
+    drop(slot);
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
@@ -1,45 +1,774 @@
+mod batch;
+mod cancel;
+mod executor;
+mod future;
+mod map;
+mod queue;
+mod retry;
+mod scope;
+mod timer;
+
 use std::{
-    sync::{mpsc, Arc, Mutex},
+    any::Any,
+    cell::Cell,
+    error::Error,
+    fmt, io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, OnceLock,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
-type Job = Box<dyn FnOnce() + Send + 'static>; // the type of closure which ThreadPool::execute receives
+pub use batch::{BatchHandle, BatchSummary};
+pub use cancel::{CancellableHandle, CancellationToken};
+pub use executor::{CurrentThread, Executor};
+pub use future::JobFuture;
+pub use map::Results;
+use queue::{JobQueue, Next, Queued};
+pub use retry::PanicPolicy;
+pub use scope::Scope;
+use timer::Timer;
+
+// the type of closure which workers run, handed the worker's state
+type Job<S> = Box<dyn FnOnce(&mut S) + Send + 'static>;
+
+// builds the state of each worker, see `ThreadPool::with_worker_init`
+type WorkerInit<S> = Box<dyn Fn() -> S + Send + Sync + 'static>;
+
+// called with the worker's id and the panic message when a job passed to `execute` panics
+type PanicHandler = Box<dyn Fn(u32, &str) + Send + Sync + 'static>;
+
+// told about workers starting jobs and exiting, see `ThreadPoolBuilder::logger`
+type Logger = Box<dyn Fn(Event) + Send + Sync + 'static>;
+
+// callbacks for feeding what the pool does into a metrics system, see
+// `ThreadPoolBuilder::on_job_queued` and the ones after it
+#[derive(Default)]
+struct Hooks {
+    queued: Option<Box<dyn Fn() + Send + Sync + 'static>>,
+    // handed how long the job waited in the queue
+    started: Option<Box<dyn Fn(Duration) + Send + Sync + 'static>>,
+    // handed how long the job ran for
+    completed: Option<Box<dyn Fn(Duration) + Send + Sync + 'static>>,
+}
+
+// state every worker can see
+struct Shared<S> {
+    queue: JobQueue<S>,
+    init: WorkerInit<S>,
+    panic_handler: PanicHandler,
+    panic_policy: PanicPolicy,
+    logger: Logger,
+    counters: Counters,
+    // every worker the pool has started and not yet joined, including those spawned to
+    // replace one stuck on a job that timed out
+    workers: Mutex<Vec<Worker>>,
+    next_id: AtomicU32,
+    options: WorkerOptions,
+    // set if the pool was given a `max_threads`
+    elastic: Option<Elastic>,
+}
+
+// when to start temporary workers on top of the pool's core ones, and how many there are
+struct Elastic {
+    max_threads: usize,
+    threshold: usize,
+    keep_alive: Duration,
+    // the pool's size, kept up to date by `resize`
+    core: AtomicUsize,
+    temporary: AtomicUsize,
+}
+
+impl<S: 'static> Shared<S> {
+    fn push(self: &Arc<Self>, priority: Priority, job: Job<S>) -> Result<(), PoolClosedError> {
+        self.queue.push(priority, job)?;
+        self.overflow();
+        Ok(())
+    }
+
+    fn push_all(
+        self: &Arc<Self>,
+        priority: Priority,
+        jobs: impl ExactSizeIterator<Item = Job<S>>,
+    ) -> Result<(), PoolClosedError> {
+        self.queue.push_all(priority, jobs)?;
+        self.overflow();
+        Ok(())
+    }
+
+    fn try_push(self: &Arc<Self>, priority: Priority, job: Job<S>) -> Result<(), TryExecuteError> {
+        self.queue.try_push(priority, job)?;
+        self.overflow();
+        Ok(())
+    }
+
+    // starts a temporary worker if every worker is busy and more jobs than the threshold
+    // are waiting. checked after queueing jobs and by workers as they start one, so
+    // whichever of the two happens last sees the other
+    fn overflow(self: &Arc<Self>) {
+        let Some(elastic) = &self.elastic else {
+            return;
+        };
+        let counters = &self.counters;
+        if self.queue.len() <= elastic.threshold
+            || counters.busy.load(Ordering::SeqCst) < counters.workers.load(Ordering::SeqCst)
+            || self.queue.is_closed()
+        {
+            return;
+        }
+
+        let core = elastic.core.load(Ordering::SeqCst);
+        let reserved =
+            elastic
+                .temporary
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |temporary| {
+                    (core + temporary < elastic.max_threads).then_some(temporary + 1)
+                });
+        if reserved.is_err() {
+            return;
+        }
+
+        // temporary workers come and go, so those that have gone are forgotten here
+        // rather than piling up until shutdown
+        self.forget_exited();
+        // the jobs still get run by the workers there are, just not as soon
+        if Worker::spawn(self, Some(elastic.keep_alive)).is_err() {
+            elastic.temporary.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    // joins the workers that have already exited
+    fn forget_exited(&self) {
+        let mut workers = self.workers.lock().unwrap();
+        workers.retain_mut(|worker| match &worker.thread {
+            Some(thread) if thread.is_finished() => {
+                worker.thread.take().unwrap().join().unwrap();
+                false
+            }
+            _ => true,
+        });
+    }
+}
+
+// what `ThreadPool::metrics` reports, apart from the queue length
+#[derive(Default)]
+struct Counters {
+    workers: AtomicUsize,
+    busy: AtomicUsize,
+    completed: AtomicU64,
+    panics: AtomicU64,
+}
+
+// how worker threads are spawned, kept so more can be spawned the same way later
+#[derive(Clone, Default)]
+struct WorkerOptions {
+    name: Option<String>,
+    stack_size: Option<usize>,
+}
 
 struct Worker {
-    // here we put unit type () because our use case doesn't return
-    // if we want to expand this thread pool struct, we can use type T
+    // the thread itself returns nothing, jobs hand back their values through the handles
+    // from `submit` or the results from `map`
     id: u32,
     thread: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
-    fn new(id: u32, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv();
+    // starts a worker with the next id and adds it to the pool's workers. a temporary
+    // worker, which has a keep-alive, must already have been counted by the caller
+    fn spawn<S: 'static>(shared: &Arc<Shared<S>>, keep_alive: Option<Duration>) -> io::Result<()> {
+        let id = shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let options = &shared.options;
+        let mut builder = thread::Builder::new();
+        if let Some(name) = &options.name {
+            builder = builder.name(format!("{name}-{id}"));
+        }
+        if let Some(stack_size) = options.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
 
-            match message {
-                Ok(job) => {
-                    println!("worker {id} got a job, executing.");
+        // counted before the thread starts, so it can't be uncounted first
+        shared.counters.workers.fetch_add(1, Ordering::SeqCst);
+        let spawned = Arc::clone(shared);
 
-                    job();
-                }
-                Err(_) => {
-                    println!("worker {id} disconnected, shutting down.");
-                    break;
-                }
+        let thread = builder.spawn(move || {
+            let shared = spawned;
+            CURRENT.set(Some(id));
+            KEEP_ALIVE.set(keep_alive);
+            // built on the worker's own thread, so the state doesn't have to be `Send`
+            match panic::catch_unwind(AssertUnwindSafe(|| (shared.init)())) {
+                Ok(mut state) => work(id, &shared, &mut state),
+                Err(payload) => (shared.panic_handler)(id, &panic_message(payload)),
             }
+            if let (Some(_), Some(elastic)) = (keep_alive, &shared.elastic) {
+                elastic.temporary.fetch_sub(1, Ordering::SeqCst);
+            }
+            shared.counters.workers.fetch_sub(1, Ordering::SeqCst);
         });
 
-        Worker {
+        let thread = match thread {
+            Ok(thread) => thread,
+            Err(err) => {
+                shared.counters.workers.fetch_sub(1, Ordering::SeqCst);
+                return Err(err);
+            }
+        };
+
+        shared.workers.lock().unwrap().push(Worker {
             id,
             thread: Some(thread),
+        });
+        Ok(())
+    }
+}
+
+thread_local! {
+    // set by a job that ran past its timeout on this worker, which has been replaced
+    static REPLACED: Cell<bool> = const { Cell::new(false) };
+    // the id of the worker on this thread, if it is one
+    static CURRENT: Cell<Option<u32>> = const { Cell::new(None) };
+    // the keep-alive of the worker on this thread, if it is a temporary one
+    static KEEP_ALIVE: Cell<Option<Duration>> = const { Cell::new(None) };
+}
+
+/// the worker a job is running on, see `current_worker`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrentWorker {
+    /// the same id the panic handler and the logger's events are given
+    pub id: u32,
+    /// the worker's thread name, if the pool was given one with
+    /// `ThreadPoolBuilder::thread_name`
+    pub name: Option<String>,
+}
+
+/// the thread name if there is one, otherwise `worker <id>`
+impl fmt::Display for CurrentWorker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => f.write_str(name),
+            None => write!(f, "worker {}", self.id),
+        }
+    }
+}
+
+/// Which worker the calling code is running on, or `None` if it isn't on one.
+///
+/// Handy for tagging log lines written by jobs, so they can be matched up with the
+/// pool's own events and panic reports. Ids are only unique within a pool.
+///
+/// ```
+/// use thread_pool::{current_worker, ThreadPool};
+///
+/// let pool = ThreadPool::builder().thread_name("io").build().unwrap();
+/// let worker = pool.submit(current_worker).unwrap().join().unwrap().unwrap();
+/// assert!(worker.to_string().starts_with("io-"));
+/// assert_eq!(None, current_worker());
+/// ```
+pub fn current_worker() -> Option<CurrentWorker> {
+    CURRENT.get().map(|id| CurrentWorker {
+        id,
+        name: thread::current().name().map(str::to_string),
+    })
+}
+
+// a worker's main loop, which runs jobs until the pool no longer needs it
+fn work<S: 'static>(id: u32, shared: &Arc<Shared<S>>, state: &mut S) {
+    let counters = &shared.counters;
+    let local = shared.queue.register(id, KEEP_ALIVE.get());
+    loop {
+        match shared.queue.pop(&local) {
+            Next::Job(Queued { job, at }) => {
+                (shared.logger)(Event::JobStarted { worker: id });
+                counters.busy.fetch_add(1, Ordering::SeqCst);
+                shared.overflow();
+                let hooks = &shared.queue.hooks;
+                if let (Some(started), Some(at)) = (&hooks.started, at) {
+                    started(at.elapsed());
+                }
+                let began = hooks.completed.is_some().then(Instant::now);
+
+                // a panicking job mustn't take the worker down with it, or the pool
+                // would quietly lose a thread every time
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| job(state))) {
+                    counters.panics.fetch_add(1, Ordering::Relaxed);
+                    (shared.panic_handler)(id, &panic_message(payload));
+                    if shared.panic_policy == PanicPolicy::Abort {
+                        std::process::abort();
+                    }
+                }
+                if let (Some(completed), Some(began)) = (&hooks.completed, began) {
+                    completed(began.elapsed());
+                }
+
+                counters.completed.fetch_add(1, Ordering::Relaxed);
+                counters.busy.fetch_sub(1, Ordering::SeqCst);
+                shared.queue.finish();
+
+                if REPLACED.replace(false) {
+                    (shared.logger)(Event::WorkerReplaced { worker: id });
+                    break;
+                }
+            }
+            Next::Retire => {
+                (shared.logger)(Event::WorkerRetired { worker: id });
+                break;
+            }
+            Next::Expire => {
+                (shared.logger)(Event::WorkerExpired { worker: id });
+                break;
+            }
+            Next::Closed => {
+                (shared.logger)(Event::WorkerStopped { worker: id });
+                break;
+            }
+        }
+    }
+}
+
+/// something the pool's workers did, handed to the logger set with
+/// `ThreadPoolBuilder::logger`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// a worker picked up a job
+    JobStarted { worker: u32 },
+    /// a worker exited because the pool was shrunk
+    WorkerRetired { worker: u32 },
+    /// a temporary worker exited after going its keep-alive without a job
+    WorkerExpired { worker: u32 },
+    /// a worker exited because the pool is shutting down
+    WorkerStopped { worker: u32 },
+    /// a worker exited after finishing a job that ran past its timeout, as another
+    /// worker had already been started in its place
+    WorkerReplaced { worker: u32 },
+    /// the pool is waiting for a worker's thread to finish, while shutting down
+    JoiningWorker { worker: u32 },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::JobStarted { worker } => write!(f, "worker {worker} got a job, executing."),
+            Event::WorkerRetired { worker } => {
+                write!(f, "worker {worker} no longer needed, shutting down.")
+            }
+            Event::WorkerExpired { worker } => {
+                write!(f, "worker {worker} idle for too long, shutting down.")
+            }
+            Event::WorkerStopped { worker } => {
+                write!(f, "worker {worker} disconnected, shutting down.")
+            }
+            Event::WorkerReplaced { worker } => {
+                write!(
+                    f,
+                    "worker {worker} was replaced after a job timed out, shutting down."
+                )
+            }
+            Event::JoiningWorker { worker } => write!(f, "Shutting down worker {worker}"),
+        }
+    }
+}
+
+/// returned when a job is submitted to a pool that is shutting down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolClosedError;
+
+impl fmt::Display for PoolClosedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "thread pool is closed")
+    }
+}
+
+impl Error for PoolClosedError {}
+
+/// why `ThreadPool::try_execute` couldn't queue a job
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryExecuteError {
+    /// the pool has a queue limit and that many jobs are already waiting
+    QueueFull,
+    /// the pool is shutting down
+    Closed,
+}
+
+impl fmt::Display for TryExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TryExecuteError::QueueFull => write!(f, "thread pool queue is full"),
+            TryExecuteError::Closed => write!(f, "{PoolClosedError}"),
+        }
+    }
+}
+
+impl Error for TryExecuteError {}
+
+/// why a submitted job didn't produce a value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobError {
+    /// the job panicked, with the panic message if it had one
+    Panicked(String),
+    /// the job was dropped without running, e.g. because the pool shut down first
+    Cancelled,
+    /// the job ran for longer than the timeout it was given
+    TimedOut,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JobError::Panicked(message) => write!(f, "job panicked: {message}"),
+            JobError::Cancelled => write!(f, "job was cancelled before it ran"),
+            JobError::TimedOut => write!(f, "job timed out"),
+        }
+    }
+}
+
+impl Error for JobError {}
+
+impl JobError {
+    fn from_panic(payload: Box<dyn Any + Send>) -> JobError {
+        JobError::Panicked(panic_message(payload))
+    }
+}
+
+// panics almost always carry a &str or a String, from `panic!("literal")` or a formatted one
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic payload".to_string(),
+        },
+    }
+}
+
+/// a handle to the result of a job started with `ThreadPool::submit`
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<Result<T, JobError>>,
+    finished: Arc<AtomicBool>,
+}
+
+impl<T> JobHandle<T> {
+    /// whether the job has finished running, successfully or not
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+
+    /// blocks until the job has finished, returning its value
+    pub fn join(self) -> Result<T, JobError> {
+        self.receiver.recv().unwrap_or(Err(JobError::Cancelled))
+    }
+
+    /// returns the job's value if it has finished, or hands the handle back if it hasn't
+    pub fn try_join(self) -> Result<Result<T, JobError>, JobHandle<T>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Ok(result),
+            Err(mpsc::TryRecvError::Empty) => Err(self),
+            Err(mpsc::TryRecvError::Disconnected) => Ok(Err(JobError::Cancelled)),
         }
     }
 }
 
-pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>, // sends jobs to workers
+/// a snapshot of what a pool is doing, from `ThreadPool::metrics`
+///
+/// Each number is read on its own while the workers carry on, so they can be
+/// slightly out of step with each other on a busy pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metrics {
+    /// jobs waiting for a worker
+    pub queued_jobs: usize,
+    /// workers running a job
+    pub busy_workers: usize,
+    /// workers waiting for a job
+    pub idle_workers: usize,
+    /// jobs that have finished running since the pool started, including ones that panicked
+    pub jobs_completed: u64,
+    /// jobs that have panicked since the pool started, whether passed to `execute` or `submit`
+    pub panics: u64,
+}
+
+/// how urgently a job should be run, jobs of a higher priority are started first
+///
+/// Jobs of the same priority run in the order they were queued. A steady stream of
+/// high priority jobs can hold lower ones back indefinitely, so keep `High` for
+/// small, latency sensitive work such as health checks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+/// a pool of worker threads that run jobs sent to it
+///
+/// `S` is the state each worker keeps between jobs, see `ThreadPool::with_worker_init`.
+pub struct ThreadPool<S = ()> {
+    shared: Arc<Shared<S>>,
+    size: usize,
+    // started the first time a job is scheduled for later
+    timer: OnceLock<Timer<S>>,
+}
+
+/// sets up a `ThreadPool` with more control than `ThreadPool::new` gives
+///
+/// ```
+/// let pool = thread_pool::ThreadPool::builder()
+///     .num_threads(8)
+///     .thread_name("http-worker")
+///     .stack_size(512 * 1024)
+///     .build()
+///     .unwrap();
+/// assert_eq!(8, pool.size());
+/// ```
+pub struct ThreadPoolBuilder {
+    num_threads: usize,
+    queue_capacity: Option<usize>,
+    options: WorkerOptions,
+    panic_handler: PanicHandler,
+    panic_policy: PanicPolicy,
+    logger: Logger,
+    hooks: Hooks,
+    max_threads: Option<usize>,
+    overflow_threshold: usize,
+    keep_alive: Duration,
+}
+
+impl ThreadPoolBuilder {
+    /// How many workers to start with, defaults to the number of CPUs.
+    pub fn num_threads(mut self, num_threads: usize) -> ThreadPoolBuilder {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// Starts `multiplier` workers for each CPU, for jobs that spend much of their time
+    /// waiting on I/O rather than computing.
+    ///
+    /// The CPUs are counted with `std::thread::available_parallelism`, which also takes
+    /// limits such as cgroup quotas into account, and taken to be one if it can't tell.
+    pub fn threads_per_cpu(self, multiplier: usize) -> ThreadPoolBuilder {
+        self.num_threads(available_parallelism() * multiplier)
+    }
+
+    /// Lets the pool start temporary workers when it falls behind, up to `max_threads`
+    /// workers in all. By default the pool only ever has its core workers, the
+    /// `num_threads` it is built with or resized to.
+    ///
+    /// A temporary worker is started whenever every worker is busy and more jobs than
+    /// the `overflow_threshold` are waiting. It exits again once it has gone the
+    /// `keep_alive` without a job. This suits load that comes in bursts, such as jobs
+    /// that block on I/O, without keeping the threads around the rest of the time.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// let pool = thread_pool::ThreadPool::builder()
+    ///     .num_threads(2)
+    ///     .max_threads(16)
+    ///     .overflow_threshold(4)
+    ///     .keep_alive(Duration::from_secs(30))
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(2, pool.size());
+    /// ```
+    ///
+    /// A `max_threads` no higher than the number of core workers starts none.
+    pub fn max_threads(mut self, max_threads: usize) -> ThreadPoolBuilder {
+        self.max_threads = Some(max_threads);
+        self
+    }
+
+    /// How many jobs have to be waiting, with every worker busy, before a temporary
+    /// worker is started, by default any at all. See `max_threads`.
+    ///
+    /// With a `queue_capacity`, the threshold has to be below it for this to ever happen.
+    pub fn overflow_threshold(mut self, threshold: usize) -> ThreadPoolBuilder {
+        self.overflow_threshold = threshold;
+        self
+    }
+
+    /// How long a temporary worker waits for a job before it exits, by default a minute.
+    /// See `max_threads`.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> ThreadPoolBuilder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Limits how many jobs can be waiting for a worker at once, by default there is no limit.
+    ///
+    /// Once the queue is full `execute` waits for room and `try_execute` fails, so a flood of
+    /// work can't grow the queue without bound. The limit counts jobs of every priority.
+    pub fn queue_capacity(mut self, capacity: usize) -> ThreadPoolBuilder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Names the worker threads `{name}-{id}`, so they can be told apart in a debugger.
+    pub fn thread_name(mut self, name: impl Into<String>) -> ThreadPoolBuilder {
+        self.options.name = Some(name.into());
+        self
+    }
+
+    /// The stack size of each worker thread in bytes, instead of the platform default.
+    pub fn stack_size(mut self, stack_size: usize) -> ThreadPoolBuilder {
+        self.options.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Called with the worker's id and the panic message whenever a job passed to `execute`
+    /// panics, by default the message is printed to stderr.
+    ///
+    /// The worker carries on with the next job either way. Jobs passed to `submit` report
+    /// their panics through their `JobHandle` instead.
+    ///
+    /// The handler runs on the worker whose job panicked, so `current_worker` gives its
+    /// thread name as well.
+    pub fn panic_handler<F>(mut self, handler: F) -> ThreadPoolBuilder
+    where
+        F: Fn(u32, &str) + Send + Sync + 'static,
+    {
+        self.panic_handler = Box::new(handler);
+        self
+    }
+
+    /// What to do after a job passed to `execute` panics, by default the worker just
+    /// carries on. See `PanicPolicy`.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> ThreadPoolBuilder {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// Called as workers start jobs and exit, by default nothing is logged.
+    ///
+    /// ```
+    /// let pool = thread_pool::ThreadPool::builder()
+    ///     .logger(|event| eprintln!("[pool] {event}"))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// It runs on the worker's thread, or the one shutting the pool down, so a slow
+    /// logger slows the pool down with it.
+    pub fn logger<F>(mut self, logger: F) -> ThreadPoolBuilder
+    where
+        F: Fn(Event) + Send + Sync + 'static,
+    {
+        self.logger = Box::new(logger);
+        self
+    }
+
+    /// Called on the sending thread each time a job is queued, including jobs from
+    /// `execute_after` once they are due. With `on_job_start` and `on_job_complete` this
+    /// is for feeding what the pool does into a metrics system:
+    ///
+    /// ```
+    /// use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    ///
+    /// let (waited, ran) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+    /// let (total_waited, total_ran) = (Arc::clone(&waited), Arc::clone(&ran));
+    /// let pool = thread_pool::ThreadPool::builder()
+    ///     .on_job_start(move |waited| {
+    ///         total_waited.fetch_add(waited.as_micros() as u64, Ordering::Relaxed);
+    ///     })
+    ///     .on_job_complete(move |ran| {
+    ///         total_ran.fetch_add(ran.as_micros() as u64, Ordering::Relaxed);
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// Like the logger, the hooks run on the pool's threads and should be quick.
+    pub fn on_job_queued<F>(mut self, hook: F) -> ThreadPoolBuilder
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.hooks.queued = Some(Box::new(hook));
+        self
+    }
+
+    /// Called by the worker as it starts a job, with how long the job waited in the queue.
+    pub fn on_job_start<F>(mut self, hook: F) -> ThreadPoolBuilder
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.hooks.started = Some(Box::new(hook));
+        self
+    }
+
+    /// Called by the worker once a job is done, with how long it ran for. Jobs that
+    /// panic count too.
+    pub fn on_job_complete<F>(mut self, hook: F) -> ThreadPoolBuilder
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.hooks.completed = Some(Box::new(hook));
+        self
+    }
+
+    /// Starts the pool.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of threads or the queue capacity is zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from the OS if a worker thread couldn't be spawned.
+    pub fn build(self) -> io::Result<ThreadPool> {
+        self.build_with_worker_init(|| ())
+    }
+
+    /// Starts the pool, with each worker calling `init` once to build its own state.
+    /// See `ThreadPool::with_worker_init`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of threads or the queue capacity is zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from the OS if a worker thread couldn't be spawned.
+    pub fn build_with_worker_init<S, F>(self, init: F) -> io::Result<ThreadPool<S>>
+    where
+        S: 'static,
+        F: Fn() -> S + Send + Sync + 'static,
+    {
+        assert!(self.num_threads > 0);
+        assert_ne!(Some(0), self.queue_capacity);
+
+        let shared = Arc::new(Shared {
+            queue: JobQueue::new(self.queue_capacity, self.hooks),
+            init: Box::new(init),
+            panic_handler: self.panic_handler,
+            panic_policy: self.panic_policy,
+            logger: self.logger,
+            counters: Counters::default(),
+            workers: Mutex::new(Vec::with_capacity(self.num_threads)),
+            next_id: AtomicU32::new(0),
+            options: self.options,
+            elastic: self.max_threads.map(|max_threads| Elastic {
+                max_threads,
+                threshold: self.overflow_threshold,
+                keep_alive: self.keep_alive,
+                core: AtomicUsize::new(self.num_threads),
+                temporary: AtomicUsize::new(0),
+            }),
+        });
+
+        let pool = ThreadPool {
+            shared,
+            size: self.num_threads,
+            timer: OnceLock::new(),
+        };
+        for _ in 0..self.num_threads {
+            // if one can't be spawned, dropping the pool closes the queue and joins the
+            // workers that already were, rather than leaving them waiting for jobs forever
+            Worker::spawn(&pool.shared, None)?;
+        }
+
+        Ok(pool)
+    }
 }
 
 impl ThreadPool {
@@ -49,48 +778,1566 @@ impl ThreadPool {
     ///
     /// # Panics
     ///
-    /// The `new` function will panic if size is zero.
+    /// The `new` function will panic if size is zero, or if a thread can't be spawned.
     pub fn new(size: u32) -> ThreadPool {
-        assert!(size > 0);
+        ThreadPool::builder()
+            .num_threads(size as usize)
+            .build()
+            .expect("failed to spawn worker thread")
+    }
 
-        let (sender, receiver) = mpsc::channel();
+    /// Creates a ThreadPool with a worker for each CPU, see `ThreadPoolBuilder::threads_per_cpu`
+    /// for pools that want more.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a thread can't be spawned.
+    pub fn auto() -> ThreadPool {
+        ThreadPool::builder()
+            .build()
+            .expect("failed to spawn worker thread")
+    }
+
+    /// Starts configuring a pool, see `ThreadPoolBuilder`.
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            num_threads: available_parallelism(),
+            queue_capacity: None,
+            options: WorkerOptions::default(),
+            panic_handler: Box::new(|id, message| match current_worker() {
+                Some(worker) if worker.name.is_some() => {
+                    eprintln!("worker {id} ({worker}) job panicked: {message}");
+                }
+                _ => eprintln!("worker {id} job panicked: {message}"),
+            }),
+            panic_policy: PanicPolicy::default(),
+            hooks: Hooks::default(),
+            logger: Box::new(|_| ()),
+            max_threads: None,
+            overflow_threshold: 0,
+            keep_alive: Duration::from_secs(60),
+        }
+    }
+}
+
+/// the same as `ThreadPool::auto`
+impl Default for ThreadPool {
+    fn default() -> ThreadPool {
+        ThreadPool::auto()
+    }
+}
+
+// how many threads can usefully run at once, or one if that can't be found out
+fn available_parallelism() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
 
-        let receiver = Arc::new(Mutex::new(receiver));
+impl<S: 'static> ThreadPool<S> {
+    /// Creates a ThreadPool whose workers each call `init` once when they start, and
+    /// keep what it returns for the jobs passed to `execute_with_state`.
+    ///
+    /// This suits things that are expensive to set up and can't be shared, such as
+    /// a database connection or a large scratch buffer. `init` runs on the worker's
+    /// own thread, so the state doesn't need to be `Send`. If it panics, the panic
+    /// handler is called and that worker exits.
+    ///
+    /// ```
+    /// let pool = thread_pool::ThreadPool::with_worker_init(2, || Vec::<u8>::with_capacity(4096));
+    /// pool.execute_with_state(|buffer| {
+    ///     buffer.clear();
+    ///     buffer.extend_from_slice(b"reused between jobs");
+    /// })
+    /// .unwrap();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if size is zero, or if a thread can't be spawned.
+    pub fn with_worker_init<F>(size: u32, init: F) -> ThreadPool<S>
+    where
+        F: Fn() -> S + Send + Sync + 'static,
+    {
+        ThreadPool::builder()
+            .num_threads(size as usize)
+            .build_with_worker_init(init)
+            .expect("failed to spawn worker thread")
+    }
 
-        let mut workers = Vec::with_capacity(size as usize);
+    /// How many workers the pool is meant to have, not counting temporary ones started
+    /// to help with a backlog, see `ThreadPoolBuilder::max_threads`.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// What the pool is up to right now, e.g. for exporting to a monitoring system.
+    pub fn metrics(&self) -> Metrics {
+        let counters = &self.shared.counters;
+        let workers = counters.workers.load(Ordering::SeqCst);
+        let busy = counters.busy.load(Ordering::SeqCst);
+
+        Metrics {
+            queued_jobs: self.shared.queue.len(),
+            busy_workers: busy,
+            idle_workers: workers.saturating_sub(busy),
+            jobs_completed: counters.completed.load(Ordering::Relaxed),
+            panics: counters.panics.load(Ordering::Relaxed),
+        }
+    }
 
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+    /// Changes the number of workers.
+    ///
+    /// Extra workers are started right away. When shrinking, surplus workers finish
+    /// the job they are running, if any, and then exit.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_size` is zero, or if a thread can't be spawned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn resize(&mut self, new_size: usize) -> Result<(), PoolClosedError> {
+        assert!(new_size > 0);
+        if self.shared.queue.is_closed() {
+            return Err(PoolClosedError);
         }
 
-        ThreadPool {
-            workers,
-            sender: Some(sender),
+        if new_size < self.size {
+            self.shared.queue.retire(self.size - new_size);
+        } else {
+            // workers that were told to retire but haven't yet can simply stay
+            let growth = new_size - self.size;
+            let kept = self.shared.queue.unretire(growth);
+            for _ in kept..growth {
+                Worker::spawn(&self.shared, None).expect("failed to spawn worker thread");
+            }
         }
+        self.size = new_size;
+        if let Some(elastic) = &self.shared.elastic {
+            elastic.core.store(new_size, Ordering::SeqCst);
+        }
+
+        self.shared.forget_exited();
+        Ok(())
     }
 
-    pub fn execute<F>(&self, f: F)
+    /// Sends a job to the pool, to be run by the next free worker.
+    ///
+    /// If the pool was built with a `queue_capacity` and the queue is full,
+    /// this waits until a worker makes room.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn execute<F>(&self, f: F) -> Result<(), PoolClosedError>
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
+        self.execute_with_priority(Priority::Normal, f)
+    }
 
-        self.sender.as_ref().unwrap().send(job).unwrap();
+    /// Like `execute`, but the job is started ahead of any queued jobs of a lower priority.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F) -> Result<(), PoolClosedError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.push(priority, Box::new(move |_: &mut S| f()))
     }
-}
 
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        // first drop our transmitter
-        drop(self.sender.take());
+    /// Like `execute`, but the job gets the state of the worker that runs it,
+    /// as built by the `init` function given to `with_worker_init`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn execute_with_state<F>(&self, f: F) -> Result<(), PoolClosedError>
+    where
+        F: FnOnce(&mut S) + Send + 'static,
+    {
+        self.shared.push(Priority::Normal, Box::new(f))
+    }
 
-        // then drop the worker threads
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+    /// Like `execute`, but gives up instead of waiting when the queue is full.
+    ///
+    /// # Errors
+    ///
+    /// Returns `TryExecuteError::QueueFull` if the queue is at its `queue_capacity`,
+    /// or `TryExecuteError::Closed` if the pool is shutting down.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), TryExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared
+            .try_push(Priority::Normal, Box::new(move |_: &mut S| f()))
+    }
 
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
-            }
-        }
+    /// Like `execute`, for callers that have no way to recover from a closed pool.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pool is shutting down.
+    pub fn execute_or_panic<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.execute(f).unwrap();
+    }
+
+    /// Sends a job to the pool once `delay` has passed, e.g. to retry something that failed
+    /// or to give up on something that is taking too long.
+    ///
+    /// The job is queued like any other once it is due, so it may start a little later
+    /// than that on a busy pool. See `execute_at` for what happens on shutdown.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is the first delayed job and the timer thread can't be spawned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn execute_after<F>(&self, delay: Duration, f: F) -> Result<(), PoolClosedError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.execute_at(Instant::now() + delay, f)
+    }
+
+    /// Sends a job to the pool once `at` has come, right away if it already has.
+    ///
+    /// ```
+    /// use std::{sync::mpsc, time::{Duration, Instant}};
+    ///
+    /// let pool = thread_pool::ThreadPool::new(2);
+    /// let (sender, receiver) = mpsc::channel();
+    /// let start = Instant::now();
+    ///
+    /// pool.execute_at(start + Duration::from_millis(20), move || {
+    ///     sender.send(start.elapsed()).unwrap();
+    /// })
+    /// .unwrap();
+    /// assert!(receiver.recv().unwrap() >= Duration::from_millis(20));
+    /// ```
+    ///
+    /// Jobs wait for their time on a timer thread, which is started with the first one.
+    /// Shutting the pool down throws away the jobs that aren't due yet.
+    ///
+    /// # Panics
+    ///
+    /// Panics if this is the first delayed job and the timer thread can't be spawned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn execute_at<F>(&self, at: Instant, f: F) -> Result<(), PoolClosedError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // a closed pool would otherwise start a timer nobody stops
+        if self.shared.queue.is_closed() {
+            return Err(PoolClosedError);
+        }
+
+        self.timer()
+            .handle()
+            .schedule(at, Box::new(move |_: &mut S| f()))
+    }
+
+    /// Like `execute`, but a job that panics is run again when the pool was built with
+    /// `PanicPolicy::Retry`, after a backoff that doubles with each attempt. This suits
+    /// background work that fails now and then for reasons that pass, such as a busy
+    /// database.
+    ///
+    /// ```
+    /// use std::sync::{atomic::{AtomicU32, Ordering}, mpsc, Arc};
+    /// use thread_pool::{PanicPolicy, ThreadPool};
+    ///
+    /// let pool = ThreadPool::builder()
+    ///     .panic_policy(PanicPolicy::Retry { max_attempts: 3 })
+    ///     .panic_handler(|_, _| ())
+    ///     .build()
+    ///     .unwrap();
+    /// let (attempts, (done, finished)) = (Arc::new(AtomicU32::new(0)), mpsc::channel());
+    ///
+    /// let job_attempts = Arc::clone(&attempts);
+    /// pool.execute_retryable(move || {
+    ///     // flaky, the first attempt fails
+    ///     if job_attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+    ///         panic!("connection refused");
+    ///     }
+    ///     done.send(()).unwrap();
+    /// })
+    /// .unwrap();
+    ///
+    /// finished.recv().unwrap();
+    /// assert_eq!(2, attempts.load(Ordering::SeqCst));
+    /// ```
+    ///
+    /// Every panic is reported to the panic handler, including those that are retried.
+    /// Retries wait on the pool's timer rather than holding up a worker, and are thrown
+    /// away if the pool shuts down first, like jobs from `execute_after`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timer thread, which is started with the first job that needs it,
+    /// can't be spawned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn execute_retryable<F>(&self, f: F) -> Result<(), PoolClosedError>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let max_attempts = self.shared.panic_policy.attempts();
+        if max_attempts == 1 {
+            return self.execute(f);
+        }
+        // a closed pool would otherwise start a timer nobody stops
+        if self.shared.queue.is_closed() {
+            return Err(PoolClosedError);
+        }
+
+        let timer = self.timer().handle().clone();
+        let job = retry::retrying(Arc::new(f), 1, max_attempts, timer);
+        self.shared.push(Priority::Normal, job)
+    }
+
+    // starts the timer thread the first time it is needed
+    fn timer(&self) -> &Timer<S> {
+        self.timer.get_or_init(|| {
+            Timer::start(Arc::clone(&self.shared)).expect("failed to spawn timer thread")
+        })
+    }
+
+    /// Like `submit`, but gives up on the job if it runs for longer than `timeout`, so a
+    /// stuck job can't hold on to a worker forever.
+    ///
+    /// The handle then gets `JobError::TimedOut` and a new worker takes the stuck one's
+    /// place. A thread can't be stopped from outside, so the job carries on in the
+    /// background and its worker exits once it is done, with the value thrown away.
+    /// Shutting the pool down still waits for it.
+    ///
+    /// ```
+    /// use std::{thread, time::Duration};
+    /// use thread_pool::JobError;
+    ///
+    /// let pool = thread_pool::ThreadPool::new(1);
+    /// let stuck = pool
+    ///     .execute_with_timeout(Duration::from_millis(10), || thread::sleep(Duration::from_millis(200)))
+    ///     .unwrap();
+    /// assert_eq!(Err(JobError::TimedOut), stuck.join());
+    ///
+    /// // the pool still has a worker free for this
+    /// assert_eq!(Ok(2), pool.submit(|| 1 + 1).unwrap().join());
+    /// ```
+    ///
+    /// The time starts when a worker picks the job up, not while it is queued.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the timer thread, which is started with the first job that needs it,
+    /// can't be spawned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn execute_with_timeout<F, T>(
+        &self,
+        timeout: Duration,
+        f: F,
+    ) -> Result<JobHandle<T>, PoolClosedError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        // whichever of the job and the timer gets to swap this from RUNNING first decides
+        // what the handle gets
+        const RUNNING: u8 = 0;
+        const FINISHED: u8 = 1;
+        const TIMED_OUT: u8 = 2;
+
+        let (sender, receiver) = mpsc::channel();
+        let finished = Arc::new(AtomicBool::new(false));
+        let job_finished = Arc::clone(&finished);
+        let shared = Arc::clone(&self.shared);
+        let timer = self.timer().handle().clone();
+
+        self.execute(move || {
+            // the worker's replacement takes its place as a temporary worker too, if it is one
+            let keep_alive = KEEP_ALIVE.get();
+            let outcome = Arc::new(AtomicU8::new(RUNNING));
+            let watched = Arc::clone(&outcome);
+            let timed_out = sender.clone();
+            let pool = Arc::clone(&shared);
+            let watchdog = Arc::clone(&job_finished);
+            // if the timer has stopped the pool is shutting down, which waits for this anyway
+            let watch = timer.call_at(Instant::now() + timeout, move || {
+                if watched
+                    .compare_exchange(RUNNING, TIMED_OUT, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    watchdog.store(true, Ordering::Release);
+                    let _ = timed_out.send(Err(JobError::TimedOut));
+                    let elastic = pool.elastic.as_ref().filter(|_| keep_alive.is_some());
+                    if let Some(elastic) = elastic {
+                        elastic.temporary.fetch_add(1, Ordering::SeqCst);
+                    }
+                    if let Err(err) = Worker::spawn(&pool, keep_alive) {
+                        if let Some(elastic) = elastic {
+                            elastic.temporary.fetch_sub(1, Ordering::SeqCst);
+                        }
+                        eprintln!("couldn't replace worker stuck on a job: {err}");
+                    }
+                }
+            });
+
+            let result = panic::catch_unwind(AssertUnwindSafe(f)).map_err(JobError::from_panic);
+            if result.is_err() {
+                shared.counters.panics.fetch_add(1, Ordering::Relaxed);
+            }
+            if outcome
+                .compare_exchange(RUNNING, FINISHED, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                // there is one of these waiting for each running job, which would otherwise
+                // hold on to everything it needs until the timeout, however long that is
+                if let Ok(seq) = watch {
+                    timer.cancel(seq);
+                }
+                job_finished.store(true, Ordering::Release);
+                let _ = sender.send(result);
+            } else {
+                // another worker has taken this one's place
+                REPLACED.set(true);
+            }
+        })?;
+
+        Ok(JobHandle { receiver, finished })
+    }
+
+    /// Sends many jobs to the pool at once, returning a handle that waits for all of them.
+    ///
+    /// The jobs are queued together, rather than one `execute` at a time, which suits
+    /// fanning a large piece of work out over the pool.
+    ///
+    /// ```
+    /// use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+    ///
+    /// let pool = thread_pool::ThreadPool::new(4);
+    /// let total = Arc::new(AtomicUsize::new(0));
+    ///
+    /// let batch = pool
+    ///     .execute_batch((1..=100).map(|n| {
+    ///         let total = Arc::clone(&total);
+    ///         move || {
+    ///             total.fetch_add(n, Ordering::Relaxed);
+    ///         }
+    ///     }))
+    ///     .unwrap();
+    /// assert_eq!(100, batch.wait().completed);
+    /// assert_eq!(5050, total.load(Ordering::Relaxed));
+    /// ```
+    ///
+    /// On a pool with a `queue_capacity`, as many jobs are queued as there is room for
+    /// while this waits for room for the rest.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down. If that happens while
+    /// waiting for room, the jobs already queued still run.
+    pub fn execute_batch<I, F>(&self, jobs: I) -> Result<BatchHandle, PoolClosedError>
+    where
+        I: IntoIterator<Item = F>,
+        F: FnOnce() + Send + 'static,
+    {
+        let jobs: Vec<_> = jobs.into_iter().collect();
+        let (handle, mut member) = BatchHandle::new(jobs.len());
+
+        let jobs = jobs.into_iter().map(|f| {
+            let member = member();
+            Box::new(move |_: &mut S| member.run(f)) as Job<S>
+        });
+        self.shared.push_all(Priority::Normal, jobs)?;
+        Ok(handle)
+    }
+
+    /// Runs `f` on every item on the pool, returning their values in the order of the items.
+    ///
+    /// The jobs are queued all together, as with `execute_batch`, and the values can be
+    /// used as they come in, which suits map-reduce style work:
+    ///
+    /// ```
+    /// let pool = thread_pool::ThreadPool::new(4);
+    /// let lines = vec!["one two", "three", "four five six"];
+    ///
+    /// let words: usize = pool
+    ///     .map(lines, |line| line.split_whitespace().count())
+    ///     .unwrap()
+    ///     .map(Result::unwrap)
+    ///     .sum();
+    /// assert_eq!(6, words);
+    /// ```
+    ///
+    /// A job that panics gives `JobError::Panicked` for its item, and one thrown away
+    /// without running, e.g. by `shutdown_now`, gives `JobError::Cancelled`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down. If that happens while
+    /// waiting for room, the jobs already queued still run.
+    pub fn map<I, F, T>(&self, items: I, f: F) -> Result<Results<T>, PoolClosedError>
+    where
+        I: IntoIterator,
+        I::Item: Send + 'static,
+        F: Fn(I::Item) -> T + Send + Sync + 'static,
+        T: Send + 'static,
+    {
+        let items: Vec<_> = items.into_iter().collect();
+        let (results, mut replier) = Results::new(items.len());
+        let f = Arc::new(f);
+
+        let jobs = items.into_iter().map(|item| {
+            let (f, replier, shared) = (Arc::clone(&f), replier(), Arc::clone(&self.shared));
+            Box::new(move |_: &mut S| {
+                let result =
+                    panic::catch_unwind(AssertUnwindSafe(|| f(item))).map_err(JobError::from_panic);
+                if result.is_err() {
+                    shared.counters.panics.fetch_add(1, Ordering::Relaxed);
+                }
+                replier.reply(result);
+            }) as Job<S>
+        });
+        self.shared.push_all(Priority::Normal, jobs)?;
+        Ok(results)
+    }
+
+    /// Sends a job to the pool, returning a handle that can be used to wait for its value.
+    ///
+    /// A panic in the job is caught and reported through the handle, rather than
+    /// taking the worker down with it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn submit<F, T>(&self, f: F) -> Result<JobHandle<T>, PoolClosedError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let finished = Arc::new(AtomicBool::new(false));
+        let job_finished = Arc::clone(&finished);
+        let shared = Arc::clone(&self.shared);
+
+        self.execute(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f)).map_err(JobError::from_panic);
+            if result.is_err() {
+                shared.counters.panics.fetch_add(1, Ordering::Relaxed);
+            }
+            job_finished.store(true, Ordering::Release);
+            // the caller may have dropped the handle, in which case nobody wants the value
+            let _ = sender.send(result);
+        })?;
+
+        Ok(JobHandle { receiver, finished })
+    }
+
+    /// Like `submit`, but the job is handed a `CancellationToken` and the handle can
+    /// cancel it, so work whose result is no longer wanted can stop early.
+    ///
+    /// ```
+    /// use std::{thread, time::Duration};
+    ///
+    /// let pool = thread_pool::ThreadPool::new(1);
+    /// let search = pool
+    ///     .execute_cancellable(|token| {
+    ///         let mut checked = 0;
+    ///         while !token.is_cancelled() {
+    ///             checked += 1;
+    ///             thread::sleep(Duration::from_millis(1));
+    ///         }
+    ///         checked
+    ///     })
+    ///     .unwrap();
+    ///
+    /// thread::sleep(Duration::from_millis(10));
+    /// search.cancel();
+    /// assert!(search.join().unwrap() > 0);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn execute_cancellable<F, T>(&self, f: F) -> Result<CancellableHandle<T>, PoolClosedError>
+    where
+        F: FnOnce(&CancellationToken) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let token = CancellationToken::new();
+        let job_token = token.clone();
+        let handle = self.submit(move || {
+            // cancelled while it was queued, so it isn't worth starting
+            (!job_token.is_cancelled()).then(|| f(&job_token))
+        })?;
+
+        Ok(CancellableHandle::new(handle, token))
+    }
+
+    /// Like `submit`, but returns a future to await the value with, so async code can
+    /// hand blocking or CPU heavy work to the pool without holding up its executor.
+    ///
+    /// ```
+    /// async fn checksum(pool: &thread_pool::ThreadPool, data: Vec<u8>) -> u32 {
+    ///     let sum = pool.submit_async(move || data.iter().map(|&byte| u32::from(byte)).sum());
+    ///     sum.unwrap().await.unwrap()
+    /// }
+    /// ```
+    ///
+    /// The future works with any executor, as all it needs is the task's waker.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn submit_async<F, T>(&self, f: F) -> Result<JobFuture<T>, PoolClosedError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (future, completer) = JobFuture::new();
+        let shared = Arc::clone(&self.shared);
+
+        self.execute(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f)).map_err(JobError::from_panic);
+            if result.is_err() {
+                shared.counters.panics.fetch_add(1, Ordering::Relaxed);
+            }
+            completer.complete(result);
+        })?;
+
+        Ok(future)
+    }
+
+    /// Runs `f` with a `Scope` whose jobs can borrow local data, and waits for every one
+    /// of them to finish before returning.
+    ///
+    /// ```
+    /// let pool = thread_pool::ThreadPool::new(4);
+    /// let mut chunks = vec![vec![1, 2], vec![3, 4], vec![5, 6]];
+    ///
+    /// pool.scope(|s| {
+    ///     for chunk in &mut chunks {
+    ///         s.execute(move || chunk.iter_mut().for_each(|n| *n *= 10)).unwrap();
+    ///     }
+    /// });
+    /// assert_eq!(vec![vec![10, 20], vec![30, 40], vec![50, 60]], chunks);
+    /// ```
+    ///
+    /// Calling this from inside one of the pool's own jobs can deadlock, as the
+    /// worker waiting on the scope can't run the scope's jobs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` or any of the scoped jobs panicked, once all of the jobs are done.
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env, S>) -> T,
+    {
+        let scope = Scope::new(self);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+
+        // even if `f` panicked, its jobs may still be borrowing from further up the stack
+        let job_panicked = scope.wait();
+
+        match result {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(_) if job_panicked => panic!("a scoped job panicked"),
+            Ok(value) => value,
+        }
+    }
+
+    /// Blocks until no jobs are queued or running, so callers can wait for the work
+    /// they've sent without keeping track of it or sleeping.
+    ///
+    /// This includes jobs sent by other threads, even ones sent while waiting, so on a
+    /// pool that never runs dry it never returns. Calling it from one of the pool's own
+    /// jobs deadlocks, as that job is running until it returns.
+    pub fn wait_idle(&self) {
+        self.shared.queue.wait_idle(None);
+    }
+
+    /// Like `wait_idle`, but gives up after `timeout`, returning whether the pool went idle.
+    pub fn wait_idle_timeout(&self, timeout: Duration) -> bool {
+        self.shared.queue.wait_idle(Some(Instant::now() + timeout))
+    }
+}
+
+impl<S> ThreadPool<S> {
+    /// Stops taking new jobs, then waits for every job already queued to finish.
+    /// Delayed jobs that aren't due yet are thrown away.
+    ///
+    /// This is also what happens when the pool is dropped.
+    pub fn shutdown(&mut self) {
+        self.stop_timer();
+        // workers leave once the queue is empty
+        self.shared.queue.close();
+
+        self.join_workers();
+    }
+
+    /// Stops taking new jobs and throws away the ones still queued,
+    /// then waits for the jobs already running to finish.
+    ///
+    /// Returns how many queued jobs were discarded, including delayed jobs that weren't due.
+    pub fn shutdown_now(&mut self) -> usize {
+        let delayed = self.stop_timer();
+        self.shared.queue.close();
+
+        let discarded = self.shared.queue.discard();
+        self.join_workers();
+        delayed + discarded
+    }
+
+    /// Like `shutdown`, but only gives queued jobs until `timeout` to get started.
+    /// Whatever is still queued by then is thrown away, and the jobs already running
+    /// are waited for.
+    ///
+    /// Returns how many queued jobs were discarded, including delayed jobs that weren't due.
+    pub fn shutdown_timeout(&mut self, timeout: Duration) -> usize {
+        let delayed = self.stop_timer();
+        self.shared.queue.close();
+
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline && !self.workers_finished() {
+            thread::sleep(Duration::from_millis(1).min(deadline - Instant::now()));
+        }
+
+        let discarded = self.shared.queue.discard();
+        self.join_workers();
+        delayed + discarded
+    }
+
+    // stops the timer thread, if there is one, returning how many delayed jobs it dropped
+    fn stop_timer(&mut self) -> usize {
+        self.timer.get_mut().map_or(0, Timer::stop)
+    }
+
+    fn workers_finished(&self) -> bool {
+        let workers = self.shared.workers.lock().unwrap();
+        workers.iter().all(|worker| {
+            worker
+                .thread
+                .as_ref()
+                .is_none_or(|thread| thread.is_finished())
+        })
+    }
+
+    fn join_workers(&mut self) {
+        // taken out first, so the lock isn't held while waiting
+        let workers = std::mem::take(&mut *self.shared.workers.lock().unwrap());
+        for mut worker in workers {
+            if let Some(thread) = worker.thread.take() {
+                (self.shared.logger)(Event::JoiningWorker { worker: worker.id });
+
+                thread.join().unwrap();
+            }
+        }
+    }
+}
+
+impl<S> Drop for ThreadPool<S> {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    #[test]
+    fn execute_runs_every_job() {
+        let pool = ThreadPool::new(2);
+        let (sender, receiver) = mpsc::channel();
+
+        for i in 0..8 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(i).unwrap()).unwrap();
+        }
+        drop(sender);
+
+        let mut results: Vec<i32> = receiver.iter().collect();
+        results.sort();
+        assert_eq!((0..8).collect::<Vec<_>>(), results);
+    }
+
+    #[test]
+    fn submit_returns_values_and_panics() {
+        let pool = ThreadPool::new(2);
+
+        let sum = pool.submit(|| (1..=10).sum::<i32>()).unwrap();
+        let panicked = pool.submit(|| -> i32 { panic!("boom") }).unwrap();
+
+        assert_eq!(Ok(55), sum.join());
+        assert_eq!(Err(JobError::Panicked("boom".into())), panicked.join());
+
+        // the worker survived the panic and can still take jobs
+        assert_eq!(
+            Ok("still here"),
+            pool.submit(|| "still here").unwrap().join()
+        );
+    }
+
+    #[test]
+    fn try_join_hands_back_unfinished_jobs() {
+        let pool = ThreadPool::new(1);
+        let (release, wait) = mpsc::channel::<()>();
+
+        let handle = pool.submit(move || wait.recv().unwrap()).unwrap();
+        let handle = handle.try_join().err().unwrap();
+        assert!(!handle.is_finished());
+
+        release.send(()).unwrap();
+        assert_eq!(Ok(()), handle.join());
+    }
+
+    #[test]
+    fn idle_workers_take_jobs_a_busy_one_has_queued() {
+        let pool = ThreadPool::new(2);
+        let (sender, receiver) = mpsc::channel();
+
+        // whichever worker runs this may have taken a batch of the jobs below with it,
+        // and those can only run if the other worker steals them
+        let waiter = pool
+            .submit(move || (0..20).all(|_| receiver.recv_timeout(Duration::from_secs(5)).is_ok()))
+            .unwrap();
+        for _ in 0..20 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(()).unwrap()).unwrap();
+        }
+
+        assert_eq!(Ok(true), waiter.join());
+    }
+
+    #[test]
+    fn map_gives_values_in_the_order_of_the_items() {
+        let pool = ThreadPool::new(3);
+
+        let results: Vec<_> = pool
+            .map([30, 0, 10, 20], |millis| {
+                // the earlier items take longest, so they finish out of order
+                thread::sleep(Duration::from_millis(millis));
+                if millis == 10 {
+                    panic!("ten");
+                }
+                millis * 2
+            })
+            .unwrap()
+            .collect();
+
+        assert_eq!(
+            vec![
+                Ok(60),
+                Ok(0),
+                Err(JobError::Panicked("ten".to_string())),
+                Ok(40)
+            ],
+            results
+        );
+    }
+
+    #[test]
+    fn batches_report_how_their_jobs_went() {
+        let pool = ThreadPool::builder()
+            .num_threads(3)
+            .queue_capacity(4)
+            .panic_handler(|_, _| ())
+            .build()
+            .unwrap();
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let batch = pool
+            .execute_batch((0..10).map(|i| {
+                let ran = Arc::clone(&ran);
+                move || {
+                    ran.fetch_add(1, Ordering::SeqCst);
+                    assert_ne!(7, i, "seven");
+                }
+            }))
+            .unwrap();
+
+        let summary = batch.wait();
+        assert!(batch.is_finished());
+        assert_eq!(10, ran.load(Ordering::SeqCst));
+        assert_eq!(
+            (9, 1, 0),
+            (summary.completed, summary.panicked, summary.cancelled)
+        );
+    }
+
+    #[test]
+    fn batches_count_jobs_that_never_ran() {
+        let (mut pool, release, _) = blocked_pool(0);
+        let batch = pool.execute_batch((0..3).map(|_| || ())).unwrap();
+        assert!(!batch.is_finished());
+
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            release.send(()).unwrap();
+        });
+        pool.shutdown_now();
+        releaser.join().unwrap();
+
+        assert_eq!(3, batch.wait().cancelled);
+    }
+
+    #[test]
+    fn wait_idle_waits_for_queued_and_running_jobs() {
+        let pool = ThreadPool::new(2);
+        let finished = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..6 {
+            let finished = Arc::clone(&finished);
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(10));
+                finished.fetch_add(1, Ordering::SeqCst);
+            })
+            .unwrap();
+        }
+        pool.wait_idle();
+        assert_eq!(6, finished.load(Ordering::SeqCst));
+
+        // an idle pool returns straight away
+        pool.wait_idle();
+
+        let (blocked, release, _) = blocked_pool(1);
+        assert!(!blocked.wait_idle_timeout(Duration::from_millis(20)));
+        release.send(()).unwrap();
+        assert!(blocked.wait_idle_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn delayed_jobs_run_in_order_of_when_they_are_due() {
+        let pool = ThreadPool::new(1);
+        let (sender, receiver) = mpsc::channel();
+        let start = Instant::now();
+
+        for (delay, name) in [(30, "third"), (10, "first"), (20, "second"), (0, "now")] {
+            let sender = sender.clone();
+            pool.execute_after(Duration::from_millis(delay), move || {
+                sender.send((name, start.elapsed())).unwrap();
+            })
+            .unwrap();
+        }
+        drop(sender);
+
+        let mut ran = Vec::new();
+        for _ in 0..4 {
+            let (name, elapsed) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            ran.push(name);
+            if name == "third" {
+                assert!(elapsed >= Duration::from_millis(30), "{elapsed:?}");
+            }
+        }
+        assert_eq!(vec!["now", "first", "second", "third"], ran);
+    }
+
+    #[test]
+    fn shutdown_drops_delayed_jobs_that_are_not_due() {
+        let mut pool = ThreadPool::new(1);
+        let ran = Arc::new(AtomicBool::new(false));
+        let job_ran = Arc::clone(&ran);
+
+        pool.execute_after(Duration::from_secs(60), move || {
+            job_ran.store(true, Ordering::SeqCst)
+        })
+        .unwrap();
+
+        assert_eq!(1, pool.shutdown_now());
+        assert!(!ran.load(Ordering::SeqCst));
+        assert_eq!(
+            Err(PoolClosedError),
+            pool.execute_after(Duration::ZERO, || ())
+        );
+    }
+
+    // runs a future to completion on this thread, parking it while the future is pending
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct Unpark(thread::Thread);
+
+        impl Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(value) => return value,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn submit_async_resolves_once_the_job_is_done() {
+        let pool = ThreadPool::new(2);
+
+        let slow = pool
+            .submit_async(|| {
+                thread::sleep(Duration::from_millis(20));
+                6 * 7
+            })
+            .unwrap();
+        let panicked = pool.submit_async(|| -> i32 { panic!("boom") }).unwrap();
+
+        assert_eq!(Ok(42), block_on(slow));
+        assert_eq!(Err(JobError::Panicked("boom".into())), block_on(panicked));
+
+        let (mut pool, release, _) = blocked_pool(0);
+        let never_ran = pool.submit_async(|| ()).unwrap();
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            release.send(()).unwrap();
+        });
+        pool.shutdown_now();
+        releaser.join().unwrap();
+        assert_eq!(Err(JobError::Cancelled), block_on(never_ran));
+    }
+
+    #[test]
+    fn jobs_past_their_timeout_are_given_up_on() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let logged = Arc::clone(&events);
+        let mut pool = ThreadPool::builder()
+            .num_threads(1)
+            .logger(move |event| logged.lock().unwrap().push(event))
+            .build()
+            .unwrap();
+        let (release, wait) = mpsc::channel::<()>();
+
+        let stuck = pool
+            .execute_with_timeout(Duration::from_millis(10), move || wait.recv().unwrap())
+            .unwrap();
+        let quick = pool
+            .execute_with_timeout(Duration::from_secs(5), || "quick")
+            .unwrap();
+
+        assert_eq!(Err(JobError::TimedOut), stuck.join());
+        // only the replacement worker can be running this
+        assert_eq!(Ok("quick"), quick.join());
+
+        release.send(()).unwrap();
+        pool.shutdown();
+        assert_eq!(0, pool.metrics().busy_workers + pool.metrics().idle_workers);
+        assert!(events
+            .lock()
+            .unwrap()
+            .contains(&Event::WorkerReplaced { worker: 0 }));
+    }
+
+    #[test]
+    fn jobs_finished_in_time_stop_being_watched() {
+        let pool = ThreadPool::new(4);
+        let handles: Vec<_> = (0..1000)
+            .map(|i| {
+                pool.execute_with_timeout(Duration::from_secs(3600), move || i)
+                    .unwrap()
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(Ok(i), handle.join());
+        }
+
+        assert_eq!(0, pool.timer().handle().waiting());
+    }
+
+    #[test]
+    fn shutdown_finishes_queued_jobs() {
+        let mut pool = ThreadPool::new(2);
+        let (sender, receiver) = mpsc::channel();
+
+        for i in 0..6 {
+            let sender = sender.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(5));
+                sender.send(i).unwrap();
+            })
+            .unwrap();
+        }
+
+        pool.shutdown();
+        drop(sender);
+        assert_eq!(6, receiver.iter().count());
+        assert_eq!(Err(PoolClosedError), pool.execute(|| ()));
+    }
+
+    // queues `queued` jobs behind one that holds the only worker until `release` is sent to
+    fn blocked_pool(queued: usize) -> (ThreadPool, mpsc::Sender<()>, Vec<JobHandle<()>>) {
+        let pool = ThreadPool::new(1);
+        let (release, wait) = mpsc::channel();
+        let (started, has_started) = mpsc::channel();
+
+        pool.execute(move || {
+            started.send(()).unwrap();
+            wait.recv().unwrap();
+        })
+        .unwrap();
+        has_started.recv().unwrap();
+
+        let handles = (0..queued).map(|_| pool.submit(|| ()).unwrap()).collect();
+        (pool, release, handles)
+    }
+
+    #[test]
+    fn shutdown_now_discards_queued_jobs() {
+        let (mut pool, release, handles) = blocked_pool(3);
+
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            release.send(()).unwrap();
+        });
+        assert_eq!(3, pool.shutdown_now());
+        releaser.join().unwrap();
+
+        for handle in handles {
+            assert_eq!(Err(JobError::Cancelled), handle.join());
+        }
+    }
+
+    #[test]
+    fn shutdown_timeout_discards_what_didnt_start() {
+        let (mut pool, release, _handles) = blocked_pool(2);
+
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            release.send(()).unwrap();
+        });
+        assert_eq!(2, pool.shutdown_timeout(Duration::from_millis(10)));
+        releaser.join().unwrap();
+
+        // with nothing queued it returns as soon as the workers are done
+        let mut pool = ThreadPool::new(2);
+        pool.execute(|| ()).unwrap();
+        assert_eq!(0, pool.shutdown_timeout(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn resize_adds_and_retires_workers() {
+        let mut pool = ThreadPool::new(1);
+
+        // three jobs that can only finish once all three are running at the same time
+        pool.resize(3).unwrap();
+        let barrier = Arc::new(std::sync::Barrier::new(3));
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                pool.submit(move || {
+                    barrier.wait();
+                })
+                .unwrap()
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        pool.resize(1).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.shared.queue.surplus() > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(0, pool.shared.queue.surplus());
+        assert_eq!(1, pool.size());
+        assert_eq!(Ok(4), pool.submit(|| 2 + 2).unwrap().join());
+    }
+
+    #[test]
+    fn temporary_workers_help_with_a_backlog_and_then_expire() {
+        let (expired, worker_expired) = mpsc::channel();
+        let expired = Mutex::new(expired);
+        let pool = ThreadPool::builder()
+            .num_threads(1)
+            .max_threads(3)
+            .keep_alive(Duration::from_millis(20))
+            .logger(move |event| {
+                if let Event::WorkerExpired { worker } = event {
+                    expired.lock().unwrap().send(worker).unwrap();
+                }
+            })
+            .build()
+            .unwrap();
+
+        // three jobs that can only finish once all three are running at the same time,
+        // on a pool with one core worker
+        let barrier = Arc::new(std::sync::Barrier::new(3));
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                pool.submit(move || {
+                    barrier.wait();
+                })
+                .unwrap()
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let mut gone: Vec<_> = worker_expired.iter().take(2).collect();
+        gone.sort();
+        assert_eq!(vec![1, 2], gone);
+        // the core worker stays
+        assert_eq!(Ok(4), pool.submit(|| 2 + 2).unwrap().join());
+    }
+
+    #[test]
+    fn pools_are_sized_from_the_cpu_count() {
+        let cpus = available_parallelism();
+
+        assert_eq!(cpus, ThreadPool::default().size());
+        let pool = ThreadPool::builder().threads_per_cpu(2).build().unwrap();
+        assert_eq!(2 * cpus, pool.size());
+    }
+
+    #[test]
+    fn jobs_cancelled_before_they_start_never_run() {
+        let pool = ThreadPool::new(1);
+        let (release, wait) = mpsc::channel::<()>();
+        pool.execute(move || wait.recv().unwrap()).unwrap();
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let job_ran = Arc::clone(&ran);
+        let handle = pool
+            .execute_cancellable(move |_| job_ran.store(true, Ordering::SeqCst))
+            .unwrap();
+        handle.cancel();
+        release.send(()).unwrap();
+
+        assert_eq!(Err(JobError::Cancelled), handle.join());
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn retryable_jobs_are_retried_until_they_run_out_of_attempts() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let handler_reported = Arc::clone(&reported);
+        let pool = ThreadPool::builder()
+            .num_threads(1)
+            .panic_policy(PanicPolicy::Retry { max_attempts: 3 })
+            .panic_handler(move |_, message| {
+                handler_reported.lock().unwrap().push(message.to_string())
+            })
+            .build()
+            .unwrap();
+        let (tried, attempts) = mpsc::channel();
+
+        pool.execute_retryable(move || {
+            tried.send(()).unwrap();
+            panic!("always fails");
+        })
+        .unwrap();
+
+        for _ in 0..3 {
+            attempts.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        // the third attempt was the last
+        assert!(attempts.recv_timeout(Duration::from_millis(200)).is_err());
+        pool.wait_idle();
+        assert_eq!(vec!["always fails"; 3], *reported.lock().unwrap());
+        assert_eq!(3, pool.metrics().panics);
+    }
+
+    #[test]
+    fn hooks_time_the_queue_and_the_jobs() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let (queued, started, completed) = (
+            Arc::clone(&events),
+            Arc::clone(&events),
+            Arc::clone(&events),
+        );
+        let pool = ThreadPool::builder()
+            .num_threads(1)
+            .on_job_queued(move || queued.lock().unwrap().push(("queued", Duration::ZERO)))
+            .on_job_start(move |waited| started.lock().unwrap().push(("started", waited)))
+            .on_job_complete(move |ran| completed.lock().unwrap().push(("completed", ran)))
+            .build()
+            .unwrap();
+
+        // the second job waits for the first
+        for _ in 0..2 {
+            pool.execute(|| thread::sleep(Duration::from_millis(20)))
+                .unwrap();
+        }
+        pool.wait_idle();
+
+        let events = events.lock().unwrap();
+        // the first job may start before the second is queued
+        let (queued, ran): (Vec<_>, Vec<_>) = events
+            .iter()
+            .map(|(name, _)| *name)
+            .partition(|name| *name == "queued");
+        assert_eq!(2, queued.len());
+        assert_eq!(vec!["started", "completed", "started", "completed"], ran);
+        let time = |name, nth| {
+            events
+                .iter()
+                .filter(|(event, _)| *event == name)
+                .nth(nth)
+                .unwrap()
+                .1
+        };
+        assert!(time("completed", 0) >= Duration::from_millis(20));
+        assert!(time("started", 1) >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn builder_names_threads() {
+        let pool = ThreadPool::builder()
+            .num_threads(2)
+            .thread_name("test-worker")
+            .stack_size(256 * 1024)
+            .build()
+            .unwrap();
+
+        let name = pool
+            .submit(|| thread::current().name().map(String::from))
+            .unwrap()
+            .join()
+            .unwrap()
+            .unwrap();
+        assert!(name.starts_with("test-worker-"), "{name}");
+    }
+
+    #[test]
+    fn workers_survive_panicking_jobs() {
+        let panics = Arc::new(Mutex::new(Vec::new()));
+        let reported = Arc::clone(&panics);
+        let pool = ThreadPool::builder()
+            .num_threads(1)
+            .panic_handler(move |_, message| reported.lock().unwrap().push(message.to_string()))
+            .build()
+            .unwrap();
+
+        pool.execute(|| panic!("first")).unwrap();
+        pool.execute(|| panic!("second {}", 2)).unwrap();
+
+        // the only worker is still around to run this
+        assert_eq!(Ok(1), pool.submit(|| 1).unwrap().join());
+        assert_eq!(vec!["first", "second 2"], *panics.lock().unwrap());
+    }
+
+    #[test]
+    fn panic_reports_know_which_worker_they_came_from() {
+        let panics = Arc::new(Mutex::new(Vec::new()));
+        let reported = Arc::clone(&panics);
+        let pool = ThreadPool::builder()
+            .num_threads(1)
+            .thread_name("named")
+            .panic_handler(move |id, _| reported.lock().unwrap().push((id, current_worker())))
+            .build()
+            .unwrap();
+
+        pool.execute(|| panic!("boom")).unwrap();
+        let running = pool.submit(current_worker).unwrap().join().unwrap();
+
+        let worker = CurrentWorker {
+            id: 0,
+            name: Some("named-0".into()),
+        };
+        assert_eq!(Some(&worker), running.as_ref());
+        assert_eq!(vec![(0, Some(worker))], *panics.lock().unwrap());
+        assert_eq!(None, current_worker());
+    }
+
+    #[test]
+    fn logger_hears_about_workers() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let logged = Arc::clone(&events);
+        let mut pool = ThreadPool::builder()
+            .num_threads(1)
+            .logger(move |event| logged.lock().unwrap().push(event))
+            .build()
+            .unwrap();
+
+        pool.execute(|| ()).unwrap();
+        pool.shutdown();
+
+        let events = events.lock().unwrap();
+        assert!(events.contains(&Event::JobStarted { worker: 0 }));
+        assert!(events.contains(&Event::WorkerStopped { worker: 0 }));
+        assert!(events.contains(&Event::JoiningWorker { worker: 0 }));
+        assert_eq!(
+            "worker 0 got a job, executing.",
+            Event::JobStarted { worker: 0 }.to_string()
+        );
+    }
+
+    #[test]
+    fn bounded_queue_pushes_back() {
+        let pool = ThreadPool::builder()
+            .num_threads(1)
+            .queue_capacity(2)
+            .build()
+            .unwrap();
+        let (release, wait) = mpsc::channel::<()>();
+        let (started, has_started) = mpsc::channel();
+
+        pool.execute(move || {
+            started.send(()).unwrap();
+            wait.recv().unwrap();
+        })
+        .unwrap();
+        has_started.recv().unwrap();
+
+        assert_eq!(Ok(()), pool.try_execute(|| ()));
+        assert_eq!(Ok(()), pool.try_execute(|| ()));
+        assert_eq!(Err(TryExecuteError::QueueFull), pool.try_execute(|| ()));
+
+        release.send(()).unwrap();
+        // blocks until the worker has made room
+        assert_eq!(Ok(()), pool.execute(|| ()));
+    }
+
+    #[test]
+    fn execute_fails_once_closed() {
+        let mut pool = ThreadPool::new(1);
+        pool.shutdown();
+
+        assert_eq!(Err(PoolClosedError), pool.execute(|| ()));
+    }
+
+    #[test]
+    fn metrics_track_workers_and_jobs() {
+        let (mut pool, release, handles) = blocked_pool(2);
+        pool.execute(|| panic!("boom")).unwrap();
+        let panicked = pool.submit(|| panic!("bang")).unwrap();
+
+        let metrics = pool.metrics();
+        assert_eq!(4, metrics.queued_jobs);
+        assert_eq!(1, metrics.busy_workers);
+        assert_eq!(0, metrics.idle_workers);
+        assert_eq!(0, metrics.jobs_completed);
+
+        release.send(()).unwrap();
+        handles
+            .into_iter()
+            .for_each(|handle| handle.join().unwrap());
+        assert!(panicked.join().is_err());
+        pool.shutdown();
+
+        let metrics = pool.metrics();
+        assert_eq!(0, metrics.queued_jobs);
+        assert_eq!(0, metrics.busy_workers + metrics.idle_workers);
+        assert_eq!(5, metrics.jobs_completed);
+        assert_eq!(2, metrics.panics);
+    }
+
+    #[test]
+    fn scope_waits_for_borrowing_jobs() {
+        let pool = ThreadPool::new(3);
+        let words = ["rust", "thread", "pool"];
+        let mut lengths = [0; 3];
+
+        pool.scope(|s| {
+            for (word, length) in words.iter().zip(&mut lengths) {
+                s.execute(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    *length = word.len();
+                })
+                .unwrap();
+            }
+        });
+
+        assert_eq!([4, 6, 4], lengths);
+    }
+
+    #[test]
+    fn scope_panics_after_its_jobs_finish() {
+        let pool = ThreadPool::builder()
+            .num_threads(2)
+            .panic_handler(|_, _| ())
+            .build()
+            .unwrap();
+        let finished = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|s| {
+                s.execute(|| panic!("boom")).unwrap();
+                s.execute(|| {
+                    thread::sleep(Duration::from_millis(20));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                })
+                .unwrap();
+            })
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(1, finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn workers_keep_their_state_between_jobs() {
+        let inits = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&inits);
+        let mut pool = ThreadPool::with_worker_init(2, move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            0
+        });
+        let (sender, receiver) = mpsc::channel();
+
+        for _ in 0..10 {
+            let sender = sender.clone();
+            pool.execute_with_state(move |jobs_run: &mut u32| {
+                *jobs_run += 1;
+                sender.send((thread::current().id(), *jobs_run)).unwrap();
+            })
+            .unwrap();
+        }
+        drop(sender);
+        pool.shutdown();
+
+        // each worker counts its own jobs, so the highest counts add up to the total
+        let mut highest = std::collections::HashMap::new();
+        for (worker, jobs_run) in receiver {
+            let seen = highest.entry(worker).or_insert(0);
+            *seen = jobs_run.max(*seen);
+        }
+        assert_eq!(2, inits.load(Ordering::SeqCst));
+        assert_eq!(10, highest.values().sum::<u32>());
+    }
+
+    #[test]
+    fn higher_priority_jobs_jump_the_queue() {
+        let (mut pool, release, _) = blocked_pool(0);
+        let (sender, receiver) = mpsc::channel();
+
+        for (priority, name) in [
+            (Priority::Low, "low"),
+            (Priority::Normal, "normal 1"),
+            (Priority::High, "high"),
+            (Priority::Normal, "normal 2"),
+        ] {
+            let sender = sender.clone();
+            pool.execute_with_priority(priority, move || sender.send(name).unwrap())
+                .unwrap();
+        }
+        drop(sender);
+        release.send(()).unwrap();
+        pool.shutdown();
+
+        let order: Vec<_> = receiver.iter().collect();
+        assert_eq!(vec!["high", "normal 1", "normal 2", "low"], order);
     }
 }
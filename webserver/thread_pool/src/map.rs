@@ -0,0 +1,93 @@
+use std::{collections::BTreeMap, sync::mpsc};
+
+use crate::JobError;
+
+type Reply<T> = (usize, Result<T, JobError>);
+
+// owned by each job of a `map`, so it answers for its item whether it ran or was dropped
+pub(crate) struct Replier<T> {
+    index: usize,
+    sender: Option<mpsc::Sender<Reply<T>>>,
+}
+
+impl<T> Replier<T> {
+    pub(crate) fn reply(mut self, result: Result<T, JobError>) {
+        if let Some(sender) = self.sender.take() {
+            // the caller may have dropped the results, in which case nobody wants this
+            let _ = sender.send((self.index, result));
+        }
+    }
+}
+
+impl<T> Drop for Replier<T> {
+    fn drop(&mut self) {
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.send((self.index, Err(JobError::Cancelled)));
+        }
+    }
+}
+
+/// the values of the jobs started by `ThreadPool::map`, in the order of their items
+///
+/// Iterating blocks until the next item's job has finished. Values that come in
+/// early are held on to until their turn.
+pub struct Results<T> {
+    receiver: mpsc::Receiver<Reply<T>>,
+    next: usize,
+    len: usize,
+    early: BTreeMap<usize, Result<T, JobError>>,
+}
+
+impl<T> Results<T> {
+    pub(crate) fn new(len: usize) -> (Results<T>, impl FnMut() -> Replier<T>) {
+        let (sender, receiver) = mpsc::channel();
+        let mut index = 0;
+        let replier = move || {
+            index += 1;
+            Replier {
+                index: index - 1,
+                sender: Some(sender.clone()),
+            }
+        };
+        let results = Results {
+            receiver,
+            next: 0,
+            len,
+            early: BTreeMap::new(),
+        };
+        (results, replier)
+    }
+}
+
+impl<T> Iterator for Results<T> {
+    type Item = Result<T, JobError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next == self.len {
+            return None;
+        }
+
+        let index = self.next;
+        self.next += 1;
+        if let Some(result) = self.early.remove(&index) {
+            return Some(result);
+        }
+        loop {
+            match self.receiver.recv() {
+                Ok((i, result)) if i == index => return Some(result),
+                Ok((i, result)) => {
+                    self.early.insert(i, result);
+                }
+                // every job replies, so this is only here to be safe
+                Err(mpsc::RecvError) => return Some(Err(JobError::Cancelled)),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.len - self.next;
+        (left, Some(left))
+    }
+}
+
+impl<T> ExactSizeIterator for Results<T> {}
@@ -0,0 +1,527 @@
+use std::{
+    iter,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Condvar, Mutex, RwLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker as Deque};
+
+use crate::{Hooks, Job, PoolClosedError, Priority, TryExecuteError};
+
+// a job waiting in the queue, with when it was queued if the hooks want to know
+pub(crate) struct Queued<S> {
+    pub(crate) job: Job<S>,
+    pub(crate) at: Option<Instant>,
+}
+
+// what a worker should do next
+pub(crate) enum Next<S> {
+    Job(Queued<S>),
+    // the pool was shrunk and this worker is one too many
+    Retire,
+    // this is a temporary worker and it has gone its keep-alive without a job
+    Expire,
+    // the pool is shutting down and there is nothing left to run
+    Closed,
+}
+
+// takes from a deque until it either has a job or is really empty, as opposed to
+// losing a race with another thread, which crossbeam reports as `Steal::Retry`
+fn steal<T>(mut attempt: impl FnMut() -> Steal<T>) -> Option<T> {
+    iter::repeat_with(&mut attempt)
+        .find(|steal| !steal.is_retry())
+        .and_then(Steal::success)
+}
+
+// the stealing end of a worker's local queue, one per priority
+type Stealers<S> = [Stealer<Queued<S>>; 3];
+
+/// the jobs a worker has taken off the shared queue in a batch, which other workers
+/// steal from when they run dry
+///
+/// Whatever is left in it when the worker exits goes back on the shared queue.
+pub(crate) struct LocalQueue<'a, S> {
+    queue: &'a JobQueue<S>,
+    id: u32,
+    // how long the worker waits for a job before exiting, for temporary workers
+    keep_alive: Option<Duration>,
+    // one per priority, the same as the shared queue
+    deques: [Deque<Queued<S>>; 3],
+}
+
+impl<S> Drop for LocalQueue<'_, S> {
+    fn drop(&mut self) {
+        let mut stealers = self.queue.stealers.write().unwrap();
+        stealers.retain(|(id, _)| *id != self.id);
+        drop(stealers);
+
+        for (deque, injector) in self.deques.iter().zip(&self.queue.injectors) {
+            while let Some(job) = deque.pop() {
+                injector.push(job);
+            }
+        }
+        self.queue.wake_all();
+    }
+}
+
+/// the jobs waiting for a worker, shared between the pool and its workers
+///
+/// Jobs are submitted to a lock free queue per priority. Each worker moves them over to
+/// its own `LocalQueue` in batches, and a worker that runs out of jobs steals from the
+/// others, so workers only contend with each other when work is scarce. Taking a job
+/// never locks, the mutex is only there for workers to sleep on when there is none.
+pub(crate) struct JobQueue<S> {
+    injectors: [Injector<Queued<S>>; 3],
+    // the other end of every worker's local queue, with the worker's id
+    stealers: RwLock<Vec<(u32, Stealers<S>)>>,
+    // jobs waiting in any of the queues, which the deques can't count cheaply themselves
+    len: AtomicUsize,
+    // jobs taken by a worker that haven't finished yet
+    active: AtomicUsize,
+    closed: AtomicBool,
+    // how many workers should still exit after a `resize` down, the first ones to ask do
+    surplus: AtomicUsize,
+    // workers waiting in `pop` that nobody has woken yet, so `push` only takes the lock
+    // when someone needs waking. only changed under `sleep`
+    sleeping: AtomicUsize,
+    // workers woken by a push that haven't found a job yet. while there is one, pushes
+    // leave the rest asleep, as it will wake the next when it finds a job, see `pop`
+    searching: AtomicUsize,
+    // pushers waiting for room in a bounded queue, the same for taking a job off it
+    waiting_for_room: AtomicUsize,
+    // only guards sleeping and waking, the jobs themselves are never behind it. holds
+    // how many workers have been woken by a push but not got to run yet
+    sleep: Mutex<usize>,
+    // signalled when a worker may have something to do
+    work: Condvar,
+    // signalled when a job is taken off a bounded queue
+    room: Condvar,
+    // signalled when the last job finishes, with nothing queued behind it
+    idle: Condvar,
+    capacity: Option<usize>,
+    pub(crate) hooks: Hooks,
+}
+
+impl<S> JobQueue<S> {
+    pub(crate) fn new(capacity: Option<usize>, hooks: Hooks) -> JobQueue<S> {
+        JobQueue {
+            injectors: Default::default(),
+            stealers: RwLock::default(),
+            len: AtomicUsize::new(0),
+            active: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            surplus: AtomicUsize::new(0),
+            sleeping: AtomicUsize::new(0),
+            searching: AtomicUsize::new(0),
+            waiting_for_room: AtomicUsize::new(0),
+            sleep: Mutex::new(0),
+            work: Condvar::new(),
+            room: Condvar::new(),
+            idle: Condvar::new(),
+            capacity,
+            hooks,
+        }
+    }
+
+    // counts up to `count` jobs in, as many as there is room for, returning how many
+    fn reserve(&self, count: usize) -> usize {
+        match self.capacity {
+            Some(capacity) => self
+                .len
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| {
+                    (len < capacity).then(|| len + count.min(capacity - len))
+                })
+                .map_or(0, |len| count.min(capacity - len)),
+            None => {
+                self.len.fetch_add(count, Ordering::SeqCst);
+                count
+            }
+        }
+    }
+
+    // counts a job out, making room for a blocked `push`. a pusher that starts waiting
+    // after the count goes down finds the room when it checks again under the lock
+    fn release(&self) {
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        if self.waiting_for_room.load(Ordering::SeqCst) > 0 {
+            let _sleep = self.sleep.lock().unwrap();
+            self.room.notify_one();
+        }
+    }
+
+    // queues `count` jobs that have been reserved. they are counted before `closed` is
+    // checked, so a worker can't see the queue closed and empty and exit while the jobs
+    // are on their way in
+    fn enqueue(
+        &self,
+        priority: Priority,
+        jobs: impl Iterator<Item = Job<S>>,
+        count: usize,
+    ) -> Result<(), PoolClosedError> {
+        if self.closed.load(Ordering::SeqCst) {
+            self.len.fetch_sub(count, Ordering::SeqCst);
+            return Err(PoolClosedError);
+        }
+
+        // only timed when someone is listening, as it isn't free
+        let stamp = self.hooks.started.is_some();
+        for job in jobs {
+            let at = stamp.then(Instant::now);
+            self.injectors[priority as usize].push(Queued { job, at });
+            if let Some(queued) = &self.hooks.queued {
+                queued();
+            }
+        }
+        if self.searching.load(Ordering::SeqCst) == 0 {
+            self.wake(count);
+        }
+        Ok(())
+    }
+
+    // wakes up to `count` sleeping workers to look for jobs
+    fn wake(&self, count: usize) {
+        if self.sleeping.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+
+        let mut woken = self.sleep.lock().unwrap();
+        // the workers are counted out here rather than once they run, so pushes in the
+        // meantime don't take the lock to wake them all over again
+        let wake = count.min(self.sleeping.load(Ordering::SeqCst));
+        self.sleeping.fetch_sub(wake, Ordering::SeqCst);
+        self.searching.fetch_add(wake, Ordering::SeqCst);
+        *woken += wake;
+        match wake {
+            0 => {}
+            1 => self.work.notify_one(),
+            _ => self.work.notify_all(),
+        }
+    }
+
+    // called by a searching worker that has found a job or is leaving. if it was the
+    // last one and there are jobs left, it wakes another, so jobs queued while it was
+    // searching still get spread over the workers
+    fn stop_searching(&self) {
+        if self.searching.fetch_sub(1, Ordering::SeqCst) == 1 && self.len() > 0 {
+            self.wake(1);
+        }
+    }
+
+    fn wake_all(&self) {
+        let _sleep = self.sleep.lock().unwrap();
+        self.work.notify_all();
+        self.room.notify_all();
+        self.idle.notify_all();
+    }
+
+    // waits for room in the queue if it is full
+    pub(crate) fn push(&self, priority: Priority, job: Job<S>) -> Result<(), PoolClosedError> {
+        self.push_all(priority, iter::once(job))
+    }
+
+    // queues the jobs with one update of the count and one wake up, or as few as there is
+    // room for in a bounded queue, waiting for room for the rest
+    pub(crate) fn push_all(
+        &self,
+        priority: Priority,
+        mut jobs: impl ExactSizeIterator<Item = Job<S>>,
+    ) -> Result<(), PoolClosedError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(PoolClosedError);
+        }
+
+        let mut left = jobs.len();
+        while left > 0 {
+            let mut reserved = self.reserve(left);
+            if reserved == 0 {
+                let mut sleep = self.sleep.lock().unwrap();
+                self.waiting_for_room.fetch_add(1, Ordering::SeqCst);
+                let waited = loop {
+                    reserved = self.reserve(left);
+                    if reserved > 0 {
+                        break Ok(());
+                    }
+                    if self.closed.load(Ordering::SeqCst) {
+                        break Err(PoolClosedError);
+                    }
+                    sleep = self.room.wait(sleep).unwrap();
+                };
+                self.waiting_for_room.fetch_sub(1, Ordering::SeqCst);
+                waited?;
+            }
+
+            self.enqueue(priority, jobs.by_ref().take(reserved), reserved)?;
+            left -= reserved;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn try_push(&self, priority: Priority, job: Job<S>) -> Result<(), TryExecuteError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(TryExecuteError::Closed);
+        }
+        if self.reserve(1) == 0 {
+            return Err(TryExecuteError::QueueFull);
+        }
+        self.enqueue(priority, iter::once(job), 1)
+            .map_err(|PoolClosedError| TryExecuteError::Closed)
+    }
+
+    /// Makes a local queue for the worker with this id, which it passes to `pop`. With a
+    /// `keep_alive`, `pop` gives up waiting for a job after that long.
+    pub(crate) fn register(&self, id: u32, keep_alive: Option<Duration>) -> LocalQueue<'_, S> {
+        let deques = [Deque::new_fifo(), Deque::new_fifo(), Deque::new_fifo()];
+        let stealers = [
+            deques[0].stealer(),
+            deques[1].stealer(),
+            deques[2].stealer(),
+        ];
+        self.stealers.write().unwrap().push((id, stealers));
+
+        LocalQueue {
+            queue: self,
+            id,
+            keep_alive,
+            deques,
+        }
+    }
+
+    // the next job for `local`'s worker, from its own queue, then the shared one, and
+    // only then from the other workers, highest priority first at each step
+    fn find(&self, local: &LocalQueue<S>) -> Option<Queued<S>> {
+        let own = local
+            .deques
+            .iter()
+            .zip(&self.injectors)
+            .find_map(|(deque, injector)| {
+                deque
+                    .pop()
+                    .or_else(|| steal(|| injector.steal_batch_and_pop(deque)))
+            });
+        if own.is_some() {
+            return own;
+        }
+
+        let stealers = self.stealers.read().unwrap();
+        (0..local.deques.len()).find_map(|priority| {
+            stealers
+                .iter()
+                .filter(|(id, _)| *id != local.id)
+                .find_map(|(_, theirs)| {
+                    steal(|| theirs[priority].steal_batch_and_pop(&local.deques[priority]))
+                })
+        })
+    }
+
+    fn take_surplus(&self) -> bool {
+        self.surplus
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |surplus| {
+                surplus.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    // blocks until there is a job to run, or the worker should exit
+    pub(crate) fn pop(&self, local: &LocalQueue<S>) -> Next<S> {
+        // whether this worker was woken by a push and counts towards `searching`
+        let mut searching = false;
+        loop {
+            // checked before taking a job, so busy workers retire as soon as they are done too
+            if self.take_surplus() {
+                if searching {
+                    self.stop_searching();
+                }
+                return Next::Retire;
+            }
+            if let Some(job) = self.find(local) {
+                // counted as active before it stops being counted as queued, so
+                // `wait_idle` can't see neither
+                self.active.fetch_add(1, Ordering::SeqCst);
+                self.release();
+                if searching {
+                    self.stop_searching();
+                }
+                return Next::Job(job);
+            }
+
+            let mut sleep = self.sleep.lock().unwrap();
+            // announced before `len` is checked, so a `push` either sees a sleeper to wake
+            // or has already been counted here. the same goes for no longer searching
+            self.sleeping.fetch_add(1, Ordering::SeqCst);
+            if searching {
+                self.searching.fetch_sub(1, Ordering::SeqCst);
+            }
+            // read under the lock, so a `close` after this has to wait to wake us up, and
+            // before `len`, see `enqueue`
+            let closed = self.closed.load(Ordering::SeqCst);
+            let queued = self.len.load(Ordering::SeqCst);
+            if queued == 0 && closed {
+                self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                return Next::Closed;
+            }
+            if queued == 0 && self.surplus.load(Ordering::SeqCst) == 0 {
+                let timed_out;
+                (sleep, timed_out) = match local.keep_alive {
+                    None => (self.work.wait(sleep).unwrap(), false),
+                    Some(keep_alive) => {
+                        let (sleep, waited) = self.work.wait_timeout(sleep, keep_alive).unwrap();
+                        (sleep, waited.timed_out())
+                    }
+                };
+                // whichever worker wakes first takes the place of one that was woken, it
+                // makes no difference which
+                searching = *sleep > 0;
+                if searching {
+                    *sleep -= 1;
+                } else {
+                    self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                    // any jobs queued since were left to a worker that is searching
+                    if timed_out {
+                        return Next::Expire;
+                    }
+                }
+            } else {
+                self.sleeping.fetch_sub(1, Ordering::SeqCst);
+                // still looking after all
+                if searching {
+                    self.searching.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            drop(sleep);
+
+            // a job that is counted but can't be found yet is halfway through being
+            // pushed or stolen, and will turn up in a moment
+            if queued > 0 {
+                thread::yield_now();
+            }
+        }
+    }
+
+    // called by the worker once a job from `pop` has finished running
+    pub(crate) fn finish(&self) {
+        if self.active.fetch_sub(1, Ordering::SeqCst) == 1 && self.len() == 0 {
+            let _sleep = self.sleep.lock().unwrap();
+            self.idle.notify_all();
+        }
+    }
+
+    // blocks until no job is queued or running, or until the deadline if there is one,
+    // returning whether that happened
+    pub(crate) fn wait_idle(&self, deadline: Option<Instant>) -> bool {
+        let mut sleep = self.sleep.lock().unwrap();
+        loop {
+            if self.len() == 0 && self.active.load(Ordering::SeqCst) == 0 {
+                return true;
+            }
+            sleep = match deadline {
+                None => self.idle.wait(sleep).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.idle.wait_timeout(sleep, deadline - now).unwrap().0
+                }
+            };
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    // stops accepting jobs, workers leave once the queue is empty
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.wake_all();
+    }
+
+    // empties the queue, returning how many jobs were in it
+    pub(crate) fn discard(&self) -> usize {
+        let mut discarded = 0;
+        for injector in &self.injectors {
+            while let Some(job) = steal(|| injector.steal()) {
+                drop(job);
+                discarded += 1;
+            }
+        }
+        for (_, stealers) in self.stealers.read().unwrap().iter() {
+            for stealer in stealers {
+                while let Some(job) = steal(|| stealer.steal()) {
+                    drop(job);
+                    discarded += 1;
+                }
+            }
+        }
+
+        self.len.fetch_sub(discarded, Ordering::SeqCst);
+        self.wake_all();
+        discarded
+    }
+
+    // asks `count` more workers to exit
+    pub(crate) fn retire(&self, count: usize) {
+        self.surplus.fetch_add(count, Ordering::SeqCst);
+        self.wake_all();
+    }
+
+    // takes back up to `count` requests to exit, returning how many there were
+    pub(crate) fn unretire(&self, count: usize) -> usize {
+        let taken = self
+            .surplus
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |surplus| {
+                Some(surplus - surplus.min(count))
+            })
+            .unwrap();
+        taken.min(count)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn surplus(&self) -> usize {
+        self.surplus.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+
+    #[test]
+    fn a_searching_worker_wakes_the_next_one() {
+        let queue = JobQueue::<()>::new(None, Hooks::default());
+        let (ran, job_ran) = mpsc::channel();
+
+        thread::scope(|s| {
+            let sleeper = s.spawn(|| match queue.pop(&queue.register(1, None)) {
+                Next::Job(queued) => (queued.job)(&mut ()),
+                _ => panic!("expected a job"),
+            });
+            while queue.sleeping.load(Ordering::SeqCst) == 0 {
+                thread::yield_now();
+            }
+
+            // as if a worker woken by an earlier push were still looking, so this push
+            // leaves the sleeper be
+            queue.searching.store(1, Ordering::SeqCst);
+            queue
+                .push(Priority::Normal, Box::new(move |_| ran.send(()).unwrap()))
+                .unwrap();
+            assert_eq!(1, queue.sleeping.load(Ordering::SeqCst));
+
+            // that worker leaving with the job still queued hands it on
+            queue.stop_searching();
+            sleeper.join().unwrap();
+        });
+        job_ran.try_recv().unwrap();
+    }
+}
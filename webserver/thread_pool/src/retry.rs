@@ -0,0 +1,90 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{timer::TimerHandle, Job};
+
+// the wait before the first retry, which doubles with each one after it up to `MAX_BACKOFF`
+const FIRST_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// what a worker does after a job passed to `execute` panics, see
+/// `ThreadPoolBuilder::panic_policy`
+///
+/// The panic is reported to the panic handler first in every case.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// carries on with the next job
+    #[default]
+    Ignore,
+    /// aborts the whole process, for jobs whose failure leaves things in a state
+    /// nothing else should carry on with
+    Abort,
+    /// runs the job again, up to `max_attempts` times in all, waiting a little longer
+    /// before each retry. Only jobs passed to `ThreadPool::execute_retryable` can be run
+    /// more than once, other jobs are treated as with `Ignore`
+    Retry { max_attempts: u32 },
+}
+
+impl PanicPolicy {
+    // how many times a retryable job may be run
+    pub(crate) fn attempts(self) -> u32 {
+        match self {
+            PanicPolicy::Retry { max_attempts } => max_attempts.max(1),
+            PanicPolicy::Ignore | PanicPolicy::Abort => 1,
+        }
+    }
+}
+
+// how long to wait before running a job for the `attempt`th time, counting from one.
+// the jitter keeps jobs that failed together from all being retried at the same moment
+fn backoff(attempt: u32) -> Duration {
+    let exponential = FIRST_BACKOFF
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_BACKOFF);
+    // somewhere between half and all of it. the standard library's hasher keys are
+    // random, which is plenty for spreading retries out
+    let random = RandomState::new().hash_one(attempt);
+    exponential.mul_f64(0.5 + (random % 1000) as f64 / 2000.0)
+}
+
+// a job that runs `f`, and if it panics with attempts left, has itself queued again by
+// the timer before passing the panic on to be reported like any other
+pub(crate) fn retrying<S: 'static>(
+    f: Arc<dyn Fn() + Send + Sync>,
+    attempt: u32,
+    max_attempts: u32,
+    timer: TimerHandle<S>,
+) -> Job<S> {
+    Box::new(move |_: &mut S| {
+        if attempt >= max_attempts {
+            return f();
+        }
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f())) {
+            let next = retrying(Arc::clone(&f), attempt + 1, max_attempts, timer.clone());
+            // the timer has stopped if the pool is shutting down, and the job with it
+            let _ = timer.schedule(Instant::now() + backoff(attempt + 1), next);
+            panic::resume_unwind(payload);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_a_limit() {
+        for attempt in 2..20 {
+            let wait = backoff(attempt);
+            let full = FIRST_BACKOFF
+                .saturating_mul(1 << (attempt - 1).min(16))
+                .min(MAX_BACKOFF);
+            assert!(wait >= full / 2 && wait <= full, "{attempt}: {wait:?}");
+        }
+    }
+}
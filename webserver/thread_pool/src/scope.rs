@@ -0,0 +1,112 @@
+use std::{
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+};
+
+use crate::{Job, PoolClosedError, Priority, ThreadPool};
+
+// what the jobs of one scope report back to it
+#[derive(Default)]
+struct ScopeState {
+    pending: Mutex<usize>,
+    all_done: Condvar,
+    panicked: AtomicBool,
+}
+
+// owned by each scoped job, so it counts as done whether it ran or was dropped unrun
+struct Pending(Arc<ScopeState>);
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let mut pending = self.0.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.0.all_done.notify_all();
+        }
+    }
+}
+
+// a scoped job and what it reports to, with `f` declared first so that if the job is
+// dropped unrun, whatever it borrows is dropped before the scope hears it's done
+struct ScopedJob<F> {
+    f: F,
+    pending: Pending,
+}
+
+impl<F: FnOnce()> ScopedJob<F> {
+    fn run(self) {
+        let ScopedJob { f, pending } = self;
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        if let Err(payload) = result {
+            pending.0.panicked.store(true, Ordering::SeqCst);
+            // once the scope hears we're done nothing borrowed may be touched,
+            // and the payload owns everything it needs
+            drop(pending);
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+/// lets jobs borrow from the stack frame that called `ThreadPool::scope`
+///
+/// Every job started through the scope has finished by the time `scope` returns,
+/// which is what makes the borrows safe.
+pub struct Scope<'scope, 'env: 'scope, S = ()> {
+    pool: &'scope ThreadPool<S>,
+    state: Arc<ScopeState>,
+    // invariant over both lifetimes, the same as `std::thread::Scope`
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env, S: 'static> Scope<'scope, 'env, S> {
+    pub(crate) fn new(pool: &'scope ThreadPool<S>) -> Scope<'scope, 'env, S> {
+        Scope {
+            pool,
+            state: Arc::default(),
+            scope: PhantomData,
+            env: PhantomData,
+        }
+    }
+
+    /// Sends a job to the pool that may borrow anything that outlives the scope.
+    ///
+    /// If the job panics, the pool's panic handler sees it as usual and `scope`
+    /// panics once all of its jobs are done.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PoolClosedError` if the pool is shutting down.
+    pub fn execute<F>(&self, f: F) -> Result<(), PoolClosedError>
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *self.state.pending.lock().unwrap() += 1;
+        let scoped = ScopedJob {
+            f,
+            pending: Pending(Arc::clone(&self.state)),
+        };
+
+        let job: Box<dyn FnOnce(&mut S) + Send + 'scope> = Box::new(move |_: &mut S| scoped.run());
+        // SAFETY: `ThreadPool::scope` doesn't return until `pending` has been dropped,
+        // which happens after `f` has run or been thrown away, so nothing it borrows
+        // can go away while the pool still holds it
+        let job = unsafe { mem::transmute::<Box<dyn FnOnce(&mut S) + Send + 'scope>, Job<S>>(job) };
+
+        self.pool.shared.push(Priority::Normal, job)
+    }
+
+    // blocks until every job sent through the scope is done, returning whether any panicked
+    pub(crate) fn wait(&self) -> bool {
+        let mut pending = self.state.pending.lock().unwrap();
+        while *pending > 0 {
+            pending = self.state.all_done.wait(pending).unwrap();
+        }
+        self.state.panicked.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    io,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Instant,
+};
+
+use crate::{Job, PoolClosedError, Priority, Shared};
+
+// what to do when the time comes
+enum Task<S> {
+    // queue a job on the pool
+    Queue(Job<S>),
+    // run something small on the timer thread itself, which works even when every worker
+    // is busy
+    Call(Box<dyn FnOnce() + Send + 'static>),
+}
+
+// a task waiting for its time to come, the earliest first and then in the order they
+// were scheduled
+struct Scheduled<S> {
+    at: Instant,
+    seq: u64,
+    task: Task<S>,
+}
+
+impl<S> Scheduled<S> {
+    fn key(&self) -> Reverse<(Instant, u64)> {
+        Reverse((self.at, self.seq))
+    }
+}
+
+impl<S> PartialEq for Scheduled<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<S> Eq for Scheduled<S> {}
+
+impl<S> PartialOrd for Scheduled<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S> Ord for Scheduled<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+struct TimerState<S> {
+    jobs: BinaryHeap<Scheduled<S>>,
+    next_seq: u64,
+    stopped: bool,
+}
+
+struct TimerShared<S> {
+    state: Mutex<TimerState<S>>,
+    // signalled when an earlier job is scheduled or the timer is stopped
+    changed: Condvar,
+}
+
+/// a thread that holds on to jobs until they are due, then queues them on the pool
+pub(crate) struct Timer<S> {
+    handle: TimerHandle<S>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+/// schedules on a `Timer` from anywhere, such as from inside a job
+pub(crate) struct TimerHandle<S> {
+    shared: Arc<TimerShared<S>>,
+}
+
+impl<S> Clone for TimerHandle<S> {
+    fn clone(&self) -> Self {
+        TimerHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<S: 'static> Timer<S> {
+    pub(crate) fn start(pool: Arc<Shared<S>>) -> io::Result<Timer<S>> {
+        let shared = Arc::new(TimerShared {
+            state: Mutex::new(TimerState {
+                jobs: BinaryHeap::new(),
+                next_seq: 0,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let mut builder = thread::Builder::new();
+        if let Some(name) = &pool.options.name {
+            builder = builder.name(format!("{name}-timer"));
+        }
+        let timer = Arc::clone(&shared);
+        let thread = builder.spawn(move || run(&timer, &pool))?;
+
+        Ok(Timer {
+            handle: TimerHandle { shared },
+            thread: Some(thread),
+        })
+    }
+}
+
+impl<S> TimerHandle<S> {
+    // returns the task's sequence number, which `cancel` takes
+    fn add(&self, at: Instant, task: Task<S>) -> Result<u64, PoolClosedError> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.stopped {
+            return Err(PoolClosedError);
+        }
+
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.jobs.push(Scheduled { at, seq, task });
+        self.shared.changed.notify_one();
+        Ok(seq)
+    }
+
+    // queues `job` on the pool at `at`
+    pub(crate) fn schedule(&self, at: Instant, job: Job<S>) -> Result<(), PoolClosedError> {
+        self.add(at, Task::Queue(job)).map(drop)
+    }
+
+    // calls `f` on the timer thread at `at`, unless the timer is stopped or the call is
+    // cancelled first, returning what to hand `cancel`
+    pub(crate) fn call_at<F>(&self, at: Instant, f: F) -> Result<u64, PoolClosedError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.add(at, Task::Call(Box::new(f)))
+    }
+
+    // throws away the task `seq` if it hasn't come due yet. this goes through every task
+    // waiting, so it is only for tasks there are few of at a time
+    pub(crate) fn cancel(&self, seq: u64) {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .jobs
+            .retain(|scheduled| scheduled.seq != seq);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn waiting(&self) -> usize {
+        self.shared.state.lock().unwrap().jobs.len()
+    }
+}
+
+impl<S> Timer<S> {
+    pub(crate) fn handle(&self) -> &TimerHandle<S> {
+        &self.handle
+    }
+
+    // stops the thread, throwing away the jobs that aren't due yet and returning how many
+    pub(crate) fn stop(&mut self) -> usize {
+        let shared = &self.handle.shared;
+        let mut state = shared.state.lock().unwrap();
+        state.stopped = true;
+        let dropped = state
+            .jobs
+            .drain()
+            .filter(|scheduled| matches!(scheduled.task, Task::Queue(_)))
+            .count();
+        shared.changed.notify_one();
+        drop(state);
+
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+        dropped
+    }
+}
+
+// the timer thread's main loop, which sleeps until the next job is due
+fn run<S: 'static>(timer: &TimerShared<S>, pool: &Arc<Shared<S>>) {
+    let mut state = timer.state.lock().unwrap();
+    loop {
+        if state.stopped {
+            return;
+        }
+
+        let now = Instant::now();
+        state = match state.jobs.peek() {
+            None => timer.changed.wait(state).unwrap(),
+            Some(next) if next.at > now => {
+                let wait = next.at - now;
+                timer.changed.wait_timeout(state, wait).unwrap().0
+            }
+            Some(_) => {
+                let due = state.jobs.pop().unwrap();
+                // a bounded queue may make us wait, which mustn't hold up `schedule`
+                drop(state);
+                match due.task {
+                    // if the pool has closed in the meantime the job is dropped unrun,
+                    // the same as if it had been queued and then discarded
+                    Task::Queue(job) => drop(pool.push(Priority::Normal, job)),
+                    Task::Call(f) => f(),
+                }
+                timer.state.lock().unwrap()
+            }
+        };
+    }
+}